@@ -10,7 +10,13 @@ pub use axfs::fops::OpenOptions as AxOpenOptions;
 pub use axio::SeekFrom as AxSeekFrom;
 
 #[cfg(feature = "myfs")]
-pub use axfs::fops::{Disk as AxDisk, MyFileSystemIf};
+pub use axfs::fops::{BlockDevice as AxBlockDevice, FileSystemType as AxFileSystemType};
+#[cfg(feature = "myfs")]
+pub use axfs::fops::{MyFileSystem as AxMyFileSystem, MyFileSystemIf};
+
+/// The block device which a [`MyFileSystemIf`] filesystem is created on.
+#[cfg(feature = "myfs")]
+pub type AxDisk = alloc::boxed::Box<dyn AxBlockDevice>;
 
 /// A handle to an opened file.
 pub struct AxFileHandle(File);
@@ -78,6 +84,22 @@ pub fn ax_rename(old: &str, new: &str) -> AxResult {
     axfs::api::rename(old, new)
 }
 
+pub fn ax_symlink(original: &str, link: &str) -> AxResult {
+    axfs::api::symlink(original, link)
+}
+
+pub fn ax_hard_link(original: &str, link: &str) -> AxResult {
+    axfs::api::hard_link(original, link)
+}
+
+pub fn ax_read_link(path: &str) -> AxResult<String> {
+    axfs::api::read_link(path)
+}
+
+pub fn ax_symlink_attr(path: &str) -> AxResult<AxFileAttr> {
+    axfs::api::symlink_metadata(path).map(|m| *m.raw_metadata())
+}
+
 pub fn ax_current_dir() -> AxResult<String> {
     axfs::api::current_dir()
 }
@@ -85,3 +107,13 @@ pub fn ax_current_dir() -> AxResult<String> {
 pub fn ax_set_current_dir(path: &str) -> AxResult {
     axfs::api::set_current_dir(path)
 }
+
+#[cfg(feature = "myfs")]
+pub fn ax_register_filesystem(fs_type: alloc::sync::Arc<dyn AxFileSystemType>) -> AxResult {
+    axfs::fops::register_filesystem(fs_type)
+}
+
+#[cfg(feature = "myfs")]
+pub fn ax_mount_device(dev: &str, path: &str, fs_type: Option<&str>, read_only: bool) -> AxResult {
+    axfs::api::mount_device(dev, path, fs_type, read_only)
+}
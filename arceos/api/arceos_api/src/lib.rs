@@ -187,6 +187,12 @@ pub mod fs {
         pub type AxDirEntry;
         pub type AxSeekFrom;
         #[cfg(feature = "myfs")]
+        pub type AxBlockDevice;
+        #[cfg(feature = "myfs")]
+        pub type AxFileSystemType;
+        #[cfg(feature = "myfs")]
+        pub type AxMyFileSystem;
+        #[cfg(feature = "myfs")]
         pub type AxDisk;
         #[cfg(feature = "myfs")]
         pub type MyFileSystemIf;
@@ -247,14 +253,43 @@ pub mod fs {
         pub fn ax_remove_file(path: &str) -> AxResult;
         /// Rename a file or directory to a new name.
         ///
-        /// It will delete the original file if `old` already exists.
+        /// It will replace the original file if `new` already exists.
         pub fn ax_rename(old: &str, new: &str) -> AxResult;
+        /// Creates a new symbolic link at `link` which points to `original`.
+        pub fn ax_symlink(original: &str, link: &str) -> AxResult;
+        /// Creates a new hard link at `link` which refers to the same file as
+        /// `original`.
+        pub fn ax_hard_link(original: &str, link: &str) -> AxResult;
+        /// Returns the path that the symbolic link points to.
+        pub fn ax_read_link(path: &str) -> AxResult<alloc::string::String>;
+        /// Returns attributes of the file at the path, without following
+        /// symbolic links.
+        pub fn ax_symlink_attr(path: &str) -> AxResult<AxFileAttr>;
 
         /// Returns the current working directory.
         pub fn ax_current_dir() -> AxResult<alloc::string::String>;
         /// Changes the current working directory to the specified path.
         pub fn ax_set_current_dir(path: &str) -> AxResult;
     }
+
+    define_api! {
+        @cfg "myfs";
+
+        /// Registers a custom filesystem type, which can be selected by its
+        /// name in [`ax_mount_device`].
+        pub fn ax_register_filesystem(
+            fs_type: alloc::sync::Arc<dyn AxFileSystemType>,
+        ) -> AxResult;
+        /// Mounts the filesystem on the block device or partition `dev` at the
+        /// absolute `path`. The filesystem type is detected if `fs_type` is
+        /// `None`, and it is mounted read-only if `read_only` is true.
+        pub fn ax_mount_device(
+            dev: &str,
+            path: &str,
+            fs_type: Option<&str>,
+            read_only: bool,
+        ) -> AxResult;
+    }
 }
 
 /// Networking primitives for TCP/UDP communication.
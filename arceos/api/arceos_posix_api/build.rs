@@ -37,6 +37,9 @@ typedef struct {{
 
         let allow_types = [
             "stat",
+            "statfs",
+            "flock",
+            "inotify_event",
             "size_t",
             "ssize_t",
             "off_t",
@@ -62,6 +65,11 @@ typedef struct {{
             "IPPROTO_.*",
             "FD_.*",
             "F_.*",
+            "LOCK_.*",
+            "IN_.*",
+            "PROT_.*",
+            "MAP_.*",
+            "MADV_.*",
             "_SC_.*",
             "EPOLL_CTL_.*",
             "EPOLL.*",
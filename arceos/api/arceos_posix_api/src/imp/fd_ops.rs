@@ -103,7 +103,10 @@ pub fn sys_dup2(old_fd: c_int, new_fd: c_int) -> c_int {
 
 /// Manipulate file descriptor.
 ///
-/// TODO: `SET/GET` command is ignored, hard-code stdin/stdout
+/// `F_GETLK`, `F_SETLK` and `F_SETLKW` place byte-range locks on files, where
+/// `arg` points to a `struct flock`. `F_SETFL` only changes `O_NONBLOCK`, and
+/// since there is no `exec`, the close-on-exec flag is not recorded. Returns
+/// `EINVAL` for the other commands.
 pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> c_int {
     debug!("sys_fcntl <= fd: {} cmd: {} arg: {}", fd, cmd, arg);
     syscall_body!(sys_fcntl, {
@@ -113,6 +116,10 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> c_int {
                 // TODO: Change fd flags
                 dup_fd(fd)
             }
+            ctypes::F_GETFD | ctypes::F_SETFD | ctypes::F_GETFL => {
+                get_file_like(fd)?;
+                Ok(0)
+            }
             ctypes::F_SETFL => {
                 if fd == 0 || fd == 1 || fd == 2 {
                     return Ok(0);
@@ -120,9 +127,13 @@ pub fn sys_fcntl(fd: c_int, cmd: c_int, arg: usize) -> c_int {
                 get_file_like(fd)?.set_nonblocking(arg & (ctypes::O_NONBLOCK as usize) > 0)?;
                 Ok(0)
             }
+            #[cfg(feature = "fs")]
+            cmd @ (ctypes::F_GETLK | ctypes::F_SETLK | ctypes::F_SETLKW) => {
+                super::fs::fcntl_lock(fd, cmd, arg as *mut ctypes::flock)
+            }
             _ => {
                 warn!("unsupported fcntl parameters: cmd {}", cmd);
-                Ok(0)
+                Err(LinuxError::EINVAL)
             }
         }
     })
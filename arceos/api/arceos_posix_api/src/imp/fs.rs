@@ -2,7 +2,7 @@ use alloc::sync::Arc;
 use core::ffi::{c_char, c_int};
 
 use axerrno::{LinuxError, LinuxResult};
-use axfs::fops::OpenOptions;
+use axfs::fops::{FileAttr, FilePerm, FileSystemInfo, LockKind, OpenOptions, SetAttr};
 use axio::{PollState, SeekFrom};
 use axsync::Mutex;
 
@@ -42,21 +42,7 @@ impl FileLike for File {
     }
 
     fn stat(&self) -> LinuxResult<ctypes::stat> {
-        let metadata = self.inner.lock().get_attr()?;
-        let ty = metadata.file_type() as u8;
-        let perm = metadata.perm().bits() as u32;
-        let st_mode = ((ty as u32) << 12) | perm;
-        Ok(ctypes::stat {
-            st_ino: 1,
-            st_nlink: 1,
-            st_mode,
-            st_uid: 1000,
-            st_gid: 1000,
-            st_size: metadata.size() as _,
-            st_blocks: metadata.blocks() as _,
-            st_blksize: 512,
-            ..Default::default()
-        })
+        Ok(attr_to_stat(&self.inner.lock().get_attr()?))
     }
 
     fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
@@ -70,13 +56,50 @@ impl FileLike for File {
         })
     }
 
-    fn set_nonblocking(&self, _nonblocking: bool) -> LinuxResult {
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.inner.lock().set_nonblocking(nonblocking);
         Ok(())
     }
 }
 
+/// Convert file attributes to [`ctypes::stat`].
+fn attr_to_stat(metadata: &FileAttr) -> ctypes::stat {
+    let ty = metadata.file_type() as u8;
+    let perm = metadata.perm().bits() as u32;
+    let st_mode = ((ty as u32) << 12) | perm;
+    ctypes::stat {
+        st_ino: metadata.ino().max(1),
+        st_nlink: metadata.nlink() as _,
+        st_mode,
+        st_uid: metadata.uid(),
+        st_gid: metadata.gid(),
+        st_size: metadata.size() as _,
+        st_blocks: metadata.blocks() as _,
+        st_blksize: 512,
+        st_atim: metadata.atime().into(),
+        st_mtim: metadata.mtime().into(),
+        st_ctim: metadata.ctime().into(),
+        ..Default::default()
+    }
+}
+
+/// Convert filesystem attributes to [`ctypes::statfs`].
+fn fs_info_to_statfs(info: &FileSystemInfo) -> ctypes::statfs {
+    ctypes::statfs {
+        f_bsize: info.block_size as _,
+        f_blocks: info.blocks as _,
+        f_bfree: info.blocks_free as _,
+        f_bavail: info.blocks_free as _,
+        f_files: info.files as _,
+        f_ffree: info.files_free as _,
+        f_namelen: info.name_max as _,
+        f_frsize: info.block_size as _,
+        ..Default::default()
+    }
+}
+
 /// Convert open flags to [`OpenOptions`].
-fn flags_to_options(flags: c_int, _mode: ctypes::mode_t) -> OpenOptions {
+fn flags_to_options(flags: c_int, mode: ctypes::mode_t) -> OpenOptions {
     let flags = flags as u32;
     let mut options = OpenOptions::new();
     match flags & 0b11 {
@@ -95,10 +118,14 @@ fn flags_to_options(flags: c_int, _mode: ctypes::mode_t) -> OpenOptions {
     }
     if flags & ctypes::O_CREAT != 0 {
         options.create(true);
+        options.mode(mode as _);
     }
     if flags & ctypes::O_EXEC != 0 {
         options.create_new(true);
     }
+    if flags & ctypes::O_NONBLOCK != 0 {
+        options.nonblocking(true);
+    }
     options
 }
 
@@ -177,7 +204,89 @@ pub unsafe fn sys_lstat(path: *const c_char, buf: *mut ctypes::stat) -> ctypes::
         if buf.is_null() {
             return Err(LinuxError::EFAULT);
         }
-        unsafe { *buf = Default::default() }; // TODO
+        let metadata = axfs::api::symlink_metadata(path?)?;
+        unsafe { *buf = attr_to_stat(metadata.raw_metadata()) };
+        Ok(0)
+    })
+}
+
+/// Get the attributes of the filesystem which `path` is located in, and
+/// write into `buf`.
+///
+/// Return 0 if success.
+pub unsafe fn sys_statfs(path: *const c_char, buf: *mut ctypes::statfs) -> c_int {
+    let path = char_ptr_to_str(path);
+    debug!("sys_statfs <= {:?} {:#x}", path, buf as usize);
+    syscall_body!(sys_statfs, {
+        if buf.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let info = axfs::api::statfs(path?)?;
+        unsafe { *buf = fs_info_to_statfs(&info) };
+        Ok(0)
+    })
+}
+
+/// Get the attributes of the filesystem which the file indicated by `fd` is
+/// located in, and write into `buf`.
+///
+/// Return 0 if success.
+pub unsafe fn sys_fstatfs(fd: c_int, buf: *mut ctypes::statfs) -> c_int {
+    debug!("sys_fstatfs <= {} {:#x}", fd, buf as usize);
+    syscall_body!(sys_fstatfs, {
+        if buf.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let info = File::from_fd(fd)?.inner.lock().statfs()?;
+        unsafe { *buf = fs_info_to_statfs(&info) };
+        Ok(0)
+    })
+}
+
+/// Read the target of the symbolic link `path` into `buf`.
+///
+/// The target is truncated if `buf` is too small, and it is not
+/// null-terminated. Return the number of bytes placed in `buf`.
+pub fn sys_readlink(path: *const c_char, buf: *mut c_char, bufsize: usize) -> ctypes::ssize_t {
+    let path = char_ptr_to_str(path);
+    debug!("sys_readlink <= {:?} {:#x} {}", path, buf as usize, bufsize);
+    syscall_body!(sys_readlink, {
+        if buf.is_null() {
+            return Err(LinuxError::EFAULT);
+        }
+        let target = axfs::api::read_link(path?)?;
+        let len = target.len().min(bufsize);
+        let dst = unsafe { core::slice::from_raw_parts_mut(buf as *mut u8, len) };
+        dst.copy_from_slice(&target.as_bytes()[..len]);
+        Ok(len as ctypes::ssize_t)
+    })
+}
+
+/// Create a symbolic link `linkpath` which points to `target`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub fn sys_symlink(target: *const c_char, linkpath: *const c_char) -> c_int {
+    syscall_body!(sys_symlink, {
+        let target = char_ptr_to_str(target)?;
+        let linkpath = char_ptr_to_str(linkpath)?;
+        debug!(
+            "sys_symlink <= target: {:?}, linkpath: {:?}",
+            target, linkpath
+        );
+        axfs::api::symlink(target, linkpath)?;
+        Ok(0)
+    })
+}
+
+/// Create a hard link `new` which refers to the same file as `old`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub fn sys_link(old: *const c_char, new: *const c_char) -> c_int {
+    syscall_body!(sys_link, {
+        let old_path = char_ptr_to_str(old)?;
+        let new_path = char_ptr_to_str(new)?;
+        debug!("sys_link <= old: {:?}, new: {:?}", old_path, new_path);
+        axfs::api::hard_link(old_path, new_path)?;
         Ok(0)
     })
 }
@@ -215,3 +324,190 @@ pub fn sys_rename(old: *const c_char, new: *const c_char) -> c_int {
         Ok(0)
     })
 }
+
+/// Returns a new handle to the file opened as `fd`, e.g., to map it into
+/// memory.
+pub fn clone_file(fd: c_int) -> LinuxResult<axfs::fops::File> {
+    Ok(File::from_fd(fd)?.inner.lock().try_clone()?)
+}
+
+/// Write all cached data and metadata of the filesystems back to the devices.
+pub fn sys_sync() {
+    debug!("sys_sync");
+    if let Err(e) = axfs::api::sync() {
+        warn!("sys_sync: failed to write back the filesystems: {:?}", e);
+    }
+}
+
+/// Convert the owner IDs passed to `chown`, where `-1` means unchanged, to
+/// [`SetAttr`].
+fn owner_to_attr(owner: ctypes::uid_t, group: ctypes::gid_t) -> SetAttr {
+    SetAttr {
+        uid: (owner != ctypes::uid_t::MAX).then_some(owner),
+        gid: (group != ctypes::gid_t::MAX).then_some(group),
+        ..Default::default()
+    }
+}
+
+/// Change the permission of the file `path` to `mode`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub fn sys_chmod(path: *const c_char, mode: ctypes::mode_t) -> c_int {
+    let path = char_ptr_to_str(path);
+    debug!("sys_chmod <= {:?} {:#o}", path, mode);
+    syscall_body!(sys_chmod, {
+        axfs::api::set_permissions(path?, FilePerm::from_bits_truncate(mode as _))?;
+        Ok(0)
+    })
+}
+
+/// Change the permission of the file indicated by `fd` to `mode`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub fn sys_fchmod(fd: c_int, mode: ctypes::mode_t) -> c_int {
+    debug!("sys_fchmod <= {} {:#o}", fd, mode);
+    syscall_body!(sys_fchmod, {
+        let attr = SetAttr {
+            perm: Some(FilePerm::from_bits_truncate(mode as _)),
+            ..Default::default()
+        };
+        File::from_fd(fd)?.inner.lock().set_attr(&attr)?;
+        Ok(0)
+    })
+}
+
+/// Change the owner and group of the file `path`. The ID which is `-1` is
+/// left unchanged.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub fn sys_chown(path: *const c_char, owner: ctypes::uid_t, group: ctypes::gid_t) -> c_int {
+    let path = char_ptr_to_str(path);
+    debug!("sys_chown <= {:?} {} {}", path, owner as i32, group as i32);
+    syscall_body!(sys_chown, {
+        let attr = owner_to_attr(owner, group);
+        axfs::api::chown(path?, attr.uid, attr.gid)?;
+        Ok(0)
+    })
+}
+
+/// Change the owner and group of the file indicated by `fd`. The ID which is
+/// `-1` is left unchanged.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub fn sys_fchown(fd: c_int, owner: ctypes::uid_t, group: ctypes::gid_t) -> c_int {
+    debug!("sys_fchown <= {} {} {}", fd, owner as i32, group as i32);
+    syscall_body!(sys_fchown, {
+        File::from_fd(fd)?
+            .inner
+            .lock()
+            .set_attr(&owner_to_attr(owner, group))?;
+        Ok(0)
+    })
+}
+
+/// Change the last access and modification times of the file `path` to
+/// `times[0]` and `times[1]`, or to the current time if `times` is null.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+pub unsafe fn sys_utimes(path: *const c_char, times: *const ctypes::timeval) -> c_int {
+    let path = char_ptr_to_str(path);
+    debug!("sys_utimes <= {:?} {:#x}", path, times as usize);
+    syscall_body!(sys_utimes, {
+        let (atime, mtime) = if times.is_null() {
+            let now = axhal::time::wall_time();
+            (now, now)
+        } else {
+            let times = unsafe { core::slice::from_raw_parts(times, 2) };
+            (times[0].into(), times[1].into())
+        };
+        axfs::api::set_times(path?, Some(atime), Some(mtime))?;
+        Ok(0)
+    })
+}
+
+/// Apply or remove an advisory lock on the whole file `fd`.
+///
+/// `operation` is `LOCK_SH`, `LOCK_EX` or `LOCK_UN`, optionally combined with
+/// `LOCK_NB` to return `EWOULDBLOCK` instead of blocking if the lock is held
+/// by other open files.
+pub fn sys_flock(fd: c_int, operation: c_int) -> c_int {
+    debug!("sys_flock <= {} {:#x}", fd, operation);
+    syscall_body!(sys_flock, {
+        let operation = operation as u32;
+        let kind = match operation & !ctypes::LOCK_NB {
+            ctypes::LOCK_SH => Some(LockKind::Shared),
+            ctypes::LOCK_EX => Some(LockKind::Exclusive),
+            ctypes::LOCK_UN => None,
+            _ => return Err(LinuxError::EINVAL),
+        };
+        let file = File::from_fd(fd)?;
+        // do not hold the file while waiting
+        let locks = file.inner.lock().locks();
+        locks.flock(kind, operation & ctypes::LOCK_NB == 0)?;
+        Ok(0)
+    })
+}
+
+/// Handle the `F_GETLK`, `F_SETLK` and `F_SETLKW` commands of `fcntl` on the
+/// file `fd`, where `lock` describes the byte-range lock.
+///
+/// The locks are owned by the open file, like the open file description locks
+/// of Linux, so `l_pid` of the conflicting lock returned by `F_GETLK` is -1.
+pub(crate) fn fcntl_lock(fd: c_int, cmd: u32, lock: *mut ctypes::flock) -> LinuxResult<c_int> {
+    if lock.is_null() {
+        return Err(LinuxError::EFAULT);
+    }
+    let lock = unsafe { &mut *lock };
+    let file = File::from_fd(fd)?;
+    let (locks, range) = {
+        let mut inner = file.inner.lock();
+        let base = match lock.l_whence {
+            0 => 0,
+            1 => inner.seek(SeekFrom::Current(0))?,
+            2 => inner.get_attr()?.size(),
+            _ => return Err(LinuxError::EINVAL),
+        };
+        let offset = (base as i64).checked_add(lock.l_start);
+        let end = offset.and_then(|off| off.checked_add(lock.l_len));
+        let (start, end) = match (offset, end) {
+            (Some(start), _) if lock.l_len == 0 => (start, u64::MAX),
+            (Some(start), Some(end)) if lock.l_len > 0 => (start, end as u64),
+            (Some(end), Some(start)) => (start, end as u64),
+            _ => return Err(LinuxError::EOVERFLOW),
+        };
+        if start < 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        (inner.locks(), start as u64..end)
+    };
+    let kind = match lock.l_type as u32 {
+        ctypes::F_RDLCK => Some(LockKind::Shared),
+        ctypes::F_WRLCK => Some(LockKind::Exclusive),
+        ctypes::F_UNLCK => None,
+        _ => return Err(LinuxError::EINVAL),
+    };
+
+    if cmd == ctypes::F_GETLK {
+        let kind = kind.ok_or(LinuxError::EINVAL)?;
+        match locks.get_lock(kind, range) {
+            Some(held) => {
+                lock.l_type = match held.kind {
+                    LockKind::Shared => ctypes::F_RDLCK,
+                    LockKind::Exclusive => ctypes::F_WRLCK,
+                } as _;
+                lock.l_whence = 0;
+                lock.l_start = held.range.start as _;
+                lock.l_len = if held.range.end == u64::MAX {
+                    0
+                } else {
+                    (held.range.end - held.range.start) as _
+                };
+                lock.l_pid = -1;
+            }
+            None => lock.l_type = ctypes::F_UNLCK as _,
+        }
+    } else {
+        locks.set_lock(kind, range, cmd == ctypes::F_SETLKW)?;
+    }
+    Ok(0)
+}
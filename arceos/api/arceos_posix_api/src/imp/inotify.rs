@@ -0,0 +1,167 @@
+use alloc::sync::Arc;
+use core::ffi::{c_char, c_int};
+use core::mem::size_of;
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use axerrno::{LinuxError, LinuxResult};
+use axfs::fops::{Credentials, WatchEvent, WatchMask, Watcher};
+use axio::PollState;
+
+use super::fd_ops::{add_file_like, get_file_like, FileLike};
+use crate::{ctypes, utils::char_ptr_to_str};
+
+/// An inotify instance, which reads the changes to the watched files as
+/// `struct inotify_event`s.
+pub struct Inotify {
+    watcher: Watcher,
+    nonblocking: AtomicBool,
+}
+
+impl Inotify {
+    fn from_fd(fd: c_int) -> LinuxResult<Arc<Self>> {
+        get_file_like(fd)?
+            .into_any()
+            .downcast::<Self>()
+            .map_err(|_| LinuxError::EINVAL)
+    }
+}
+
+/// Returns the size of `event` read from the inotify instance, whose name is
+/// padded with NULs to align the next event.
+fn event_size(event: &WatchEvent) -> usize {
+    let header_size = size_of::<ctypes::inotify_event>();
+    if event.name.is_empty() {
+        header_size
+    } else {
+        header_size + (event.name.len() + 1).next_multiple_of(header_size)
+    }
+}
+
+/// Writes `event` as a `struct inotify_event` to `buf`, which has at least
+/// [`event_size`] bytes.
+fn write_event(event: &WatchEvent, buf: &mut [u8]) {
+    let header_size = size_of::<ctypes::inotify_event>();
+    let name_len = (event_size(event) - header_size) as u32;
+    let fields = [
+        event.wd.to_ne_bytes(),
+        event.mask.bits().to_ne_bytes(),
+        event.cookie.to_ne_bytes(),
+        name_len.to_ne_bytes(),
+    ];
+    for (dst, src) in buf.chunks_exact_mut(4).zip(fields) {
+        dst.copy_from_slice(&src);
+    }
+    let name = &mut buf[header_size..header_size + name_len as usize];
+    name.fill(0);
+    name[..event.name.len()].copy_from_slice(event.name.as_bytes());
+}
+
+impl FileLike for Inotify {
+    fn read(&self, buf: &mut [u8]) -> LinuxResult<usize> {
+        loop {
+            let mut len = 0;
+            self.watcher.read_events(|event| {
+                let size = event_size(event);
+                if len + size > buf.len() {
+                    return false;
+                }
+                write_event(event, &mut buf[len..len + size]);
+                len += size;
+                true
+            });
+            if len > 0 {
+                return Ok(len);
+            }
+            if self.watcher.has_events() {
+                // the buffer is too small for the next event
+                return Err(LinuxError::EINVAL);
+            }
+            if self.nonblocking.load(Ordering::Relaxed) {
+                return Err(LinuxError::EAGAIN);
+            }
+            self.watcher.wait_events()?;
+        }
+    }
+
+    fn write(&self, _buf: &[u8]) -> LinuxResult<usize> {
+        Err(LinuxError::EINVAL)
+    }
+
+    fn stat(&self) -> LinuxResult<ctypes::stat> {
+        Ok(ctypes::stat {
+            st_ino: 1,
+            st_nlink: 1,
+            st_mode: 0o600, // rw-------
+            st_blksize: 4096,
+            ..Default::default()
+        })
+    }
+
+    fn into_any(self: Arc<Self>) -> Arc<dyn core::any::Any + Send + Sync> {
+        self
+    }
+
+    fn poll(&self) -> LinuxResult<PollState> {
+        Ok(PollState {
+            readable: self.watcher.has_events(),
+            writable: false,
+        })
+    }
+
+    fn set_nonblocking(&self, nonblocking: bool) -> LinuxResult {
+        self.nonblocking.store(nonblocking, Ordering::Relaxed);
+        Ok(())
+    }
+}
+
+/// Create an inotify instance.
+///
+/// `flags` may contain `IN_NONBLOCK` and `IN_CLOEXEC`. Return the file
+/// descriptor of the instance, which is readable by `select` and `epoll` when
+/// there are events to read.
+pub fn sys_inotify_init1(flags: c_int) -> c_int {
+    debug!("sys_inotify_init1 <= {:#x}", flags);
+    syscall_body!(sys_inotify_init1, {
+        let flags = flags as u32;
+        if flags & !(ctypes::IN_NONBLOCK | ctypes::IN_CLOEXEC) != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        add_file_like(Arc::new(Inotify {
+            watcher: Watcher::new(),
+            nonblocking: AtomicBool::new(flags & ctypes::IN_NONBLOCK != 0),
+        }))
+    })
+}
+
+/// Watch the changes of `mask` to the file or directory at `pathname` by the
+/// inotify instance `fd`.
+///
+/// Only `IN_MODIFY`, `IN_MOVED_FROM`, `IN_MOVED_TO`, `IN_CREATE`,
+/// `IN_DELETE` and `IN_DELETE_SELF` events are reported, and only on
+/// filesystems supporting change notification. Return the watch descriptor.
+pub fn sys_inotify_add_watch(fd: c_int, pathname: *const c_char, mask: u32) -> c_int {
+    let pathname = char_ptr_to_str(pathname);
+    debug!("sys_inotify_add_watch <= {} {:?} {:#x}", fd, pathname, mask);
+    syscall_body!(sys_inotify_add_watch, {
+        if mask & ctypes::IN_ALL_EVENTS == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let inotify = Inotify::from_fd(fd)?;
+        let mask = WatchMask::from_bits_truncate(mask);
+        let wd = inotify
+            .watcher
+            .add_watch(pathname?, mask, &Credentials::root())?;
+        Ok(wd)
+    })
+}
+
+/// Remove the watch `wd` from the inotify instance `fd`.
+///
+/// Return 0 if success.
+pub fn sys_inotify_rm_watch(fd: c_int, wd: c_int) -> c_int {
+    debug!("sys_inotify_rm_watch <= {} {}", fd, wd);
+    syscall_body!(sys_inotify_rm_watch, {
+        Inotify::from_fd(fd)?.watcher.remove_watch(wd)?;
+        Ok(0)
+    })
+}
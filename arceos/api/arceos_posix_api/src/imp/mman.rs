@@ -0,0 +1,274 @@
+//! Memory mapping syscalls on a user address space.
+//!
+//! ArceOS itself has no processes, so the address space is passed by the
+//! caller, e.g., the one of the current user task in a monolithic kernel.
+
+#[cfg(feature = "fs")]
+use alloc::sync::Arc;
+use core::ffi::{c_int, c_void};
+
+use axerrno::{LinuxError, LinuxResult};
+use axhal::paging::MappingFlags;
+use axmm::AddrSpace;
+use memory_addr::{VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use crate::ctypes;
+
+/// The lowest address that [`sys_mmap`] maps at without `MAP_FIXED`.
+pub const MMAP_MIN_ADDR: usize = 0x1_0000;
+
+/// Converts the `PROT_*` bits to the mapping flags of user pages.
+fn prot_to_flags(prot: c_int) -> LinuxResult<MappingFlags> {
+    let prot = prot as u32;
+    if prot & !(ctypes::PROT_READ | ctypes::PROT_WRITE | ctypes::PROT_EXEC) != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let mut flags = MappingFlags::USER;
+    if prot & ctypes::PROT_READ != 0 {
+        flags |= MappingFlags::READ;
+    }
+    if prot & ctypes::PROT_WRITE != 0 {
+        flags |= MappingFlags::WRITE;
+    }
+    if prot & ctypes::PROT_EXEC != 0 {
+        flags |= MappingFlags::EXECUTE;
+    }
+    Ok(flags)
+}
+
+/// Returns the pages of `[addr, addr + len)`, or `err` if they are out of the
+/// address space.
+fn page_range(
+    aspace: &AddrSpace,
+    addr: usize,
+    len: usize,
+    err: LinuxError,
+) -> LinuxResult<(VirtAddr, usize)> {
+    if addr % PAGE_SIZE_4K != 0 {
+        return Err(LinuxError::EINVAL);
+    }
+    let size = len.checked_next_multiple_of(PAGE_SIZE_4K).ok_or(err)?;
+    if addr.checked_add(size).is_none() || !aspace.contains_range(addr.into(), size) {
+        return Err(err);
+    }
+    Ok((addr.into(), size))
+}
+
+/// The flags of [`sys_mmap`] other than the mapping type, which are all
+/// validated with `MAP_SHARED_VALIDATE`.
+const MAP_KNOWN_FLAGS: u32 = ctypes::MAP_FIXED
+    | ctypes::MAP_ANONYMOUS
+    | ctypes::MAP_NORESERVE
+    | ctypes::MAP_POPULATE
+    | ctypes::MAP_FIXED_NOREPLACE;
+
+/// Creates a mapping of the file `fd`, which must be a regular file opened for
+/// reading, and also for writing if the changes are written back.
+#[cfg(feature = "fs")]
+fn file_mapping(fd: c_int, writes_back: bool) -> LinuxResult<Arc<axmm::FileMapping>> {
+    let file = super::fs::clone_file(fd).map_err(|e| match e {
+        LinuxError::EINVAL => LinuxError::ENODEV, // not a regular file
+        e => e,
+    })?;
+    if !file.readable() || (writes_back && !file.writable()) {
+        return Err(LinuxError::EACCES);
+    }
+    Ok(Arc::new(axmm::FileMapping::new(file)))
+}
+
+/// Map files or anonymous memory into the address space.
+///
+/// Returns the start address of the mapping. Without `MAP_FIXED` or
+/// `MAP_FIXED_NOREPLACE`, `addr` is only a hint, and the mapping is placed at
+/// the first free area from it. With `MAP_FIXED`, the pages already mapped
+/// there are unmapped only after the arguments are validated.
+///
+/// The private anonymous pages are allocated on demand unless `MAP_POPULATE`
+/// is given, and the shared ones are always allocated on demand. Unknown
+/// flags are ignored, except with `MAP_SHARED_VALIDATE`, where they fail with
+/// `EOPNOTSUPP`.
+#[cfg_attr(not(feature = "fs"), allow(unused_variables))]
+pub fn sys_mmap(
+    aspace: &mut AddrSpace,
+    addr: *mut c_void,
+    len: usize,
+    prot: c_int,
+    flags: c_int,
+    fd: c_int,
+    offset: ctypes::off_t,
+) -> isize {
+    debug!(
+        "sys_mmap <= addr: {:#x}, len: {:#x}, prot: {:#x}, flags: {:#x}, fd: {}, offset: {:#x}",
+        addr as usize, len, prot, flags, fd, offset
+    );
+    syscall_body!(sys_mmap, {
+        let map_flags = prot_to_flags(prot)?;
+        let flags = flags as u32;
+        let shared = match flags & ctypes::MAP_TYPE {
+            ctypes::MAP_SHARED => true,
+            ctypes::MAP_SHARED_VALIDATE => {
+                if flags & !(ctypes::MAP_TYPE | MAP_KNOWN_FLAGS) != 0 {
+                    return Err(LinuxError::EOPNOTSUPP);
+                }
+                true
+            }
+            ctypes::MAP_PRIVATE => false,
+            _ => return Err(LinuxError::EINVAL),
+        };
+        if len == 0 || offset < 0 || offset as usize % PAGE_SIZE_4K != 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let anonymous = flags & ctypes::MAP_ANONYMOUS != 0;
+        #[cfg(feature = "fs")]
+        let file = if anonymous {
+            None
+        } else {
+            let writes_back = shared && map_flags.contains(MappingFlags::WRITE);
+            Some(file_mapping(fd, writes_back)?)
+        };
+        #[cfg(not(feature = "fs"))]
+        if !anonymous {
+            return Err(LinuxError::ENODEV);
+        }
+
+        let (start, size) = if flags & (ctypes::MAP_FIXED | ctypes::MAP_FIXED_NOREPLACE) != 0 {
+            let (start, size) = page_range(aspace, addr as usize, len, LinuxError::ENOMEM)?;
+            if aspace.overlaps(start, size) {
+                if flags & ctypes::MAP_FIXED_NOREPLACE != 0 {
+                    return Err(LinuxError::EEXIST);
+                }
+                // nothing can fail from here on but the mapping itself
+                aspace.unmap(start, size)?;
+            }
+            (start, size)
+        } else {
+            let size = len
+                .checked_next_multiple_of(PAGE_SIZE_4K)
+                .ok_or(LinuxError::ENOMEM)?;
+            let hint = (addr as usize & !(PAGE_SIZE_4K - 1)).max(MMAP_MIN_ADDR);
+            let limit = VirtAddrRange::new(aspace.base().max(MMAP_MIN_ADDR.into()), aspace.end());
+            let start = aspace
+                .find_free_area(hint.into(), size, limit)
+                .ok_or(LinuxError::ENOMEM)?;
+            (start, size)
+        };
+
+        #[cfg(feature = "fs")]
+        if let Some(file) = file {
+            aspace.map_file(start, size, map_flags, file, offset as u64, shared)?;
+            return Ok(start.as_usize());
+        }
+        if shared {
+            aspace.map_shared(start, size, map_flags)?;
+        } else {
+            let populate = flags & ctypes::MAP_POPULATE != 0;
+            aspace.map_alloc(start, size, map_flags, populate)?;
+        }
+        Ok(start.as_usize())
+    })
+}
+
+/// Unmap the pages in `[addr, addr + len)`, which may split the mappings.
+///
+/// The pages that are not mapped are ignored.
+pub fn sys_munmap(aspace: &mut AddrSpace, addr: *mut c_void, len: usize) -> c_int {
+    debug!("sys_munmap <= addr: {:#x}, len: {:#x}", addr as usize, len);
+    syscall_body!(sys_munmap, {
+        if len == 0 {
+            return Err(LinuxError::EINVAL);
+        }
+        let (start, size) = page_range(aspace, addr as usize, len, LinuxError::EINVAL)?;
+        if aspace.overlaps(start, size) {
+            aspace.unmap(start, size)?;
+        }
+        Ok(0)
+    })
+}
+
+/// Change the access protections of the pages in `[addr, addr + len)`, which
+/// must be all mapped.
+pub fn sys_mprotect(aspace: &mut AddrSpace, addr: *mut c_void, len: usize, prot: c_int) -> c_int {
+    debug!(
+        "sys_mprotect <= addr: {:#x}, len: {:#x}, prot: {:#x}",
+        addr as usize, len, prot
+    );
+    syscall_body!(sys_mprotect, {
+        let flags = prot_to_flags(prot)?;
+        let (start, size) = page_range(aspace, addr as usize, len, LinuxError::ENOMEM)?;
+        if size == 0 {
+            return Ok(0);
+        }
+        if !aspace.is_mapped(start, size) {
+            return Err(LinuxError::ENOMEM);
+        }
+        aspace.protect(start, size, flags)?;
+        Ok(0)
+    })
+}
+
+/// Give advice about the use of the pages in `[addr, addr + len)`, which must
+/// be all mapped.
+///
+/// Only `MADV_DONTNEED` takes effect, which frees the pages, so that they are
+/// zero-filled (or read from the file again) on the next access. The other
+/// supported advices are ignored.
+pub fn sys_madvise(aspace: &mut AddrSpace, addr: *mut c_void, len: usize, advice: c_int) -> c_int {
+    debug!(
+        "sys_madvise <= addr: {:#x}, len: {:#x}, advice: {}",
+        addr as usize, len, advice
+    );
+    syscall_body!(sys_madvise, {
+        match advice as u32 {
+            ctypes::MADV_NORMAL
+            | ctypes::MADV_RANDOM
+            | ctypes::MADV_SEQUENTIAL
+            | ctypes::MADV_WILLNEED
+            | ctypes::MADV_DONTNEED => {}
+            _ => return Err(LinuxError::EINVAL),
+        }
+        let (start, size) = page_range(aspace, addr as usize, len, LinuxError::ENOMEM)?;
+        if size == 0 {
+            return Ok(0);
+        }
+        if !aspace.is_mapped(start, size) {
+            return Err(LinuxError::ENOMEM);
+        }
+        if advice as u32 == ctypes::MADV_DONTNEED {
+            aspace.discard(start, size)?;
+        }
+        Ok(0)
+    })
+}
+
+/// Set the program break to `addr`.
+///
+/// Returns the new program break, or the current one if it fails, as the
+/// Linux syscall does. So `addr` of NULL queries the current program break.
+pub fn sys_brk(aspace: &mut AddrSpace, addr: *mut c_void) -> isize {
+    debug!("sys_brk <= addr: {:#x}", addr as usize);
+    if !addr.is_null() {
+        if let Err(e) = aspace.set_brk(VirtAddr::from(addr as usize)) {
+            debug!("sys_brk: failed to set the program break: {:?}", e);
+        }
+    }
+    let brk = aspace.brk().as_usize();
+    debug!("sys_brk => {:#x}", brk);
+    brk as isize
+}
+
+/// Move the program break by `increment` bytes.
+///
+/// Returns the previous program break.
+pub fn sys_sbrk(aspace: &mut AddrSpace, increment: isize) -> isize {
+    debug!("sys_sbrk <= increment: {}", increment);
+    syscall_body!(sys_sbrk, {
+        let old_brk = aspace.brk().as_usize();
+        let new_brk = old_brk
+            .checked_add_signed(increment)
+            .ok_or(LinuxError::ENOMEM)?;
+        aspace
+            .set_brk(new_brk.into())
+            .map_err(|_| LinuxError::ENOMEM)?;
+        Ok(old_brk)
+    })
+}
@@ -10,8 +10,12 @@ pub mod time;
 pub mod fd_ops;
 #[cfg(feature = "fs")]
 pub mod fs;
+#[cfg(feature = "fs")]
+pub mod inotify;
 #[cfg(any(feature = "select", feature = "epoll"))]
 pub mod io_mpx;
+#[cfg(feature = "mm")]
+pub mod mman;
 #[cfg(feature = "net")]
 pub mod net;
 #[cfg(feature = "pipe")]
@@ -39,11 +39,21 @@ pub use imp::time::{sys_clock_gettime, sys_nanosleep};
 #[cfg(feature = "fd")]
 pub use imp::fd_ops::{sys_close, sys_dup, sys_dup2, sys_fcntl, get_file_like};
 #[cfg(feature = "fs")]
-pub use imp::fs::{sys_fstat, sys_getcwd, sys_lseek, sys_lstat, sys_open, sys_rename, sys_stat};
+pub use imp::fs::{
+    sys_chmod, sys_chown, sys_fchmod, sys_fchown, sys_flock, sys_fstat, sys_fstatfs, sys_getcwd,
+    sys_link, sys_lseek, sys_lstat, sys_open, sys_readlink, sys_rename, sys_stat, sys_statfs,
+    sys_symlink, sys_sync, sys_utimes,
+};
+#[cfg(feature = "fs")]
+pub use imp::fs::clone_file;
+#[cfg(feature = "fs")]
+pub use imp::inotify::{sys_inotify_add_watch, sys_inotify_init1, sys_inotify_rm_watch};
 #[cfg(feature = "select")]
 pub use imp::io_mpx::sys_select;
 #[cfg(feature = "epoll")]
 pub use imp::io_mpx::{sys_epoll_create, sys_epoll_ctl, sys_epoll_wait};
+#[cfg(feature = "mm")]
+pub use imp::mman::{sys_brk, sys_madvise, sys_mmap, sys_mprotect, sys_munmap, sys_sbrk};
 #[cfg(feature = "net")]
 pub use imp::net::{
     sys_accept, sys_bind, sys_connect, sys_freeaddrinfo, sys_getaddrinfo, sys_getpeername,
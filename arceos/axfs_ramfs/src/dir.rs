@@ -1,12 +1,15 @@
 use alloc::collections::BTreeMap;
 use alloc::sync::{Arc, Weak};
 use alloc::{string::String, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
 
-use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType};
-use axfs_vfs::{VfsError, VfsResult};
+use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeRef, VfsNodeType};
+use axfs_vfs::{VfsError, VfsEventMask, VfsResult, VfsSetAttr, VfsWatcher};
 use spin::RwLock;
 
 use crate::file::FileNode;
+use crate::meta::{FsContext, NodeMeta};
+use crate::symlink::SymlinkNode;
 
 /// The directory node in the RAM filesystem.
 ///
@@ -15,15 +18,21 @@ pub struct DirNode {
     this: Weak<DirNode>,
     parent: RwLock<Weak<dyn VfsNodeOps>>,
     children: RwLock<BTreeMap<String, VfsNodeRef>>,
+    pub(crate) meta: NodeMeta,
 }
 
 impl DirNode {
-    pub(super) fn new(parent: Option<Weak<dyn VfsNodeOps>>) -> Arc<Self> {
-        Arc::new_cyclic(|this| Self {
+    pub(super) fn new(
+        parent: Option<Weak<dyn VfsNodeOps>>,
+        ctx: &Arc<FsContext>,
+    ) -> VfsResult<Arc<Self>> {
+        let meta = NodeMeta::new(ctx, VfsNodePerm::default_dir())?;
+        Ok(Arc::new_cyclic(|this| Self {
             this: this.clone(),
             parent: RwLock::new(parent.unwrap_or_else(|| Weak::<Self>::new())),
             children: RwLock::new(BTreeMap::new()),
-        })
+            meta,
+        }))
     }
 
     pub(super) fn set_parent(&self, parent: Option<&VfsNodeRef>) {
@@ -47,11 +56,56 @@ impl DirNode {
             return Err(VfsError::AlreadyExists);
         }
         let node: VfsNodeRef = match ty {
-            VfsNodeType::File => Arc::new(FileNode::new()),
-            VfsNodeType::Dir => Self::new(Some(self.this.clone())),
+            VfsNodeType::File => {
+                let file = FileNode::new(self.meta.ctx())?;
+                file.add_link(&self.this, name);
+                Arc::new(file)
+            }
+            VfsNodeType::Dir => Self::new(Some(self.this.clone()), self.meta.ctx())?,
             _ => return Err(VfsError::Unsupported),
         };
+        let mask = VfsEventMask::CREATE | dir_flag(&node);
         self.children.write().insert(name.into(), node);
+        self.meta.touch_modify();
+        self.meta.notify(mask, name, 0);
+        Ok(())
+    }
+
+    /// Creates a new symbolic link with the given name in this directory,
+    /// which points to `target`.
+    pub fn create_symlink(&self, name: &str, target: &str) -> VfsResult {
+        let mut children = self.children.write();
+        if children.contains_key(name) {
+            log::error!("AlreadyExists {}", name);
+            return Err(VfsError::AlreadyExists);
+        }
+        let symlink = SymlinkNode::new(self.meta.ctx(), target)?;
+        children.insert(name.into(), Arc::new(symlink));
+        self.meta.touch_modify();
+        self.meta.notify(VfsEventMask::CREATE, name, 0);
+        Ok(())
+    }
+
+    /// Creates a new hard link with the given name in this directory, which
+    /// refers to an existing file or symbolic link `node`.
+    pub fn link_node(&self, name: &str, node: &VfsNodeRef) -> VfsResult {
+        if node.as_any().is::<DirNode>() {
+            return Err(VfsError::PermissionDenied); // hard links to directories
+        }
+        let nlink = link_count_of(node).ok_or(VfsError::CrossesDevices)?;
+        let mut children = self.children.write();
+        if children.contains_key(name) {
+            log::error!("AlreadyExists {}", name);
+            return Err(VfsError::AlreadyExists);
+        }
+        nlink.fetch_add(1, Ordering::Relaxed);
+        touch_change_of(node);
+        if let Some(file) = node.as_any().downcast_ref::<FileNode>() {
+            file.add_link(&self.this, name);
+        }
+        children.insert(name.into(), node.clone());
+        self.meta.touch_modify();
+        self.meta.notify(VfsEventMask::CREATE, name, 0);
         Ok(())
     }
 
@@ -64,23 +118,161 @@ impl DirNode {
                 return Err(VfsError::DirectoryNotEmpty);
             }
         }
+        let mask = VfsEventMask::DELETE | dir_flag(node);
+        self.unlink(name, node);
         children.remove(name);
+        self.meta.touch_modify();
+        self.meta.notify(mask, name, 0);
         Ok(())
     }
 
-    /// Renames a node
+    /// Renames a node in this directory.
     pub fn rename_node(&self, old_name: &str, new_name: &str) -> VfsResult {
-        let mut children = self.children.write();
+        let this = self.this.upgrade().ok_or(VfsError::NotFound)?;
+        self.move_node(old_name, &this, new_name)
+    }
 
-        let node = children.remove(old_name).ok_or(VfsError::NotFound)?;
-        children.insert(new_name.into(), node);
+    /// Moves the node `old_name` in this directory to `new_name` in the
+    /// directory `new_dir`.
+    ///
+    /// An existing node at `new_name` is replaced atomically, as long as it
+    /// is of a compatible type (a file for a file, an empty directory for a
+    /// directory). A directory cannot be moved into its own subtree.
+    pub fn move_node(&self, old_name: &str, new_dir: &Arc<DirNode>, new_name: &str) -> VfsResult {
+        let node = self
+            .children
+            .read()
+            .get(old_name)
+            .cloned()
+            .ok_or(VfsError::NotFound)?;
+        if let Some(dir) = node.as_any().downcast_ref::<DirNode>() {
+            if new_dir.is_in_subtree_of(dir) {
+                return Err(VfsError::InvalidInput);
+            }
+        }
+
+        // lock the two directories in a fixed order to avoid deadlocks
+        let same_dir = core::ptr::eq(self, new_dir.as_ref());
+        let (mut src, mut dst) = if same_dir {
+            (self.children.write(), None)
+        } else if (self as *const Self) < Arc::as_ptr(new_dir) {
+            let src = self.children.write();
+            (src, Some(new_dir.children.write()))
+        } else {
+            let dst = new_dir.children.write();
+            (self.children.write(), Some(dst))
+        };
+
+        // the node may have been changed before the locks are taken
+        let node = src.get(old_name).cloned().ok_or(VfsError::NotFound)?;
+        let target = match &dst {
+            Some(dst) => dst.get(new_name).cloned(),
+            None => src.get(new_name).cloned(),
+        };
+        if let Some(target) = &target {
+            if Arc::ptr_eq(target, &node) {
+                return Ok(()); // the same node, e.g. hard links
+            }
+            check_replace(&node, target)?;
+            new_dir.unlink(new_name, target);
+        }
+        src.remove(old_name);
+        if let Some(dir) = node.as_any().downcast_ref::<DirNode>() {
+            *dir.parent.write() = new_dir.this.clone() as Weak<dyn VfsNodeOps>;
+        }
+        if let Some(file) = node.as_any().downcast_ref::<FileNode>() {
+            file.remove_link(&self.this, old_name);
+            file.add_link(&new_dir.this, new_name);
+        }
+        touch_change_of(&node);
+        let dir_flag = dir_flag(&node);
+        match &mut dst {
+            Some(dst) => dst.insert(new_name.into(), node),
+            None => src.insert(new_name.into(), node),
+        };
+        self.meta.touch_modify();
+        if !same_dir {
+            new_dir.meta.touch_modify();
+        }
+        let cookie = self.meta.ctx().new_cookie();
+        self.meta
+            .notify(VfsEventMask::MOVED_FROM | dir_flag, old_name, cookie);
+        new_dir
+            .meta
+            .notify(VfsEventMask::MOVED_TO | dir_flag, new_name, cookie);
         Ok(())
     }
+
+    /// Drops the link `name` to `node`, which is being removed from this
+    /// directory, and reports the removal to the watchers of the node if it
+    /// is the last link.
+    fn unlink(&self, name: &str, node: &VfsNodeRef) {
+        let last = match link_count_of(node) {
+            Some(nlink) => nlink.fetch_sub(1, Ordering::Relaxed) == 1,
+            None => true,
+        };
+        if let Some(file) = node.as_any().downcast_ref::<FileNode>() {
+            file.remove_link(&self.this, name);
+        }
+        if let Some(meta) = meta_of(node) {
+            meta.touch_change();
+            if last {
+                meta.notify(VfsEventMask::DELETE_SELF, "", 0);
+            }
+        }
+    }
+
+    /// Checks whether this directory is `dir` itself or located under it.
+    fn is_in_subtree_of(&self, dir: &DirNode) -> bool {
+        if core::ptr::eq(self, dir) {
+            return true;
+        }
+        let mut parent = self.parent();
+        while let Some(node) = parent {
+            match node.as_any().downcast_ref::<DirNode>() {
+                Some(node) if core::ptr::eq(node, dir) => return true,
+                Some(node) => parent = node.parent(),
+                None => break, // out of this filesystem
+            }
+        }
+        false
+    }
+
+    /// Looks up the directory containing the last component of `path`, and
+    /// returns it with the name of the last component.
+    fn lookup_parent<'a>(&self, path: &'a str) -> VfsResult<(Arc<DirNode>, &'a str)> {
+        let path = path.trim_end_matches('/');
+        let (parent_path, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if name.is_empty() || name == "." || name == ".." {
+            return Err(VfsError::InvalidInput);
+        }
+        let this = self.this.upgrade().ok_or(VfsError::NotFound)?;
+        let parent = this.lookup(parent_path)?;
+        match parent.as_any().downcast_ref::<DirNode>() {
+            Some(dir) => Ok((dir.this.upgrade().ok_or(VfsError::NotFound)?, name)),
+            None if parent.get_attr()?.is_dir() => Err(VfsError::CrossesDevices),
+            None => Err(VfsError::NotADirectory),
+        }
+    }
 }
 
 impl VfsNodeOps for DirNode {
     fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
-        Ok(VfsNodeAttr::new_dir(4096, 0))
+        let mut attr = VfsNodeAttr::new_dir(4096, 0);
+        let subdirs = self
+            .children
+            .read()
+            .values()
+            .filter(|node| node.as_any().is::<DirNode>())
+            .count();
+        attr.set_nlink(2 + subdirs as u64); // `.`, the entry in the parent and `..` of subdirs
+        self.meta.fill_attr(&mut attr);
+        Ok(attr)
+    }
+
+    fn set_attr(&self, attr: &VfsSetAttr) -> VfsResult {
+        self.meta.set_attr(attr);
+        Ok(())
     }
 
     fn parent(&self) -> Option<VfsNodeRef> {
@@ -110,6 +302,7 @@ impl VfsNodeOps for DirNode {
     fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
         let children = self.children.read();
         let mut children = children.iter().skip(start_idx.max(2) - 2);
+        self.meta.touch_access();
         for (i, ent) in dirents.iter_mut().enumerate() {
             match i + start_idx {
                 0 => *ent = VfsDirEntry::new(".", VfsNodeType::Dir),
@@ -176,22 +369,128 @@ impl VfsNodeOps for DirNode {
 
     fn rename(&self, old_path: &str, new_path: &str) -> VfsResult {
         log::debug!("rename at ramfs: {} -> {}", old_path, new_path);
-        let new_name = if let Some((_, name)) = new_path.rsplit_once('/') {
-            name
+        let (old_dir, old_name) = self.lookup_parent(old_path)?;
+        let (new_dir, new_name) = self.lookup_parent(new_path)?;
+        old_dir.move_node(old_name, &new_dir, new_name)
+    }
+
+    fn symlink(&self, path: &str, target: &str) -> VfsResult {
+        log::debug!("symlink at ramfs: {} -> {}", path, target);
+        let (name, rest) = split_path(path);
+        if let Some(rest) = rest {
+            match name {
+                "" | "." => self.symlink(rest, target),
+                ".." => self
+                    .parent()
+                    .ok_or(VfsError::NotFound)?
+                    .symlink(rest, target),
+                _ => {
+                    let subdir = self
+                        .children
+                        .read()
+                        .get(name)
+                        .ok_or(VfsError::NotFound)?
+                        .clone();
+                    subdir.symlink(rest, target)
+                }
+            }
+        } else if name.is_empty() || name == "." || name == ".." {
+            Err(VfsError::AlreadyExists)
         } else {
-            new_path
-        };
-        let old_name = if let Some((_, name)) = old_path.rsplit_once('/') {
-            name
+            self.create_symlink(name, target)
+        }
+    }
+
+    fn link(&self, path: &str, node: &VfsNodeRef) -> VfsResult {
+        log::debug!("link at ramfs: {}", path);
+        let (name, rest) = split_path(path);
+        if let Some(rest) = rest {
+            match name {
+                "" | "." => self.link(rest, node),
+                ".." => self.parent().ok_or(VfsError::NotFound)?.link(rest, node),
+                _ => {
+                    let subdir = self
+                        .children
+                        .read()
+                        .get(name)
+                        .ok_or(VfsError::NotFound)?
+                        .clone();
+                    subdir.link(rest, node)
+                }
+            }
+        } else if name.is_empty() || name == "." || name == ".." {
+            Err(VfsError::AlreadyExists)
         } else {
-            old_path
-        };
-        self.rename_node(old_name, new_name)
+            self.link_node(name, node)
+        }
+    }
+
+    fn watch(&self, watcher: Weak<dyn VfsWatcher>) -> VfsResult {
+        self.meta.watch(watcher);
+        Ok(())
     }
 
     axfs_vfs::impl_vfs_dir_default! {}
 }
 
+/// Checks whether `node` can replace the existing `target` in a rename.
+fn check_replace(node: &VfsNodeRef, target: &VfsNodeRef) -> VfsResult {
+    let target_dir = target.as_any().downcast_ref::<DirNode>();
+    if node.as_any().is::<DirNode>() {
+        match target_dir {
+            Some(dir) if !dir.children.read().is_empty() => Err(VfsError::DirectoryNotEmpty),
+            Some(_) => Ok(()),
+            None => Err(VfsError::NotADirectory),
+        }
+    } else if target_dir.is_some() {
+        Err(VfsError::IsADirectory)
+    } else {
+        Ok(())
+    }
+}
+
+/// Returns the hard link counter of a file or symbolic link node in the RAM
+/// filesystem.
+fn link_count_of(node: &VfsNodeRef) -> Option<&AtomicU64> {
+    let node = node.as_any();
+    if let Some(file) = node.downcast_ref::<FileNode>() {
+        Some(&file.nlink)
+    } else if let Some(symlink) = node.downcast_ref::<SymlinkNode>() {
+        Some(&symlink.nlink)
+    } else {
+        None
+    }
+}
+
+/// Returns the metadata of `node` in the RAM filesystem.
+fn meta_of(node: &VfsNodeRef) -> Option<&NodeMeta> {
+    let node = node.as_any();
+    if let Some(file) = node.downcast_ref::<FileNode>() {
+        Some(&file.meta)
+    } else if let Some(symlink) = node.downcast_ref::<SymlinkNode>() {
+        Some(&symlink.meta)
+    } else {
+        node.downcast_ref::<DirNode>().map(|dir| &dir.meta)
+    }
+}
+
+/// Updates the change time of `node` in the RAM filesystem, after its number
+/// of links or its location is changed.
+fn touch_change_of(node: &VfsNodeRef) {
+    if let Some(meta) = meta_of(node) {
+        meta.touch_change();
+    }
+}
+
+/// Returns [`VfsEventMask::IS_DIR`] if `node` is a directory.
+fn dir_flag(node: &VfsNodeRef) -> VfsEventMask {
+    if node.as_any().is::<DirNode>() {
+        VfsEventMask::IS_DIR
+    } else {
+        VfsEventMask::empty()
+    }
+}
+
 fn split_path(path: &str) -> (&str, Option<&str>) {
     let trimmed_path = path.trim_start_matches('/');
     trimmed_path.find('/').map_or((trimmed_path, None), |n| {
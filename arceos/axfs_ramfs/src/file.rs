@@ -1,55 +1,100 @@
-use alloc::vec::Vec;
-use axfs_vfs::{impl_vfs_non_dir_default, VfsNodeAttr, VfsNodeOps, VfsResult};
+use alloc::sync::{Arc, Weak};
+use alloc::{string::String, vec::Vec};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axfs_vfs::{impl_vfs_non_dir_default, VfsNodeAttr, VfsNodeOps, VfsNodePerm};
+use axfs_vfs::{VfsEventMask, VfsResult, VfsSetAttr, VfsWatcher};
 use spin::RwLock;
 
+use crate::dir::DirNode;
+use crate::meta::{FsContext, NodeMeta};
+use crate::page::{FileContent, PAGE_SIZE};
+
 /// The file node in the RAM filesystem.
 ///
-/// It implements [`axfs_vfs::VfsNodeOps`].
+/// It implements [`axfs_vfs::VfsNodeOps`]. The contents are stored in pages
+/// allocated on demand, so that sparse files only take the memory of the
+/// written ranges.
 pub struct FileNode {
-    content: RwLock<Vec<u8>>,
+    content: RwLock<FileContent>,
+    /// The directories and names of the hard links to the file, which are
+    /// notified of its modifications.
+    links: RwLock<Vec<(Weak<DirNode>, String)>>,
+    pub(crate) nlink: AtomicU64,
+    pub(crate) meta: NodeMeta,
 }
 
 impl FileNode {
-    pub(super) const fn new() -> Self {
-        Self {
-            content: RwLock::new(Vec::new()),
+    pub(super) fn new(ctx: &Arc<FsContext>) -> VfsResult<Self> {
+        Ok(Self {
+            content: RwLock::new(FileContent::new(ctx.clone())),
+            links: RwLock::new(Vec::new()),
+            nlink: AtomicU64::new(1),
+            meta: NodeMeta::new(ctx, VfsNodePerm::default_file())?,
+        })
+    }
+
+    pub(crate) fn add_link(&self, dir: &Weak<DirNode>, name: &str) {
+        self.links.write().push((dir.clone(), name.into()));
+    }
+
+    pub(crate) fn remove_link(&self, dir: &Weak<DirNode>, name: &str) {
+        let mut links = self.links.write();
+        if let Some(idx) = links.iter().position(|(d, n)| d.ptr_eq(dir) && n == name) {
+            links.swap_remove(idx);
+        }
+    }
+
+    /// Reports a modification to the watchers of the file and of the
+    /// directories containing it.
+    fn notify_modify(&self) {
+        self.meta.notify(VfsEventMask::MODIFY, "", 0);
+        for (dir, name) in self.links.read().iter() {
+            if let Some(dir) = dir.upgrade() {
+                dir.meta.notify(VfsEventMask::MODIFY, name, 0);
+            }
         }
     }
 }
 
 impl VfsNodeOps for FileNode {
     fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
-        Ok(VfsNodeAttr::new_file(self.content.read().len() as _, 0))
+        let content = self.content.read();
+        let blocks = (content.num_pages() * PAGE_SIZE / 512) as u64;
+        let mut attr = VfsNodeAttr::new_file(content.size(), blocks);
+        attr.set_nlink(self.nlink.load(Ordering::Relaxed));
+        self.meta.fill_attr(&mut attr);
+        Ok(attr)
+    }
+
+    fn set_attr(&self, attr: &VfsSetAttr) -> VfsResult {
+        self.meta.set_attr(attr);
+        Ok(())
     }
 
     fn truncate(&self, size: u64) -> VfsResult {
-        let mut content = self.content.write();
-        if size < content.len() as u64 {
-            content.truncate(size as _);
-        } else {
-            content.resize(size as _, 0);
-        }
+        self.content.write().truncate(size);
+        self.meta.touch_modify();
+        self.notify_modify();
         Ok(())
     }
 
     fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
-        let content = self.content.read();
-        let start = content.len().min(offset as usize);
-        let end = content.len().min(offset as usize + buf.len());
-        let src = &content[start..end];
-        buf[..src.len()].copy_from_slice(src);
-        Ok(src.len())
+        let read_len = self.content.read().read_at(offset, buf);
+        self.meta.touch_access();
+        Ok(read_len)
     }
 
     fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
-        let offset = offset as usize;
-        let mut content = self.content.write();
-        if offset + buf.len() > content.len() {
-            content.resize(offset + buf.len(), 0);
-        }
-        let dst = &mut content[offset..offset + buf.len()];
-        dst.copy_from_slice(&buf[..dst.len()]);
-        Ok(buf.len())
+        let write_len = self.content.write().write_at(offset, buf)?;
+        self.meta.touch_modify();
+        self.notify_modify();
+        Ok(write_len)
+    }
+
+    fn watch(&self, watcher: Weak<dyn VfsWatcher>) -> VfsResult {
+        self.meta.watch(watcher);
+        Ok(())
     }
 
     impl_vfs_non_dir_default! {}
@@ -8,29 +8,101 @@ extern crate alloc;
 
 mod dir;
 mod file;
+mod meta;
+mod page;
+mod symlink;
 
 #[cfg(test)]
 mod tests;
 
 pub use self::dir::DirNode;
 pub use self::file::FileNode;
+pub use self::page::{GlobalPageAlloc, PageAlloc, PAGE_SIZE};
+pub use self::symlink::SymlinkNode;
 
 use alloc::sync::Arc;
-use axfs_vfs::{VfsNodeRef, VfsOps, VfsResult};
+use core::time::Duration;
+
+use axfs_vfs::{FileSystemInfo, VfsNodeRef, VfsOps, VfsResult};
 use spin::once::Once;
 
+use self::meta::FsContext;
+
+/// Options to create a [`RamFileSystem`] by [`RamFileSystem::with_options`].
+#[derive(Clone, Copy)]
+pub struct RamFsOptions {
+    /// Returns the current time, which the timestamps of the nodes are set
+    /// to. The default one always returns zero.
+    pub clock: fn() -> Duration,
+    /// Allocator of the pages which the file contents are stored in. The
+    /// default one is [`GlobalPageAlloc`].
+    pub page_alloc: &'static dyn PageAlloc,
+    /// Maximum total size of the file contents in bytes, which is rounded up
+    /// to pages, or 0 if unlimited (the default).
+    pub max_bytes: u64,
+    /// Maximum number of nodes including the root directory, or 0 if
+    /// unlimited (the default).
+    pub max_inodes: u64,
+}
+
+impl Default for RamFsOptions {
+    fn default() -> Self {
+        Self {
+            clock: || Duration::ZERO,
+            page_alloc: &GlobalPageAlloc,
+            max_bytes: 0,
+            max_inodes: 0,
+        }
+    }
+}
+
 /// A RAM filesystem that implements [`axfs_vfs::VfsOps`].
 pub struct RamFileSystem {
     parent: Once<VfsNodeRef>,
     root: Arc<DirNode>,
+    ctx: Arc<FsContext>,
 }
 
 impl RamFileSystem {
-    /// Create a new instance.
+    /// Create a new instance with the default options.
+    ///
+    /// All timestamps of the nodes are zero. Use [`RamFileSystem::with_clock`]
+    /// to record the real time.
     pub fn new() -> Self {
+        Self::with_options(RamFsOptions::default())
+    }
+
+    /// Create a new instance, which reads the current time from `clock` to
+    /// set the timestamps of the nodes.
+    pub fn with_clock(clock: fn() -> Duration) -> Self {
+        Self::with_options(RamFsOptions {
+            clock,
+            ..Default::default()
+        })
+    }
+
+    /// Create a new instance which holds at most `max_bytes` bytes of file
+    /// contents and `max_inodes` nodes. Operations exceeding the limits fail
+    /// with [`StorageFull`](axfs_vfs::VfsError::StorageFull).
+    ///
+    /// A limit of 0 means unlimited.
+    pub fn with_limits(max_bytes: u64, max_inodes: u64) -> Self {
+        Self::with_options(RamFsOptions {
+            max_bytes,
+            max_inodes,
+            ..Default::default()
+        })
+    }
+
+    /// Create a new instance with the given options.
+    pub fn with_options(opts: RamFsOptions) -> Self {
+        let ctx = FsContext::new(opts);
+        // the root is the first node, which is always within the limit
+        let root = DirNode::new(None, &ctx).unwrap();
         Self {
             parent: Once::new(),
-            root: DirNode::new(None),
+            root,
+            ctx,
         }
     }
 
@@ -50,9 +122,17 @@ impl VfsOps for RamFileSystem {
         Ok(())
     }
 
+    fn statfs(&self) -> VfsResult<FileSystemInfo> {
+        Ok(self.ctx.statfs())
+    }
+
     fn root_dir(&self) -> VfsNodeRef {
         self.root.clone()
     }
+
+    fn name(&self) -> &str {
+        "ramfs"
+    }
 }
 
 impl Default for RamFileSystem {
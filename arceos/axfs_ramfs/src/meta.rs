@@ -0,0 +1,238 @@
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+use core::ptr::NonNull;
+use core::sync::atomic::{AtomicU32, AtomicU64, Ordering};
+use core::time::Duration;
+
+use axfs_vfs::{FileSystemInfo, VfsError, VfsNodeAttr, VfsNodePerm, VfsResult, VfsSetAttr};
+use axfs_vfs::{VfsEvent, VfsEventMask, VfsWatcher};
+use spin::{Mutex, RwLock};
+
+use crate::{PageAlloc, RamFsOptions, PAGE_SIZE};
+
+/// States shared by all nodes in a filesystem.
+pub(crate) struct FsContext {
+    clock: fn() -> Duration,
+    page_alloc: &'static dyn PageAlloc,
+    next_ino: AtomicU64,
+    /// Maximum number of pages, or 0 if unlimited.
+    max_pages: u64,
+    /// Maximum number of inodes, or 0 if unlimited.
+    max_inodes: u64,
+    used_pages: AtomicU64,
+    used_inodes: AtomicU64,
+    next_cookie: AtomicU32,
+}
+
+/// Increases `counter` by one if it is less than `max` (or `max` is 0).
+fn try_inc(counter: &AtomicU64, max: u64) -> bool {
+    counter
+        .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |n| {
+            (max == 0 || n < max).then_some(n + 1)
+        })
+        .is_ok()
+}
+
+/// Returns the total and the free numbers of blocks or inodes, given the
+/// maximum (or 0 if unlimited) and the used ones.
+///
+/// An unlimited filesystem reports a capacity as large as the total size in
+/// bytes can hold, so that it never looks full.
+fn capacity(max: u64, used: u64) -> (u64, u64) {
+    let total = if max == 0 {
+        u64::MAX / PAGE_SIZE as u64
+    } else {
+        max
+    };
+    (total, total.saturating_sub(used))
+}
+
+impl FsContext {
+    pub fn new(opts: RamFsOptions) -> Arc<Self> {
+        Arc::new(Self {
+            clock: opts.clock,
+            page_alloc: opts.page_alloc,
+            next_ino: AtomicU64::new(1),
+            max_pages: opts.max_bytes.div_ceil(PAGE_SIZE as u64),
+            max_inodes: opts.max_inodes,
+            used_pages: AtomicU64::new(0),
+            used_inodes: AtomicU64::new(0),
+            next_cookie: AtomicU32::new(1),
+        })
+    }
+
+    pub fn now(&self) -> Duration {
+        (self.clock)()
+    }
+
+    /// Allocates a page for file contents, within the size limit.
+    pub fn alloc_page(&self) -> VfsResult<NonNull<u8>> {
+        if !try_inc(&self.used_pages, self.max_pages) {
+            return Err(VfsError::StorageFull);
+        }
+        self.page_alloc.alloc_page().ok_or_else(|| {
+            self.used_pages.fetch_sub(1, Ordering::Relaxed);
+            VfsError::NoMemory
+        })
+    }
+
+    /// Frees a page allocated by [`FsContext::alloc_page`].
+    ///
+    /// # Safety
+    ///
+    /// `page` must not be used after it is freed.
+    pub unsafe fn dealloc_page(&self, page: NonNull<u8>) {
+        self.page_alloc.dealloc_page(page);
+        self.used_pages.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    /// Allocates a new inode number, within the inode limit.
+    fn alloc_inode(&self) -> VfsResult<u64> {
+        if !try_inc(&self.used_inodes, self.max_inodes) {
+            return Err(VfsError::StorageFull);
+        }
+        Ok(self.next_ino.fetch_add(1, Ordering::Relaxed))
+    }
+
+    /// Allocates a cookie to pair the events of a rename.
+    pub fn new_cookie(&self) -> u32 {
+        self.next_cookie.fetch_add(1, Ordering::Relaxed)
+    }
+
+    pub fn statfs(&self) -> FileSystemInfo {
+        let used_pages = self.used_pages.load(Ordering::Relaxed);
+        let used_inodes = self.used_inodes.load(Ordering::Relaxed);
+        let (blocks, blocks_free) = capacity(self.max_pages, used_pages);
+        let (files, files_free) = capacity(self.max_inodes, used_inodes);
+        FileSystemInfo {
+            block_size: PAGE_SIZE as u64,
+            blocks,
+            blocks_free,
+            files,
+            files_free,
+            name_max: 255,
+        }
+    }
+}
+
+/// The inode number, permission, owner and timestamps of a node, and the
+/// watchers of its changes.
+pub(crate) struct NodeMeta {
+    ctx: Arc<FsContext>,
+    ino: u64,
+    inner: RwLock<MetaInner>,
+    watchers: Mutex<Vec<Weak<dyn VfsWatcher>>>,
+}
+
+struct MetaInner {
+    perm: VfsNodePerm,
+    uid: u32,
+    gid: u32,
+    atime: Duration,
+    mtime: Duration,
+    ctime: Duration,
+}
+
+impl NodeMeta {
+    /// Creates the metadata of a new node, with a new inode number.
+    pub fn new(ctx: &Arc<FsContext>, perm: VfsNodePerm) -> VfsResult<Self> {
+        let now = ctx.now();
+        Ok(Self {
+            ctx: ctx.clone(),
+            ino: ctx.alloc_inode()?,
+            inner: RwLock::new(MetaInner {
+                perm,
+                uid: 0,
+                gid: 0,
+                atime: now,
+                mtime: now,
+                ctime: now,
+            }),
+            watchers: Mutex::new(Vec::new()),
+        })
+    }
+
+    pub fn ctx(&self) -> &Arc<FsContext> {
+        &self.ctx
+    }
+
+    /// Fills the inode number, permission, owner and timestamps in `attr`.
+    pub fn fill_attr(&self, attr: &mut VfsNodeAttr) {
+        let inner = self.inner.read();
+        attr.set_ino(self.ino);
+        attr.set_perm(inner.perm);
+        attr.set_owner(inner.uid, inner.gid);
+        attr.set_times(inner.atime, inner.mtime, inner.ctime);
+    }
+
+    /// Updates the access time, after the contents are read.
+    pub fn touch_access(&self) {
+        self.inner.write().atime = self.ctx.now();
+    }
+
+    /// Updates the modification and change times, after the contents are
+    /// modified.
+    pub fn touch_modify(&self) {
+        let now = self.ctx.now();
+        let mut inner = self.inner.write();
+        inner.mtime = now;
+        inner.ctime = now;
+    }
+
+    /// Updates the change time, after the attributes (e.g., the number of
+    /// links) are changed.
+    pub fn touch_change(&self) {
+        self.inner.write().ctime = self.ctx.now();
+    }
+
+    pub fn set_attr(&self, attr: &VfsSetAttr) {
+        let now = self.ctx.now();
+        let mut inner = self.inner.write();
+        if let Some(perm) = attr.perm {
+            inner.perm = perm;
+        }
+        if let Some(uid) = attr.uid {
+            inner.uid = uid;
+        }
+        if let Some(gid) = attr.gid {
+            inner.gid = gid;
+        }
+        if let Some(atime) = attr.atime {
+            inner.atime = atime;
+        }
+        if let Some(mtime) = attr.mtime {
+            inner.mtime = mtime;
+        }
+        inner.ctime = now;
+    }
+
+    /// Adds a watcher of the node, and forgets the dropped ones.
+    pub fn watch(&self, watcher: Weak<dyn VfsWatcher>) {
+        let mut watchers = self.watchers.lock();
+        watchers.retain(|w| w.strong_count() > 0);
+        watchers.push(watcher);
+    }
+
+    /// Reports a change of the node, or of its entry `name` if it is a
+    /// directory, to the watchers.
+    pub fn notify(&self, mask: VfsEventMask, name: &str, cookie: u32) {
+        let mut watchers = self.watchers.lock();
+        if watchers.is_empty() {
+            return;
+        }
+        let event = VfsEvent { mask, name, cookie };
+        watchers.retain(|w| match w.upgrade() {
+            Some(watcher) => {
+                watcher.notify(&event);
+                true
+            }
+            None => false,
+        });
+    }
+}
+
+impl Drop for NodeMeta {
+    fn drop(&mut self) {
+        self.ctx.used_inodes.fetch_sub(1, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,166 @@
+use alloc::alloc::{alloc_zeroed, dealloc, Layout};
+use alloc::{collections::BTreeMap, sync::Arc};
+use core::ptr::NonNull;
+
+use axfs_vfs::{VfsError, VfsResult};
+
+use crate::meta::FsContext;
+
+/// Size of the pages which the file contents are stored in.
+pub const PAGE_SIZE: usize = 0x1000;
+
+/// Allocator of the pages which the file contents are stored in.
+pub trait PageAlloc: Send + Sync {
+    /// Allocates a zero-filled page of [`PAGE_SIZE`] bytes, which is aligned
+    /// to [`PAGE_SIZE`]. Returns `None` if there is no memory.
+    fn alloc_page(&self) -> Option<NonNull<u8>>;
+
+    /// Frees a page allocated by [`PageAlloc::alloc_page`].
+    ///
+    /// # Safety
+    ///
+    /// `page` must be allocated by this allocator, and must not be used after
+    /// it is freed.
+    unsafe fn dealloc_page(&self, page: NonNull<u8>);
+}
+
+/// A [`PageAlloc`] which allocates pages from the global allocator.
+pub struct GlobalPageAlloc;
+
+const PAGE_LAYOUT: Layout = match Layout::from_size_align(PAGE_SIZE, PAGE_SIZE) {
+    Ok(layout) => layout,
+    Err(_) => panic!("invalid page layout"),
+};
+
+impl PageAlloc for GlobalPageAlloc {
+    fn alloc_page(&self) -> Option<NonNull<u8>> {
+        NonNull::new(unsafe { alloc_zeroed(PAGE_LAYOUT) })
+    }
+
+    unsafe fn dealloc_page(&self, page: NonNull<u8>) {
+        dealloc(page.as_ptr(), PAGE_LAYOUT)
+    }
+}
+
+/// The contents of a file, stored in pages which are allocated on the first
+/// write to them.
+///
+/// The pages which are never written are holes, and read as zeros. The bytes
+/// beyond the file size in the last page are always zeros.
+pub(crate) struct FileContent {
+    ctx: Arc<FsContext>,
+    size: u64,
+    pages: BTreeMap<u64, NonNull<u8>>,
+}
+
+// SAFETY: the pages are owned by `FileContent` exclusively.
+unsafe impl Send for FileContent {}
+unsafe impl Sync for FileContent {}
+
+impl FileContent {
+    pub const fn new(ctx: Arc<FsContext>) -> Self {
+        Self {
+            ctx,
+            size: 0,
+            pages: BTreeMap::new(),
+        }
+    }
+
+    pub const fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the number of allocated pages.
+    pub fn num_pages(&self) -> usize {
+        self.pages.len()
+    }
+
+    fn page(&self, idx: u64) -> Option<&[u8]> {
+        let page = self.pages.get(&idx)?;
+        Some(unsafe { core::slice::from_raw_parts(page.as_ptr(), PAGE_SIZE) })
+    }
+
+    fn page_mut_or_alloc(&mut self, idx: u64) -> VfsResult<&mut [u8]> {
+        let page = match self.pages.get(&idx) {
+            Some(page) => *page,
+            None => {
+                let page = self.ctx.alloc_page()?;
+                self.pages.insert(idx, page);
+                page
+            }
+        };
+        Ok(unsafe { core::slice::from_raw_parts_mut(page.as_ptr(), PAGE_SIZE) })
+    }
+
+    pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> usize {
+        if offset >= self.size {
+            return 0;
+        }
+        let len = buf.len().min((self.size - offset) as usize);
+        let mut read = 0;
+        while read < len {
+            let pos = offset + read as u64;
+            let page_off = (pos % PAGE_SIZE as u64) as usize;
+            let n = (PAGE_SIZE - page_off).min(len - read);
+            let dst = &mut buf[read..read + n];
+            match self.page(pos / PAGE_SIZE as u64) {
+                Some(page) => dst.copy_from_slice(&page[page_off..page_off + n]),
+                None => dst.fill(0), // a hole
+            }
+            read += n;
+        }
+        len
+    }
+
+    /// Writes `buf` at `offset`, and allocates the pages on demand.
+    ///
+    /// If it runs out of memory or exceeds the size limit of the filesystem in
+    /// the middle, returns the number of bytes written so far.
+    pub fn write_at(&mut self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        offset
+            .checked_add(buf.len() as u64)
+            .ok_or(VfsError::InvalidInput)?;
+        let mut written = 0;
+        while written < buf.len() {
+            let pos = offset + written as u64;
+            let page_off = (pos % PAGE_SIZE as u64) as usize;
+            let n = (PAGE_SIZE - page_off).min(buf.len() - written);
+            let page = match self.page_mut_or_alloc(pos / PAGE_SIZE as u64) {
+                Ok(page) => page,
+                Err(_) if written > 0 => break,
+                Err(e) => return Err(e),
+            };
+            page[page_off..page_off + n].copy_from_slice(&buf[written..written + n]);
+            written += n;
+        }
+        self.size = self.size.max(offset + written as u64);
+        Ok(written)
+    }
+
+    /// Changes the file size to `size`. The pages beyond the new size are
+    /// freed, while an extended range becomes a hole.
+    pub fn truncate(&mut self, size: u64) {
+        if size < self.size {
+            let first_freed = size.div_ceil(PAGE_SIZE as u64);
+            for (_, page) in self.pages.split_off(&first_freed) {
+                unsafe { self.ctx.dealloc_page(page) };
+            }
+            let page_off = (size % PAGE_SIZE as u64) as usize;
+            if page_off != 0 {
+                if let Some(page) = self.pages.get(&(size / PAGE_SIZE as u64)) {
+                    let page = unsafe { core::slice::from_raw_parts_mut(page.as_ptr(), PAGE_SIZE) };
+                    page[page_off..].fill(0);
+                }
+            }
+        }
+        self.size = size;
+    }
+}
+
+impl Drop for FileContent {
+    fn drop(&mut self) {
+        for page in self.pages.values() {
+            unsafe { self.ctx.dealloc_page(*page) };
+        }
+    }
+}
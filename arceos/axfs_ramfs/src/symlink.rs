@@ -0,0 +1,56 @@
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axfs_vfs::{impl_vfs_non_dir_default, VfsError, VfsNodeAttr, VfsNodeOps, VfsNodePerm};
+use axfs_vfs::{VfsResult, VfsSetAttr, VfsWatcher};
+
+use crate::meta::{FsContext, NodeMeta};
+
+/// The symbolic link node in the RAM filesystem.
+///
+/// It implements [`axfs_vfs::VfsNodeOps`].
+pub struct SymlinkNode {
+    target: String,
+    pub(crate) nlink: AtomicU64,
+    pub(crate) meta: NodeMeta,
+}
+
+impl SymlinkNode {
+    pub(super) fn new(ctx: &Arc<FsContext>, target: &str) -> VfsResult<Self> {
+        Ok(Self {
+            target: target.into(),
+            nlink: AtomicU64::new(1),
+            meta: NodeMeta::new(ctx, VfsNodePerm::from_bits_truncate(0o777))?,
+        })
+    }
+}
+
+impl VfsNodeOps for SymlinkNode {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let mut attr = VfsNodeAttr::new_symlink(self.target.len() as _);
+        attr.set_nlink(self.nlink.load(Ordering::Relaxed));
+        self.meta.fill_attr(&mut attr);
+        Ok(attr)
+    }
+
+    fn set_attr(&self, attr: &VfsSetAttr) -> VfsResult {
+        if attr.perm.is_some() {
+            return Err(VfsError::Unsupported); // the permission is always `0o777`
+        }
+        self.meta.set_attr(attr);
+        Ok(())
+    }
+
+    fn readlink(&self) -> VfsResult<String> {
+        self.meta.touch_access();
+        Ok(self.target.clone())
+    }
+
+    fn watch(&self, watcher: Weak<dyn VfsWatcher>) -> VfsResult {
+        self.meta.watch(watcher);
+        Ok(())
+    }
+
+    impl_vfs_non_dir_default! {}
+}
@@ -1,6 +1,10 @@
-use std::sync::Arc;
+use std::ptr::NonNull;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
-use axfs_vfs::{VfsError, VfsNodeType, VfsResult};
+use axfs_vfs::{VfsError, VfsNodePerm, VfsNodeType, VfsResult, VfsSetAttr};
+use axfs_vfs::{VfsEvent, VfsEventMask, VfsWatcher};
 
 use crate::*;
 
@@ -134,3 +138,430 @@ fn test_ramfs() {
     assert_eq!(root.remove("./foo"), Ok(()));
     assert!(ramfs.root_dir_node().get_entries().is_empty());
 }
+
+#[test]
+fn test_symlink_hardlink() {
+    // .
+    // ├── foo
+    // │   ├── f1
+    // │   └── l2 -> ../l1
+    // ├── f2 (hard link to foo/f1)
+    // └── l1 -> foo/f1
+
+    let ramfs = RamFileSystem::new();
+    let root = ramfs.root_dir();
+    root.create("foo", VfsNodeType::Dir).unwrap();
+    root.create("foo/f1", VfsNodeType::File).unwrap();
+    assert_eq!(
+        root.create("l1", VfsNodeType::SymLink).err(),
+        Some(VfsError::Unsupported)
+    );
+
+    // symbolic links
+    assert_eq!(root.symlink("l1", "foo/f1"), Ok(()));
+    assert_eq!(root.symlink("./foo//l2", "../l1"), Ok(()));
+    assert_eq!(
+        root.symlink("l1", "foo").err(),
+        Some(VfsError::AlreadyExists)
+    );
+    assert_eq!(
+        root.symlink("bar/l3", "foo").err(),
+        Some(VfsError::NotFound)
+    );
+    let l1 = root.clone().lookup("l1").unwrap();
+    assert_eq!(l1.get_attr().unwrap().file_type(), VfsNodeType::SymLink);
+    assert_eq!(l1.get_attr().unwrap().size(), 6);
+    assert_eq!(l1.readlink().unwrap(), "foo/f1");
+    assert_eq!(
+        root.clone().lookup("foo/l2").unwrap().readlink().unwrap(),
+        "../l1"
+    );
+    assert_eq!(
+        root.clone().lookup("foo/f1").unwrap().readlink().err(),
+        Some(VfsError::InvalidInput)
+    );
+    assert_eq!(
+        root.clone().lookup("l1/f1").err(),
+        Some(VfsError::NotADirectory)
+    );
+
+    // hard links
+    let f1 = root.clone().lookup("foo/f1").unwrap();
+    assert_eq!(f1.get_attr().unwrap().nlink(), 1);
+    assert_eq!(root.link("f2", &f1), Ok(()));
+    assert_eq!(root.link("foo/../f3", &f1), Ok(()));
+    assert_eq!(f1.get_attr().unwrap().nlink(), 3);
+    assert_eq!(root.link("f2", &f1).err(), Some(VfsError::AlreadyExists));
+    let foo = root.clone().lookup("foo").unwrap();
+    assert_eq!(
+        root.link("foo2", &foo).err(),
+        Some(VfsError::PermissionDenied)
+    );
+
+    let mut buf = [0; 5];
+    assert_eq!(f1.write_at(0, b"hello"), Ok(5));
+    assert_eq!(root.clone().lookup("f3").unwrap().read_at(0, &mut buf), Ok(5));
+    assert_eq!(&buf, b"hello");
+
+    assert_eq!(root.remove("foo/f1"), Ok(()));
+    assert_eq!(root.remove("f3"), Ok(()));
+    assert_eq!(f1.get_attr().unwrap().nlink(), 1);
+    assert!(Arc::ptr_eq(&root.clone().lookup("f2").unwrap(), &f1));
+
+    // link count of directories
+    assert_eq!(root.get_attr().unwrap().nlink(), 3);
+    assert_eq!(foo.get_attr().unwrap().nlink(), 2);
+}
+
+#[test]
+fn test_rename() {
+    // .
+    // ├── a
+    // │   ├── sub
+    // │   │   └── f2
+    // │   └── f1
+    // ├── b
+    // │   └── f3
+    // └── c
+
+    let ramfs = RamFileSystem::new();
+    let root = ramfs.root_dir();
+    root.create("a", VfsNodeType::Dir).unwrap();
+    root.create("a/sub", VfsNodeType::Dir).unwrap();
+    root.create("a/sub/f2", VfsNodeType::File).unwrap();
+    root.create("a/f1", VfsNodeType::File).unwrap();
+    root.create("b", VfsNodeType::Dir).unwrap();
+    root.create("b/f3", VfsNodeType::File).unwrap();
+    root.create("c", VfsNodeType::Dir).unwrap();
+
+    // move a file across directories
+    let f1 = root.clone().lookup("a/f1").unwrap();
+    assert_eq!(root.rename("a/f1", "b/x"), Ok(()));
+    assert_eq!(root.clone().lookup("a/f1").err(), Some(VfsError::NotFound));
+    assert_eq!(root.clone().lookup("a/x").err(), Some(VfsError::NotFound));
+    assert!(Arc::ptr_eq(&root.clone().lookup("b/x").unwrap(), &f1));
+
+    // replace an existing file
+    assert_eq!(root.rename("b/x", "./b//f3"), Ok(()));
+    assert!(Arc::ptr_eq(&root.clone().lookup("b/f3").unwrap(), &f1));
+    assert_eq!(root.clone().lookup("b/x").err(), Some(VfsError::NotFound));
+    assert_eq!(root.rename("b/f3", "b/f3"), Ok(()));
+
+    // move a directory, its parent is updated
+    assert_eq!(root.rename("a/sub", "b/sub2"), Ok(()));
+    let sub = root.clone().lookup("b/sub2").unwrap();
+    assert!(Arc::ptr_eq(
+        &sub.parent().unwrap(),
+        &root.clone().lookup("b").unwrap()
+    ));
+    assert!(root.clone().lookup("b/sub2/../f3").is_ok());
+    assert!(root.clone().lookup("b/sub2/f2").is_ok());
+
+    // error cases
+    assert_eq!(root.rename("b", "b/sub2/b").err(), Some(VfsError::InvalidInput));
+    assert_eq!(root.rename("b", "b/b").err(), Some(VfsError::InvalidInput));
+    assert_eq!(root.rename("b/f3", "c").err(), Some(VfsError::IsADirectory));
+    assert_eq!(root.rename("c", "b/f3").err(), Some(VfsError::NotADirectory));
+    assert_eq!(root.rename("c", "b").err(), Some(VfsError::DirectoryNotEmpty));
+    assert_eq!(root.rename("none", "c/x").err(), Some(VfsError::NotFound));
+    assert_eq!(root.rename("b/f3", "none/x").err(), Some(VfsError::NotFound));
+    assert_eq!(root.rename("b/f3", "b/f3/x").err(), Some(VfsError::NotADirectory));
+    assert_eq!(root.rename("b/..", "c/x").err(), Some(VfsError::InvalidInput));
+
+    // replace an empty directory
+    assert_eq!(root.rename("b/sub2", "c"), Ok(()));
+    assert!(Arc::ptr_eq(&root.clone().lookup("c").unwrap(), &sub));
+    assert!(Arc::ptr_eq(&sub.parent().unwrap(), &root));
+}
+
+#[test]
+fn test_attrs() {
+    static NOW: AtomicU64 = AtomicU64::new(100);
+    fn now() -> Duration {
+        Duration::from_secs(NOW.load(Ordering::Relaxed))
+    }
+    let set_now = |secs| NOW.store(secs, Ordering::Relaxed);
+
+    let ramfs = RamFileSystem::with_clock(now);
+    let root = ramfs.root_dir();
+    assert_eq!(root.get_attr().unwrap().ino(), 1);
+    assert_eq!(root.get_attr().unwrap().perm().bits(), 0o755);
+
+    set_now(200);
+    root.create("f1", VfsNodeType::File).unwrap();
+    root.symlink("l1", "f1").unwrap();
+    let f1 = root.clone().lookup("f1").unwrap();
+    let attr = f1.get_attr().unwrap();
+    assert_eq!(attr.ino(), 2);
+    assert_eq!(
+        root.clone().lookup("l1").unwrap().get_attr().unwrap().ino(),
+        3
+    );
+    assert_eq!(attr.perm().bits(), 0o666);
+    assert_eq!((attr.uid(), attr.gid()), (0, 0));
+    assert_eq!(
+        (attr.atime(), attr.mtime(), attr.ctime()),
+        (now(), now(), now())
+    );
+    assert_eq!(root.get_attr().unwrap().mtime(), now());
+
+    // reads update the access time, writes update the modification time
+    set_now(300);
+    f1.write_at(0, b"hello").unwrap();
+    let attr = f1.get_attr().unwrap();
+    assert_eq!(attr.atime(), Duration::from_secs(200));
+    assert_eq!((attr.mtime(), attr.ctime()), (now(), now()));
+    set_now(400);
+    f1.read_at(0, &mut [0; 5]).unwrap();
+    let attr = f1.get_attr().unwrap();
+    assert_eq!(attr.atime(), now());
+    assert_eq!(attr.mtime(), Duration::from_secs(300));
+
+    // chmod, chown and utimens
+    set_now(500);
+    let perm = VfsNodePerm::from_bits_truncate(0o600);
+    f1.set_attr(&VfsSetAttr {
+        perm: Some(perm),
+        uid: Some(1000),
+        gid: Some(100),
+        mtime: Some(Duration::from_secs(42)),
+        ..Default::default()
+    })
+    .unwrap();
+    let attr = f1.get_attr().unwrap();
+    assert_eq!(attr.perm().bits(), 0o600);
+    assert_eq!((attr.uid(), attr.gid()), (1000, 100));
+    assert_eq!(attr.atime(), Duration::from_secs(400));
+    assert_eq!(attr.mtime(), Duration::from_secs(42));
+    assert_eq!(attr.ctime(), now());
+    let l1 = root.clone().lookup("l1").unwrap();
+    assert_eq!(
+        l1.set_attr(&VfsSetAttr {
+            perm: Some(perm),
+            ..Default::default()
+        })
+        .err(),
+        Some(VfsError::Unsupported)
+    );
+
+    // links and renames change the node and the directories
+    set_now(600);
+    root.create("d", VfsNodeType::Dir).unwrap();
+    set_now(700);
+    root.link("d/f2", &f1).unwrap();
+    assert_eq!(f1.get_attr().unwrap().ctime(), now());
+    assert_eq!(f1.get_attr().unwrap().mtime(), Duration::from_secs(42));
+    let d = root.clone().lookup("d").unwrap();
+    assert_eq!(d.get_attr().unwrap().mtime(), now());
+    set_now(800);
+    root.rename("d/f2", "f3").unwrap();
+    assert_eq!(d.get_attr().unwrap().mtime(), now());
+    assert_eq!(root.get_attr().unwrap().mtime(), now());
+    assert_eq!(f1.get_attr().unwrap().ino(), 2);
+    assert_eq!(
+        root.clone().lookup("f3").unwrap().get_attr().unwrap().ino(),
+        2
+    );
+    assert_eq!(d.get_attr().unwrap().ino(), 4);
+}
+
+/// Allocates at most 4 pages at the same time.
+struct LimitedPageAlloc(AtomicUsize);
+
+impl PageAlloc for LimitedPageAlloc {
+    fn alloc_page(&self) -> Option<NonNull<u8>> {
+        if self.0.fetch_add(1, Ordering::Relaxed) >= 4 {
+            self.0.fetch_sub(1, Ordering::Relaxed);
+            return None;
+        }
+        GlobalPageAlloc.alloc_page()
+    }
+
+    unsafe fn dealloc_page(&self, page: NonNull<u8>) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+        GlobalPageAlloc.dealloc_page(page)
+    }
+}
+
+#[test]
+fn test_sparse_file() {
+    static PAGES: LimitedPageAlloc = LimitedPageAlloc(AtomicUsize::new(0));
+    let ramfs = RamFileSystem::with_options(RamFsOptions {
+        page_alloc: &PAGES,
+        ..Default::default()
+    });
+    let root = ramfs.root_dir();
+    root.create("f1", VfsNodeType::File).unwrap();
+    let f1 = root.clone().lookup("f1").unwrap();
+
+    // a write far away only allocates the pages it covers
+    const FAR: u64 = 1 << 40;
+    let data = [0xaa; 10];
+    assert_eq!(f1.write_at(FAR - 5, &data), Ok(10));
+    let attr = f1.get_attr().unwrap();
+    assert_eq!(attr.size(), FAR + 5);
+    assert_eq!(attr.blocks(), (2 * PAGE_SIZE / 512) as u64);
+    assert_eq!(PAGES.0.load(Ordering::Relaxed), 2);
+
+    let mut buf = [0xff; 20];
+    assert_eq!(f1.read_at(FAR - 10, &mut buf), Ok(15));
+    assert_eq!(buf[..5], [0; 5]);
+    assert_eq!(buf[5..15], data);
+    assert_eq!(f1.read_at(12345, &mut buf), Ok(20));
+    assert_eq!(buf, [0; 20]);
+
+    // shrinking frees the pages, and extending again reads zeros
+    f1.truncate(FAR - 3).unwrap();
+    assert_eq!(PAGES.0.load(Ordering::Relaxed), 1);
+    f1.truncate(FAR + 100).unwrap();
+    let mut buf = [0xff; 6];
+    assert_eq!(f1.read_at(FAR - 5, &mut buf), Ok(6));
+    assert_eq!(buf, [0xaa, 0xaa, 0, 0, 0, 0]);
+
+    // runs out of pages in the middle of a write
+    let big = vec![1; 4 * PAGE_SIZE];
+    assert_eq!(f1.write_at(0, &big), Ok(3 * PAGE_SIZE));
+    assert_eq!(
+        f1.write_at(8 * PAGE_SIZE as u64, &big).err(),
+        Some(VfsError::NoMemory)
+    );
+    assert_eq!(f1.get_attr().unwrap().size(), FAR + 100);
+
+    // all pages are freed with the file
+    drop(f1);
+    root.remove("f1").unwrap();
+    assert_eq!(PAGES.0.load(Ordering::Relaxed), 0);
+}
+
+#[test]
+fn test_limits() {
+    let ramfs = RamFileSystem::with_limits(3 * PAGE_SIZE as u64 - 100, 4);
+    let info = ramfs.statfs().unwrap();
+    assert_eq!(info.block_size, PAGE_SIZE as u64);
+    assert_eq!((info.blocks, info.blocks_free), (3, 3));
+    assert_eq!((info.files, info.files_free), (4, 3)); // the root is used
+
+    let root = ramfs.root_dir();
+    root.create("f1", VfsNodeType::File).unwrap();
+    root.create("d", VfsNodeType::Dir).unwrap();
+    root.symlink("d/l", "../f1").unwrap();
+    assert_eq!(
+        root.create("f2", VfsNodeType::File).err(),
+        Some(VfsError::StorageFull)
+    );
+    assert_eq!(root.symlink("l2", "f1").err(), Some(VfsError::StorageFull));
+    let f1 = root.clone().lookup("f1").unwrap();
+    root.link("d/f1", &f1).unwrap(); // hard links take no inodes
+
+    let buf = vec![1; 2 * PAGE_SIZE];
+    assert_eq!(f1.write_at(PAGE_SIZE as u64, &buf), Ok(2 * PAGE_SIZE));
+    let big = vec![2; 4 * PAGE_SIZE];
+    assert_eq!(f1.write_at(0, &big), Ok(3 * PAGE_SIZE)); // stops at the limit
+    assert_eq!(
+        f1.write_at(10 * PAGE_SIZE as u64, &buf).err(),
+        Some(VfsError::StorageFull)
+    );
+    let info = ramfs.statfs().unwrap();
+    assert_eq!((info.blocks_free, info.files_free), (0, 0));
+
+    // removing nodes and shrinking files give the space back
+    f1.truncate(PAGE_SIZE as u64).unwrap();
+    root.remove("d/l").unwrap();
+    let info = ramfs.statfs().unwrap();
+    assert_eq!((info.blocks_free, info.files_free), (2, 1));
+    root.remove("f1").unwrap();
+    root.remove("d/f1").unwrap();
+    drop(f1);
+    let info = ramfs.statfs().unwrap();
+    assert_eq!((info.blocks_free, info.files_free), (3, 2));
+
+    // unlimited by default, which never looks full
+    let ramfs = RamFileSystem::new();
+    let info = ramfs.statfs().unwrap();
+    assert!(info.blocks > 0 && info.files > 0);
+    assert_eq!(info.blocks_free, info.blocks);
+    assert_eq!(info.files_free, info.files - 1);
+    let root = ramfs.root_dir();
+    root.create("f", VfsNodeType::File).unwrap();
+    let f = root.lookup("f").unwrap();
+    assert_eq!(f.write_at(0, &buf), Ok(2 * PAGE_SIZE));
+    let info = ramfs.statfs().unwrap();
+    assert_eq!(info.blocks_free, info.blocks - 2);
+}
+
+/// Records the events reported to it.
+#[derive(Default)]
+struct EventLog(Mutex<Vec<(VfsEventMask, String, u32)>>);
+
+impl VfsWatcher for EventLog {
+    fn notify(&self, event: &VfsEvent) {
+        let mut log = self.0.lock().unwrap();
+        log.push((event.mask, event.name.into(), event.cookie));
+    }
+}
+
+impl EventLog {
+    fn take(&self) -> Vec<(VfsEventMask, String, u32)> {
+        core::mem::take(&mut self.0.lock().unwrap())
+    }
+}
+
+#[test]
+fn test_watch() {
+    use VfsEventMask as M;
+    let ramfs = RamFileSystem::new();
+    let root = ramfs.root_dir();
+    root.create("d", VfsNodeType::Dir).unwrap();
+    let dir = root.clone().lookup("d").unwrap();
+    let (dir_log, file_log) = (Arc::new(EventLog::default()), Arc::new(EventLog::default()));
+    dir.watch(Arc::downgrade(&dir_log) as _).unwrap();
+
+    // entries of the directory
+    root.create("d/f", VfsNodeType::File).unwrap();
+    root.create("d/sub", VfsNodeType::Dir).unwrap();
+    root.symlink("d/l", "f").unwrap();
+    let f = root.clone().lookup("d/f").unwrap();
+    f.watch(Arc::downgrade(&file_log) as _).unwrap();
+    root.link("d/f2", &f).unwrap();
+    f.write_at(0, b"hello").unwrap();
+    assert_eq!(
+        dir_log.take(),
+        [
+            (M::CREATE, "f".into(), 0),
+            (M::CREATE | M::IS_DIR, "sub".into(), 0),
+            (M::CREATE, "l".into(), 0),
+            (M::CREATE, "f2".into(), 0),
+            (M::MODIFY, "f".into(), 0),
+            (M::MODIFY, "f2".into(), 0),
+        ]
+    );
+    assert_eq!(file_log.take(), [(M::MODIFY, String::new(), 0)]);
+
+    // renames are paired by cookies
+    root.rename("d/f2", "f3").unwrap();
+    root.rename("d/sub", "d/sub2").unwrap();
+    let events = dir_log.take();
+    assert_eq!(events.len(), 3);
+    assert_eq!(events[0].0, M::MOVED_FROM);
+    assert_eq!(
+        (events[1].0, events[2].0),
+        (M::MOVED_FROM | M::IS_DIR, M::MOVED_TO | M::IS_DIR)
+    );
+    assert_eq!(events[1].2, events[2].2);
+    assert_ne!(events[0].2, events[1].2);
+    f.truncate(0).unwrap(); // the link moved out is not reported
+    assert_eq!(dir_log.take(), [(M::MODIFY, "f".into(), 0)]);
+    assert_eq!(file_log.take(), [(M::MODIFY, String::new(), 0)]);
+
+    // the file is deleted with the last link
+    root.remove("d/f").unwrap();
+    assert_eq!(file_log.take(), []);
+    root.remove("f3").unwrap();
+    assert_eq!(file_log.take(), [(M::DELETE_SELF, String::new(), 0)]);
+    assert_eq!(dir_log.take(), [(M::DELETE, "f".into(), 0)]);
+
+    // dropped watchers are not notified
+    drop(dir_log);
+    root.remove("d/l").unwrap();
+    assert_eq!(file_log.take(), []);
+}
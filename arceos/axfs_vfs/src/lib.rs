@@ -0,0 +1,251 @@
+//! Virtual filesystem interfaces used by [ArceOS](https://github.com/arceos-org/arceos).
+//!
+//! A filesystem is a set of files, directories and symbolic links,
+//! collectively referred to as **nodes**, which are conceptually similar to
+//! [inodes] in Linux. A file system needs to implement the [`VfsOps`] trait,
+//! its files and directories need to implement the [`VfsNodeOps`] trait.
+//!
+//! The [`VfsOps`] trait provides the following operations:
+//!
+//! - `mount()`: Do something when the filesystem is mounted.
+//! - `umount()`: Do something when the filesystem is unmounted.
+//! - `format()`: Format the filesystem.
+//! - `statfs()`: Get the attributes of the filesystem.
+//! - `root_dir()`: Get root directory of the filesystem.
+//! - `name()`: Get the name of the filesystem type.
+//!
+//! The [`VfsNodeOps`] trait provides the following operations on a file or a
+//! directory:
+//!
+//! | Operation | Description | file/directory |
+//! | --- | --- | --- |
+//! | `open()` | Do something when the node is opened | both |
+//! | `release()` | Do something when the node is closed | both |
+//! | `get_attr()` | Get the attributes of the node | both |
+//! | `set_attr()` | Change the permission, owner or times of the node | both |
+//! | `read_at()` | Read data from the file | file |
+//! | `write_at()` | Write data to the file | file |
+//! | `fsync()` | Synchronize the file data to disk | file |
+//! | `truncate()` | Truncate the file | file |
+//! | `readlink()` | Read the target of the symbolic link | file |
+//! | `parent()` | Get the parent directory | directory |
+//! | `lookup()` | Lookup the node with the given path | directory |
+//! | `create()` | Create a new node with the given path | directory |
+//! | `remove()` | Remove the node with the given path | directory |
+//! | `read_dir()` | Read directory entries | directory |
+//! | `symlink()` | Create a symbolic link with the given path | directory |
+//! | `link()` | Create a hard link with the given path | directory |
+//! | `watch()` | Report changes of the node to a [`VfsWatcher`] | both |
+//!
+//! [inodes]: https://en.wikipedia.org/wiki/Inode
+
+#![no_std]
+
+extern crate alloc;
+
+mod macros;
+mod structs;
+
+pub mod path;
+
+use alloc::{
+    string::String,
+    sync::{Arc, Weak},
+};
+use axerrno::{ax_err, AxError, AxResult};
+
+pub use self::structs::{
+    FileSystemInfo, VfsDirEntry, VfsEvent, VfsEventMask, VfsNodeAttr, VfsNodePerm, VfsNodeType,
+    VfsSetAttr,
+};
+
+/// A wrapper of [`Arc<dyn VfsNodeOps>`].
+pub type VfsNodeRef = Arc<dyn VfsNodeOps>;
+
+/// Alias of [`AxError`].
+pub type VfsError = AxError;
+
+/// Alias of [`AxResult`].
+pub type VfsResult<T = ()> = AxResult<T>;
+
+/// Filesystem operations.
+pub trait VfsOps: Send + Sync {
+    /// Do something when the filesystem is mounted.
+    fn mount(&self, _path: &str, _mount_point: VfsNodeRef) -> VfsResult {
+        Ok(())
+    }
+
+    /// Do something when the filesystem is unmounted.
+    fn umount(&self) -> VfsResult {
+        Ok(())
+    }
+
+    /// Format the filesystem.
+    fn format(&self) -> VfsResult {
+        ax_err!(Unsupported)
+    }
+
+    /// Get the attributes of the filesystem.
+    fn statfs(&self) -> VfsResult<FileSystemInfo> {
+        ax_err!(Unsupported)
+    }
+
+    /// Write all cached data and metadata of the filesystem back to the
+    /// device, so that it is consistent on the device.
+    fn sync(&self) -> VfsResult {
+        Ok(())
+    }
+
+    /// Get the volume label of the filesystem, if it has one.
+    fn label(&self) -> Option<String> {
+        None
+    }
+
+    /// Get the root directory of the filesystem.
+    fn root_dir(&self) -> VfsNodeRef;
+
+    /// Get the name of the filesystem type (e.g., `"ramfs"`), as shown in
+    /// `/proc/mounts`.
+    fn name(&self) -> &str {
+        "unknown"
+    }
+}
+
+/// A receiver of the changes to the nodes it watches, see
+/// [`VfsNodeOps::watch`].
+pub trait VfsWatcher: Send + Sync {
+    /// Called when the watched node is changed.
+    ///
+    /// It may be called with locks of the filesystem held, so it must not
+    /// block or access the filesystem.
+    fn notify(&self, event: &VfsEvent);
+}
+
+/// Node (file/directory) operations.
+pub trait VfsNodeOps: Send + Sync {
+    /// Do something when the node is opened.
+    fn open(&self) -> VfsResult {
+        Ok(())
+    }
+
+    /// Do something when the node is closed.
+    fn release(&self) -> VfsResult {
+        Ok(())
+    }
+
+    /// Get the attributes of the node.
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        ax_err!(Unsupported)
+    }
+
+    /// Change the attributes of the node given in `attr`, and update its
+    /// change time.
+    fn set_attr(&self, _attr: &VfsSetAttr) -> VfsResult {
+        ax_err!(Unsupported)
+    }
+
+    // file operations:
+
+    /// Read data from the file at the given offset.
+    fn read_at(&self, _offset: u64, _buf: &mut [u8]) -> VfsResult<usize> {
+        ax_err!(InvalidInput)
+    }
+
+    /// Write data to the file at the given offset.
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> VfsResult<usize> {
+        ax_err!(InvalidInput)
+    }
+
+    /// Flush the file, synchronize the data to disk.
+    fn fsync(&self) -> VfsResult {
+        ax_err!(InvalidInput)
+    }
+
+    /// Truncate the file to the given size.
+    fn truncate(&self, _size: u64) -> VfsResult {
+        ax_err!(InvalidInput)
+    }
+
+    /// Read the target path of the symbolic link.
+    ///
+    /// Return [`InvalidInput`](AxError::InvalidInput) if the node is not a
+    /// symbolic link.
+    fn readlink(&self) -> VfsResult<String> {
+        ax_err!(InvalidInput)
+    }
+
+    // directory operations:
+
+    /// Get the parent directory of this directory.
+    ///
+    /// Return `None` if the node is a file.
+    fn parent(&self) -> Option<VfsNodeRef> {
+        None
+    }
+
+    /// Lookup the node with given `path` in the directory.
+    ///
+    /// Return the node if found.
+    fn lookup(self: Arc<Self>, _path: &str) -> VfsResult<VfsNodeRef> {
+        ax_err!(Unsupported)
+    }
+
+    /// Create a new node with the given `path` in the directory
+    ///
+    /// Return [`Ok(())`](Ok) if it already exists.
+    fn create(&self, _path: &str, _ty: VfsNodeType) -> VfsResult {
+        ax_err!(Unsupported)
+    }
+
+    /// Remove the node with the given `path` in the directory.
+    fn remove(&self, _path: &str) -> VfsResult {
+        ax_err!(Unsupported)
+    }
+
+    /// Read directory entries into `dirents`, starting from `start_idx`.
+    fn read_dir(&self, _start_idx: usize, _dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        ax_err!(Unsupported)
+    }
+
+    /// Renames or moves existing file or directory.
+    fn rename(&self, _src_path: &str, _dst_path: &str) -> VfsResult {
+        ax_err!(Unsupported)
+    }
+
+    /// Create a symbolic link with the given `path` in the directory, which
+    /// points to `target`.
+    ///
+    /// The `target` is stored as is, it is not required to exist.
+    fn symlink(&self, _path: &str, _target: &str) -> VfsResult {
+        ax_err!(Unsupported)
+    }
+
+    /// Create a hard link with the given `path` in the directory, which refers
+    /// to the existing `node` of the same filesystem.
+    fn link(&self, _path: &str, _node: &VfsNodeRef) -> VfsResult {
+        ax_err!(Unsupported)
+    }
+
+    // change notification:
+
+    /// Report the changes of this node, and of its entries if it is a
+    /// directory, to `watcher` until it is dropped.
+    fn watch(&self, _watcher: Weak<dyn VfsWatcher>) -> VfsResult {
+        ax_err!(Unsupported)
+    }
+
+    /// Convert `&self` to [`&dyn Any`][1] that can use
+    /// [`Any::downcast_ref`][2].
+    ///
+    /// [1]: core::any::Any
+    /// [2]: core::any::Any#method.downcast_ref
+    fn as_any(&self) -> &dyn core::any::Any {
+        unimplemented!()
+    }
+}
+
+#[doc(hidden)]
+pub mod __priv {
+    pub use alloc::sync::Arc;
+    pub use axerrno::ax_err;
+}
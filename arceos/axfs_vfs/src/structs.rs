@@ -0,0 +1,467 @@
+use core::time::Duration;
+
+/// Filesystem attributes, as returned by `statfs`.
+///
+/// The total and free numbers are 0 if the filesystem has no limit on them.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FileSystemInfo {
+    /// Size of blocks, in bytes.
+    pub block_size: u64,
+    /// Total number of blocks.
+    pub blocks: u64,
+    /// Number of free blocks.
+    pub blocks_free: u64,
+    /// Total number of inodes.
+    pub files: u64,
+    /// Number of free inodes.
+    pub files_free: u64,
+    /// Maximum length of file names, in bytes.
+    pub name_max: u64,
+}
+
+/// Node (file/directory) attributes.
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy)]
+pub struct VfsNodeAttr {
+    /// File permission mode.
+    mode: VfsNodePerm,
+    /// File type.
+    ty: VfsNodeType,
+    /// Total size, in bytes.
+    size: u64,
+    /// Number of 512B blocks allocated.
+    blocks: u64,
+    /// Number of hard links.
+    nlink: u64,
+    /// Inode number, or 0 if the filesystem does not have one.
+    ino: u64,
+    /// User ID of the owner.
+    uid: u32,
+    /// Group ID of the owner.
+    gid: u32,
+    /// Time of the last access, since the UNIX epoch.
+    atime: Duration,
+    /// Time of the last modification of the contents.
+    mtime: Duration,
+    /// Time of the last change of the contents or attributes.
+    ctime: Duration,
+}
+
+/// Changes to the attributes of a node, made by
+/// [`VfsNodeOps::set_attr`](crate::VfsNodeOps::set_attr).
+///
+/// Attributes which are `None` are left unchanged.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct VfsSetAttr {
+    /// New permission mode, as by `chmod`.
+    pub perm: Option<VfsNodePerm>,
+    /// New user ID of the owner, as by `chown`.
+    pub uid: Option<u32>,
+    /// New group ID of the owner, as by `chown`.
+    pub gid: Option<u32>,
+    /// New time of the last access, as by `utimensat`.
+    pub atime: Option<Duration>,
+    /// New time of the last modification, as by `utimensat`.
+    pub mtime: Option<Duration>,
+}
+
+bitflags::bitflags! {
+    /// Node (file/directory) permission mode.
+    #[derive(Debug, Clone, Copy)]
+    pub struct VfsNodePerm: u16 {
+        /// Owner has read permission.
+        const OWNER_READ = 0o400;
+        /// Owner has write permission.
+        const OWNER_WRITE = 0o200;
+        /// Owner has execute permission.
+        const OWNER_EXEC = 0o100;
+
+        /// Group has read permission.
+        const GROUP_READ = 0o40;
+        /// Group has write permission.
+        const GROUP_WRITE = 0o20;
+        /// Group has execute permission.
+        const GROUP_EXEC = 0o10;
+
+        /// Others have read permission.
+        const OTHER_READ = 0o4;
+        /// Others have write permission.
+        const OTHER_WRITE = 0o2;
+        /// Others have execute permission.
+        const OTHER_EXEC = 0o1;
+    }
+}
+
+bitflags::bitflags! {
+    /// Types of changes to nodes reported to [`VfsWatcher`](crate::VfsWatcher)s,
+    /// with the same values as inotify.
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub struct VfsEventMask: u32 {
+        /// The file was modified, e.g., written or truncated.
+        const MODIFY = 0x2;
+        /// An entry was moved out of the directory.
+        const MOVED_FROM = 0x40;
+        /// An entry was moved into the directory.
+        const MOVED_TO = 0x80;
+        /// An entry was created in the directory.
+        const CREATE = 0x100;
+        /// An entry was removed from the directory.
+        const DELETE = 0x200;
+        /// The watched node itself was removed.
+        const DELETE_SELF = 0x400;
+        /// Events were dropped as the queue of the watcher overflowed. It is
+        /// reported by watchers rather than filesystems.
+        const Q_OVERFLOW = 0x4000;
+        /// The watch was removed, explicitly or as the node was removed. It
+        /// is reported by watchers rather than filesystems.
+        const IGNORED = 0x8000;
+        /// The node of the event is a directory.
+        const IS_DIR = 0x4000_0000;
+    }
+}
+
+/// A change to a node, or to an entry of a directory node.
+#[derive(Debug, Clone, Copy)]
+pub struct VfsEvent<'a> {
+    /// Type of the change.
+    pub mask: VfsEventMask,
+    /// Name of the entry in the directory, or empty if the change is to the
+    /// watched node itself.
+    pub name: &'a str,
+    /// A number which is the same for the [`MOVED_FROM`] and [`MOVED_TO`]
+    /// events of a rename, or 0 for other events.
+    ///
+    /// [`MOVED_FROM`]: VfsEventMask::MOVED_FROM
+    /// [`MOVED_TO`]: VfsEventMask::MOVED_TO
+    pub cookie: u32,
+}
+
+/// Node (file/directory) type.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum VfsNodeType {
+    /// FIFO (named pipe)
+    Fifo = 0o1,
+    /// Character device
+    CharDevice = 0o2,
+    /// Directory
+    Dir = 0o4,
+    /// Block device
+    BlockDevice = 0o6,
+    /// Regular file
+    File = 0o10,
+    /// Symbolic link
+    SymLink = 0o12,
+    /// Socket
+    Socket = 0o14,
+}
+
+/// Directory entry.
+pub struct VfsDirEntry {
+    d_type: VfsNodeType,
+    d_name: [u8; 63],
+}
+
+impl VfsNodePerm {
+    /// Returns the default permission for a file.
+    ///
+    /// The default permission is `0o666` (owner/group/others can read and write).
+    pub const fn default_file() -> Self {
+        Self::from_bits_truncate(0o666)
+    }
+
+    /// Returns the default permission for a directory.
+    ///
+    /// The default permission is `0o755` (owner can read, write and execute,
+    /// group and others can read and execute).
+    pub const fn default_dir() -> Self {
+        Self::from_bits_truncate(0o755)
+    }
+
+    /// Returns a 9-bytes string representation of the permission.
+    ///
+    /// For example, `0o755` is represented as `rwxr-xr-x`.
+    pub const fn rwx_buf(&self) -> [u8; 9] {
+        let mut perm = [b'-'; 9];
+        if self.contains(Self::OWNER_READ) {
+            perm[0] = b'r';
+        }
+        if self.contains(Self::OWNER_WRITE) {
+            perm[1] = b'w';
+        }
+        if self.contains(Self::OWNER_EXEC) {
+            perm[2] = b'x';
+        }
+        if self.contains(Self::GROUP_READ) {
+            perm[3] = b'r';
+        }
+        if self.contains(Self::GROUP_WRITE) {
+            perm[4] = b'w';
+        }
+        if self.contains(Self::GROUP_EXEC) {
+            perm[5] = b'x';
+        }
+        if self.contains(Self::OTHER_READ) {
+            perm[6] = b'r';
+        }
+        if self.contains(Self::OTHER_WRITE) {
+            perm[7] = b'w';
+        }
+        if self.contains(Self::OTHER_EXEC) {
+            perm[8] = b'x';
+        }
+        perm
+    }
+
+    /// Whether the owner has read permission.
+    pub const fn owner_readable(&self) -> bool {
+        self.contains(Self::OWNER_READ)
+    }
+
+    /// Whether the owner has write permission.
+    pub const fn owner_writable(&self) -> bool {
+        self.contains(Self::OWNER_WRITE)
+    }
+
+    /// Whether the owner has execute permission.
+    pub const fn owner_executable(&self) -> bool {
+        self.contains(Self::OWNER_EXEC)
+    }
+}
+
+impl VfsNodeType {
+    /// Tests whether this node type represents a regular file.
+    pub const fn is_file(self) -> bool {
+        matches!(self, Self::File)
+    }
+
+    /// Tests whether this node type represents a directory.
+    pub const fn is_dir(self) -> bool {
+        matches!(self, Self::Dir)
+    }
+
+    /// Tests whether this node type represents a symbolic link.
+    pub const fn is_symlink(self) -> bool {
+        matches!(self, Self::SymLink)
+    }
+
+    /// Returns `true` if this node type is a block device.
+    pub const fn is_block_device(self) -> bool {
+        matches!(self, Self::BlockDevice)
+    }
+
+    /// Returns `true` if this node type is a char device.
+    pub const fn is_char_device(self) -> bool {
+        matches!(self, Self::CharDevice)
+    }
+
+    /// Returns `true` if this node type is a fifo.
+    pub const fn is_fifo(self) -> bool {
+        matches!(self, Self::Fifo)
+    }
+
+    /// Returns `true` if this node type is a socket.
+    pub const fn is_socket(self) -> bool {
+        matches!(self, Self::Socket)
+    }
+
+    /// Returns a character representation of the node type.
+    ///
+    /// For example, `d` for directory, `-` for regular file, etc.
+    pub const fn as_char(self) -> char {
+        match self {
+            Self::Fifo => 'p',
+            Self::CharDevice => 'c',
+            Self::Dir => 'd',
+            Self::BlockDevice => 'b',
+            Self::File => '-',
+            Self::SymLink => 'l',
+            Self::Socket => 's',
+        }
+    }
+}
+
+impl VfsNodeAttr {
+    /// Creates a new `VfsNodeAttr` with the given permission mode, type, size
+    /// and number of blocks.
+    pub const fn new(mode: VfsNodePerm, ty: VfsNodeType, size: u64, blocks: u64) -> Self {
+        Self {
+            mode,
+            ty,
+            size,
+            blocks,
+            nlink: 1,
+            ino: 0,
+            uid: 0,
+            gid: 0,
+            atime: Duration::ZERO,
+            mtime: Duration::ZERO,
+            ctime: Duration::ZERO,
+        }
+    }
+
+    /// Creates a new `VfsNodeAttr` for a file, with the default file permission.
+    pub const fn new_file(size: u64, blocks: u64) -> Self {
+        Self::new(VfsNodePerm::default_file(), VfsNodeType::File, size, blocks)
+    }
+
+    /// Creates a new `VfsNodeAttr` for a directory, with the default directory
+    /// permission.
+    pub const fn new_dir(size: u64, blocks: u64) -> Self {
+        Self::new(VfsNodePerm::default_dir(), VfsNodeType::Dir, size, blocks)
+    }
+
+    /// Creates a new `VfsNodeAttr` for a symbolic link whose target path has
+    /// `size` bytes.
+    ///
+    /// The permission of a symbolic link is always `0o777`.
+    pub const fn new_symlink(size: u64) -> Self {
+        Self::new(
+            VfsNodePerm::from_bits_truncate(0o777),
+            VfsNodeType::SymLink,
+            size,
+            0,
+        )
+    }
+
+    /// Returns the size of the node.
+    pub const fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// Returns the number of blocks the node occupies on the disk.
+    pub const fn blocks(&self) -> u64 {
+        self.blocks
+    }
+
+    /// Returns the number of hard links to the node.
+    pub const fn nlink(&self) -> u64 {
+        self.nlink
+    }
+
+    /// Returns the inode number of the node, or 0 if the filesystem does not
+    /// have inode numbers.
+    pub const fn ino(&self) -> u64 {
+        self.ino
+    }
+
+    /// Returns the user ID of the owner of the node.
+    pub const fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    /// Returns the group ID of the owner of the node.
+    pub const fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    /// Returns the time of the last access to the node, since the UNIX epoch.
+    pub const fn atime(&self) -> Duration {
+        self.atime
+    }
+
+    /// Returns the time of the last modification of the node contents, since
+    /// the UNIX epoch.
+    pub const fn mtime(&self) -> Duration {
+        self.mtime
+    }
+
+    /// Returns the time of the last change of the node contents or
+    /// attributes, since the UNIX epoch.
+    pub const fn ctime(&self) -> Duration {
+        self.ctime
+    }
+
+    /// Returns the permission of the node.
+    pub const fn perm(&self) -> VfsNodePerm {
+        self.mode
+    }
+
+    /// Sets the permission of the node.
+    pub fn set_perm(&mut self, perm: VfsNodePerm) {
+        self.mode = perm
+    }
+
+    /// Sets the number of hard links to the node.
+    pub fn set_nlink(&mut self, nlink: u64) {
+        self.nlink = nlink
+    }
+
+    /// Sets the inode number of the node.
+    pub fn set_ino(&mut self, ino: u64) {
+        self.ino = ino
+    }
+
+    /// Sets the user and group IDs of the owner of the node.
+    pub fn set_owner(&mut self, uid: u32, gid: u32) {
+        self.uid = uid;
+        self.gid = gid;
+    }
+
+    /// Sets the access, modification and change times of the node.
+    pub fn set_times(&mut self, atime: Duration, mtime: Duration, ctime: Duration) {
+        self.atime = atime;
+        self.mtime = mtime;
+        self.ctime = ctime;
+    }
+
+    /// Returns the type of the node.
+    pub const fn file_type(&self) -> VfsNodeType {
+        self.ty
+    }
+
+    /// Whether the node is a file.
+    pub const fn is_file(&self) -> bool {
+        self.ty.is_file()
+    }
+
+    /// Whether the node is a directory.
+    pub const fn is_dir(&self) -> bool {
+        self.ty.is_dir()
+    }
+
+    /// Whether the node is a symbolic link.
+    pub const fn is_symlink(&self) -> bool {
+        self.ty.is_symlink()
+    }
+}
+
+impl VfsDirEntry {
+    /// Creates an empty `VfsDirEntry`.
+    pub const fn default() -> Self {
+        Self {
+            d_type: VfsNodeType::File,
+            d_name: [0; 63],
+        }
+    }
+
+    /// Creates a new `VfsDirEntry` with the given name and type.
+    pub fn new(name: &str, ty: VfsNodeType) -> Self {
+        let mut d_name = [0; 63];
+        if name.len() > d_name.len() {
+            log::warn!(
+                "directory entry name too long: {} > {}",
+                name.len(),
+                d_name.len()
+            );
+        }
+        let len = name.len().min(d_name.len());
+        d_name[..len].copy_from_slice(&name.as_bytes()[..len]);
+        Self { d_type: ty, d_name }
+    }
+
+    /// Returns the type of the entry.
+    pub fn entry_type(&self) -> VfsNodeType {
+        self.d_type
+    }
+
+    /// Converts the name of the entry to a byte slice.
+    pub fn name_as_bytes(&self) -> &[u8] {
+        let len = self
+            .d_name
+            .iter()
+            .position(|&c| c == 0)
+            .unwrap_or(self.d_name.len());
+        &self.d_name[..len]
+    }
+}
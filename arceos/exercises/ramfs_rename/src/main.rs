@@ -38,11 +38,12 @@ fn print_file(fname: &str) -> io::Result<()> {
 }
 
 fn process() -> io::Result<()> {
-    create_file("/tmp/f1", "hello")?;
+    ramfs::mount_ramfs("/myramfs")?;
+    create_file("/myramfs/f1", "hello")?;
     // Just rename, NOT move.
     // So this must happen in the same directory.
-    rename_file("/tmp/f1", "/tmp/f2")?;
-    print_file("/tmp/f2")
+    rename_file("/myramfs/f1", "/myramfs/f2")?;
+    print_file("/myramfs/f2")
 }
 
 #[cfg_attr(feature = "axstd", no_mangle)]
@@ -1,15 +1,28 @@
 extern crate alloc;
 
-use alloc::sync::Arc;
+use alloc::{boxed::Box, sync::Arc};
 use axfs_ramfs::RamFileSystem;
 use axfs_vfs::VfsOps;
-use std::os::arceos::api::fs::{AxDisk, MyFileSystemIf};
+use std::io;
+use std::os::arceos::api::fs::{ax_mount_device, ax_register_filesystem};
+use std::os::arceos::api::fs::{AxBlockDevice, AxFileSystemType};
 
-struct MyFileSystemIfImpl;
+struct RamFsType;
 
-#[crate_interface::impl_interface]
-impl MyFileSystemIf for MyFileSystemIfImpl {
-    fn new_myfs(_disk: AxDisk) -> Arc<dyn VfsOps> {
-        Arc::new(RamFileSystem::new())
+impl AxFileSystemType for RamFsType {
+    fn name(&self) -> &str {
+        "ramfs"
     }
+
+    fn mount(&self, _dev: Box<dyn AxBlockDevice>) -> io::Result<Arc<dyn VfsOps>> {
+        Ok(Arc::new(RamFileSystem::new()))
+    }
+}
+
+/// Registers the ramfs as a custom filesystem type, and mounts it at `path`.
+///
+/// The ramfs does not use the block device, any one is fine.
+pub fn mount_ramfs(path: &str) -> io::Result<()> {
+    ax_register_filesystem(Arc::new(RamFsType))?;
+    ax_mount_device("vda0", path, Some("ramfs"), false)
 }
@@ -20,6 +20,7 @@ const ELF_HEAD_BUF_SIZE: usize = 256;
 pub fn load_user_app(fname: &str, uspace: &mut AddrSpace) -> io::Result<usize> {
     let mut file = File::open(fname)?;
     let (phdrs, entry, _, _) = load_elf_phdrs(&mut file)?;
+    let mut heap_start = VirtAddr::from(0);
 
     for phdr in &phdrs {
         ax_println!(
@@ -45,8 +46,11 @@ pub fn load_user_app(fname: &str, uspace: &mut AddrSpace) -> io::Result<usize> {
         }
         assert_eq!(index, filesz);
         uspace.write(VirtAddr::from(phdr.p_vaddr as usize), &data)?;
+        heap_start = heap_start.max(vaddr_end);
     }
 
+    // The heap grows by `brk` from the end of the loaded segments.
+    uspace.init_heap(heap_start);
     Ok(entry)
 }
 
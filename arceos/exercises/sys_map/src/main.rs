@@ -22,6 +22,8 @@ use alloc::string::String;
 use alloc::collections::BTreeMap;
 use axmm::AddrSpace;
 use loader::load_user_app;
+use axtask::TaskExtRef;
+use axhal::trap::{register_trap_handler, PAGE_FAULT};
 
 const USER_STACK_SIZE: usize = 0x10000;
 const KERNEL_STACK_SIZE: usize = 0x40000; // 256 KiB
@@ -80,3 +82,23 @@ fn init_user_stack(uspace: &mut AddrSpace, populating: bool) -> io::Result<VirtA
 
     Ok(ustack_pointer.into())
 }
+
+/// Maps the pages of `mmap`ed files and anonymous memory, and the pages of the
+/// heap, which are all allocated on demand.
+#[register_trap_handler(PAGE_FAULT)]
+fn handle_page_fault(vaddr: VirtAddr, access_flags: MappingFlags, is_user: bool) -> bool {
+    if is_user {
+        if !axtask::current()
+            .task_ext()
+            .aspace
+            .lock()
+            .handle_page_fault(vaddr, access_flags)
+        {
+            ax_println!("{}: segmentation fault, exit!", axtask::current().id_name());
+            axtask::exit(-1);
+        }
+        true
+    } else {
+        false
+    }
+}
@@ -3,14 +3,10 @@
 use arceos_posix_api as api;
 use axerrno::LinuxError;
 use axhal::arch::TrapFrame;
-use axhal::paging::MappingFlags;
 use axhal::trap::{register_trap_handler, SYSCALL};
 use axtask::current;
 use axtask::TaskExtRef;
 use core::ffi::{c_char, c_int, c_void};
-use memory_addr::{MemoryAddr, VirtAddrRange, PAGE_SIZE_4K};
-
-use crate::USER_STACK_SIZE;
 
 const SYS_IOCTL: usize = 29;
 const SYS_OPENAT: usize = 56;
@@ -21,7 +17,11 @@ const SYS_WRITEV: usize = 66;
 const SYS_EXIT: usize = 93;
 const SYS_EXIT_GROUP: usize = 94;
 const SYS_SET_TID_ADDRESS: usize = 96;
+const SYS_BRK: usize = 214;
+const SYS_MUNMAP: usize = 215;
 const SYS_MMAP: usize = 222;
+const SYS_MPROTECT: usize = 226;
+const SYS_MADVISE: usize = 233;
 
 const AT_FDCWD: i32 = -100;
 
@@ -47,58 +47,6 @@ macro_rules! syscall_body {
     }};
 }
 
-bitflags::bitflags! {
-    #[derive(Debug)]
-    /// permissions for sys_mmap
-    ///
-    /// See <https://github.com/bminor/glibc/blob/master/bits/mman.h>
-    struct MmapProt: i32 {
-        /// Page can be read.
-        const PROT_READ = 1 << 0;
-        /// Page can be written.
-        const PROT_WRITE = 1 << 1;
-        /// Page can be executed.
-        const PROT_EXEC = 1 << 2;
-    }
-}
-
-impl From<MmapProt> for MappingFlags {
-    fn from(value: MmapProt) -> Self {
-        let mut flags = MappingFlags::USER;
-        if value.contains(MmapProt::PROT_READ) {
-            flags |= MappingFlags::READ;
-        }
-        if value.contains(MmapProt::PROT_WRITE) {
-            flags |= MappingFlags::WRITE;
-        }
-        if value.contains(MmapProt::PROT_EXEC) {
-            flags |= MappingFlags::EXECUTE;
-        }
-        flags
-    }
-}
-
-bitflags::bitflags! {
-    #[derive(Debug)]
-    /// flags for sys_mmap
-    ///
-    /// See <https://github.com/bminor/glibc/blob/master/bits/mman.h>
-    struct MmapFlags: i32 {
-        /// Share changes
-        const MAP_SHARED = 1 << 0;
-        /// Changes private; copy pages on write.
-        const MAP_PRIVATE = 1 << 1;
-        /// Map address must be exactly as requested, no matter whether it is available.
-        const MAP_FIXED = 1 << 4;
-        /// Don't use a file.
-        const MAP_ANONYMOUS = 1 << 5;
-        /// Don't check for reservations.
-        const MAP_NORESERVE = 1 << 14;
-        /// Allocation is for a stack.
-        const MAP_STACK = 0x20000;
-    }
-}
-
 #[register_trap_handler(SYSCALL)]
 fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
     ax_println!("handle_syscall [{}] ...", syscall_num);
@@ -123,6 +71,8 @@ fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
             ax_println!("[SYS_EXIT]: system is exiting ..");
             axtask::exit(tf.arg0() as _)
         }
+        SYS_BRK => sys_brk(tf.arg0() as _),
+        SYS_MUNMAP => sys_munmap(tf.arg0() as _, tf.arg1() as _),
         SYS_MMAP => sys_mmap(
             tf.arg0() as _,
             tf.arg1() as _,
@@ -131,6 +81,8 @@ fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
             tf.arg4() as _,
             tf.arg5() as _,
         ),
+        SYS_MPROTECT => sys_mprotect(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
+        SYS_MADVISE => sys_madvise(tf.arg0() as _, tf.arg1() as _, tf.arg2() as _),
         _ => {
             ax_println!("Unimplemented syscall: {}", syscall_num);
             -LinuxError::ENOSYS.code() as _
@@ -139,49 +91,32 @@ fn handle_syscall(tf: &TrapFrame, syscall_num: usize) -> isize {
     ret
 }
 
-#[allow(unused_variables)]
+fn sys_brk(addr: *mut c_void) -> isize {
+    api::sys_brk(&mut current().task_ext().aspace.lock(), addr)
+}
+
+fn sys_munmap(addr: *mut c_void, length: usize) -> isize {
+    api::sys_munmap(&mut current().task_ext().aspace.lock(), addr, length) as isize
+}
+
 fn sys_mmap(
-    addr: *mut usize,
+    addr: *mut c_void,
     length: usize,
     prot: i32,
     flags: i32,
     fd: i32,
-    _offset: isize,
+    offset: isize,
 ) -> isize {
-    let curr = current();
-    let mut aspace = curr.task_ext().aspace.lock();
-    let hint = if addr.is_null() { 0 } else { unsafe { *addr } };
-    let length = length.align_up(PAGE_SIZE_4K);
-
-    if let Some(area) = aspace.find_free_area(
-        hint.into(),
-        length,
-        VirtAddrRange::new(0.into(), aspace.end() - USER_STACK_SIZE),
-    ) {
-        let mut flags = MappingFlags::USER;
-        if let Some(p) = MappingFlags::from_bits(prot as usize) {
-            flags |= p;
-        } else {
-            return -1;
-        }
-        let map_res = aspace.map_alloc(area, length, flags, true);
-        if map_res.is_err() {
-            return -1;
-        }
-        if let Some(buffers) = aspace.translated_byte_buffer(area, length) {
-            for buf in buffers {
-                let ret = sys_read(fd, buf.as_ptr() as *mut c_void, buf.len());
-                if ret == 0 {
-                    break;
-                } else if ret < 0 {
-                    return -1;
-                }
-            }
-        }
-        area.as_usize() as isize
-    } else {
-        -1
-    }
+    let mut aspace = current().task_ext().aspace.lock();
+    api::sys_mmap(&mut aspace, addr, length, prot, flags, fd, offset as _)
+}
+
+fn sys_mprotect(addr: *mut c_void, length: usize, prot: i32) -> isize {
+    api::sys_mprotect(&mut current().task_ext().aspace.lock(), addr, length, prot) as isize
+}
+
+fn sys_madvise(addr: *mut c_void, length: usize, advice: i32) -> isize {
+    api::sys_madvise(&mut current().task_ext().aspace.lock(), addr, length, advice) as isize
 }
 
 fn sys_openat(dfd: c_int, fname: *const c_char, flags: c_int, mode: api::ctypes::mode_t) -> isize {
@@ -1,6 +1,7 @@
 const NET_DEV_FEATURES: &[&str] = &["ixgbe", "virtio-net"];
 const BLOCK_DEV_FEATURES: &[&str] = &["ramdisk", "bcm2835-sdhci", "virtio-blk"];
 const DISPLAY_DEV_FEATURES: &[&str] = &["virtio-gpu"];
+const NINEP_DEV_FEATURES: &[&str] = &["virtio-9p"];
 
 fn make_cfg_values(str_list: &[&str]) -> String {
     str_list
@@ -36,6 +37,7 @@ fn main() {
         ("net", NET_DEV_FEATURES),
         ("block", BLOCK_DEV_FEATURES),
         ("display", DISPLAY_DEV_FEATURES),
+        ("ninep", NINEP_DEV_FEATURES),
     ] {
         if !has_feature(dev_kind) {
             continue;
@@ -72,4 +74,8 @@ fn main() {
         "cargo::rustc-check-cfg=cfg(display_dev, values({}, \"dummy\"))",
         make_cfg_values(DISPLAY_DEV_FEATURES)
     );
+    println!(
+        "cargo::rustc-check-cfg=cfg(ninep_dev, values({}, \"dummy\"))",
+        make_cfg_values(NINEP_DEV_FEATURES)
+    );
 }
@@ -100,3 +100,29 @@ cfg_if! {
         }
     }
 }
+
+cfg_if! {
+    if #[cfg(ninep_dev = "dummy")] {
+        pub struct DummyNinePDev;
+        pub struct DummyNinePDriver;
+        register_ninep_driver!(DummyNinePDriver, DummyNinePDev);
+
+        impl BaseDriverOps for DummyNinePDev {
+            fn device_type(&self) -> DeviceType {
+                DeviceType::Char
+            }
+            fn device_name(&self) -> &str {
+                "dummy-9p"
+            }
+        }
+
+        impl NinePDriverOps for DummyNinePDev {
+            fn mount_tag(&self) -> &str {
+                ""
+            }
+            fn transact(&mut self, _: &[u8], _: &mut [u8]) -> DevResult<usize> {
+                Err(DevError::Unsupported)
+            }
+        }
+    }
+}
@@ -8,8 +8,9 @@
 //! driver they want.
 //!
 //! For each device category (i.e., net, block, display, etc.), an unified type
-//! is used to represent all devices in that category. Currently, there are 3
-//! categories: [`AxNetDevice`], [`AxBlockDevice`], and [`AxDisplayDevice`].
+//! is used to represent all devices in that category. Currently, there are 4
+//! categories: [`AxNetDevice`], [`AxBlockDevice`], [`AxDisplayDevice`], and
+//! [`AxNinePDevice`].
 //!
 //! # Concepts
 //!
@@ -34,6 +35,7 @@
 //! | Block | `virtio-blk` | VirtIO block device |
 //! | Network | `virtio-net` | VirtIO network device |
 //! | Display | `virtio-gpu` | VirtIO graphics device |
+//! | 9P | `virtio-9p` | VirtIO 9P transport, to access the files shared by the host |
 //!
 //! # Other Cargo Features
 //!
@@ -42,12 +44,14 @@
 //! - `bus-pci`: use PCI bus to probe all PCI devices. This feature is
 //!    enabeld by default.
 //! - `virtio`: use VirtIO devices. This is enabled if any of `virtio-blk`,
-//!   `virtio-net` or `virtio-gpu` is enabled.
+//!   `virtio-net`, `virtio-gpu` or `virtio-9p` is enabled.
 //! - `net`: use network devices. This is enabled if any feature of network
 //!    devices is selected. If this feature is enabled without any network device
 //!    features, a dummy struct is used for [`AxNetDevice`].
 //! - `block`: use block storage devices. Similar to the `net` feature.
 //! - `display`: use graphics display devices. Similar to the `net` feature.
+//! - `ninep`: use [9P transports](ninep), over which the files of a 9P server
+//!   are accessed. Similar to the `net` feature.
 //!
 //! [`VirtioNetDev`]: axdriver_virtio::VirtIoNetDev
 //! [`Box<dyn NetDriverOps>`]: axdriver_net::NetDriverOps
@@ -78,6 +82,8 @@ mod virtio;
 #[cfg(feature = "ixgbe")]
 mod ixgbe;
 
+#[cfg(feature = "ninep")]
+pub mod ninep;
 pub mod prelude;
 
 #[allow(unused_imports)]
@@ -90,6 +96,8 @@ pub use self::structs::AxBlockDevice;
 pub use self::structs::AxDisplayDevice;
 #[cfg(feature = "net")]
 pub use self::structs::AxNetDevice;
+#[cfg(feature = "ninep")]
+pub use self::structs::AxNinePDevice;
 
 /// A structure that contains all device drivers, organized by their category.
 #[derive(Default)]
@@ -103,6 +111,9 @@ pub struct AllDevices {
     /// All graphics device drivers.
     #[cfg(feature = "display")]
     pub display: AxDeviceContainer<AxDisplayDevice>,
+    /// All 9P transport drivers.
+    #[cfg(feature = "ninep")]
+    pub ninep: AxDeviceContainer<AxNinePDevice>,
 }
 
 impl AllDevices {
@@ -143,6 +154,8 @@ impl AllDevices {
             AxDeviceEnum::Block(dev) => self.block.push(dev),
             #[cfg(feature = "display")]
             AxDeviceEnum::Display(dev) => self.display.push(dev),
+            #[cfg(feature = "ninep")]
+            AxDeviceEnum::NineP(dev) => self.ninep.push(dev),
         }
     }
 }
@@ -179,6 +192,18 @@ pub fn init_drivers() -> AllDevices {
             debug!("  graphics device {}: {:?}", i, dev.device_name());
         }
     }
+    #[cfg(feature = "ninep")]
+    {
+        debug!("number of 9P transports: {}", all_devs.ninep.len());
+        for (i, dev) in all_devs.ninep.iter().enumerate() {
+            debug!(
+                "  9P transport {}: {:?} ({:?})",
+                i,
+                dev.device_name(),
+                dev.mount_tag()
+            );
+        }
+    }
 
     all_devs
 }
@@ -26,6 +26,14 @@ macro_rules! register_display_driver {
     };
 }
 
+macro_rules! register_ninep_driver {
+    ($driver_type:ty, $device_type:ty) => {
+        /// The unified type of the 9P transport devices.
+        #[cfg(not(feature = "dyn"))]
+        pub type AxNinePDevice = $device_type;
+    };
+}
+
 macro_rules! for_each_drivers {
     (type $drv_type:ident, $code:block) => {{
         #[allow(unused_imports)]
@@ -49,6 +57,11 @@ macro_rules! for_each_drivers {
             type $drv_type = <virtio::VirtIoGpu as VirtIoDevMeta>::Driver;
             $code
         }
+        #[cfg(ninep_dev = "virtio-9p")]
+        {
+            type $drv_type = <virtio::VirtIo9p as VirtIoDevMeta>::Driver;
+            $code
+        }
         #[cfg(block_dev = "ramdisk")]
         {
             type $drv_type = crate::drivers::RamDiskDriver;
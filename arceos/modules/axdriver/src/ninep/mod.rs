@@ -0,0 +1,50 @@
+//! 9P transports, over which [9P] messages are exchanged with a server to
+//! access the files it exports.
+//!
+//! [9P]: https://en.wikipedia.org/wiki/9P_(protocol)
+
+#[cfg(feature = "virtio-9p")]
+mod virtio;
+
+#[cfg(feature = "virtio-9p")]
+pub use self::virtio::VirtIo9pDev;
+
+use axdriver_base::{BaseDriverOps, DevResult};
+
+/// Operations that require a 9P transport driver to implement.
+pub trait NinePDriverOps: BaseDriverOps {
+    /// The tag of the exported filesystem, which is used to choose the
+    /// filesystem to mount (e.g., the `mount_tag` of QEMU).
+    fn mount_tag(&self) -> &str;
+
+    /// Sends the request message `req` to the server, and receives its reply
+    /// into `resp`. Returns the length of the reply.
+    ///
+    /// `resp` must be large enough for the reply, i.e., as large as the
+    /// maximum message size negotiated with the server.
+    fn transact(&mut self, req: &[u8], resp: &mut [u8]) -> DevResult<usize>;
+}
+
+#[cfg(feature = "dyn")]
+impl BaseDriverOps for alloc::boxed::Box<dyn NinePDriverOps> {
+    fn device_type(&self) -> axdriver_base::DeviceType {
+        (**self).device_type()
+    }
+
+    fn device_name(&self) -> &str {
+        (**self).device_name()
+    }
+}
+
+/// Makes [`AxNinePDevice`](crate::AxNinePDevice) of the `dyn` device model a
+/// 9P transport, like that of the static model.
+#[cfg(feature = "dyn")]
+impl NinePDriverOps for alloc::boxed::Box<dyn NinePDriverOps> {
+    fn mount_tag(&self) -> &str {
+        (**self).mount_tag()
+    }
+
+    fn transact(&mut self, req: &[u8], resp: &mut [u8]) -> DevResult<usize> {
+        (**self).transact(req, resp)
+    }
+}
@@ -0,0 +1,120 @@
+use axdriver_base::{BaseDriverOps, DevError, DevResult, DeviceType};
+use virtio_drivers::queue::VirtQueue;
+use virtio_drivers::transport::{DeviceStatus, Transport};
+use virtio_drivers::{Error, Hal, PAGE_SIZE};
+
+use super::NinePDriverOps;
+
+/// The mount tag is available in the config space.
+const VIRTIO_9P_MOUNT_TAG: u64 = 1 << 0;
+/// Compliance with the VirtIO 1.0 specification.
+const VIRTIO_F_VERSION_1: u64 = 1 << 32;
+
+const QUEUE_REQUEST: u16 = 0;
+const QUEUE_SIZE: usize = 16;
+const MAX_TAG_LEN: usize = 64;
+
+/// The VirtIO 9P transport driver.
+pub struct VirtIo9pDev<H: Hal, T: Transport> {
+    transport: T,
+    queue: VirtQueue<H, QUEUE_SIZE>,
+    tag: [u8; MAX_TAG_LEN],
+    tag_len: usize,
+}
+
+unsafe impl<H: Hal, T: Transport> Send for VirtIo9pDev<H, T> {}
+unsafe impl<H: Hal, T: Transport> Sync for VirtIo9pDev<H, T> {}
+
+impl<H: Hal, T: Transport> VirtIo9pDev<H, T> {
+    /// Creates a new driver instance and initializes the device, or returns
+    /// an error if any step fails.
+    pub fn try_new(mut transport: T) -> DevResult<Self> {
+        let status = DeviceStatus::ACKNOWLEDGE | DeviceStatus::DRIVER;
+        transport.set_status(DeviceStatus::empty());
+        transport.set_status(status);
+        let features =
+            transport.read_device_features() & (VIRTIO_9P_MOUNT_TAG | VIRTIO_F_VERSION_1);
+        transport.write_driver_features(features);
+        transport.set_status(status | DeviceStatus::FEATURES_OK);
+        if !transport.get_status().contains(DeviceStatus::FEATURES_OK) {
+            return Err(DevError::Unsupported);
+        }
+        transport.set_guest_page_size(PAGE_SIZE as u32);
+
+        let queue = VirtQueue::new(&mut transport, QUEUE_REQUEST, false, false)
+            .map_err(as_dev_err)?;
+        let mut tag = [0; MAX_TAG_LEN];
+        let tag_len = if features & VIRTIO_9P_MOUNT_TAG != 0 {
+            read_mount_tag(&transport, &mut tag)?
+        } else {
+            0
+        };
+        transport.finish_init();
+        Ok(Self {
+            transport,
+            queue,
+            tag,
+            tag_len,
+        })
+    }
+}
+
+impl<H: Hal, T: Transport> BaseDriverOps for VirtIo9pDev<H, T> {
+    fn device_name(&self) -> &str {
+        "virtio-9p"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        // there is no device type for 9P transports
+        DeviceType::Char
+    }
+}
+
+impl<H: Hal, T: Transport> NinePDriverOps for VirtIo9pDev<H, T> {
+    fn mount_tag(&self) -> &str {
+        core::str::from_utf8(&self.tag[..self.tag_len]).unwrap_or_default()
+    }
+
+    fn transact(&mut self, req: &[u8], resp: &mut [u8]) -> DevResult<usize> {
+        let len = self
+            .queue
+            .add_notify_wait_pop(&[req], &mut [resp], &mut self.transport)
+            .map_err(as_dev_err)?;
+        Ok(len as usize)
+    }
+}
+
+impl<H: Hal, T: Transport> Drop for VirtIo9pDev<H, T> {
+    fn drop(&mut self) {
+        // clear the pointers to the queue, which is about to be freed
+        self.transport.queue_unset(QUEUE_REQUEST);
+    }
+}
+
+/// Reads the mount tag from the config space into `tag`, returns its length.
+fn read_mount_tag<T: Transport>(transport: &T, tag: &mut [u8; MAX_TAG_LEN]) -> DevResult<usize> {
+    // struct virtio_9p_config { le16 tag_len; u8 tag[]; }
+    let config = transport.config_space::<u16>().map_err(as_dev_err)?;
+    let len = u16::from_le(unsafe { config.as_ptr().read_volatile() }) as usize;
+    if len > MAX_TAG_LEN {
+        return Err(DevError::InvalidParam);
+    }
+    let bytes = unsafe { config.as_ptr().add(1).cast::<u8>() };
+    for (i, b) in tag[..len].iter_mut().enumerate() {
+        *b = unsafe { bytes.add(i).read_volatile() };
+    }
+    Ok(len)
+}
+
+const fn as_dev_err(e: Error) -> DevError {
+    match e {
+        Error::QueueFull | Error::WrongToken => DevError::BadState,
+        Error::NotReady => DevError::Again,
+        Error::AlreadyUsed => DevError::AlreadyExists,
+        Error::InvalidParam => DevError::InvalidParam,
+        Error::DmaError => DevError::NoMemory,
+        Error::IoError => DevError::Io,
+        Error::Unsupported => DevError::Unsupported,
+        _ => DevError::BadState,
+    }
+}
@@ -8,3 +8,5 @@ pub use {crate::structs::AxBlockDevice, axdriver_block::BlockDriverOps};
 pub use {crate::structs::AxDisplayDevice, axdriver_display::DisplayDriverOps};
 #[cfg(feature = "net")]
 pub use {crate::structs::AxNetDevice, axdriver_net::NetDriverOps};
+#[cfg(feature = "ninep")]
+pub use {crate::ninep::NinePDriverOps, crate::structs::AxNinePDevice};
@@ -12,6 +12,9 @@ pub type AxBlockDevice = Box<dyn BlockDriverOps>;
 /// The unified type of the graphics display devices.
 #[cfg(feature = "display")]
 pub type AxDisplayDevice = Box<dyn DisplayDriverOps>;
+/// The unified type of the 9P transport devices.
+#[cfg(feature = "ninep")]
+pub type AxNinePDevice = Box<dyn NinePDriverOps>;
 
 impl super::AxDeviceEnum {
     /// Constructs a network device.
@@ -31,6 +34,12 @@ impl super::AxDeviceEnum {
     pub fn from_display(dev: impl DisplayDriverOps + 'static) -> Self {
         Self::Display(Box::new(dev))
     }
+
+    /// Constructs a 9P transport device.
+    #[cfg(feature = "ninep")]
+    pub fn from_ninep(dev: impl NinePDriverOps + 'static) -> Self {
+        Self::NineP(Box::new(dev))
+    }
 }
 
 /// A structure that contains all device drivers of a certain category.
@@ -4,6 +4,8 @@ pub use crate::drivers::AxBlockDevice;
 pub use crate::drivers::AxDisplayDevice;
 #[cfg(feature = "net")]
 pub use crate::drivers::AxNetDevice;
+#[cfg(feature = "ninep")]
+pub use crate::drivers::AxNinePDevice;
 
 impl super::AxDeviceEnum {
     /// Constructs a network device.
@@ -23,6 +25,12 @@ impl super::AxDeviceEnum {
     pub const fn from_display(dev: AxDisplayDevice) -> Self {
         Self::Display(dev)
     }
+
+    /// Constructs a 9P transport device.
+    #[cfg(feature = "ninep")]
+    pub const fn from_ninep(dev: AxNinePDevice) -> Self {
+        Self::NineP(dev)
+    }
 }
 
 /// A structure that contains all device drivers of a certain category.
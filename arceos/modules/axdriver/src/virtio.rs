@@ -73,6 +73,79 @@ cfg_if! {
     }
 }
 
+cfg_if! {
+    if #[cfg(ninep_dev = "virtio-9p")] {
+        use virtio_drivers::transport::{DeviceType as VirtIoDevType, Transport};
+
+        pub struct VirtIo9p;
+
+        impl VirtIoDevMeta for VirtIo9p {
+            const DEVICE_TYPE: DeviceType = DeviceType::Char;
+            type Device = crate::ninep::VirtIo9pDev<VirtIoHalImpl, VirtIoTransport>;
+            type Driver = VirtIo9pDriver;
+
+            fn try_new(transport: VirtIoTransport) -> DevResult<AxDeviceEnum> {
+                Ok(AxDeviceEnum::from_ninep(Self::Device::try_new(transport)?))
+            }
+        }
+
+        /// The driver for VirtIO 9P devices, which are not probed by
+        /// `axdriver_virtio`, so the transports are created here.
+        pub struct VirtIo9pDriver;
+
+        impl DriverProbe for VirtIo9pDriver {
+            #[cfg(bus = "mmio")]
+            fn probe_mmio(mmio_base: usize, mmio_size: usize) -> Option<AxDeviceEnum> {
+                use virtio_drivers::transport::mmio::VirtIOHeader;
+
+                let base_vaddr = phys_to_virt(mmio_base.into());
+                let header = NonNull::new(base_vaddr.as_mut_ptr() as *mut VirtIOHeader)?;
+                let transport = unsafe { VirtIoTransport::new(header) }.ok()?;
+                if transport.device_type() != VirtIoDevType::_9P {
+                    return None;
+                }
+                match VirtIo9p::try_new(transport) {
+                    Ok(dev) => Some(dev),
+                    Err(e) => {
+                        warn!(
+                            "failed to initialize MMIO device at [PA:{:#x}, PA:{:#x}): {:?}",
+                            mmio_base,
+                            mmio_base + mmio_size,
+                            e
+                        );
+                        None
+                    }
+                }
+            }
+
+            #[cfg(bus = "pci")]
+            fn probe_pci(
+                root: &mut PciRoot,
+                bdf: DeviceFunction,
+                dev_info: &DeviceFunctionInfo,
+            ) -> Option<AxDeviceEnum> {
+                // the transitional and the modern device IDs
+                if dev_info.vendor_id != 0x1af4 || !matches!(dev_info.device_id, 0x1009 | 0x1049) {
+                    return None;
+                }
+                let transport = VirtIoTransport::new::<VirtIoHalImpl>(root, bdf)
+                    .map_err(|e| warn!("failed to probe PCI device at {}: {:?}", bdf, e))
+                    .ok()?;
+                match VirtIo9p::try_new(transport) {
+                    Ok(dev) => Some(dev),
+                    Err(e) => {
+                        warn!(
+                            "failed to initialize PCI device at {}({}): {:?}",
+                            bdf, dev_info, e
+                        );
+                        None
+                    }
+                }
+            }
+        }
+    }
+}
+
 /// A common driver for all VirtIO devices that implements [`DriverProbe`].
 pub struct VirtIoDriver<D: VirtIoDevMeta + ?Sized>(PhantomData<D>);
 
@@ -0,0 +1,22 @@
+use std::path::Path;
+
+fn main() {
+    println!("cargo:rerun-if-env-changed=AX_INITRAMFS");
+    if std::env::var("CARGO_FEATURE_INITRAMFS").is_err() {
+        return;
+    }
+
+    // Copy the archive to be embedded into `OUT_DIR`, or create an empty one
+    // if `AX_INITRAMFS` is not set.
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    let out_path = Path::new(&out_dir).join("initramfs.cpio");
+    match std::env::var("AX_INITRAMFS") {
+        Ok(path) if !path.is_empty() => {
+            println!("cargo:rerun-if-changed={}", path);
+            if let Err(e) = std::fs::copy(&path, &out_path) {
+                panic!("failed to read the initramfs archive {:?}: {}", path, e);
+            }
+        }
+        _ => std::fs::write(&out_path, []).unwrap(),
+    }
+}
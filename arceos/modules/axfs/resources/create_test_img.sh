@@ -28,3 +28,49 @@ create_test_img() {
 
 create_test_img "$CUR_DIR/fat16.img" 2500 16
 create_test_img "$CUR_DIR/fat32.img" 34000 32
+
+# Fills the directory with the same files as in the FAT images, and a
+# symbolic link
+fill_test_dir() {
+	local root=$1
+	for i in $(seq 1 1000); do
+	  echo "Rust is cool!" >>"$root/long.txt"
+	done
+	echo "Rust is cool!" >>"$root/short.txt"
+	mkdir -p "$root/very/long/path"
+	echo "Rust is cool!" >>"$root/very/long/path/test.txt"
+	mkdir -p "$root/very-long-dir-name"
+	echo "Rust is cool!" >>"$root/very-long-dir-name/very-long-file-name.txt"
+	chmod 644 "$root"/*.txt
+	ln -s "very/long/path/test.txt" "$root/short-link"
+}
+
+# ext2/ext4 images are populated from a directory by `mke2fs -d`, without
+# mounting them
+create_ext_img() {
+	local name=$1
+	local blkcount=$2
+	local fsType=$3
+	local root=$(mktemp -d)
+	fill_test_dir "$root"
+	rm -f "$name"
+	mke2fs -q -t $fsType -b 1024 -U 12345678-1234-1234-1234-123456789abc -d "$root" "$name" ${blkcount}k
+	rm -rf "$root"
+}
+
+create_ext_img "$CUR_DIR/ext2.img" 4096 ext2
+create_ext_img "$CUR_DIR/ext4.img" 8192 ext4
+
+# The initramfs is a cpio archive in the "newc" format, with a hard link and
+# a FIFO in addition
+create_cpio_img() {
+	local name=$(realpath -m "$1")
+	local root=$(mktemp -d)
+	fill_test_dir "$root"
+	ln "$root/short.txt" "$root/very/short-hard-link.txt"
+	mkfifo "$root/fifo"
+	(cd "$root" && find . | cpio -o -H newc --quiet >"$name")
+	rm -rf "$root"
+}
+
+create_cpio_img "$CUR_DIR/initramfs.img"
@@ -1,5 +1,5 @@
 use axio::{prelude::*, Result, SeekFrom};
-use core::fmt;
+use core::{fmt, time::Duration};
 
 use crate::fops;
 
@@ -16,7 +16,7 @@ pub struct File {
 }
 
 /// Metadata information about a file.
-pub struct Metadata(fops::FileAttr);
+pub struct Metadata(pub(super) fops::FileAttr);
 
 /// Options and flags which can be used to configure how a file is opened.
 #[derive(Clone, Debug)]
@@ -90,6 +90,15 @@ impl Metadata {
         self.0.is_file()
     }
 
+    /// Returns `true` if this metadata is for a symbolic link.
+    ///
+    /// It is only possible for metadata returned by
+    /// [`symlink_metadata`](super::symlink_metadata), as other methods follow
+    /// symbolic links.
+    pub const fn is_symlink(&self) -> bool {
+        self.0.is_symlink()
+    }
+
     /// Returns the size of the file, in bytes, this metadata is for.
     #[allow(clippy::len_without_is_empty)]
     pub const fn len(&self) -> u64 {
@@ -110,6 +119,47 @@ impl Metadata {
     pub const fn blocks(&self) -> u64 {
         self.0.blocks()
     }
+
+    /// Returns the number of hard links pointing to this file.
+    pub const fn nlink(&self) -> u64 {
+        self.0.nlink()
+    }
+
+    /// Returns the inode number of the file, or 0 if the filesystem does not
+    /// have inode numbers.
+    pub const fn ino(&self) -> u64 {
+        self.0.ino()
+    }
+
+    /// Returns the user ID of the owner of the file.
+    pub const fn uid(&self) -> u32 {
+        self.0.uid()
+    }
+
+    /// Returns the group ID of the owner of the file.
+    pub const fn gid(&self) -> u32 {
+        self.0.gid()
+    }
+
+    /// Returns the last access time of the file, since the UNIX epoch.
+    pub const fn accessed(&self) -> Duration {
+        self.0.atime()
+    }
+
+    /// Returns the last modification time of the file, since the UNIX epoch.
+    pub const fn modified(&self) -> Duration {
+        self.0.mtime()
+    }
+
+    /// Returns the last status change time of the file, since the UNIX epoch.
+    pub const fn changed(&self) -> Duration {
+        self.0.ctime()
+    }
+
+    /// Returns the raw attributes of the file.
+    pub const fn raw_metadata(&self) -> &fops::FileAttr {
+        &self.0
+    }
 }
 
 impl fmt::Debug for Metadata {
@@ -162,6 +212,59 @@ impl File {
     pub fn metadata(&self) -> Result<Metadata> {
         self.inner.get_attr().map(Metadata)
     }
+
+    /// Changes the permissions on the underlying file.
+    pub fn set_permissions(&self, perm: Permissions) -> Result<()> {
+        self.inner.set_attr(&fops::SetAttr {
+            perm: Some(perm),
+            ..Default::default()
+        })
+    }
+
+    /// Changes the last modification time of the underlying file.
+    pub fn set_modified(&self, time: Duration) -> Result<()> {
+        self.inner.set_attr(&fops::SetAttr {
+            mtime: Some(time),
+            ..Default::default()
+        })
+    }
+
+    /// Acquires an exclusive advisory lock on the file, blocking until it can
+    /// be acquired.
+    pub fn lock(&self) -> Result<()> {
+        self.inner
+            .locks()
+            .flock(Some(fops::LockKind::Exclusive), true)
+    }
+
+    /// Acquires a shared advisory lock on the file, blocking until it can be
+    /// acquired.
+    pub fn lock_shared(&self) -> Result<()> {
+        self.inner.locks().flock(Some(fops::LockKind::Shared), true)
+    }
+
+    /// Tries to acquire an exclusive advisory lock on the file. Returns
+    /// [`WouldBlock`](axio::Error::WouldBlock) if it is held by others.
+    pub fn try_lock(&self) -> Result<()> {
+        self.inner
+            .locks()
+            .flock(Some(fops::LockKind::Exclusive), false)
+    }
+
+    /// Tries to acquire a shared advisory lock on the file. Returns
+    /// [`WouldBlock`](axio::Error::WouldBlock) if an exclusive lock is held
+    /// by others.
+    pub fn try_lock_shared(&self) -> Result<()> {
+        self.inner
+            .locks()
+            .flock(Some(fops::LockKind::Shared), false)
+    }
+
+    /// Releases the advisory lock on the file, which is also released when the
+    /// file is closed.
+    pub fn unlock(&self) -> Result<()> {
+        self.inner.locks().flock(None, false)
+    }
 }
 
 impl Read for File {
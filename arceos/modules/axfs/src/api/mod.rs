@@ -6,8 +6,15 @@ mod file;
 pub use self::dir::{DirBuilder, DirEntry, ReadDir};
 pub use self::file::{File, FileType, Metadata, OpenOptions, Permissions};
 
-use alloc::{string::String, vec::Vec};
+use alloc::{string::String, sync::Arc, vec::Vec};
+use axfs_vfs::VfsOps;
 use axio::{self as io, prelude::*};
+use core::time::Duration;
+
+use crate::fops::{Credentials, FileSystemInfo, SetAttr};
+
+/// The credentials of the operations in this module.
+static ROOT_CRED: Credentials = Credentials::root();
 
 /// Returns an iterator over the entries within a directory.
 pub fn read_dir(path: &str) -> io::Result<ReadDir> {
@@ -27,7 +34,7 @@ pub fn current_dir() -> io::Result<String> {
 
 /// Changes the current working directory to the specified path.
 pub fn set_current_dir(path: &str) -> io::Result<()> {
-    crate::root::set_current_dir(path)
+    crate::root::set_current_dir(path, &ROOT_CRED)
 }
 
 /// Read the entire contents of a file into a bytes vector.
@@ -59,6 +66,101 @@ pub fn metadata(path: &str) -> io::Result<Metadata> {
     File::open(path)?.metadata()
 }
 
+/// Query the metadata about a file without following symbolic links.
+pub fn symlink_metadata(path: &str) -> io::Result<Metadata> {
+    crate::root::lookup_no_follow(None, path, &ROOT_CRED)?
+        .get_attr()
+        .map(Metadata)
+}
+
+/// Changes the permissions found on a file or a directory.
+pub fn set_permissions(path: &str, perm: Permissions) -> io::Result<()> {
+    set_attr(
+        path,
+        SetAttr {
+            perm: Some(perm),
+            ..Default::default()
+        },
+    )
+}
+
+/// Changes the owner and group of a file or a directory. The ID which is
+/// `None` is left unchanged.
+pub fn chown(path: &str, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+    set_attr(
+        path,
+        SetAttr {
+            uid,
+            gid,
+            ..Default::default()
+        },
+    )
+}
+
+/// Changes the last access and modification times of a file or a directory.
+/// The time which is `None` is left unchanged.
+pub fn set_times(
+    path: &str,
+    accessed: Option<Duration>,
+    modified: Option<Duration>,
+) -> io::Result<()> {
+    set_attr(
+        path,
+        SetAttr {
+            atime: accessed,
+            mtime: modified,
+            ..Default::default()
+        },
+    )
+}
+
+fn set_attr(path: &str, attr: SetAttr) -> io::Result<()> {
+    let node = crate::root::lookup(None, path, &ROOT_CRED)?;
+    ROOT_CRED.check_set_attr(&node.get_attr()?, &attr)?;
+    node.set_attr(&attr)
+}
+
+/// Returns the attributes of the filesystem which `path` is located in, such
+/// as the total and free space.
+pub fn statfs(path: &str) -> io::Result<FileSystemInfo> {
+    crate::root::lookup(None, path, &ROOT_CRED)?;
+    crate::root::statfs(crate::root::mount_point_of(path)?.as_ref())
+}
+
+/// Returns the volume label of the filesystem which `path` is located in, or
+/// `None` if it has no label.
+pub fn volume_label(path: &str) -> io::Result<Option<String>> {
+    crate::root::lookup(None, path, &ROOT_CRED)?;
+    Ok(crate::root::fs_of(crate::root::mount_point_of(path)?.as_ref()).label())
+}
+
+/// Writes the cached data and metadata of all filesystems back to their
+/// devices, so that they are consistent on the devices.
+pub fn sync() -> io::Result<()> {
+    crate::root::sync_all()
+}
+
+/// Creates a new symbolic link at `link` which points to `original`.
+///
+/// The `original` path is stored as is, it is not required to exist.
+pub fn symlink(original: &str, link: &str) -> io::Result<()> {
+    crate::root::create_symlink(None, original, link, &ROOT_CRED)
+}
+
+/// Creates a new hard link at `link` which refers to the same file as
+/// `original`.
+///
+/// Both paths must be located in the same mounted fs, and `original` must
+/// not be a directory.
+pub fn hard_link(original: &str, link: &str) -> io::Result<()> {
+    crate::root::create_link(None, original, link, &ROOT_CRED)
+}
+
+/// Reads a symbolic link, returning the path that the link points to.
+pub fn read_link(path: &str) -> io::Result<String> {
+    crate::root::read_link(None, path, &ROOT_CRED)
+}
+
 /// Creates a new, empty directory at the provided path.
 pub fn create_dir(path: &str) -> io::Result<()> {
     DirBuilder::new().create(path)
@@ -72,18 +174,63 @@ pub fn create_dir_all(path: &str) -> io::Result<()> {
 
 /// Removes an empty directory.
 pub fn remove_dir(path: &str) -> io::Result<()> {
-    crate::root::remove_dir(None, path)
+    crate::root::remove_dir(None, path, &ROOT_CRED)
 }
 
 /// Removes a file from the filesystem.
 pub fn remove_file(path: &str) -> io::Result<()> {
-    crate::root::remove_file(None, path)
+    crate::root::remove_file(None, path, &ROOT_CRED)
 }
 
 /// Rename a file or directory to a new name.
-/// Delete the original file if `old` already exists.
+/// Replace the original file if `new` already exists.
 ///
-/// This only works then the new path is in the same mounted fs.
+/// This only works then the new path is in the same mounted fs, otherwise
+/// [`CrossesDevices`](io::Error::CrossesDevices) is returned.
 pub fn rename(old: &str, new: &str) -> io::Result<()> {
-    crate::root::rename(old, new)
+    crate::root::rename(old, new, &ROOT_CRED)
+}
+
+/// Mounts the filesystem `fs` at the absolute `path`.
+///
+/// The mount point directory is created if it does not exist. Mount points
+/// can be nested, e.g. `/mnt` and `/mnt/usb`.
+pub fn mount(path: &str, fs: Arc<dyn VfsOps>) -> io::Result<()> {
+    crate::root::mount(path, fs, false)
+}
+
+/// Mounts the filesystem `fs` at the absolute `path` read-only, so that its
+/// files can not be modified, created or removed.
+///
+/// Such modifications fail with
+/// [`ReadOnlyFilesystem`](io::Error::ReadOnlyFilesystem).
+pub fn mount_read_only(path: &str, fs: Arc<dyn VfsOps>) -> io::Result<()> {
+    crate::root::mount(path, fs, true)
+}
+
+/// Mounts the filesystem on the block device or partition `dev` (e.g.,
+/// `vda0p2`) at the absolute `path`.
+///
+/// `fs_type` is the name of a built-in or custom filesystem type, or `None` to
+/// detect the built-in one on the device. If it is `9p`, `dev` is the mount tag
+/// of a [registered](crate::fops::register_ninep_device) 9P device instead.
+/// It is mounted read-only if `read_only` is true. Returns
+/// [`NotFound`](io::Error::NotFound) if the device or filesystem type does not
+/// exist.
+pub fn mount_device(
+    dev: &str,
+    path: &str,
+    fs_type: Option<&str>,
+    read_only: bool,
+) -> io::Result<()> {
+    crate::root::mount(path, crate::fs::new_device_fs(dev, fs_type)?, read_only)
+}
+
+/// Unmounts the filesystem mounted at the absolute `path`.
+///
+/// It fails with [`ResourceBusy`](io::Error::ResourceBusy) if the filesystem
+/// has opened files or directories, contains the current directory, or has
+/// other filesystems mounted under it.
+pub fn umount(path: &str) -> io::Result<()> {
+    crate::root::umount(path)
 }
@@ -0,0 +1,257 @@
+//! Write-back cache of disk blocks.
+//!
+//! Each block device has a cache shared by all the [`Disk`]s on it. Blocks are
+//! evicted in least-recently-used order when the number of cached blocks
+//! exceeds the [capacity](set_capacity), and dirty blocks are written back to
+//! the device when they are evicted, when a file is flushed, when a
+//! filesystem is unmounted, or periodically by a background task (only with
+//! the `multitask` feature).
+//!
+//! [`Disk`]: crate::fops::Disk
+
+use alloc::{boxed::Box, collections::BTreeMap, sync::Arc, sync::Weak, vec::Vec};
+use core::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+
+use axdriver::prelude::*;
+use axerrno::{ax_err, AxResult};
+use axsync::Mutex;
+
+const BLOCK_SIZE: usize = 512;
+
+/// The default capacity of each cache in blocks (512 KiB).
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// The interval of the periodic write-back.
+#[cfg(feature = "multitask")]
+const WRITEBACK_INTERVAL: core::time::Duration = core::time::Duration::from_secs(5);
+
+static CAPACITY: AtomicUsize = AtomicUsize::new(DEFAULT_CAPACITY);
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+static CACHES: Mutex<Vec<Weak<BlockCache>>> = Mutex::new(Vec::new());
+
+/// Statistics of the block caches, summed over all block devices.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CacheStats {
+    /// Number of block accesses served from the cache.
+    pub hits: u64,
+    /// Number of block accesses that missed the cache.
+    pub misses: u64,
+    /// Number of blocks in the cache.
+    pub cached: usize,
+    /// Number of cached blocks not yet written back.
+    pub dirty: usize,
+}
+
+/// Returns the capacity of each cache in blocks.
+pub fn capacity() -> usize {
+    CAPACITY.load(Ordering::Relaxed)
+}
+
+/// Sets the capacity of each cache in blocks.
+///
+/// A cache larger than the new capacity shrinks on its next access. Returns
+/// `false` if `blocks` is 0.
+pub fn set_capacity(blocks: usize) -> bool {
+    if blocks == 0 {
+        return false;
+    }
+    CAPACITY.store(blocks, Ordering::Relaxed);
+    true
+}
+
+/// Returns the statistics of the block caches.
+pub fn stats() -> CacheStats {
+    let mut stats = CacheStats {
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+        cached: 0,
+        dirty: 0,
+    };
+    for cache in caches() {
+        let inner = cache.inner.lock();
+        stats.cached += inner.blocks.len();
+        stats.dirty += inner.dirty;
+    }
+    stats
+}
+
+/// Writes all dirty blocks back to the block devices.
+pub fn flush_all() -> AxResult {
+    for cache in caches() {
+        if cache.flush().is_err() {
+            return ax_err!(Io, "failed to write back the block cache");
+        }
+    }
+    Ok(())
+}
+
+/// Writes all dirty blocks back to the block devices, and drops all cached
+/// blocks, e.g., to free the memory of the blocks that will not be read soon.
+pub fn drop_all() -> AxResult {
+    for cache in caches() {
+        let mut inner = cache.inner.lock();
+        if inner.flush().is_err() || inner.evict(0).is_err() {
+            return ax_err!(Io, "failed to write back the block cache");
+        }
+    }
+    Ok(())
+}
+
+/// Spawns the background task that writes back dirty blocks periodically.
+#[cfg(feature = "multitask")]
+pub(crate) fn spawn_writeback_task() {
+    axtask::spawn(|| loop {
+        axtask::sleep(WRITEBACK_INTERVAL);
+        if let Err(e) = flush_all() {
+            warn!("periodic write-back failed: {:?}", e);
+        }
+    });
+}
+
+fn caches() -> Vec<Arc<BlockCache>> {
+    CACHES.lock().iter().filter_map(Weak::upgrade).collect()
+}
+
+struct CachedBlock {
+    data: Box<[u8; BLOCK_SIZE]>,
+    dirty: bool,
+    stamp: u64,
+}
+
+struct CacheInner {
+    dev: AxBlockDevice,
+    blocks: BTreeMap<u64, CachedBlock>,
+    /// Block IDs ordered by the time of last use.
+    lru: BTreeMap<u64, u64>,
+    clock: u64,
+    dirty: usize,
+}
+
+impl CacheInner {
+    /// Returns the cached block `block_id`, loading it from the device if
+    /// `load` is true or zeroing it otherwise on a miss.
+    fn get(&mut self, block_id: u64, load: bool) -> DevResult<&mut CachedBlock> {
+        self.clock += 1;
+        let stamp = self.clock;
+        if let Some(block) = self.blocks.get_mut(&block_id) {
+            HITS.fetch_add(1, Ordering::Relaxed);
+            self.lru.remove(&block.stamp);
+            self.lru.insert(stamp, block_id);
+            block.stamp = stamp;
+        } else {
+            MISSES.fetch_add(1, Ordering::Relaxed);
+            let mut data = Box::new([0; BLOCK_SIZE]);
+            if load {
+                self.dev.read_block(block_id, data.as_mut_slice())?;
+            }
+            self.evict(capacity() - 1)?;
+            let block = CachedBlock {
+                data,
+                dirty: false,
+                stamp,
+            };
+            self.blocks.insert(block_id, block);
+            self.lru.insert(stamp, block_id);
+        }
+        Ok(self.blocks.get_mut(&block_id).unwrap())
+    }
+
+    /// Marks the block as dirty after it is modified.
+    fn set_dirty(&mut self, block_id: u64) {
+        let block = self.blocks.get_mut(&block_id).unwrap();
+        if !block.dirty {
+            block.dirty = true;
+            self.dirty += 1;
+        }
+    }
+
+    /// Evicts the least recently used blocks until at most `max` are left.
+    fn evict(&mut self, max: usize) -> DevResult {
+        while self.blocks.len() > max {
+            let Some((&stamp, &block_id)) = self.lru.first_key_value() else {
+                break;
+            };
+            let block = &self.blocks[&block_id];
+            if block.dirty {
+                self.dev.write_block(block_id, block.data.as_slice())?;
+                self.dirty -= 1;
+            }
+            self.lru.remove(&stamp);
+            self.blocks.remove(&block_id);
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> DevResult {
+        if self.dirty > 0 {
+            for (&block_id, block) in self.blocks.iter_mut().filter(|(_, b)| b.dirty) {
+                self.dev.write_block(block_id, block.data.as_slice())?;
+                block.dirty = false;
+                self.dirty -= 1;
+            }
+        }
+        self.dev.flush()
+    }
+}
+
+/// The block cache of a block device.
+pub(crate) struct BlockCache {
+    num_blocks: u64,
+    inner: Mutex<CacheInner>,
+}
+
+impl BlockCache {
+    pub fn new(dev: AxBlockDevice) -> Arc<Self> {
+        assert_eq!(BLOCK_SIZE, dev.block_size());
+        let cache = Arc::new(Self {
+            num_blocks: dev.num_blocks(),
+            inner: Mutex::new(CacheInner {
+                dev,
+                blocks: BTreeMap::new(),
+                lru: BTreeMap::new(),
+                clock: 0,
+                dirty: 0,
+            }),
+        });
+        let mut caches = CACHES.lock();
+        caches.retain(|c| c.strong_count() > 0);
+        caches.push(Arc::downgrade(&cache));
+        cache
+    }
+
+    pub fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    /// Reads `buf.len()` bytes at `offset` within the block `block_id`.
+    pub fn read_block(&self, block_id: u64, offset: usize, buf: &mut [u8]) -> DevResult {
+        let mut inner = self.inner.lock();
+        let block = inner.get(block_id, true)?;
+        buf.copy_from_slice(&block.data[offset..offset + buf.len()]);
+        Ok(())
+    }
+
+    /// Writes `buf` at `offset` within the block `block_id`.
+    pub fn write_block(&self, block_id: u64, offset: usize, buf: &[u8]) -> DevResult {
+        let mut inner = self.inner.lock();
+        // no need to read the block if it is overwritten entirely
+        let block = inner.get(block_id, buf.len() < BLOCK_SIZE)?;
+        block.data[offset..offset + buf.len()].copy_from_slice(buf);
+        inner.set_dirty(block_id);
+        Ok(())
+    }
+
+    /// Writes all dirty blocks back to the device.
+    pub fn flush(&self) -> DevResult {
+        self.inner.lock().flush()
+    }
+}
+
+impl Drop for BlockCache {
+    fn drop(&mut self) {
+        if self.inner.get_mut().flush().is_err() {
+            warn!("failed to write back the block cache");
+        }
+    }
+}
@@ -1,28 +1,99 @@
-use axdriver::prelude::*;
+use alloc::{format, string::String, sync::Arc, vec::Vec};
+use axdriver::{prelude::*, AxDeviceContainer};
+#[cfg(feature = "myfs")]
+use axerrno::{ax_err, AxError, AxResult};
+use lazyinit::LazyInit;
+
+use crate::cache::BlockCache;
 
 const BLOCK_SIZE: usize = 512;
 
+/// The probed disks with their names, see [`probe_disks`].
+static DISKS: LazyInit<Vec<(String, Disk)>> = LazyInit::new();
+
+/// A block device which filesystems are created on, i.e., a whole device or
+/// one of its partitions.
+///
+/// Block IDs are relative to the start of the device, and accesses go through
+/// the block cache.
+#[cfg(feature = "myfs")]
+pub trait BlockDevice: Send + Sync {
+    /// The size of a block in bytes.
+    fn block_size(&self) -> usize;
+
+    /// The number of blocks.
+    fn num_blocks(&self) -> u64;
+
+    /// Reads the blocks starting from `block_id` into `buf`, whose length
+    /// must be a multiple of the block size.
+    fn read_blocks(&self, block_id: u64, buf: &mut [u8]) -> AxResult;
+
+    /// Writes `buf` to the blocks starting from `block_id`, whose length must
+    /// be a multiple of the block size.
+    fn write_blocks(&self, block_id: u64, buf: &[u8]) -> AxResult;
+
+    /// Writes all dirty blocks in the cache back to the device.
+    fn flush(&self) -> AxResult;
+}
+
 /// A disk device with a cursor.
+///
+/// A disk covers either the whole device or one of its partitions. Accesses
+/// go through the block cache of the device, which is shared by the disks
+/// created by [`Disk::try_clone`] and [`Disk::partition`], each of which has
+/// its own cursor.
 pub struct Disk {
     block_id: u64,
     offset: usize,
-    dev: AxBlockDevice,
+    start_block: u64,
+    num_blocks: u64,
+    cache: Arc<BlockCache>,
 }
 
 impl Disk {
     /// Create a new disk.
     pub fn new(dev: AxBlockDevice) -> Self {
-        assert_eq!(BLOCK_SIZE, dev.block_size());
+        let cache = BlockCache::new(dev);
+        Self {
+            block_id: 0,
+            offset: 0,
+            start_block: 0,
+            num_blocks: cache.num_blocks(),
+            cache,
+        }
+    }
+
+    /// Create a new disk on the same device, with the cursor at the start.
+    pub fn try_clone(&self) -> Self {
+        Self {
+            block_id: 0,
+            offset: 0,
+            start_block: self.start_block,
+            num_blocks: self.num_blocks,
+            cache: self.cache.clone(),
+        }
+    }
+
+    /// Create a new disk on the `num_blocks` blocks starting from
+    /// `start_block` of this disk, with the cursor at the start.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the range exceeds this disk.
+    pub fn partition(&self, start_block: u64, num_blocks: u64) -> Self {
+        assert!(start_block + num_blocks <= self.num_blocks);
         Self {
             block_id: 0,
             offset: 0,
-            dev,
+            start_block: self.start_block + start_block,
+            num_blocks,
+            cache: self.cache.clone(),
         }
     }
 
     /// Get the size of the disk.
     pub fn size(&self) -> u64 {
-        self.dev.num_blocks() * BLOCK_SIZE as u64
+        self.num_blocks * BLOCK_SIZE as u64
     }
 
     /// Get the position of the cursor.
@@ -38,55 +109,133 @@ impl Disk {
 
     /// Read within one block, returns the number of bytes read.
     pub fn read_one(&mut self, buf: &mut [u8]) -> DevResult<usize> {
-        let read_size = if self.offset == 0 && buf.len() >= BLOCK_SIZE {
-            // whole block
-            self.dev
-                .read_block(self.block_id, &mut buf[0..BLOCK_SIZE])?;
-            self.block_id += 1;
-            BLOCK_SIZE
-        } else {
-            // partial block
-            let mut data = [0u8; BLOCK_SIZE];
-            let start = self.offset;
-            let count = buf.len().min(BLOCK_SIZE - self.offset);
-
-            self.dev.read_block(self.block_id, &mut data)?;
-            buf[..count].copy_from_slice(&data[start..start + count]);
-
-            self.offset += count;
-            if self.offset >= BLOCK_SIZE {
-                self.block_id += 1;
-                self.offset -= BLOCK_SIZE;
-            }
-            count
-        };
-        Ok(read_size)
+        let block_id = self.device_block_id()?;
+        let count = buf.len().min(BLOCK_SIZE - self.offset);
+        self.cache
+            .read_block(block_id, self.offset, &mut buf[..count])?;
+        self.advance(count);
+        Ok(count)
     }
 
     /// Write within one block, returns the number of bytes written.
     pub fn write_one(&mut self, buf: &[u8]) -> DevResult<usize> {
-        let write_size = if self.offset == 0 && buf.len() >= BLOCK_SIZE {
-            // whole block
-            self.dev.write_block(self.block_id, &buf[0..BLOCK_SIZE])?;
-            self.block_id += 1;
-            BLOCK_SIZE
+        let block_id = self.device_block_id()?;
+        let count = buf.len().min(BLOCK_SIZE - self.offset);
+        self.cache
+            .write_block(block_id, self.offset, &buf[..count])?;
+        self.advance(count);
+        Ok(count)
+    }
+
+    /// Write all dirty blocks in the cache back to the device.
+    pub fn flush(&mut self) -> DevResult {
+        self.cache.flush()
+    }
+
+    /// Returns the block ID on the device of the cursor.
+    fn device_block_id(&self) -> DevResult<u64> {
+        if self.block_id < self.num_blocks {
+            Ok(self.start_block + self.block_id)
         } else {
-            // partial block
-            let mut data = [0u8; BLOCK_SIZE];
-            let start = self.offset;
-            let count = buf.len().min(BLOCK_SIZE - self.offset);
-
-            self.dev.read_block(self.block_id, &mut data)?;
-            data[start..start + count].copy_from_slice(&buf[..count]);
-            self.dev.write_block(self.block_id, &data)?;
-
-            self.offset += count;
-            if self.offset >= BLOCK_SIZE {
-                self.block_id += 1;
-                self.offset -= BLOCK_SIZE;
-            }
-            count
-        };
-        Ok(write_size)
+            Err(DevError::InvalidParam)
+        }
+    }
+
+    fn advance(&mut self, count: usize) {
+        self.offset += count;
+        if self.offset >= BLOCK_SIZE {
+            self.block_id += 1;
+            self.offset -= BLOCK_SIZE;
+        }
+    }
+
+    /// Checks that `buf` covers whole blocks within the disk starting from
+    /// `block_id`, returns the block ID on the device.
+    #[cfg(feature = "myfs")]
+    fn check_range(&self, block_id: u64, buf: &[u8]) -> AxResult<u64> {
+        let count = (buf.len() / BLOCK_SIZE) as u64;
+        if buf.len() % BLOCK_SIZE != 0 {
+            return ax_err!(InvalidInput, "buffer is not block-aligned");
+        }
+        match block_id.checked_add(count) {
+            Some(end) if end <= self.num_blocks => Ok(self.start_block + block_id),
+            _ => ax_err!(InvalidInput, "blocks out of range"),
+        }
+    }
+}
+
+#[cfg(feature = "myfs")]
+impl BlockDevice for Disk {
+    fn block_size(&self) -> usize {
+        BLOCK_SIZE
+    }
+
+    fn num_blocks(&self) -> u64 {
+        self.num_blocks
+    }
+
+    fn read_blocks(&self, block_id: u64, buf: &mut [u8]) -> AxResult {
+        let start = self.check_range(block_id, buf)?;
+        for (i, block) in buf.chunks_exact_mut(BLOCK_SIZE).enumerate() {
+            self.cache
+                .read_block(start + i as u64, 0, block)
+                .map_err(|_| AxError::Io)?;
+        }
+        Ok(())
+    }
+
+    fn write_blocks(&self, block_id: u64, buf: &[u8]) -> AxResult {
+        let start = self.check_range(block_id, buf)?;
+        for (i, block) in buf.chunks_exact(BLOCK_SIZE).enumerate() {
+            self.cache
+                .write_block(start + i as u64, 0, block)
+                .map_err(|_| AxError::Io)?;
+        }
+        Ok(())
+    }
+
+    fn flush(&self) -> AxResult {
+        self.cache.flush().map_err(|_| AxError::Io)
+    }
+}
+
+/// Probes all block devices and their partitions, and records the disks with
+/// their names, i.e., `vdaN` for the `N`-th device and `vdaNpM` for its
+/// `M`-th partition.
+pub(crate) fn probe_disks(blk_devs: AxDeviceContainer<AxBlockDevice>) -> &'static [(String, Disk)] {
+    DISKS.init_once(probe_all(blk_devs))
+}
+
+/// Returns a new disk on the block device or partition named `name`.
+pub(crate) fn find_disk(name: &str) -> Option<Disk> {
+    let disks = DISKS.get()?;
+    let (_, disk) = disks.iter().find(|(n, _)| n == name)?;
+    Some(disk.try_clone())
+}
+
+fn probe_all(mut blk_devs: AxDeviceContainer<AxBlockDevice>) -> Vec<(String, Disk)> {
+    let mut disks = Vec::new();
+    let mut idx = 0;
+    while let Some(dev) = blk_devs.take_one() {
+        let name = format!("vda{}", idx);
+        info!("  found block device {}: {:?}", name, dev.device_name());
+        let disk = Disk::new(dev);
+        let parts = crate::partition::parse(&disk).unwrap_or_else(|e| {
+            warn!("  failed to read the partition table of {}: {:?}", name, e);
+            Vec::new()
+        });
+        for part in parts {
+            let part_name = format!("{}p{}", name, part.number);
+            info!(
+                "    partition {}: blocks {}..{}",
+                part_name,
+                part.start,
+                part.start + part.num_blocks
+            );
+            disks.push((part_name, disk.partition(part.start, part.num_blocks)));
+        }
+        disks.push((name, disk));
+        idx += 1;
     }
+    disks
 }
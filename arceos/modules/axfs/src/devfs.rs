@@ -0,0 +1,250 @@
+//! Device files in devfs (mounted on `/dev`).
+//!
+//! The following devices are provided by default:
+//!
+//! - `null`, `zero`: see [`axfs_devfs`].
+//! - `console`, `tty`: the console of the platform. Reads block until at
+//!   least one byte is available, unless the file is opened non-blocking.
+//! - `random`, `urandom`: an endless stream of pseudo-random bytes, which are
+//!   **not** cryptographically secure.
+//! - `rtc`: the wall-clock time in seconds since the Unix epoch, as text.
+//! - `vdaN`, `vdaNpM`: raw access to the `N`-th block device and its `M`-th
+//!   partition, including the one that the root filesystem resides on.
+//!
+//! Drivers can add their own device nodes at initialization, e.g.:
+//!
+//! ```no_run
+//! use std::sync::Arc;
+//!
+//! axfs::devfs::register_device("misc/zero", Arc::new(axfs_devfs::ZeroDev)).unwrap();
+//! ```
+
+use alloc::{format, string::String, sync::Arc};
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axerrno::{ax_err, AxResult};
+use axfs_vfs::{
+    VfsError, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeRef, VfsNodeType, VfsResult,
+};
+use axsync::Mutex;
+use lazyinit::LazyInit;
+
+use crate::dev::Disk;
+use crate::fs::pseudofs::PseudoFileSystem;
+
+static DEVFS: LazyInit<Arc<PseudoFileSystem>> = LazyInit::new();
+
+pub(crate) fn init(devfs: Arc<PseudoFileSystem>) -> Arc<PseudoFileSystem> {
+    DEVFS.init_once(devfs).clone()
+}
+
+/// Registers a device node at `path` (relative to `/dev`).
+///
+/// Missing parent directories are created. Returns
+/// [`AlreadyExists`](axerrno::AxError::AlreadyExists) if `path` exists, or
+/// [`BadState`](axerrno::AxError::BadState) if the filesystems have not been
+/// initialized.
+pub fn register_device(path: &str, node: VfsNodeRef) -> AxResult {
+    let Some(devfs) = DEVFS.get() else {
+        return ax_err!(BadState, "devfs is not initialized");
+    };
+    devfs.root_dir_node().add_path(path, node)
+}
+
+/// Registers the raw block device nodes for `disks` with their names.
+pub(crate) fn register_block_devices(disks: &[(String, Disk)]) {
+    for (name, disk) in disks {
+        let node = Arc::new(BlockDev(Mutex::new(disk.try_clone())));
+        if let Err(e) = register_device(name, node) {
+            warn!("failed to register /dev/{}: {:?}", name, e);
+        }
+    }
+}
+
+/// The console device (`/dev/console` and `/dev/tty`).
+pub(crate) struct ConsoleDev;
+
+impl ConsoleDev {
+    fn getchar() -> Option<u8> {
+        axhal::console::getchar().map(|c| if c == b'\r' { b'\n' } else { c })
+    }
+}
+
+impl VfsNodeOps for ConsoleDev {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::default_file(),
+            VfsNodeType::CharDevice,
+            0,
+            0,
+        ))
+    }
+
+    /// Reads the available input, or fails with `WouldBlock` if there is
+    /// none. Blocking reads are retried by [`File`](crate::fops::File).
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        let Some(first) = Self::getchar() else {
+            return Err(VfsError::WouldBlock);
+        };
+        buf[0] = first;
+        let mut read_len = 1;
+        while read_len < buf.len() {
+            match Self::getchar() {
+                Some(c) => {
+                    buf[read_len] = c;
+                    read_len += 1;
+                }
+                None => break,
+            }
+        }
+        Ok(read_len)
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        axhal::console::write_bytes(buf);
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        Ok(())
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+/// The random number device (`/dev/random` and `/dev/urandom`).
+///
+/// It generates bytes by SplitMix64, mixed with the monotonic time. Data
+/// written to it is discarded.
+pub(crate) struct RandomDev;
+
+impl RandomDev {
+    fn next_u64() -> u64 {
+        static STATE: AtomicU64 = AtomicU64::new(0);
+        let mut z = STATE
+            .fetch_add(0x9e37_79b9_7f4a_7c15, Ordering::Relaxed)
+            .wrapping_add(axhal::time::monotonic_time_nanos());
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+impl VfsNodeOps for RandomDev {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::default_file(),
+            VfsNodeType::CharDevice,
+            0,
+            0,
+        ))
+    }
+
+    fn read_at(&self, _offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        for chunk in buf.chunks_mut(8) {
+            chunk.copy_from_slice(&Self::next_u64().to_ne_bytes()[..chunk.len()]);
+        }
+        Ok(buf.len())
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        Ok(())
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+/// The real-time clock device (`/dev/rtc`).
+pub(crate) struct RtcDev;
+
+impl VfsNodeOps for RtcDev {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::from_bits_truncate(0o444),
+            VfsNodeType::CharDevice,
+            0,
+            0,
+        ))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let content = format!("{}\n", axhal::time::wall_time().as_secs());
+        let start = content.len().min(offset as usize);
+        let end = content.len().min(start + buf.len());
+        let src = &content.as_bytes()[start..end];
+        buf[..src.len()].copy_from_slice(src);
+        Ok(src.len())
+    }
+
+    /// The clock cannot be set, even by the superuser.
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> VfsResult<usize> {
+        Err(VfsError::PermissionDenied)
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        Err(VfsError::PermissionDenied)
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+/// A raw block device (`/dev/vdaN`).
+struct BlockDev(Mutex<Disk>);
+
+impl VfsNodeOps for BlockDev {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let size = self.0.lock().size();
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::from_bits_truncate(0o660),
+            VfsNodeType::BlockDevice,
+            size,
+            size.div_ceil(512),
+        ))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let mut disk = self.0.lock();
+        let len = disk.size().saturating_sub(offset).min(buf.len() as u64) as usize;
+        disk.set_position(offset);
+        let mut read_len = 0;
+        while read_len < len {
+            read_len += disk
+                .read_one(&mut buf[read_len..len])
+                .map_err(|_| VfsError::Io)?;
+        }
+        Ok(read_len)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let mut disk = self.0.lock();
+        if offset >= disk.size() && !buf.is_empty() {
+            return Err(VfsError::StorageFull);
+        }
+        let len = disk.size().saturating_sub(offset).min(buf.len() as u64) as usize;
+        disk.set_position(offset);
+        let mut write_len = 0;
+        while write_len < len {
+            write_len += disk
+                .write_one(&buf[write_len..len])
+                .map_err(|_| VfsError::Io)?;
+        }
+        Ok(write_len)
+    }
+
+    fn fsync(&self) -> VfsResult {
+        self.0.lock().flush().map_err(|_| VfsError::Io)
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        Ok(())
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
@@ -1,15 +1,23 @@
 //! Low-level filesystem operations.
 
+use alloc::{sync::Arc, vec::Vec};
 use axerrno::{ax_err, ax_err_type, AxError, AxResult};
 use axfs_vfs::{VfsError, VfsNodeRef};
 use axio::SeekFrom;
 use cap_access::{Cap, WithCap};
 use core::fmt;
 
+use crate::root::MountPoint;
+
 #[cfg(feature = "myfs")]
-pub use crate::dev::Disk;
+pub use crate::dev::{BlockDevice, Disk};
 #[cfg(feature = "myfs")]
-pub use crate::fs::myfs::MyFileSystemIf;
+pub use crate::fs::myfs::{register_filesystem, FileSystemType, MyFileSystem, MyFileSystemIf};
+#[cfg(feature = "ninepfs")]
+pub use crate::fs::ninepfs::{register_ninep_device, NinePFileSystem};
+pub use crate::fs::overlay::OverlayFs;
+pub use crate::lock::{FileLocks, LockKind, RecordLock};
+pub use crate::notify::{WatchEvent, WatchMask, Watcher, MAX_QUEUED_EVENTS};
 
 /// Alias of [`axfs_vfs::VfsNodeType`].
 pub type FileType = axfs_vfs::VfsNodeType;
@@ -19,12 +27,132 @@ pub type DirEntry = axfs_vfs::VfsDirEntry;
 pub type FileAttr = axfs_vfs::VfsNodeAttr;
 /// Alias of [`axfs_vfs::VfsNodePerm`].
 pub type FilePerm = axfs_vfs::VfsNodePerm;
+/// Alias of [`axfs_vfs::VfsSetAttr`].
+pub type SetAttr = axfs_vfs::VfsSetAttr;
+/// Alias of [`axfs_vfs::FileSystemInfo`].
+pub type FileSystemInfo = axfs_vfs::FileSystemInfo;
+
+/// The identity of the caller of filesystem operations, whose access to files
+/// is checked against their owner, group and permission bits.
+///
+/// Uid 0 is the superuser, which can read and write any file, search any
+/// directory and change the attributes of any file. It can only execute
+/// files with at least one execute bit, as in Unix.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Credentials {
+    /// The user ID.
+    pub uid: u32,
+    /// The primary group ID.
+    pub gid: u32,
+    /// The supplementary group IDs.
+    pub groups: Vec<u32>,
+}
+
+impl Credentials {
+    /// The credentials of uid 0 and gid 0, which own the files created
+    /// without credentials. It is the default of [`OpenOptions`].
+    pub const fn root() -> Self {
+        Self::new(0, 0, Vec::new())
+    }
+
+    /// Creates credentials with the user ID, the primary group ID and the
+    /// supplementary group IDs.
+    pub const fn new(uid: u32, gid: u32, groups: Vec<u32>) -> Self {
+        Self { uid, gid, groups }
+    }
+
+    /// Whether the user is a member of the group `gid`.
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.groups.contains(&gid)
+    }
+
+    /// Whether the user is the superuser.
+    pub const fn is_root(&self) -> bool {
+        self.uid == 0
+    }
+
+    /// Returns the capabilities granted to the user by the permission bits of
+    /// a file with the attributes `attr`.
+    ///
+    /// Only the bits of the first matching class (owner, group or others) are
+    /// used, as in Unix.
+    pub(crate) fn caps_of(&self, attr: &FileAttr) -> Cap {
+        let bits = attr.perm().bits() as u32;
+        if self.is_root() {
+            return if attr.is_dir() || bits & 0o111 != 0 {
+                Cap::READ | Cap::WRITE | Cap::EXECUTE
+            } else {
+                Cap::READ | Cap::WRITE
+            };
+        }
+        let rwx = if self.uid == attr.uid() {
+            bits >> 6
+        } else if self.in_group(attr.gid()) {
+            bits >> 3
+        } else {
+            bits
+        };
+        let mut cap = Cap::empty();
+        if rwx & 0o4 != 0 {
+            cap |= Cap::READ;
+        }
+        if rwx & 0o2 != 0 {
+            cap |= Cap::WRITE;
+        }
+        if rwx & 0o1 != 0 {
+            cap |= Cap::EXECUTE;
+        }
+        cap
+    }
+
+    /// Checks that the user has the capabilities `cap` on a file with the
+    /// attributes `attr`.
+    pub(crate) fn check_access(&self, attr: &FileAttr, cap: Cap) -> AxResult {
+        if self.caps_of(attr).contains(cap) {
+            Ok(())
+        } else {
+            ax_err!(PermissionDenied)
+        }
+    }
+
+    /// Checks that the user can change the attributes of a file with the
+    /// attributes `attr` as given in `set`.
+    ///
+    /// Only the owner can change the permission and the timestamps, and only
+    /// the superuser can change the owner. The owner can change the group to
+    /// any of its groups.
+    pub(crate) fn check_set_attr(&self, attr: &FileAttr, set: &SetAttr) -> AxResult {
+        if self.is_root() {
+            return Ok(());
+        }
+        let is_owner = self.uid == attr.uid();
+        let changes_mode = set.perm.is_some() || set.atime.is_some() || set.mtime.is_some();
+        let changes_owner = set.uid.is_some_and(|uid| uid != attr.uid());
+        let changes_group = set
+            .gid
+            .is_some_and(|gid| gid != attr.gid() && !self.in_group(gid));
+        if changes_owner || ((changes_mode || set.gid.is_some()) && !is_owner) || changes_group {
+            return ax_err!(PermissionDenied);
+        }
+        Ok(())
+    }
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self::root()
+    }
+}
 
 /// An opened file object, with open permissions and a cursor.
 pub struct File {
     node: WithCap<VfsNodeRef>,
     is_append: bool,
+    nonblocking: bool,
     offset: u64,
+    mount: Option<Arc<MountPoint>>,
+    locks: FileLocks,
+    cred: Credentials,
 }
 
 /// An opened directory object, with open permissions and a cursor for
@@ -32,6 +160,8 @@ pub struct File {
 pub struct Directory {
     node: WithCap<VfsNodeRef>,
     entry_idx: usize,
+    mount: Option<Arc<MountPoint>>,
+    cred: Credentials,
 }
 
 /// Options and flags which can be used to configure how a file is opened.
@@ -44,9 +174,12 @@ pub struct OpenOptions {
     truncate: bool,
     create: bool,
     create_new: bool,
+    execute: bool,
+    nonblocking: bool,
+    cred: Credentials,
     // system-specific
     _custom_flags: i32,
-    _mode: u32,
+    mode: Option<FilePerm>,
 }
 
 impl OpenOptions {
@@ -60,9 +193,12 @@ impl OpenOptions {
             truncate: false,
             create: false,
             create_new: false,
+            execute: false,
+            nonblocking: false,
+            cred: Credentials::root(),
             // system-specific
             _custom_flags: 0,
-            _mode: 0o666,
+            mode: None,
         }
     }
     /// Sets the option for read access.
@@ -89,9 +225,32 @@ impl OpenOptions {
     pub fn create_new(&mut self, create_new: bool) {
         self.create_new = create_new;
     }
+    /// Sets the option for execute access, which requires the file to be a
+    /// regular file with an execute permission bit for the caller.
+    pub fn execute(&mut self, execute: bool) {
+        self.execute = execute;
+    }
+    /// Sets the option for non-blocking reads, which fail with
+    /// [`WouldBlock`](AxError::WouldBlock) instead of waiting for data that
+    /// is not available yet, e.g., from the console.
+    pub fn nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+    /// Sets the credentials of the caller, which are checked against the
+    /// permissions of the file and the directories in its path. It is also
+    /// used by the operations of the directory opened with the options.
+    pub fn credentials(&mut self, cred: Credentials) {
+        self.cred = cred;
+    }
+    /// Sets the permission mode of the file if it is created, as the `mode`
+    /// argument of `open` on Unix. Otherwise the default permission of the
+    /// filesystem is used.
+    pub fn mode(&mut self, mode: u32) {
+        self.mode = Some(FilePerm::from_bits_truncate(mode as u16));
+    }
 
     const fn is_valid(&self) -> bool {
-        if !self.read && !self.write && !self.append {
+        if !self.read && !self.write && !self.append && !self.execute {
             return false;
         }
         match (self.write, self.append) {
@@ -116,29 +275,49 @@ impl File {
         self.node.access_or_err(cap, AxError::PermissionDenied)
     }
 
-    fn _open_at(dir: Option<&VfsNodeRef>, path: &str, opts: &OpenOptions) -> AxResult<Self> {
+    fn _open_at(
+        dir: Option<&VfsNodeRef>,
+        path: &str,
+        opts: &OpenOptions,
+        mount: Option<Arc<MountPoint>>,
+    ) -> AxResult<Self> {
         debug!("open file: {} {:?}", path, opts);
         if !opts.is_valid() {
             return ax_err!(InvalidInput);
         }
 
-        let node_option = crate::root::lookup(dir, path);
-        let node = if opts.create || opts.create_new {
+        let cred = &opts.cred;
+        let node_option = crate::root::lookup(dir, path, cred);
+        let (node, created) = if opts.create || opts.create_new {
             match node_option {
                 Ok(node) => {
                     // already exists
                     if opts.create_new {
                         return ax_err!(AlreadyExists);
                     }
-                    node
+                    (node, false)
                 }
                 // not exists, create new
-                Err(VfsError::NotFound) => crate::root::create_file(dir, path)?,
+                Err(VfsError::NotFound) => {
+                    let node = crate::root::create_file(dir, path, cred)?;
+                    if let Some(perm) = opts.mode {
+                        let attr = SetAttr {
+                            perm: Some(perm),
+                            ..Default::default()
+                        };
+                        // filesystems without permissions keep their default
+                        match node.set_attr(&attr) {
+                            Ok(()) | Err(AxError::Unsupported) => {}
+                            Err(e) => return Err(e),
+                        }
+                    }
+                    (node, true)
+                }
                 Err(e) => return Err(e),
             }
         } else {
             // just open the existing
-            node_option?
+            (node_option?, false)
         };
 
         let attr = node.get_attr()?;
@@ -147,26 +326,39 @@ impl File {
         {
             return ax_err!(IsADirectory);
         }
+        if (opts.write || opts.append || opts.truncate) && crate::root::is_read_only(mount.as_ref())
+        {
+            return ax_err!(ReadOnlyFilesystem);
+        }
         let access_cap = opts.into();
-        if !perm_to_cap(attr.perm()).contains(access_cap) {
-            return ax_err!(PermissionDenied);
+        // the creator gets the requested access regardless of the mode
+        if !created {
+            cred.check_access(&attr, access_cap)?;
+        }
+        if opts.execute && !attr.is_file() {
+            return ax_err!(PermissionDenied, "only regular files can be executed");
         }
 
         node.open()?;
         if opts.truncate {
             node.truncate(0)?;
         }
+        let locks = FileLocks::new(crate::root::fs_of(mount.as_ref()), &node, &attr);
         Ok(Self {
             node: WithCap::new(node, access_cap),
             is_append: opts.append,
+            nonblocking: opts.nonblocking,
             offset: 0,
+            mount,
+            locks,
+            cred: cred.clone(),
         })
     }
 
     /// Opens a file at the path relative to the current directory. Returns a
     /// [`File`] object.
     pub fn open(path: &str, opts: &OpenOptions) -> AxResult<Self> {
-        Self::_open_at(None, path, opts)
+        Self::_open_at(None, path, opts, crate::root::mount_point_of(path)?)
     }
 
     /// Truncates the file to the specified size.
@@ -180,19 +372,24 @@ impl File {
     ///
     /// After the read, the cursor will be advanced by the number of bytes read.
     pub fn read(&mut self, buf: &mut [u8]) -> AxResult<usize> {
-        let node = self.access_node(Cap::READ)?;
-        let read_len = node.read_at(self.offset, buf)?;
+        let read_len = self.read_at(self.offset, buf)?;
         self.offset += read_len as u64;
         Ok(read_len)
     }
 
     /// Reads the file at the given position. Returns the number of bytes read.
     ///
-    /// It does not update the file cursor.
+    /// It does not update the file cursor. If no data is available yet, it
+    /// waits for the data unless the file is [non-blocking](Self::set_nonblocking).
     pub fn read_at(&self, offset: u64, buf: &mut [u8]) -> AxResult<usize> {
         let node = self.access_node(Cap::READ)?;
-        let read_len = node.read_at(offset, buf)?;
-        Ok(read_len)
+        loop {
+            match node.read_at(offset, buf) {
+                #[cfg(feature = "devfs")]
+                Err(AxError::WouldBlock) if !self.nonblocking => axtask::yield_now(),
+                res => return res,
+            }
+        }
     }
 
     /// Writes the file at the current position. Returns the number of bytes
@@ -246,6 +443,61 @@ impl File {
     pub fn get_attr(&self) -> AxResult<FileAttr> {
         self.access_node(Cap::empty())?.get_attr()
     }
+
+    /// Changes the permission, owner or timestamps of the file, which is
+    /// checked against the credentials that the file is opened with.
+    pub fn set_attr(&self, attr: &SetAttr) -> AxResult {
+        let node = self.access_node(Cap::empty())?;
+        self.cred.check_set_attr(&node.get_attr()?, attr)?;
+        node.set_attr(attr)
+    }
+
+    /// Gets the attributes of the filesystem which the file is located in.
+    pub fn statfs(&self) -> AxResult<FileSystemInfo> {
+        crate::root::statfs(self.mount.as_ref())
+    }
+
+    /// Returns the handle to the advisory locks held through the file, which
+    /// are released when it is closed.
+    pub fn locks(&self) -> FileLocks {
+        self.locks
+    }
+
+    /// Sets whether reads fail with [`WouldBlock`](AxError::WouldBlock)
+    /// instead of waiting for data.
+    pub fn set_nonblocking(&mut self, nonblocking: bool) {
+        self.nonblocking = nonblocking;
+    }
+
+    /// Whether the file is opened for reading.
+    pub fn readable(&self) -> bool {
+        self.node.can_access(Cap::READ)
+    }
+
+    /// Whether the file is opened for writing.
+    pub fn writable(&self) -> bool {
+        self.node.can_access(Cap::WRITE)
+    }
+
+    /// Creates a new handle to the same file with the same permissions, e.g.,
+    /// to keep using the file in a memory mapping after it is closed.
+    ///
+    /// The new handle has its own cursor, starting at the beginning, and its
+    /// own [locks](Self::locks).
+    pub fn try_clone(&self) -> AxResult<Self> {
+        let node = unsafe { self.node.access_unchecked() };
+        let attr = node.get_attr()?;
+        node.open()?;
+        Ok(Self {
+            node: WithCap::new(node.clone(), self.node.cap()),
+            is_append: self.is_append,
+            nonblocking: self.nonblocking,
+            offset: 0,
+            mount: self.mount.clone(),
+            locks: FileLocks::new(crate::root::fs_of(self.mount.as_ref()), node, &attr),
+            cred: self.cred.clone(),
+        })
+    }
 }
 
 impl Directory {
@@ -253,7 +505,12 @@ impl Directory {
         self.node.access_or_err(cap, AxError::PermissionDenied)
     }
 
-    fn _open_dir_at(dir: Option<&VfsNodeRef>, path: &str, opts: &OpenOptions) -> AxResult<Self> {
+    fn _open_dir_at(
+        dir: Option<&VfsNodeRef>,
+        path: &str,
+        opts: &OpenOptions,
+        mount: Option<Arc<MountPoint>>,
+    ) -> AxResult<Self> {
         debug!("open dir: {}", path);
         if !opts.read {
             return ax_err!(InvalidInput);
@@ -262,67 +519,81 @@ impl Directory {
             return ax_err!(InvalidInput);
         }
 
-        let node = crate::root::lookup(dir, path)?;
+        let node = crate::root::lookup(dir, path, &opts.cred)?;
         let attr = node.get_attr()?;
         if !attr.is_dir() {
             return ax_err!(NotADirectory);
         }
         let access_cap = opts.into();
-        if !perm_to_cap(attr.perm()).contains(access_cap) {
-            return ax_err!(PermissionDenied);
-        }
+        opts.cred.check_access(&attr, access_cap)?;
 
         node.open()?;
         Ok(Self {
             node: WithCap::new(node, access_cap),
             entry_idx: 0,
+            mount,
+            cred: opts.cred.clone(),
         })
     }
 
+    /// Returns the directory that `path` is relative to, or `None` if it is
+    /// an absolute path. The search permission of the directory is checked
+    /// during path resolution.
     fn access_at(&self, path: &str) -> AxResult<Option<&VfsNodeRef>> {
         if path.starts_with('/') {
             Ok(None)
         } else {
-            Ok(Some(self.access_node(Cap::EXECUTE)?))
+            Ok(Some(self.access_node(Cap::empty())?))
+        }
+    }
+
+    fn mount_at(&self, path: &str) -> AxResult<Option<Arc<MountPoint>>> {
+        if path.starts_with('/') {
+            crate::root::mount_point_of(path)
+        } else {
+            Ok(self.mount.clone())
         }
     }
 
     /// Opens a directory at the path relative to the current directory.
     /// Returns a [`Directory`] object.
     pub fn open_dir(path: &str, opts: &OpenOptions) -> AxResult<Self> {
-        Self::_open_dir_at(None, path, opts)
+        Self::_open_dir_at(None, path, opts, crate::root::mount_point_of(path)?)
     }
 
     /// Opens a directory at the path relative to this directory. Returns a
     /// [`Directory`] object.
+    ///
+    /// The credentials in `opts` are used for the new directory, instead of
+    /// the ones of this directory.
     pub fn open_dir_at(&self, path: &str, opts: &OpenOptions) -> AxResult<Self> {
-        Self::_open_dir_at(self.access_at(path)?, path, opts)
+        Self::_open_dir_at(self.access_at(path)?, path, opts, self.mount_at(path)?)
     }
 
     /// Opens a file at the path relative to this directory. Returns a [`File`]
     /// object.
     pub fn open_file_at(&self, path: &str, opts: &OpenOptions) -> AxResult<File> {
-        File::_open_at(self.access_at(path)?, path, opts)
+        File::_open_at(self.access_at(path)?, path, opts, self.mount_at(path)?)
     }
 
     /// Creates an empty file at the path relative to this directory.
     pub fn create_file(&self, path: &str) -> AxResult<VfsNodeRef> {
-        crate::root::create_file(self.access_at(path)?, path)
+        crate::root::create_file(self.access_at(path)?, path, &self.cred)
     }
 
     /// Creates an empty directory at the path relative to this directory.
     pub fn create_dir(&self, path: &str) -> AxResult {
-        crate::root::create_dir(self.access_at(path)?, path)
+        crate::root::create_dir(self.access_at(path)?, path, &self.cred)
     }
 
     /// Removes a file at the path relative to this directory.
     pub fn remove_file(&self, path: &str) -> AxResult {
-        crate::root::remove_file(self.access_at(path)?, path)
+        crate::root::remove_file(self.access_at(path)?, path, &self.cred)
     }
 
     /// Removes a directory at the path relative to this directory.
     pub fn remove_dir(&self, path: &str) -> AxResult {
-        crate::root::remove_dir(self.access_at(path)?, path)
+        crate::root::remove_dir(self.access_at(path)?, path, &self.cred)
     }
 
     /// Reads directory entries starts from the current position into the
@@ -339,16 +610,37 @@ impl Directory {
     }
 
     /// Rename a file or directory to a new name.
-    /// Delete the original file if `old` already exists.
+    /// Replace the original file if `new` already exists.
     ///
-    /// This only works then the new path is in the same mounted fs.
+    /// This only works then the new path is in the same mounted fs, otherwise
+    /// [`CrossesDevices`](AxError::CrossesDevices) is returned.
     pub fn rename(&self, old: &str, new: &str) -> AxResult {
-        crate::root::rename(old, new)
+        crate::root::rename(old, new, &self.cred)
+    }
+
+    /// Gets the directory attributes.
+    pub fn get_attr(&self) -> AxResult<FileAttr> {
+        self.access_node(Cap::empty())?.get_attr()
+    }
+
+    /// Changes the permission, owner or timestamps of the directory, which
+    /// is checked against the credentials that it is opened with.
+    pub fn set_attr(&self, attr: &SetAttr) -> AxResult {
+        let node = self.access_node(Cap::empty())?;
+        self.cred.check_set_attr(&node.get_attr()?, attr)?;
+        node.set_attr(attr)
+    }
+
+    /// Gets the attributes of the filesystem which the directory is located
+    /// in.
+    pub fn statfs(&self) -> AxResult<FileSystemInfo> {
+        crate::root::statfs(self.mount.as_ref())
     }
 }
 
 impl Drop for File {
     fn drop(&mut self) {
+        self.locks.release_all();
         unsafe { self.node.access_unchecked().release().ok() };
     }
 }
@@ -380,6 +672,7 @@ impl fmt::Debug for OpenOptions {
         fmt_opt!(truncate, "TRUNC");
         fmt_opt!(create, "CREATE");
         fmt_opt!(create_new, "CREATE_NEW");
+        fmt_opt!(execute, "EXEC");
         Ok(())
     }
 }
@@ -393,20 +686,9 @@ impl From<&OpenOptions> for Cap {
         if opts.write | opts.append {
             cap |= Cap::WRITE;
         }
+        if opts.execute {
+            cap |= Cap::EXECUTE;
+        }
         cap
     }
 }
-
-fn perm_to_cap(perm: FilePerm) -> Cap {
-    let mut cap = Cap::empty();
-    if perm.owner_readable() {
-        cap |= Cap::READ;
-    }
-    if perm.owner_writable() {
-        cap |= Cap::WRITE;
-    }
-    if perm.owner_executable() {
-        cap |= Cap::EXECUTE;
-    }
-    cap
-}
@@ -0,0 +1,390 @@
+//! Directories, whose data blocks are lists of variable-length entries.
+
+use alloc::{string::String, vec, vec::Vec};
+
+use axfs_vfs::{VfsError, VfsNodeType, VfsResult};
+
+use super::layout::*;
+use super::FsInner;
+
+const MAX_NAME_LEN: usize = 255;
+const DIRENT_HEADER_SIZE: usize = 8;
+
+/// An entry in a directory.
+pub(super) struct DirEntry {
+    pub ino: u32,
+    pub name: String,
+    /// The file type, or 0 if unknown.
+    pub file_type: u8,
+}
+
+/// Returns the type of a directory entry for the inode `mode`.
+pub(super) fn dirent_type(mode: u16) -> u8 {
+    match mode & S_IFMT {
+        S_IFREG => 1,
+        S_IFDIR => 2,
+        0o020000 => 3,
+        0o060000 => 4,
+        0o010000 => 5,
+        0o140000 => 6,
+        S_IFLNK => 7,
+        _ => 0,
+    }
+}
+
+/// Converts the type of a directory entry to the node type.
+pub(super) fn dirent_node_type(file_type: u8) -> Option<VfsNodeType> {
+    Some(match file_type {
+        1 => VfsNodeType::File,
+        2 => VfsNodeType::Dir,
+        3 => VfsNodeType::CharDevice,
+        4 => VfsNodeType::BlockDevice,
+        5 => VfsNodeType::Fifo,
+        6 => VfsNodeType::Socket,
+        7 => VfsNodeType::SymLink,
+        _ => return None,
+    })
+}
+
+/// The size of an entry with a name of `name_len` bytes, aligned to 4 bytes.
+const fn entry_size(name_len: usize) -> usize {
+    (DIRENT_HEADER_SIZE + name_len + 3) & !3
+}
+
+/// A raw entry in a directory block.
+struct RawEntry {
+    offset: usize,
+    ino: u32,
+    rec_len: usize,
+    name_len: usize,
+}
+
+impl FsInner {
+    /// The end of the entries in a directory block, before the checksum tail.
+    fn dir_block_end(&self) -> usize {
+        if self.sb.has_metadata_csum() {
+            self.block_size - DIR_TAIL_SIZE
+        } else {
+            self.block_size
+        }
+    }
+
+    fn rec_len_from_disk(&self, len: u16) -> usize {
+        if self.block_size >= 65536 && (len == 0 || len == u16::MAX) {
+            self.block_size
+        } else {
+            len as usize
+        }
+    }
+
+    fn rec_len_to_disk(&self, len: usize) -> u16 {
+        if len >= 65536 {
+            u16::MAX
+        } else {
+            len as u16
+        }
+    }
+
+    /// Parses the entries in a directory block, including unused ones.
+    fn parse_dir_block(&self, data: &[u8]) -> VfsResult<Vec<RawEntry>> {
+        let mut entries = Vec::new();
+        let mut offset = 0;
+        while offset + DIRENT_HEADER_SIZE <= data.len() {
+            let rec_len = self.rec_len_from_disk(get_u16(data, offset + 4));
+            let name_len = if self.sb.has_incompat(INCOMPAT_FILETYPE) {
+                data[offset + 6] as usize
+            } else {
+                get_u16(data, offset + 6) as usize
+            };
+            if rec_len < DIRENT_HEADER_SIZE
+                || offset + rec_len > data.len()
+                || DIRENT_HEADER_SIZE + name_len > rec_len
+            {
+                warn!("ext4: corrupted directory entry at offset {}", offset);
+                return Err(VfsError::InvalidData);
+            }
+            entries.push(RawEntry {
+                offset,
+                ino: get_u32(data, offset),
+                rec_len,
+                name_len,
+            });
+            offset += rec_len;
+        }
+        Ok(entries)
+    }
+
+    fn write_raw_entry(&self, data: &mut [u8], entry: &RawEntry, name: &[u8], file_type: u8) {
+        let offset = entry.offset;
+        set_u32(data, offset, entry.ino);
+        set_u16(data, offset + 4, self.rec_len_to_disk(entry.rec_len));
+        if self.sb.has_incompat(INCOMPAT_FILETYPE) {
+            data[offset + 6] = name.len() as u8;
+            data[offset + 7] = file_type;
+        } else {
+            set_u16(data, offset + 6, name.len() as u16);
+        }
+        data[offset + DIRENT_HEADER_SIZE..][..name.len()].copy_from_slice(name);
+    }
+
+    /// Writes a directory block, with the checksum tail if needed.
+    fn write_dir_block(&mut self, dir: &Inode, block: u64, data: &mut [u8]) -> VfsResult {
+        self.fill_dir_tail(dir, data);
+        self.write_block(block, data)
+    }
+
+    fn fill_dir_tail(&self, dir: &Inode, data: &mut [u8]) {
+        if !self.sb.has_metadata_csum() {
+            return;
+        }
+        let end = self.dir_block_end();
+        let tail = &mut data[end..];
+        tail.fill(0);
+        set_u16(tail, 4, DIR_TAIL_SIZE as u16);
+        tail[7] = 0xde;
+        let csum = crc32c(dir.csum_seed(self.csum_seed), &data[..end]);
+        set_u32(data, end + 8, csum);
+    }
+
+    /// Returns the data blocks of directory `dir`.
+    fn dir_blocks(&mut self, dir: &Inode) -> VfsResult<Vec<u64>> {
+        let count = dir.size().div_ceil(self.block_size as u64) as u32;
+        let mut blocks = Vec::with_capacity(count as usize);
+        for lblk in 0..count {
+            match self.map_block(dir, lblk)? {
+                Some(block) => blocks.push(block),
+                None => return Err(VfsError::InvalidData), // holes are not allowed
+            }
+        }
+        Ok(blocks)
+    }
+
+    /// Returns all entries in directory `dir`, including `.` and `..`.
+    pub(super) fn read_dir_entries(&mut self, dir: &Inode) -> VfsResult<Vec<DirEntry>> {
+        let mut entries = Vec::new();
+        for block in self.dir_blocks(dir)? {
+            let data = self.read_block(block)?;
+            for raw in self.parse_dir_block(&data)? {
+                if raw.ino == 0 || raw.name_len == 0 {
+                    continue;
+                }
+                let name = &data[raw.offset + DIRENT_HEADER_SIZE..][..raw.name_len];
+                let file_type = if self.sb.has_incompat(INCOMPAT_FILETYPE) {
+                    data[raw.offset + 7]
+                } else {
+                    0
+                };
+                entries.push(DirEntry {
+                    ino: raw.ino,
+                    name: String::from_utf8_lossy(name).into_owned(),
+                    file_type,
+                });
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Returns the inode number of entry `name` in directory `dir`.
+    pub(super) fn find_entry(&mut self, dir: &Inode, name: &str) -> VfsResult<Option<u32>> {
+        Ok(self
+            .read_dir_entries(dir)?
+            .into_iter()
+            .find(|e| e.name == name)
+            .map(|e| e.ino))
+    }
+
+    /// Whether directory `dir` has no entries other than `.` and `..`.
+    pub(super) fn is_dir_empty(&mut self, dir: &Inode) -> VfsResult<bool> {
+        Ok(self
+            .read_dir_entries(dir)?
+            .iter()
+            .all(|e| e.name == "." || e.name == ".."))
+    }
+
+    /// Adds the entry `name` for inode `ino` of `mode` to directory `dir`,
+    /// which is extended by a block if there is no room. `dir` is not written
+    /// to the disk.
+    pub(super) fn add_entry(
+        &mut self,
+        dir: &mut Inode,
+        name: &str,
+        ino: u32,
+        mode: u16,
+    ) -> VfsResult {
+        if name.len() > MAX_NAME_LEN {
+            return Err(VfsError::InvalidInput);
+        }
+        if dir.has_flag(INODE_FLAG_INDEX) {
+            self.deindex_dir(dir)?;
+        }
+        let name = name.as_bytes();
+        let needed = entry_size(name.len());
+        let end = self.dir_block_end();
+        for block in self.dir_blocks(dir)? {
+            let mut data = self.read_block(block)?;
+            for raw in self.parse_dir_block(&data[..end])? {
+                let used = if raw.ino == 0 {
+                    0
+                } else {
+                    entry_size(raw.name_len)
+                };
+                if raw.rec_len < used + needed {
+                    continue;
+                }
+                let new = if used == 0 {
+                    RawEntry { ino, ..raw }
+                } else {
+                    let shrunk = RawEntry {
+                        rec_len: used,
+                        ..raw
+                    };
+                    set_u16(
+                        &mut data,
+                        raw.offset + 4,
+                        self.rec_len_to_disk(shrunk.rec_len),
+                    );
+                    RawEntry {
+                        offset: raw.offset + used,
+                        ino,
+                        rec_len: raw.rec_len - used,
+                        name_len: name.len(),
+                    }
+                };
+                self.write_raw_entry(&mut data, &new, name, dirent_type(mode));
+                self.write_dir_block(dir, block, &mut data)?;
+                dir.set_times(false, true, true, super::now());
+                return Ok(());
+            }
+        }
+
+        // append a new block
+        let mut data = vec![0; self.block_size];
+        let entry = RawEntry {
+            offset: 0,
+            ino,
+            rec_len: end,
+            name_len: name.len(),
+        };
+        self.write_raw_entry(&mut data, &entry, name, dirent_type(mode));
+        self.fill_dir_tail(dir, &mut data);
+        let size = dir.size();
+        self.write_data(dir, size, &data)
+    }
+
+    /// Finds the entry `name` in directory `dir`, and calls `f` with the block
+    /// containing it, the entry and the previous entry in the block.
+    fn modify_entry<F>(&mut self, dir: &mut Inode, name: &str, f: F) -> VfsResult<u32>
+    where
+        F: FnOnce(&Self, &mut [u8], &RawEntry, Option<&RawEntry>),
+    {
+        if dir.has_flag(INODE_FLAG_INDEX) {
+            self.deindex_dir(dir)?;
+        }
+        let end = self.dir_block_end();
+        for block in self.dir_blocks(dir)? {
+            let mut data = self.read_block(block)?;
+            let entries = self.parse_dir_block(&data[..end])?;
+            let found = entries.iter().position(|raw| {
+                raw.ino != 0
+                    && &data[raw.offset + DIRENT_HEADER_SIZE..][..raw.name_len] == name.as_bytes()
+            });
+            if let Some(i) = found {
+                let prev = i.checked_sub(1).map(|p| &entries[p]);
+                f(self, &mut data, &entries[i], prev);
+                self.write_dir_block(dir, block, &mut data)?;
+                dir.set_times(false, true, true, super::now());
+                return Ok(entries[i].ino);
+            }
+        }
+        Err(VfsError::NotFound)
+    }
+
+    /// Removes the entry `name` from directory `dir`, returns the inode number
+    /// of the entry. `dir` is not written to the disk.
+    pub(super) fn remove_entry(&mut self, dir: &mut Inode, name: &str) -> VfsResult<u32> {
+        self.modify_entry(dir, name, |fs, data, entry, prev| match prev {
+            Some(prev) => {
+                let rec_len = fs.rec_len_to_disk(prev.rec_len + entry.rec_len);
+                set_u16(data, prev.offset + 4, rec_len);
+            }
+            None => set_u32(data, entry.offset, 0),
+        })
+    }
+
+    /// Points the entry `name` in directory `dir` to inode `ino` of `mode`.
+    /// `dir` is not written to the disk.
+    pub(super) fn replace_entry(
+        &mut self,
+        dir: &mut Inode,
+        name: &str,
+        ino: u32,
+        mode: u16,
+    ) -> VfsResult {
+        self.modify_entry(dir, name, |fs, data, entry, _| {
+            set_u32(data, entry.offset, ino);
+            if fs.sb.has_incompat(INCOMPAT_FILETYPE) {
+                data[entry.offset + 7] = dirent_type(mode);
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Writes the first block of a new directory `dir` with entries `.` and
+    /// `..`. `dir` is not written to the disk.
+    pub(super) fn init_dir(&mut self, dir: &mut Inode, parent: u32) -> VfsResult {
+        let mut data = vec![0; self.block_size];
+        let dot = RawEntry {
+            offset: 0,
+            ino: dir.ino,
+            rec_len: entry_size(1),
+            name_len: 1,
+        };
+        let dotdot = RawEntry {
+            offset: dot.rec_len,
+            ino: parent,
+            rec_len: self.dir_block_end() - dot.rec_len,
+            name_len: 2,
+        };
+        self.write_raw_entry(&mut data, &dot, b".", dirent_type(S_IFDIR));
+        self.write_raw_entry(&mut data, &dotdot, b"..", dirent_type(S_IFDIR));
+        self.fill_dir_tail(dir, &mut data);
+        self.write_data(dir, 0, &data)
+    }
+
+    /// Converts a hash-indexed directory to a linear one, so that entries can
+    /// be added or removed without maintaining the index.
+    ///
+    /// The index is stored in the blocks after `.` and `..` in the first block
+    /// and in blocks which start with an empty entry covering the whole block,
+    /// so that they look like empty blocks to readers of linear directories.
+    fn deindex_dir(&mut self, dir: &mut Inode) -> VfsResult {
+        let end = self.dir_block_end();
+        for (i, block) in self.dir_blocks(dir)?.into_iter().enumerate() {
+            let mut data = self.read_block(block)?;
+            if i == 0 {
+                let dot_len = self.rec_len_from_disk(get_u16(&data, 4));
+                if dot_len + entry_size(2) > end {
+                    return Err(VfsError::InvalidData);
+                }
+                let dotdot = RawEntry {
+                    offset: dot_len,
+                    ino: get_u32(&data, dot_len),
+                    rec_len: end - dot_len,
+                    name_len: 2,
+                };
+                data[dot_len..].fill(0);
+                self.write_raw_entry(&mut data, &dotdot, b"..", dirent_type(S_IFDIR));
+            } else if get_u32(&data, 0) == 0
+                && self.rec_len_from_disk(get_u16(&data, 4)) == self.block_size
+            {
+                data.fill(0);
+                set_u16(&mut data, 4, self.rec_len_to_disk(end));
+            } else {
+                continue;
+            }
+            self.write_dir_block(dir, block, &mut data)?;
+        }
+        dir.set_flags(dir.flags() & !INODE_FLAG_INDEX);
+        Ok(())
+    }
+}
@@ -0,0 +1,602 @@
+//! Data of inodes, addressed by either block maps or extent trees.
+
+use alloc::{vec, vec::Vec};
+
+use axfs_vfs::{VfsError, VfsResult};
+
+use super::layout::*;
+use super::FsInner;
+
+/// The maximum number of extents in the root of an extent tree.
+const ROOT_EXTENTS: usize = 4;
+/// The maximum depth of extent trees.
+const MAX_EXTENT_DEPTH: usize = 5;
+const HEADER_SIZE: usize = EXTENT_ENTRY_SIZE;
+
+/// Initializes an empty extent tree in `i_block` of `inode`.
+pub(super) fn init_extent_root(inode: &mut Inode) {
+    let root = inode.i_block_mut();
+    root.fill(0);
+    ExtentHeader {
+        entries: 0,
+        max: ROOT_EXTENTS,
+        depth: 0,
+    }
+    .write(root);
+}
+
+/// A node on the path from the root of an extent tree to a leaf.
+struct PathNode {
+    /// The block of the node, or `None` for the root in the inode.
+    block: Option<u64>,
+    data: Vec<u8>,
+}
+
+impl FsInner {
+    /// Whether `inode` is a symbolic link whose target is stored in `i_block`.
+    pub(super) fn is_fast_symlink(&self, inode: &Inode) -> bool {
+        let xattr_blocks = if inode.file_acl() != 0 {
+            self.block_size as u64 / 512
+        } else {
+            0
+        };
+        inode.file_type() == S_IFLNK && inode.raw_blocks(&self.sb) == xattr_blocks
+    }
+
+    /// Reads the data of `inode` at `offset`, returns the number of bytes
+    /// read.
+    pub(super) fn read_data(
+        &mut self,
+        inode: &Inode,
+        offset: u64,
+        buf: &mut [u8],
+    ) -> VfsResult<usize> {
+        if inode.has_flag(INODE_FLAG_INLINE_DATA) {
+            return Err(VfsError::Unsupported);
+        }
+        let size = inode.size();
+        let len = size.saturating_sub(offset).min(buf.len() as u64) as usize;
+        let bs = self.block_size as u64;
+        let mut read_len = 0;
+        while read_len < len {
+            let pos = offset + read_len as u64;
+            let in_block = (pos % bs) as usize;
+            let n = (len - read_len).min(self.block_size - in_block);
+            let dst = &mut buf[read_len..read_len + n];
+            match self.map_block(inode, (pos / bs) as u32)? {
+                Some(block) => self.read_bytes(block * bs + in_block as u64, dst)?,
+                None => dst.fill(0),
+            }
+            read_len += n;
+        }
+        Ok(len)
+    }
+
+    /// Writes `buf` to the data of `inode` at `offset`, allocating blocks as
+    /// needed. The size and the times of `inode` are updated, but it is not
+    /// written to the disk.
+    pub(super) fn write_data(&mut self, inode: &mut Inode, offset: u64, buf: &[u8]) -> VfsResult {
+        if inode.has_flag(INODE_FLAG_INLINE_DATA) {
+            return Err(VfsError::Unsupported);
+        }
+        let bs = self.block_size as u64;
+        let mut written = 0;
+        while written < buf.len() {
+            let pos = offset + written as u64;
+            let lblk = u32::try_from(pos / bs).map_err(|_| VfsError::StorageFull)?;
+            let in_block = (pos % bs) as usize;
+            let n = (buf.len() - written).min(self.block_size - in_block);
+            let src = &buf[written..written + n];
+            let (block, fresh) = self.get_or_alloc_block(inode, lblk)?;
+            if fresh && n < self.block_size {
+                let mut data = vec![0; self.block_size];
+                data[in_block..in_block + n].copy_from_slice(src);
+                self.write_block(block, &data)?;
+            } else {
+                self.write_bytes(block * bs + in_block as u64, src)?;
+            }
+            written += n;
+        }
+        let end = offset + buf.len() as u64;
+        if end > inode.size() {
+            inode.set_size(end);
+        }
+        inode.set_times(false, true, true, super::now());
+        Ok(())
+    }
+
+    /// Changes the size of `inode`, freeing the blocks beyond the new size.
+    /// The inode is not written to the disk.
+    pub(super) fn set_data_size(&mut self, inode: &mut Inode, size: u64) -> VfsResult {
+        let old_size = inode.size();
+        if size < old_size {
+            self.truncate_blocks(inode, size)?;
+            // zero the tail of the last block, which may be exposed later
+            let bs = self.block_size as u64;
+            if size % bs != 0 {
+                if let Some(block) = self.map_block(inode, (size / bs) as u32)? {
+                    let zeros = vec![0; (bs - size % bs) as usize];
+                    self.write_bytes(block * bs + size % bs, &zeros)?;
+                }
+            }
+        }
+        inode.set_size(size);
+        inode.set_times(false, true, true, super::now());
+        Ok(())
+    }
+
+    /// Returns the physical block of the logical block `lblk` of `inode`, or
+    /// `None` if it is a hole or unwritten.
+    pub(super) fn map_block(&mut self, inode: &Inode, lblk: u32) -> VfsResult<Option<u64>> {
+        if inode.has_flag(INODE_FLAG_EXTENTS) {
+            Ok(self
+                .find_extent(inode, lblk)?
+                .filter(|e| !e.unwritten)
+                .map(|e| e.start + (lblk - e.block) as u64))
+        } else {
+            self.map_indirect(inode, lblk)
+        }
+    }
+
+    /// Returns the physical block of `lblk`, which is allocated if it is not
+    /// mapped yet, and whether it is newly allocated (with undefined content).
+    fn get_or_alloc_block(&mut self, inode: &mut Inode, lblk: u32) -> VfsResult<(u64, bool)> {
+        let goal = match lblk.checked_sub(1) {
+            Some(prev) => self.map_block(inode, prev)?.map(|b| b + 1),
+            None => None,
+        }
+        .unwrap_or_else(|| self.inode_goal(inode));
+        if inode.has_flag(INODE_FLAG_EXTENTS) {
+            if let Some(ext) = self.find_extent(inode, lblk)? {
+                if ext.unwritten {
+                    self.init_unwritten_extent(inode, ext)?;
+                }
+                return Ok((ext.start + (lblk - ext.block) as u64, false));
+            }
+            let block = self.alloc_block(goal)?;
+            self.add_inode_blocks(inode, 1);
+            self.insert_extent(inode, lblk, block)?;
+            Ok((block, true))
+        } else {
+            self.alloc_indirect(inode, lblk, goal)
+        }
+    }
+
+    /// Returns the first block of the group of `inode`, where its blocks are
+    /// preferably allocated.
+    fn inode_goal(&self, inode: &Inode) -> u64 {
+        self.group_first_block((inode.ino - 1) / self.sb.inodes_per_group())
+    }
+
+    /// Frees all blocks of `inode` from the new `size` on.
+    pub(super) fn truncate_blocks(&mut self, inode: &mut Inode, size: u64) -> VfsResult {
+        let keep = size.div_ceil(self.block_size as u64);
+        if inode.has_flag(INODE_FLAG_EXTENTS) {
+            self.truncate_extents(inode, keep)
+        } else {
+            self.truncate_indirect(inode, keep)
+        }
+    }
+
+    // ---- block maps ----
+
+    /// Returns the path to `lblk` in a block map: the index in `i_block` and
+    /// the indices in the indirect blocks.
+    fn indirect_path(&self, lblk: u32) -> VfsResult<Vec<usize>> {
+        let per_block = (self.block_size / 4) as u64;
+        let mut n = lblk as u64;
+        if n < 12 {
+            return Ok(vec![n as usize]);
+        }
+        n -= 12;
+        if n < per_block {
+            return Ok(vec![12, n as usize]);
+        }
+        n -= per_block;
+        if n < per_block * per_block {
+            return Ok(vec![13, (n / per_block) as usize, (n % per_block) as usize]);
+        }
+        n -= per_block * per_block;
+        if n < per_block * per_block * per_block {
+            let (hi, lo) = (n / per_block, n % per_block);
+            return Ok(vec![
+                14,
+                (hi / per_block) as usize,
+                (hi % per_block) as usize,
+                lo as usize,
+            ]);
+        }
+        Err(VfsError::StorageFull)
+    }
+
+    fn map_indirect(&mut self, inode: &Inode, lblk: u32) -> VfsResult<Option<u64>> {
+        let path = self.indirect_path(lblk)?;
+        let mut block = get_u32(inode.i_block(), path[0] * 4);
+        for &index in &path[1..] {
+            if block == 0 {
+                break;
+            }
+            let mut entry = [0; 4];
+            let pos = block as u64 * self.block_size as u64 + index as u64 * 4;
+            self.read_bytes(pos, &mut entry)?;
+            block = u32::from_le_bytes(entry);
+        }
+        Ok((block != 0).then_some(block as u64))
+    }
+
+    fn alloc_indirect(
+        &mut self,
+        inode: &mut Inode,
+        lblk: u32,
+        goal: u64,
+    ) -> VfsResult<(u64, bool)> {
+        let path = self.indirect_path(lblk)?;
+        let levels = path.len();
+        let mut block = get_u32(inode.i_block(), path[0] * 4) as u64;
+        let mut fresh = false;
+        if block == 0 {
+            block = if levels == 1 {
+                self.alloc_block(goal)?
+            } else {
+                self.alloc_zeroed_block(goal)?
+            };
+            self.add_inode_blocks(inode, 1);
+            set_u32(inode.i_block_mut(), path[0] * 4, block as u32);
+            fresh = true;
+        }
+        for (i, &index) in path.iter().enumerate().skip(1) {
+            let pos = block * self.block_size as u64 + index as u64 * 4;
+            let mut entry = [0; 4];
+            self.read_bytes(pos, &mut entry)?;
+            block = u32::from_le_bytes(entry) as u64;
+            fresh = block == 0;
+            if fresh {
+                block = if i + 1 == levels {
+                    self.alloc_block(goal)?
+                } else {
+                    self.alloc_zeroed_block(goal)?
+                };
+                self.add_inode_blocks(inode, 1);
+                self.write_bytes(pos, &(block as u32).to_le_bytes())?;
+            }
+        }
+        Ok((block, fresh))
+    }
+
+    fn truncate_indirect(&mut self, inode: &mut Inode, keep: u64) -> VfsResult {
+        let per_block = (self.block_size / 4) as u64;
+        for i in keep.min(12) as usize..12 {
+            let block = get_u32(inode.i_block(), i * 4);
+            if block != 0 {
+                self.free_block(block as u64)?;
+                self.add_inode_blocks(inode, -1);
+                set_u32(inode.i_block_mut(), i * 4, 0);
+            }
+        }
+        let mut base = 12;
+        for (slot, level) in [(12, 1), (13, 2), (14, 3)] {
+            let block = get_u32(inode.i_block(), slot * 4);
+            if block != 0 && self.truncate_indirect_block(inode, block as u64, level, base, keep)? {
+                set_u32(inode.i_block_mut(), slot * 4, 0);
+            }
+            base += per_block.pow(level);
+        }
+        Ok(())
+    }
+
+    /// Frees the blocks from `keep` on under an indirect block of `level`,
+    /// whose first entry maps the logical block `base`. Returns whether the
+    /// indirect block itself is freed.
+    fn truncate_indirect_block(
+        &mut self,
+        inode: &mut Inode,
+        block: u64,
+        level: u32,
+        base: u64,
+        keep: u64,
+    ) -> VfsResult<bool> {
+        let per_block = (self.block_size / 4) as u64;
+        let span = per_block.pow(level - 1);
+        if base + per_block * span <= keep {
+            return Ok(false); // nothing to free
+        }
+        let mut data = self.read_block(block)?;
+        for i in 0..per_block as usize {
+            let child = get_u32(&data, i * 4) as u64;
+            let child_base = base + i as u64 * span;
+            if child == 0 || child_base + span <= keep {
+                continue;
+            }
+            let freed = if level == 1 {
+                self.free_block(child)?;
+                self.add_inode_blocks(inode, -1);
+                true
+            } else {
+                self.truncate_indirect_block(inode, child, level - 1, child_base, keep)?
+            };
+            if freed {
+                set_u32(&mut data, i * 4, 0);
+            }
+        }
+        if data.iter().all(|&b| b == 0) {
+            self.free_block(block)?;
+            self.add_inode_blocks(inode, -1);
+            Ok(true)
+        } else {
+            self.write_block(block, &data)?;
+            Ok(false)
+        }
+    }
+
+    // ---- extent trees ----
+
+    fn find_extent(&mut self, inode: &Inode, lblk: u32) -> VfsResult<Option<Extent>> {
+        let path = self.extent_path(inode, lblk)?;
+        let leaf = &path.last().unwrap().data;
+        let hdr = ExtentHeader::parse(leaf).ok_or(VfsError::InvalidData)?;
+        Ok(leaf[HEADER_SIZE..]
+            .chunks_exact(EXTENT_ENTRY_SIZE)
+            .take(hdr.entries)
+            .map(Extent::parse)
+            .find(|e| e.contains(lblk)))
+    }
+
+    /// Returns the nodes from the root to the leaf that covers `lblk`.
+    fn extent_path(&mut self, inode: &Inode, lblk: u32) -> VfsResult<Vec<PathNode>> {
+        let mut path = vec![PathNode {
+            block: None,
+            data: inode.i_block().to_vec(),
+        }];
+        loop {
+            let node = &path.last().unwrap().data;
+            let hdr = ExtentHeader::parse(node).ok_or(VfsError::InvalidData)?;
+            if hdr.depth == 0 {
+                return Ok(path);
+            } else if path.len() > MAX_EXTENT_DEPTH {
+                return Err(VfsError::InvalidData);
+            }
+            // the last index whose first block is not after `lblk`
+            let child = node[HEADER_SIZE..]
+                .chunks_exact(EXTENT_ENTRY_SIZE)
+                .take(hdr.entries)
+                .map(parse_extent_index)
+                .enumerate()
+                .take_while(|(i, (first, _))| *i == 0 || *first <= lblk)
+                .last();
+            let Some((_, (_, block))) = child else {
+                // an empty index node
+                return Err(VfsError::InvalidData);
+            };
+            let data = self.read_block(block)?;
+            path.push(PathNode {
+                block: Some(block),
+                data,
+            });
+        }
+    }
+
+    /// Writes an extent tree node back to the disk, or to `inode` if it is the
+    /// root.
+    fn write_extent_node(&mut self, inode: &mut Inode, node: &mut PathNode) -> VfsResult {
+        match node.block {
+            None => {
+                inode.i_block_mut().copy_from_slice(&node.data);
+                Ok(())
+            }
+            Some(block) => {
+                if self.sb.has_metadata_csum() {
+                    let hdr = ExtentHeader::parse(&node.data).ok_or(VfsError::InvalidData)?;
+                    let tail = HEADER_SIZE + hdr.max * EXTENT_ENTRY_SIZE;
+                    if tail + 4 <= node.data.len() {
+                        let csum = crc32c(inode.csum_seed(self.csum_seed), &node.data[..tail]);
+                        set_u32(&mut node.data, tail, csum);
+                    }
+                }
+                self.write_block(block, &node.data)
+            }
+        }
+    }
+
+    /// Maps `lblk` to `block` in the extent tree, which is not mapped yet.
+    fn insert_extent(&mut self, inode: &mut Inode, lblk: u32, block: u64) -> VfsResult {
+        let mut path = self.extent_path(inode, lblk)?;
+        let is_root = path.len() == 1;
+        let leaf = path.last_mut().unwrap();
+        let hdr = ExtentHeader::parse(&leaf.data).ok_or(VfsError::InvalidData)?;
+        let entries = &mut leaf.data[HEADER_SIZE..HEADER_SIZE + hdr.max * EXTENT_ENTRY_SIZE];
+        let pos = entries
+            .chunks_exact(EXTENT_ENTRY_SIZE)
+            .take(hdr.entries)
+            .take_while(|e| Extent::parse(e).block < lblk)
+            .count();
+
+        // extend the previous extent if possible
+        if pos > 0 {
+            let entry = &mut entries[(pos - 1) * EXTENT_ENTRY_SIZE..pos * EXTENT_ENTRY_SIZE];
+            let mut prev = Extent::parse(entry);
+            if !prev.unwritten
+                && prev.end() == lblk
+                && prev.start + prev.len as u64 == block
+                && prev.len < EXTENT_MAX_INIT_LEN as u32
+            {
+                prev.len += 1;
+                prev.write(entry);
+                return self.write_extent_node(inode, leaf);
+            }
+        }
+        // insert into the leaf if it has room, and the first block of the
+        // leaf is unchanged (otherwise the index above must be updated too)
+        if hdr.entries < hdr.max && (pos > 0 || is_root) {
+            let at = pos * EXTENT_ENTRY_SIZE;
+            entries.copy_within(at..hdr.entries * EXTENT_ENTRY_SIZE, at + EXTENT_ENTRY_SIZE);
+            let ext = Extent {
+                block: lblk,
+                len: 1,
+                start: block,
+                unwritten: false,
+            };
+            ext.write(&mut entries[at..at + EXTENT_ENTRY_SIZE]);
+            ExtentHeader {
+                entries: hdr.entries + 1,
+                ..hdr
+            }
+            .write(&mut leaf.data);
+            return self.write_extent_node(inode, leaf);
+        }
+
+        // otherwise rebuild the whole tree
+        let (mut extents, nodes) = self.collect_extents(inode)?;
+        let pos = extents.iter().take_while(|e| e.block < lblk).count();
+        extents.insert(
+            pos,
+            Extent {
+                block: lblk,
+                len: 1,
+                start: block,
+                unwritten: false,
+            },
+        );
+        self.rebuild_extent_tree(inode, &extents, &nodes)
+    }
+
+    /// Zeros the blocks of an unwritten extent and marks it as written.
+    fn init_unwritten_extent(&mut self, inode: &mut Inode, ext: Extent) -> VfsResult {
+        let zeros = vec![0; self.block_size];
+        for block in ext.start..ext.start + ext.len as u64 {
+            self.write_block(block, &zeros)?;
+        }
+        let mut path = self.extent_path(inode, ext.block)?;
+        let leaf = path.last_mut().unwrap();
+        let hdr = ExtentHeader::parse(&leaf.data).ok_or(VfsError::InvalidData)?;
+        for i in 0..hdr.entries {
+            let entry = &mut leaf.data[HEADER_SIZE + i * EXTENT_ENTRY_SIZE..][..EXTENT_ENTRY_SIZE];
+            let mut e = Extent::parse(entry);
+            if e.block == ext.block {
+                e.unwritten = false;
+                e.write(entry);
+                return self.write_extent_node(inode, leaf);
+            }
+        }
+        Err(VfsError::InvalidData)
+    }
+
+    /// Returns all extents of the tree in order, and the blocks of the tree
+    /// nodes other than the root.
+    fn collect_extents(&mut self, inode: &Inode) -> VfsResult<(Vec<Extent>, Vec<u64>)> {
+        let mut extents = Vec::new();
+        let mut nodes = Vec::new();
+        self.collect_extents_in(inode.i_block().to_vec(), 0, &mut extents, &mut nodes)?;
+        Ok((extents, nodes))
+    }
+
+    fn collect_extents_in(
+        &mut self,
+        node: Vec<u8>,
+        level: usize,
+        extents: &mut Vec<Extent>,
+        nodes: &mut Vec<u64>,
+    ) -> VfsResult {
+        let hdr = ExtentHeader::parse(&node).ok_or(VfsError::InvalidData)?;
+        if level > MAX_EXTENT_DEPTH {
+            return Err(VfsError::InvalidData);
+        }
+        let entries = node[HEADER_SIZE..]
+            .chunks_exact(EXTENT_ENTRY_SIZE)
+            .take(hdr.entries);
+        if hdr.depth == 0 {
+            extents.extend(entries.map(Extent::parse));
+        } else {
+            for (_, child) in entries.map(parse_extent_index) {
+                nodes.push(child);
+                let data = self.read_block(child)?;
+                self.collect_extents_in(data, level + 1, extents, nodes)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Replaces the extent tree of `inode` by a new one with `extents`. The
+    /// blocks of the old tree `old_nodes` are freed.
+    fn rebuild_extent_tree(
+        &mut self,
+        inode: &mut Inode,
+        extents: &[Extent],
+        old_nodes: &[u64],
+    ) -> VfsResult {
+        for &block in old_nodes {
+            self.free_block(block)?;
+            self.add_inode_blocks(inode, -1);
+        }
+        let mut items: Vec<[u8; EXTENT_ENTRY_SIZE]> = extents
+            .iter()
+            .map(|e| {
+                let mut entry = [0; EXTENT_ENTRY_SIZE];
+                e.write(&mut entry);
+                entry
+            })
+            .collect();
+        let per_block = (self.block_size - HEADER_SIZE) / EXTENT_ENTRY_SIZE;
+        let mut depth = 0;
+        let mut goal = self.inode_goal(inode);
+        while items.len() > ROOT_EXTENTS {
+            let mut upper = Vec::new();
+            for chunk in items.chunks(per_block) {
+                let block = self.alloc_block(goal)?;
+                goal = block + 1;
+                self.add_inode_blocks(inode, 1);
+                let mut node = PathNode {
+                    block: Some(block),
+                    data: vec![0; self.block_size],
+                };
+                fill_extent_node(&mut node.data, chunk, per_block, depth);
+                self.write_extent_node(inode, &mut node)?;
+                let mut index = [0; EXTENT_ENTRY_SIZE];
+                write_extent_index(&mut index, get_u32(&chunk[0], 0), block);
+                upper.push(index);
+            }
+            items = upper;
+            depth += 1;
+        }
+        fill_extent_node(inode.i_block_mut(), &items, ROOT_EXTENTS, depth);
+        Ok(())
+    }
+
+    fn truncate_extents(&mut self, inode: &mut Inode, keep: u64) -> VfsResult {
+        let (extents, nodes) = self.collect_extents(inode)?;
+        let mut kept = Vec::with_capacity(extents.len());
+        let mut changed = false;
+        for mut ext in extents {
+            let (block, end) = (ext.block as u64, ext.end() as u64);
+            if end <= keep {
+                kept.push(ext);
+                continue;
+            }
+            changed = true;
+            let from = block.max(keep);
+            self.free_blocks(ext.start + (from - block), end - from)?;
+            self.add_inode_blocks(inode, -((end - from) as i64));
+            if from > block {
+                ext.len = (from - block) as u32;
+                kept.push(ext);
+            }
+        }
+        if changed {
+            self.rebuild_extent_tree(inode, &kept, &nodes)?;
+        }
+        Ok(())
+    }
+}
+
+/// Fills an extent tree node with the header and `entries`.
+fn fill_extent_node(node: &mut [u8], entries: &[[u8; EXTENT_ENTRY_SIZE]], max: usize, depth: u16) {
+    node.fill(0);
+    ExtentHeader {
+        entries: entries.len(),
+        max,
+        depth,
+    }
+    .write(node);
+    for (i, entry) in entries.iter().enumerate() {
+        node[HEADER_SIZE + i * EXTENT_ENTRY_SIZE..][..EXTENT_ENTRY_SIZE].copy_from_slice(entry);
+    }
+}
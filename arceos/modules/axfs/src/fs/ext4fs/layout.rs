@@ -0,0 +1,682 @@
+//! On-disk structures of ext2/ext4, kept as raw bytes with accessors so that
+//! fields not known to us are preserved when they are written back.
+
+use alloc::vec::Vec;
+
+pub const SUPERBLOCK_OFFSET: u64 = 1024;
+pub const SUPERBLOCK_SIZE: usize = 1024;
+pub const EXT4_MAGIC: u16 = 0xef53;
+pub const ROOT_INO: u32 = 2;
+
+pub const COMPAT_HAS_JOURNAL: u32 = 0x4;
+pub const COMPAT_SPARSE_SUPER2: u32 = 0x200;
+
+pub const INCOMPAT_FILETYPE: u32 = 0x2;
+pub const INCOMPAT_RECOVER: u32 = 0x4;
+pub const INCOMPAT_META_BG: u32 = 0x10;
+pub const INCOMPAT_EXTENTS: u32 = 0x40;
+pub const INCOMPAT_64BIT: u32 = 0x80;
+pub const INCOMPAT_MMP: u32 = 0x100;
+pub const INCOMPAT_FLEX_BG: u32 = 0x200;
+pub const INCOMPAT_EA_INODE: u32 = 0x400;
+pub const INCOMPAT_CSUM_SEED: u32 = 0x2000;
+pub const INCOMPAT_LARGEDIR: u32 = 0x4000;
+/// Incompatible features that we can read.
+pub const INCOMPAT_SUPPORTED: u32 = INCOMPAT_FILETYPE
+    | INCOMPAT_RECOVER
+    | INCOMPAT_META_BG
+    | INCOMPAT_EXTENTS
+    | INCOMPAT_64BIT
+    | INCOMPAT_MMP
+    | INCOMPAT_FLEX_BG
+    | INCOMPAT_EA_INODE
+    | INCOMPAT_CSUM_SEED
+    | INCOMPAT_LARGEDIR;
+/// Incompatible features that we can read but not write.
+pub const INCOMPAT_READ_ONLY: u32 =
+    INCOMPAT_RECOVER | INCOMPAT_META_BG | INCOMPAT_MMP | INCOMPAT_EA_INODE;
+
+pub const RO_COMPAT_SPARSE_SUPER: u32 = 0x1;
+pub const RO_COMPAT_LARGE_FILE: u32 = 0x2;
+pub const RO_COMPAT_BTREE_DIR: u32 = 0x4;
+pub const RO_COMPAT_HUGE_FILE: u32 = 0x8;
+pub const RO_COMPAT_GDT_CSUM: u32 = 0x10;
+pub const RO_COMPAT_DIR_NLINK: u32 = 0x20;
+pub const RO_COMPAT_EXTRA_ISIZE: u32 = 0x40;
+pub const RO_COMPAT_METADATA_CSUM: u32 = 0x400;
+/// Read-only compatible features that we can write.
+pub const RO_COMPAT_WRITABLE: u32 = RO_COMPAT_SPARSE_SUPER
+    | RO_COMPAT_LARGE_FILE
+    | RO_COMPAT_BTREE_DIR
+    | RO_COMPAT_HUGE_FILE
+    | RO_COMPAT_GDT_CSUM
+    | RO_COMPAT_DIR_NLINK
+    | RO_COMPAT_EXTRA_ISIZE
+    | RO_COMPAT_METADATA_CSUM;
+
+pub const BG_INODE_UNINIT: u16 = 0x1;
+pub const BG_BLOCK_UNINIT: u16 = 0x2;
+
+pub const INODE_FLAG_INDEX: u32 = 0x1000;
+pub const INODE_FLAG_HUGE_FILE: u32 = 0x40000;
+pub const INODE_FLAG_EXTENTS: u32 = 0x80000;
+pub const INODE_FLAG_INLINE_DATA: u32 = 0x1000_0000;
+
+pub const S_IFMT: u16 = 0o170000;
+pub const S_IFDIR: u16 = 0o040000;
+pub const S_IFREG: u16 = 0o100000;
+pub const S_IFLNK: u16 = 0o120000;
+
+pub const EXTENT_MAGIC: u16 = 0xf30a;
+pub const EXTENT_ENTRY_SIZE: usize = 12;
+/// Extents longer than this are unwritten, i.e., read as zeros.
+pub const EXTENT_MAX_INIT_LEN: u16 = 32768;
+
+pub const DIR_TAIL_SIZE: usize = 12;
+pub const XATTR_MAGIC: u32 = 0xea02_0000;
+
+pub fn get_u16(buf: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes(buf[offset..offset + 2].try_into().unwrap())
+}
+
+pub fn get_u32(buf: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(buf[offset..offset + 4].try_into().unwrap())
+}
+
+pub fn set_u16(buf: &mut [u8], offset: usize, val: u16) {
+    buf[offset..offset + 2].copy_from_slice(&val.to_le_bytes());
+}
+
+pub fn set_u32(buf: &mut [u8], offset: usize, val: u32) {
+    buf[offset..offset + 4].copy_from_slice(&val.to_le_bytes());
+}
+
+/// The superblock.
+pub struct Superblock(pub [u8; SUPERBLOCK_SIZE]);
+
+impl Superblock {
+    pub fn inodes_count(&self) -> u32 {
+        get_u32(&self.0, 0x0)
+    }
+
+    pub fn blocks_count(&self) -> u64 {
+        let hi = if self.is_64bit() {
+            get_u32(&self.0, 0x150)
+        } else {
+            0
+        };
+        get_u32(&self.0, 0x4) as u64 | (hi as u64) << 32
+    }
+
+    pub fn free_blocks_count(&self) -> u64 {
+        let hi = if self.is_64bit() {
+            get_u32(&self.0, 0x158)
+        } else {
+            0
+        };
+        get_u32(&self.0, 0xc) as u64 | (hi as u64) << 32
+    }
+
+    pub fn set_free_blocks_count(&mut self, count: u64) {
+        set_u32(&mut self.0, 0xc, count as u32);
+        if self.is_64bit() {
+            set_u32(&mut self.0, 0x158, (count >> 32) as u32);
+        }
+    }
+
+    pub fn r_blocks_count(&self) -> u64 {
+        let hi = if self.is_64bit() {
+            get_u32(&self.0, 0x154)
+        } else {
+            0
+        };
+        get_u32(&self.0, 0x8) as u64 | (hi as u64) << 32
+    }
+
+    pub fn free_inodes_count(&self) -> u32 {
+        get_u32(&self.0, 0x10)
+    }
+
+    pub fn set_free_inodes_count(&mut self, count: u32) {
+        set_u32(&mut self.0, 0x10, count)
+    }
+
+    pub fn first_data_block(&self) -> u32 {
+        get_u32(&self.0, 0x14)
+    }
+
+    pub fn log_block_size(&self) -> u32 {
+        get_u32(&self.0, 0x18)
+    }
+
+    pub fn blocks_per_group(&self) -> u32 {
+        get_u32(&self.0, 0x20)
+    }
+
+    pub fn inodes_per_group(&self) -> u32 {
+        get_u32(&self.0, 0x28)
+    }
+
+    pub fn set_wtime(&mut self, time: u32) {
+        set_u32(&mut self.0, 0x30, time)
+    }
+
+    pub fn magic(&self) -> u16 {
+        get_u16(&self.0, 0x38)
+    }
+
+    pub fn rev_level(&self) -> u32 {
+        get_u32(&self.0, 0x4c)
+    }
+
+    pub fn first_ino(&self) -> u32 {
+        if self.rev_level() == 0 {
+            11
+        } else {
+            get_u32(&self.0, 0x54)
+        }
+    }
+
+    pub fn inode_size(&self) -> usize {
+        if self.rev_level() == 0 {
+            128
+        } else {
+            get_u16(&self.0, 0x58) as usize
+        }
+    }
+
+    pub fn feature_compat(&self) -> u32 {
+        get_u32(&self.0, 0x5c)
+    }
+
+    pub fn feature_incompat(&self) -> u32 {
+        get_u32(&self.0, 0x60)
+    }
+
+    pub fn feature_ro_compat(&self) -> u32 {
+        get_u32(&self.0, 0x64)
+    }
+
+    pub fn uuid(&self) -> &[u8] {
+        &self.0[0x68..0x78]
+    }
+
+    pub fn reserved_gdt_blocks(&self) -> u32 {
+        get_u16(&self.0, 0xce) as u32
+    }
+
+    pub fn desc_size(&self) -> usize {
+        if self.is_64bit() {
+            get_u16(&self.0, 0xfe) as usize
+        } else {
+            32
+        }
+    }
+
+    pub fn first_meta_bg(&self) -> u32 {
+        get_u32(&self.0, 0x104)
+    }
+
+    pub fn want_extra_isize(&self) -> u16 {
+        get_u16(&self.0, 0x15e)
+    }
+
+    /// Returns the first inode on the list of orphan inodes, which are
+    /// removed but still opened. The list is linked through `i_dtime`.
+    pub fn last_orphan(&self) -> u32 {
+        get_u32(&self.0, 0xe8)
+    }
+
+    pub fn set_last_orphan(&mut self, ino: u32) {
+        set_u32(&mut self.0, 0xe8, ino)
+    }
+
+    pub fn backup_bgs(&self) -> [u32; 2] {
+        [get_u32(&self.0, 0x24c), get_u32(&self.0, 0x250)]
+    }
+
+    pub fn checksum_seed(&self) -> u32 {
+        get_u32(&self.0, 0x270)
+    }
+
+    pub fn has_compat(&self, feature: u32) -> bool {
+        self.feature_compat() & feature != 0
+    }
+
+    pub fn has_incompat(&self, feature: u32) -> bool {
+        self.feature_incompat() & feature != 0
+    }
+
+    pub fn has_ro_compat(&self, feature: u32) -> bool {
+        self.feature_ro_compat() & feature != 0
+    }
+
+    pub fn is_64bit(&self) -> bool {
+        self.has_incompat(INCOMPAT_64BIT)
+    }
+
+    pub fn has_metadata_csum(&self) -> bool {
+        self.has_ro_compat(RO_COMPAT_METADATA_CSUM)
+    }
+
+    pub fn update_checksum(&mut self) {
+        if self.has_metadata_csum() {
+            let csum = crc32c(!0, &self.0[..0x3fc]);
+            set_u32(&mut self.0, 0x3fc, csum);
+        }
+    }
+}
+
+/// A block group descriptor.
+pub struct GroupDesc(pub Vec<u8>);
+
+impl GroupDesc {
+    fn get_lo_hi32(&self, lo: usize, hi: usize) -> u64 {
+        let hi = if self.0.len() >= 64 {
+            get_u32(&self.0, hi)
+        } else {
+            0
+        };
+        get_u32(&self.0, lo) as u64 | (hi as u64) << 32
+    }
+
+    fn get_lo_hi16(&self, lo: usize, hi: usize) -> u32 {
+        let hi = if self.0.len() >= 64 {
+            get_u16(&self.0, hi)
+        } else {
+            0
+        };
+        get_u16(&self.0, lo) as u32 | (hi as u32) << 16
+    }
+
+    fn set_lo_hi16(&mut self, lo: usize, hi: usize, val: u32) {
+        set_u16(&mut self.0, lo, val as u16);
+        if self.0.len() >= 64 {
+            set_u16(&mut self.0, hi, (val >> 16) as u16);
+        }
+    }
+
+    pub fn block_bitmap(&self) -> u64 {
+        self.get_lo_hi32(0x0, 0x20)
+    }
+
+    pub fn inode_bitmap(&self) -> u64 {
+        self.get_lo_hi32(0x4, 0x24)
+    }
+
+    pub fn inode_table(&self) -> u64 {
+        self.get_lo_hi32(0x8, 0x28)
+    }
+
+    pub fn free_blocks_count(&self) -> u32 {
+        self.get_lo_hi16(0xc, 0x2c)
+    }
+
+    pub fn set_free_blocks_count(&mut self, count: u32) {
+        self.set_lo_hi16(0xc, 0x2c, count)
+    }
+
+    pub fn free_inodes_count(&self) -> u32 {
+        self.get_lo_hi16(0xe, 0x2e)
+    }
+
+    pub fn set_free_inodes_count(&mut self, count: u32) {
+        self.set_lo_hi16(0xe, 0x2e, count)
+    }
+
+    pub fn used_dirs_count(&self) -> u32 {
+        self.get_lo_hi16(0x10, 0x30)
+    }
+
+    pub fn set_used_dirs_count(&mut self, count: u32) {
+        self.set_lo_hi16(0x10, 0x30, count)
+    }
+
+    pub fn flags(&self) -> u16 {
+        get_u16(&self.0, 0x12)
+    }
+
+    pub fn set_flags(&mut self, flags: u16) {
+        set_u16(&mut self.0, 0x12, flags)
+    }
+
+    pub fn itable_unused(&self) -> u32 {
+        self.get_lo_hi16(0x1c, 0x32)
+    }
+
+    pub fn set_itable_unused(&mut self, count: u32) {
+        self.set_lo_hi16(0x1c, 0x32, count)
+    }
+
+    pub fn set_block_bitmap_csum(&mut self, csum: u32) {
+        self.set_lo_hi16(0x18, 0x38, csum)
+    }
+
+    pub fn set_inode_bitmap_csum(&mut self, csum: u32) {
+        self.set_lo_hi16(0x1a, 0x3a, csum)
+    }
+
+    /// Updates the checksum of the descriptor of group `group`.
+    pub fn update_checksum(&mut self, sb: &Superblock, csum_seed: u32, group: u32) {
+        let csum = if sb.has_metadata_csum() {
+            let mut desc = self.0.clone();
+            set_u16(&mut desc, 0x1e, 0);
+            let csum = crc32c(csum_seed, &group.to_le_bytes());
+            crc32c(csum, &desc) as u16
+        } else if sb.has_ro_compat(RO_COMPAT_GDT_CSUM) {
+            let mut csum = crc16(!0, sb.uuid());
+            csum = crc16(csum, &group.to_le_bytes());
+            csum = crc16(csum, &self.0[..0x1e]);
+            crc16(csum, &self.0[0x20..])
+        } else {
+            return;
+        };
+        set_u16(&mut self.0, 0x1e, csum);
+    }
+}
+
+/// An inode with its number.
+#[derive(Clone)]
+pub struct Inode {
+    pub ino: u32,
+    pub raw: Vec<u8>,
+}
+
+impl Inode {
+    pub fn mode(&self) -> u16 {
+        get_u16(&self.raw, 0x0)
+    }
+
+    pub fn set_mode(&mut self, mode: u16) {
+        set_u16(&mut self.raw, 0x0, mode)
+    }
+
+    pub fn file_type(&self) -> u16 {
+        self.mode() & S_IFMT
+    }
+
+    pub fn is_dir(&self) -> bool {
+        self.file_type() == S_IFDIR
+    }
+
+    pub fn size(&self) -> u64 {
+        get_u32(&self.raw, 0x4) as u64 | (get_u32(&self.raw, 0x6c) as u64) << 32
+    }
+
+    pub fn set_size(&mut self, size: u64) {
+        set_u32(&mut self.raw, 0x4, size as u32);
+        set_u32(&mut self.raw, 0x6c, (size >> 32) as u32);
+    }
+
+    /// Sets the access, change and modification time.
+    pub fn set_times(&mut self, atime: bool, ctime: bool, mtime: bool, now: u32) {
+        for (set, offset) in [(atime, 0x8), (ctime, 0xc), (mtime, 0x10)] {
+            if set {
+                set_u32(&mut self.raw, offset, now);
+            }
+        }
+    }
+
+    /// Returns the access, change and modification time.
+    pub fn times(&self) -> [u32; 3] {
+        [0x8, 0xc, 0x10].map(|offset| get_u32(&self.raw, offset))
+    }
+
+    pub fn set_atime(&mut self, time: u32) {
+        set_u32(&mut self.raw, 0x8, time)
+    }
+
+    pub fn set_mtime(&mut self, time: u32) {
+        set_u32(&mut self.raw, 0x10, time)
+    }
+
+    /// Returns the deletion time, or the next inode on the orphan list.
+    pub fn dtime(&self) -> u32 {
+        get_u32(&self.raw, 0x14)
+    }
+
+    pub fn set_dtime(&mut self, time: u32) {
+        set_u32(&mut self.raw, 0x14, time)
+    }
+
+    pub fn uid(&self) -> u32 {
+        get_u16(&self.raw, 0x2) as u32 | (get_u16(&self.raw, 0x78) as u32) << 16
+    }
+
+    pub fn set_uid(&mut self, uid: u32) {
+        set_u16(&mut self.raw, 0x2, uid as u16);
+        set_u16(&mut self.raw, 0x78, (uid >> 16) as u16);
+    }
+
+    pub fn gid(&self) -> u32 {
+        get_u16(&self.raw, 0x18) as u32 | (get_u16(&self.raw, 0x7a) as u32) << 16
+    }
+
+    pub fn set_gid(&mut self, gid: u32) {
+        set_u16(&mut self.raw, 0x18, gid as u16);
+        set_u16(&mut self.raw, 0x7a, (gid >> 16) as u16);
+    }
+
+    pub fn links_count(&self) -> u16 {
+        get_u16(&self.raw, 0x1a)
+    }
+
+    pub fn set_links_count(&mut self, count: u16) {
+        set_u16(&mut self.raw, 0x1a, count)
+    }
+
+    /// Returns `i_blocks` as is, which is in 512-byte units unless the
+    /// `HUGE_FILE` flag is set.
+    pub fn raw_blocks(&self, sb: &Superblock) -> u64 {
+        let hi = if sb.has_ro_compat(RO_COMPAT_HUGE_FILE) {
+            get_u16(&self.raw, 0x74)
+        } else {
+            0
+        };
+        get_u32(&self.raw, 0x1c) as u64 | (hi as u64) << 32
+    }
+
+    pub fn set_raw_blocks(&mut self, sb: &Superblock, blocks: u64) {
+        set_u32(&mut self.raw, 0x1c, blocks as u32);
+        if sb.has_ro_compat(RO_COMPAT_HUGE_FILE) {
+            set_u16(&mut self.raw, 0x74, (blocks >> 32) as u16);
+        }
+    }
+
+    pub fn flags(&self) -> u32 {
+        get_u32(&self.raw, 0x20)
+    }
+
+    pub fn set_flags(&mut self, flags: u32) {
+        set_u32(&mut self.raw, 0x20, flags)
+    }
+
+    pub fn has_flag(&self, flag: u32) -> bool {
+        self.flags() & flag != 0
+    }
+
+    /// The `i_block` field: block map, extent tree root or symlink target.
+    pub fn i_block(&self) -> &[u8] {
+        &self.raw[0x28..0x64]
+    }
+
+    pub fn i_block_mut(&mut self) -> &mut [u8] {
+        &mut self.raw[0x28..0x64]
+    }
+
+    pub fn generation(&self) -> u32 {
+        get_u32(&self.raw, 0x64)
+    }
+
+    pub fn file_acl(&self) -> u64 {
+        get_u32(&self.raw, 0x68) as u64 | (get_u16(&self.raw, 0x76) as u64) << 32
+    }
+
+    pub fn set_file_acl(&mut self, block: u64) {
+        set_u32(&mut self.raw, 0x68, block as u32);
+        set_u16(&mut self.raw, 0x76, (block >> 32) as u16);
+    }
+
+    pub fn extra_isize(&self) -> usize {
+        if self.raw.len() > 128 {
+            get_u16(&self.raw, 0x80) as usize
+        } else {
+            0
+        }
+    }
+
+    /// The seed of the checksums of this inode and its metadata blocks.
+    pub fn csum_seed(&self, fs_seed: u32) -> u32 {
+        let csum = crc32c(fs_seed, &self.ino.to_le_bytes());
+        crc32c(csum, &self.generation().to_le_bytes())
+    }
+
+    pub fn update_checksum(&mut self, fs_seed: u32) {
+        let has_hi = self.extra_isize() >= 4;
+        set_u16(&mut self.raw, 0x7c, 0);
+        if has_hi {
+            set_u16(&mut self.raw, 0x82, 0);
+        }
+        let csum = crc32c(self.csum_seed(fs_seed), &self.raw);
+        set_u16(&mut self.raw, 0x7c, csum as u16);
+        if has_hi {
+            set_u16(&mut self.raw, 0x82, (csum >> 16) as u16);
+        }
+    }
+}
+
+/// The header of an extent tree node.
+pub struct ExtentHeader {
+    pub entries: usize,
+    pub max: usize,
+    pub depth: u16,
+}
+
+impl ExtentHeader {
+    pub fn parse(node: &[u8]) -> Option<Self> {
+        if get_u16(node, 0) != EXTENT_MAGIC {
+            return None;
+        }
+        let hdr = Self {
+            entries: get_u16(node, 2) as usize,
+            max: get_u16(node, 4) as usize,
+            depth: get_u16(node, 6),
+        };
+        let fits = (hdr.max + 1) * EXTENT_ENTRY_SIZE <= node.len();
+        (hdr.entries <= hdr.max && fits).then_some(hdr)
+    }
+
+    pub fn write(&self, node: &mut [u8]) {
+        set_u16(node, 0, EXTENT_MAGIC);
+        set_u16(node, 2, self.entries as u16);
+        set_u16(node, 4, self.max as u16);
+        set_u16(node, 6, self.depth);
+    }
+}
+
+/// A leaf entry of an extent tree.
+#[derive(Debug, Clone, Copy)]
+pub struct Extent {
+    /// The first logical block.
+    pub block: u32,
+    pub len: u32,
+    /// The first physical block.
+    pub start: u64,
+    pub unwritten: bool,
+}
+
+impl Extent {
+    pub fn parse(entry: &[u8]) -> Self {
+        let len = get_u16(entry, 4);
+        let (len, unwritten) = if len > EXTENT_MAX_INIT_LEN {
+            (len - EXTENT_MAX_INIT_LEN, true)
+        } else {
+            (len, false)
+        };
+        Self {
+            block: get_u32(entry, 0),
+            len: len as u32,
+            start: get_u32(entry, 8) as u64 | (get_u16(entry, 6) as u64) << 32,
+            unwritten,
+        }
+    }
+
+    pub fn write(&self, entry: &mut [u8]) {
+        let len = self.len as u16
+            + if self.unwritten {
+                EXTENT_MAX_INIT_LEN
+            } else {
+                0
+            };
+        set_u32(entry, 0, self.block);
+        set_u16(entry, 4, len);
+        set_u16(entry, 6, (self.start >> 32) as u16);
+        set_u32(entry, 8, self.start as u32);
+    }
+
+    pub fn end(&self) -> u32 {
+        self.block + self.len
+    }
+
+    pub fn contains(&self, block: u32) -> bool {
+        self.block <= block && block < self.end()
+    }
+}
+
+/// Returns the first logical block and the child of an index entry.
+pub fn parse_extent_index(entry: &[u8]) -> (u32, u64) {
+    let leaf = get_u32(entry, 4) as u64 | (get_u16(entry, 8) as u64) << 32;
+    (get_u32(entry, 0), leaf)
+}
+
+pub fn write_extent_index(entry: &mut [u8], block: u32, leaf: u64) {
+    set_u32(entry, 0, block);
+    set_u32(entry, 4, leaf as u32);
+    set_u16(entry, 8, (leaf >> 32) as u16);
+    set_u16(entry, 10, 0);
+}
+
+const fn crc32c_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut i = 0;
+    while i < 256 {
+        let mut crc = i as u32;
+        let mut j = 0;
+        while j < 8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0x82f6_3b78
+            } else {
+                crc >> 1
+            };
+            j += 1;
+        }
+        table[i] = crc;
+        i += 1;
+    }
+    table
+}
+
+static CRC32C_TABLE: [u32; 256] = crc32c_table();
+
+/// CRC32C without the final inversion, as used by ext4.
+pub fn crc32c(mut crc: u32, data: &[u8]) -> u32 {
+    for &b in data {
+        crc = CRC32C_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8);
+    }
+    crc
+}
+
+/// CRC16 (polynomial `0x8005`, reflected) for the `GDT_CSUM` feature.
+pub fn crc16(mut crc: u16, data: &[u8]) -> u16 {
+    for &b in data {
+        crc ^= b as u16;
+        for _ in 0..8 {
+            crc = if crc & 1 != 0 {
+                (crc >> 1) ^ 0xa001
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
@@ -0,0 +1,749 @@
+//! The ext2/ext3/ext4 filesystem.
+//!
+//! Block maps and extent trees, linear and hash-indexed directories, fast and
+//! slow symbolic links, hard links and the checksums of `metadata_csum` and
+//! `uninit_bg` are supported for both reading and writing.
+//!
+//! Some features are not supported yet:
+//!
+//! - The journal is neither replayed nor written, so the filesystem must be
+//!   cleanly unmounted before. It is mounted read-only if it needs recovery.
+//! - Extended attributes, inline data, encryption and quotas. Filesystems with
+//!   unknown incompatible features cannot be mounted, and those with unknown
+//!   read-only compatible features are mounted read-only.
+//! - Hash-indexed directories are converted to linear ones when modified.
+//!
+//! Removed inodes that are still opened are put on the orphan list, and freed
+//! when they are closed for the last time, or at the next mount if the
+//! filesystem is not unmounted before.
+
+mod dir;
+mod inode;
+mod layout;
+mod node;
+
+use alloc::{collections::BTreeMap, sync::Arc, sync::Weak, vec, vec::Vec};
+
+use axfs_vfs::{FileSystemInfo, VfsError, VfsNodeRef, VfsOps, VfsResult};
+use axsync::Mutex;
+
+use self::layout::*;
+use self::node::Ext4Node;
+use crate::dev::Disk;
+
+/// The ext2/ext3/ext4 filesystem that implements [`axfs_vfs::VfsOps`].
+pub struct Ext4FileSystem {
+    this: Weak<Self>,
+    name: &'static str,
+    parent: Mutex<Option<VfsNodeRef>>,
+    inner: Mutex<FsInner>,
+}
+
+impl Ext4FileSystem {
+    /// Checks whether `disk` contains an ext2/ext3/ext4 filesystem.
+    pub fn probe(disk: &Disk) -> bool {
+        let mut disk = disk.try_clone();
+        let mut magic = [0; 2];
+        disk.set_position(SUPERBLOCK_OFFSET + 0x38);
+        matches!(disk.read_one(&mut magic), Ok(2)) && u16::from_le_bytes(magic) == EXT4_MAGIC
+    }
+
+    /// Opens the filesystem on `disk`.
+    pub fn open(disk: Disk) -> VfsResult<Arc<Self>> {
+        let inner = FsInner::load(disk)?;
+        let sb = &inner.sb;
+        let name = if sb.has_incompat(INCOMPAT_EXTENTS | INCOMPAT_64BIT | INCOMPAT_FLEX_BG) {
+            "ext4"
+        } else if sb.has_compat(COMPAT_HAS_JOURNAL) {
+            "ext3"
+        } else {
+            "ext2"
+        };
+        info!(
+            "{}: {} blocks of {} bytes, {} inodes{}",
+            name,
+            sb.blocks_count(),
+            inner.block_size,
+            sb.inodes_count(),
+            if inner.writable { "" } else { ", read-only" }
+        );
+        Ok(Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            name,
+            parent: Mutex::new(None),
+            inner: Mutex::new(inner),
+        }))
+    }
+
+    fn node(&self, ino: u32) -> Arc<Ext4Node> {
+        Arc::new(Ext4Node::new(self.this.upgrade().unwrap(), ino))
+    }
+}
+
+impl VfsOps for Ext4FileSystem {
+    fn mount(&self, _path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        *self.parent.lock() = mount_point.parent();
+        Ok(())
+    }
+
+    fn umount(&self) -> VfsResult {
+        self.inner.lock().flush()
+    }
+
+    fn sync(&self) -> VfsResult {
+        self.inner.lock().flush()
+    }
+
+    fn statfs(&self) -> VfsResult<FileSystemInfo> {
+        let fs = self.inner.lock();
+        let sb = &fs.sb;
+        Ok(FileSystemInfo {
+            block_size: fs.block_size as u64,
+            blocks: sb.blocks_count(),
+            // the reserved blocks are not available to unprivileged users
+            blocks_free: sb.free_blocks_count().saturating_sub(sb.r_blocks_count()),
+            files: sb.inodes_count() as u64,
+            files_free: sb.free_inodes_count() as u64,
+            name_max: 255,
+        })
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        self.node(ROOT_INO)
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+}
+
+/// The state of the filesystem, protected by a single lock.
+struct FsInner {
+    disk: Disk,
+    sb: Superblock,
+    groups: Vec<GroupDesc>,
+    block_size: usize,
+    csum_seed: u32,
+    writable: bool,
+    /// The number of times that each opened inode is opened.
+    opened: BTreeMap<u32, usize>,
+}
+
+impl FsInner {
+    fn load(mut disk: Disk) -> VfsResult<Self> {
+        let mut sb = Superblock([0; SUPERBLOCK_SIZE]);
+        read_disk(&mut disk, SUPERBLOCK_OFFSET, &mut sb.0)?;
+        if sb.magic() != EXT4_MAGIC {
+            return Err(VfsError::InvalidData);
+        }
+        let unsupported = sb.feature_incompat() & !INCOMPAT_SUPPORTED;
+        if unsupported != 0 {
+            warn!("ext4: unsupported incompatible features {:#x}", unsupported);
+            return Err(VfsError::Unsupported);
+        }
+        if sb.log_block_size() > 6
+            || sb.blocks_per_group() == 0
+            || sb.inodes_per_group() == 0
+            || sb.inode_size() < 128
+            || !sb.inode_size().is_power_of_two()
+            || sb.desc_size() < 32
+        {
+            return Err(VfsError::InvalidData);
+        }
+        let block_size = 1024 << sb.log_block_size();
+        if sb.blocks_count() * block_size as u64 > disk.size() {
+            warn!("ext4: the filesystem is larger than the disk");
+            return Err(VfsError::InvalidData);
+        }
+
+        let writable = if sb.has_incompat(INCOMPAT_RECOVER) {
+            warn!("ext4: the journal needs recovery, mounting read-only");
+            false
+        } else {
+            sb.feature_incompat() & INCOMPAT_READ_ONLY == 0
+                && sb.feature_ro_compat() & !RO_COMPAT_WRITABLE == 0
+                && !sb.has_compat(COMPAT_SPARSE_SUPER2)
+        };
+        let csum_seed = if sb.has_incompat(INCOMPAT_CSUM_SEED) {
+            sb.checksum_seed()
+        } else {
+            crc32c(!0, sb.uuid())
+        };
+        let mut fs = Self {
+            disk,
+            sb,
+            groups: Vec::new(),
+            block_size,
+            csum_seed,
+            writable,
+            opened: BTreeMap::new(),
+        };
+        let desc_size = fs.sb.desc_size();
+        for group in 0..fs.group_count() {
+            let mut desc = vec![0; desc_size];
+            fs.read_bytes(fs.desc_pos(group), &mut desc)?;
+            fs.groups.push(GroupDesc(desc));
+        }
+        if fs.writable {
+            fs.release_orphans()?;
+        }
+        Ok(fs)
+    }
+
+    /// Frees the inodes left on the orphan list, which were still opened when
+    /// the filesystem was last used.
+    fn release_orphans(&mut self) -> VfsResult {
+        let mut count = 0;
+        while self.sb.last_orphan() != 0 && count < self.sb.inodes_count() {
+            let mut inode = self.read_inode(self.sb.last_orphan())?;
+            self.sb.set_last_orphan(inode.dtime());
+            if inode.links_count() == 0 {
+                self.evict_inode(inode)?;
+            } else {
+                // not removed, e.g., by an interrupted truncation
+                inode.set_dtime(0);
+                self.write_inode(&mut inode)?;
+            }
+            count += 1;
+        }
+        if count > 0 {
+            info!("ext4: released {} orphan inodes", count);
+            self.sb.set_last_orphan(0);
+            self.write_superblock()?;
+        }
+        Ok(())
+    }
+
+    fn group_count(&self) -> u32 {
+        let data_blocks = self.sb.blocks_count() - self.sb.first_data_block() as u64;
+        data_blocks.div_ceil(self.sb.blocks_per_group() as u64) as u32
+    }
+
+    fn group_first_block(&self, group: u32) -> u64 {
+        self.sb.first_data_block() as u64 + group as u64 * self.sb.blocks_per_group() as u64
+    }
+
+    fn blocks_in_group(&self, group: u32) -> u32 {
+        let end = self.group_first_block(group) + self.sb.blocks_per_group() as u64;
+        (end.min(self.sb.blocks_count()) - self.group_first_block(group)) as u32
+    }
+
+    /// Whether the group has a backup of the superblock and descriptors.
+    fn group_has_super(&self, group: u32) -> bool {
+        let is_power_of = |mut n: u32, base: u32| {
+            while n > 1 && n % base == 0 {
+                n /= base;
+            }
+            n == 1
+        };
+        if group == 0 {
+            true
+        } else if self.sb.has_compat(COMPAT_SPARSE_SUPER2) {
+            self.sb.backup_bgs().contains(&group)
+        } else if !self.sb.has_ro_compat(RO_COMPAT_SPARSE_SUPER) {
+            true
+        } else {
+            is_power_of(group, 3) || is_power_of(group, 5) || is_power_of(group, 7)
+        }
+    }
+
+    /// Returns the position on the disk of the descriptor of `group`.
+    fn desc_pos(&self, group: u32) -> u64 {
+        let desc_size = self.sb.desc_size();
+        let descs_per_block = (self.block_size / desc_size) as u32;
+        let (index, offset) = (group / descs_per_block, group % descs_per_block);
+        let block = if self.sb.has_incompat(INCOMPAT_META_BG) && index >= self.sb.first_meta_bg() {
+            let first = index * descs_per_block;
+            self.group_first_block(first) + self.group_has_super(first) as u64
+        } else {
+            self.sb.first_data_block() as u64 + 1 + index as u64
+        };
+        block * self.block_size as u64 + offset as u64 * desc_size as u64
+    }
+
+    fn read_bytes(&mut self, pos: u64, buf: &mut [u8]) -> VfsResult {
+        read_disk(&mut self.disk, pos, buf)
+    }
+
+    fn write_bytes(&mut self, pos: u64, buf: &[u8]) -> VfsResult {
+        self.disk.set_position(pos);
+        let mut write_len = 0;
+        while write_len < buf.len() {
+            write_len += self
+                .disk
+                .write_one(&buf[write_len..])
+                .map_err(|_| VfsError::Io)?;
+        }
+        Ok(())
+    }
+
+    fn read_block(&mut self, block: u64) -> VfsResult<Vec<u8>> {
+        let mut buf = vec![0; self.block_size];
+        self.read_bytes(block * self.block_size as u64, &mut buf)?;
+        Ok(buf)
+    }
+
+    fn write_block(&mut self, block: u64, data: &[u8]) -> VfsResult {
+        self.write_bytes(block * self.block_size as u64, data)
+    }
+
+    fn flush(&mut self) -> VfsResult {
+        self.disk.flush().map_err(|_| VfsError::Io)
+    }
+
+    fn check_writable(&self) -> VfsResult {
+        if self.writable {
+            Ok(())
+        } else {
+            Err(VfsError::PermissionDenied)
+        }
+    }
+
+    fn write_superblock(&mut self) -> VfsResult {
+        self.sb.set_wtime(now());
+        self.sb.update_checksum();
+        let sb = self.sb.0;
+        self.write_bytes(SUPERBLOCK_OFFSET, &sb)
+    }
+
+    fn write_group_desc(&mut self, group: u32) -> VfsResult {
+        self.groups[group as usize].update_checksum(&self.sb, self.csum_seed, group);
+        let pos = self.desc_pos(group);
+        let desc = self.groups[group as usize].0.clone();
+        self.write_bytes(pos, &desc)
+    }
+
+    fn bitmap_csum(&self, bitmap: &[u8], bits: u32) -> u32 {
+        crc32c(self.csum_seed, &bitmap[..bits as usize / 8])
+    }
+
+    fn has_group_csum(&self) -> bool {
+        self.sb.has_metadata_csum() || self.sb.has_ro_compat(RO_COMPAT_GDT_CSUM)
+    }
+
+    /// Reads the block bitmap of `group`, which is initialized first if the
+    /// group is marked uninitialized.
+    fn read_block_bitmap(&mut self, group: u32) -> VfsResult<Vec<u8>> {
+        let desc = &self.groups[group as usize];
+        if !self.has_group_csum() || desc.flags() & BG_BLOCK_UNINIT == 0 {
+            return self.read_block(desc.block_bitmap());
+        }
+        // only the metadata of the group itself is in use
+        let mut bitmap = vec![0; self.block_size];
+        let first = self.group_first_block(group);
+        let mut used = Vec::new();
+        if self.group_has_super(group) {
+            let descs_per_block = (self.block_size / self.sb.desc_size()) as u32;
+            let gdt_blocks = self.group_count().div_ceil(descs_per_block);
+            used.extend(first..first + 1 + (gdt_blocks + self.sb.reserved_gdt_blocks()) as u64);
+        }
+        let inode_table_blocks =
+            (self.sb.inodes_per_group() as usize * self.sb.inode_size()).div_ceil(self.block_size);
+        let desc = &self.groups[group as usize];
+        used.push(desc.block_bitmap());
+        used.push(desc.inode_bitmap());
+        used.extend(desc.inode_table()..desc.inode_table() + inode_table_blocks as u64);
+        let range = first..first + self.blocks_in_group(group) as u64;
+        for block in used.into_iter().filter(|b| range.contains(b)) {
+            set_bit(&mut bitmap, (block - first) as usize);
+        }
+        for bit in self.blocks_in_group(group) as usize..self.block_size * 8 {
+            set_bit(&mut bitmap, bit);
+        }
+        Ok(bitmap)
+    }
+
+    /// Allocates a block, preferably at or after `goal`.
+    fn alloc_block(&mut self, goal: u64) -> VfsResult<u64> {
+        if self.sb.free_blocks_count() == 0 {
+            return Err(VfsError::StorageFull);
+        }
+        let goal = goal.clamp(
+            self.sb.first_data_block() as u64,
+            self.sb.blocks_count() - 1,
+        );
+        let goal_group =
+            ((goal - self.sb.first_data_block() as u64) / self.sb.blocks_per_group() as u64) as u32;
+        let groups = self.group_count();
+        for group in (goal_group..groups).chain(0..goal_group) {
+            if self.groups[group as usize].free_blocks_count() == 0 {
+                continue;
+            }
+            let mut bitmap = self.read_block_bitmap(group)?;
+            let first = self.group_first_block(group);
+            let start = if group == goal_group {
+                (goal - first) as usize
+            } else {
+                0
+            };
+            let count = self.blocks_in_group(group) as usize;
+            let Some(bit) =
+                find_zero_bit(&bitmap, start, count).or_else(|| find_zero_bit(&bitmap, 0, start))
+            else {
+                continue;
+            };
+            set_bit(&mut bitmap, bit);
+            let csum = self.bitmap_csum(&bitmap, self.sb.blocks_per_group());
+            let desc = &mut self.groups[group as usize];
+            let bitmap_block = desc.block_bitmap();
+            desc.set_free_blocks_count(desc.free_blocks_count() - 1);
+            desc.set_flags(desc.flags() & !BG_BLOCK_UNINIT);
+            if self.sb.has_metadata_csum() {
+                desc.set_block_bitmap_csum(csum);
+            }
+            self.write_block(bitmap_block, &bitmap)?;
+            self.write_group_desc(group)?;
+            let free = self.sb.free_blocks_count();
+            self.sb.set_free_blocks_count(free - 1);
+            self.write_superblock()?;
+            return Ok(first + bit as u64);
+        }
+        Err(VfsError::StorageFull)
+    }
+
+    /// Allocates a block filled with zeros.
+    fn alloc_zeroed_block(&mut self, goal: u64) -> VfsResult<u64> {
+        let block = self.alloc_block(goal)?;
+        self.write_block(block, &vec![0; self.block_size])?;
+        Ok(block)
+    }
+
+    fn free_block(&mut self, block: u64) -> VfsResult {
+        self.free_blocks(block, 1)
+    }
+
+    /// Frees `count` contiguous blocks starting from `start`.
+    fn free_blocks(&mut self, mut start: u64, mut count: u64) -> VfsResult {
+        let first_data_block = self.sb.first_data_block() as u64;
+        if start < first_data_block || start + count > self.sb.blocks_count() {
+            warn!("ext4: freeing invalid blocks {}+{}", start, count);
+            return Err(VfsError::InvalidData);
+        }
+        let blocks_per_group = self.sb.blocks_per_group() as u64;
+        while count > 0 {
+            let group = ((start - first_data_block) / blocks_per_group) as u32;
+            let first_bit = ((start - first_data_block) % blocks_per_group) as usize;
+            let n = count.min(blocks_per_group - first_bit as u64) as usize;
+            let mut bitmap = self.read_block_bitmap(group)?;
+            let mut freed = 0;
+            for bit in first_bit..first_bit + n {
+                if test_bit(&bitmap, bit) {
+                    clear_bit(&mut bitmap, bit);
+                    freed += 1;
+                } else {
+                    warn!(
+                        "ext4: freeing free block {}",
+                        start + (bit - first_bit) as u64
+                    );
+                }
+            }
+            let csum = self.bitmap_csum(&bitmap, self.sb.blocks_per_group());
+            let desc = &mut self.groups[group as usize];
+            let bitmap_block = desc.block_bitmap();
+            desc.set_free_blocks_count(desc.free_blocks_count() + freed);
+            desc.set_flags(desc.flags() & !BG_BLOCK_UNINIT);
+            if self.sb.has_metadata_csum() {
+                desc.set_block_bitmap_csum(csum);
+            }
+            self.write_block(bitmap_block, &bitmap)?;
+            self.write_group_desc(group)?;
+            let free = self.sb.free_blocks_count();
+            self.sb.set_free_blocks_count(free + freed as u64);
+            start += n as u64;
+            count -= n as u64;
+        }
+        self.write_superblock()
+    }
+
+    fn read_inode_bitmap(&mut self, group: u32) -> VfsResult<Vec<u8>> {
+        let desc = &self.groups[group as usize];
+        if !self.has_group_csum() || desc.flags() & BG_INODE_UNINIT == 0 {
+            return self.read_block(desc.inode_bitmap());
+        }
+        let mut bitmap = vec![0; self.block_size];
+        for bit in self.sb.inodes_per_group() as usize..self.block_size * 8 {
+            set_bit(&mut bitmap, bit);
+        }
+        Ok(bitmap)
+    }
+
+    /// Allocates an inode, preferably in the group of inode `near`.
+    fn alloc_inode(&mut self, near: u32, is_dir: bool) -> VfsResult<u32> {
+        let ipg = self.sb.inodes_per_group();
+        let near_group = (near - 1) / ipg;
+        let groups = self.group_count();
+        for group in (near_group..groups).chain(0..near_group) {
+            if self.groups[group as usize].free_inodes_count() == 0 {
+                continue;
+            }
+            let mut bitmap = self.read_inode_bitmap(group)?;
+            let start = if group == 0 {
+                self.sb.first_ino() as usize - 1
+            } else {
+                0
+            };
+            let Some(bit) = find_zero_bit(&bitmap, start, ipg as usize) else {
+                continue;
+            };
+            set_bit(&mut bitmap, bit);
+            let csum = self.bitmap_csum(&bitmap, ipg);
+            let has_group_csum = self.has_group_csum();
+            let desc = &mut self.groups[group as usize];
+            let bitmap_block = desc.inode_bitmap();
+            desc.set_free_inodes_count(desc.free_inodes_count() - 1);
+            if is_dir {
+                desc.set_used_dirs_count(desc.used_dirs_count() + 1);
+            }
+            if has_group_csum {
+                desc.set_flags(desc.flags() & !BG_INODE_UNINIT);
+                let unused = ipg - bit as u32 - 1;
+                if unused < desc.itable_unused() {
+                    desc.set_itable_unused(unused);
+                }
+            }
+            if self.sb.has_metadata_csum() {
+                desc.set_inode_bitmap_csum(csum);
+            }
+            self.write_block(bitmap_block, &bitmap)?;
+            self.write_group_desc(group)?;
+            let free = self.sb.free_inodes_count();
+            self.sb.set_free_inodes_count(free - 1);
+            self.write_superblock()?;
+            return Ok(group * ipg + bit as u32 + 1);
+        }
+        Err(VfsError::StorageFull)
+    }
+
+    fn free_inode(&mut self, ino: u32, is_dir: bool) -> VfsResult {
+        let ipg = self.sb.inodes_per_group();
+        let (group, bit) = ((ino - 1) / ipg, ((ino - 1) % ipg) as usize);
+        let mut bitmap = self.read_inode_bitmap(group)?;
+        if !test_bit(&bitmap, bit) {
+            warn!("ext4: freeing free inode {}", ino);
+            return Ok(());
+        }
+        clear_bit(&mut bitmap, bit);
+        let csum = self.bitmap_csum(&bitmap, ipg);
+        let desc = &mut self.groups[group as usize];
+        let bitmap_block = desc.inode_bitmap();
+        desc.set_free_inodes_count(desc.free_inodes_count() + 1);
+        if is_dir {
+            desc.set_used_dirs_count(desc.used_dirs_count().saturating_sub(1));
+        }
+        if self.sb.has_metadata_csum() {
+            desc.set_inode_bitmap_csum(csum);
+        }
+        self.write_block(bitmap_block, &bitmap)?;
+        self.write_group_desc(group)?;
+        let free = self.sb.free_inodes_count();
+        self.sb.set_free_inodes_count(free + 1);
+        self.write_superblock()
+    }
+
+    fn inode_pos(&self, ino: u32) -> VfsResult<u64> {
+        if ino == 0 || ino > self.sb.inodes_count() {
+            return Err(VfsError::InvalidData);
+        }
+        let ipg = self.sb.inodes_per_group();
+        let (group, index) = ((ino - 1) / ipg, ((ino - 1) % ipg) as u64);
+        let table = self.groups[group as usize].inode_table();
+        Ok(table * self.block_size as u64 + index * self.sb.inode_size() as u64)
+    }
+
+    fn read_inode(&mut self, ino: u32) -> VfsResult<Inode> {
+        let mut raw = vec![0; self.sb.inode_size()];
+        self.read_bytes(self.inode_pos(ino)?, &mut raw)?;
+        Ok(Inode { ino, raw })
+    }
+
+    fn write_inode(&mut self, inode: &mut Inode) -> VfsResult {
+        if self.sb.has_metadata_csum() {
+            inode.update_checksum(self.csum_seed);
+        }
+        let pos = self.inode_pos(inode.ino)?;
+        self.write_bytes(pos, &inode.raw)
+    }
+
+    /// Allocates and initializes a new inode of `mode`, counting the entry
+    /// in its parent and, for directories, its own `.` entry as links.
+    fn new_inode(&mut self, near: u32, mode: u16) -> VfsResult<Inode> {
+        let is_dir = mode & S_IFMT == S_IFDIR;
+        let ino = self.alloc_inode(near, is_dir)?;
+        let mut inode = Inode {
+            ino,
+            raw: vec![0; self.sb.inode_size()],
+        };
+        inode.set_mode(mode);
+        inode.set_links_count(if is_dir { 2 } else { 1 });
+        inode.set_times(true, true, true, now());
+        if inode.raw.len() > 128 {
+            let extra_isize = match self.sb.want_extra_isize() as usize {
+                0 => 32,
+                n => n,
+            };
+            let extra_isize = extra_isize.min(inode.raw.len() - 128);
+            set_u16(&mut inode.raw, 0x80, extra_isize as u16);
+            set_u32(&mut inode.raw, 0x90, now()); // i_crtime
+        }
+        if self.sb.has_incompat(INCOMPAT_EXTENTS) && mode & S_IFMT != S_IFLNK {
+            inode.set_flags(INODE_FLAG_EXTENTS);
+            self::inode::init_extent_root(&mut inode);
+        }
+        self.write_inode(&mut inode)?;
+        Ok(inode)
+    }
+
+    /// Adds `delta` blocks of the filesystem to `i_blocks` of `inode`.
+    fn add_inode_blocks(&self, inode: &mut Inode, delta: i64) {
+        let unit = if inode.has_flag(INODE_FLAG_HUGE_FILE) {
+            1
+        } else {
+            self.block_size as i64 / 512
+        };
+        let blocks = inode.raw_blocks(&self.sb) as i64 + delta * unit;
+        inode.set_raw_blocks(&self.sb, blocks.max(0) as u64);
+    }
+
+    /// Releases the data and the extended attributes of an inode without
+    /// links, and the inode itself.
+    fn evict_inode(&mut self, mut inode: Inode) -> VfsResult {
+        if !(inode.file_type() == S_IFLNK && self.is_fast_symlink(&inode)) {
+            self.truncate_blocks(&mut inode, 0)?;
+        }
+        let acl = inode.file_acl();
+        if acl != 0 {
+            self.release_xattr_block(acl)?;
+            inode.set_file_acl(0);
+        }
+        inode.set_links_count(0);
+        inode.set_dtime(now());
+        self.write_inode(&mut inode)?;
+        self.free_inode(inode.ino, inode.is_dir())
+    }
+
+    /// Drops a reference to the shared extended attribute block.
+    fn release_xattr_block(&mut self, block: u64) -> VfsResult {
+        let mut data = self.read_block(block)?;
+        let refcount = get_u32(&data, 0x4);
+        if get_u32(&data, 0) != XATTR_MAGIC || refcount <= 1 {
+            return self.free_block(block);
+        }
+        set_u32(&mut data, 0x4, refcount - 1);
+        if self.sb.has_metadata_csum() {
+            set_u32(&mut data, 0x10, 0);
+            let csum = crc32c(self.csum_seed, &block.to_le_bytes());
+            let csum = crc32c(csum, &data);
+            set_u32(&mut data, 0x10, csum);
+        }
+        self.write_block(block, &data)
+    }
+
+    /// Removes a link to inode `ino`, which is released when it has no links.
+    /// A directory is always released.
+    ///
+    /// The inode is put on the orphan list instead if it is still opened.
+    fn drop_link(&mut self, ino: u32) -> VfsResult {
+        let mut inode = self.read_inode(ino)?;
+        let links = inode.links_count();
+        if !inode.is_dir() && links > 1 {
+            inode.set_links_count(links - 1);
+            inode.set_times(false, true, false, now());
+            self.write_inode(&mut inode)
+        } else if self.opened.contains_key(&ino) {
+            inode.set_links_count(0);
+            inode.set_times(false, true, false, now());
+            inode.set_dtime(self.sb.last_orphan());
+            self.write_inode(&mut inode)?;
+            self.sb.set_last_orphan(ino);
+            self.write_superblock()
+        } else {
+            self.evict_inode(inode)
+        }
+    }
+
+    fn open_inode(&mut self, ino: u32) {
+        *self.opened.entry(ino).or_default() += 1;
+    }
+
+    /// Closes inode `ino`, which is released if it is an orphan and not
+    /// opened anymore.
+    fn close_inode(&mut self, ino: u32) -> VfsResult {
+        let Some(count) = self.opened.get_mut(&ino) else {
+            return Ok(());
+        };
+        *count -= 1;
+        if *count > 0 {
+            return Ok(());
+        }
+        self.opened.remove(&ino);
+        let inode = self.read_inode(ino)?;
+        if inode.links_count() != 0 || !self.writable {
+            return Ok(());
+        }
+        self.remove_orphan(ino, inode.dtime())?;
+        self.evict_inode(inode)
+    }
+
+    /// Removes inode `ino` from the orphan list, `next` is the inode after it.
+    fn remove_orphan(&mut self, ino: u32, next: u32) -> VfsResult {
+        if self.sb.last_orphan() == ino {
+            self.sb.set_last_orphan(next);
+            return self.write_superblock();
+        }
+        let mut prev = self.sb.last_orphan();
+        for _ in 0..self.sb.inodes_count() {
+            if prev == 0 {
+                break;
+            }
+            let mut inode = self.read_inode(prev)?;
+            if inode.dtime() == ino {
+                inode.set_dtime(next);
+                return self.write_inode(&mut inode);
+            }
+            prev = inode.dtime();
+        }
+        warn!("ext4: inode {} is not on the orphan list", ino);
+        Ok(())
+    }
+}
+
+fn read_disk(disk: &mut Disk, pos: u64, buf: &mut [u8]) -> VfsResult {
+    disk.set_position(pos);
+    let mut read_len = 0;
+    while read_len < buf.len() {
+        read_len += disk
+            .read_one(&mut buf[read_len..])
+            .map_err(|_| VfsError::Io)?;
+    }
+    Ok(())
+}
+
+fn now() -> u32 {
+    axhal::time::wall_time().as_secs() as u32
+}
+
+fn test_bit(bitmap: &[u8], bit: usize) -> bool {
+    bitmap[bit / 8] & (1 << (bit % 8)) != 0
+}
+
+fn set_bit(bitmap: &mut [u8], bit: usize) {
+    bitmap[bit / 8] |= 1 << (bit % 8);
+}
+
+fn clear_bit(bitmap: &mut [u8], bit: usize) {
+    bitmap[bit / 8] &= !(1 << (bit % 8));
+}
+
+fn find_zero_bit(bitmap: &[u8], start: usize, end: usize) -> Option<usize> {
+    let mut bit = start;
+    while bit < end {
+        if bit % 8 == 0 && bitmap[bit / 8] == 0xff {
+            bit += 8; // skip full bytes
+        } else if test_bit(bitmap, bit) {
+            bit += 1;
+        } else {
+            return Some(bit);
+        }
+    }
+    None
+}
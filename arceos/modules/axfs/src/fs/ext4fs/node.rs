@@ -0,0 +1,458 @@
+//! Inodes as VFS nodes.
+
+use alloc::{string::String, sync::Arc};
+use core::time::Duration;
+
+use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeRef, VfsNodeType};
+use axfs_vfs::{VfsError, VfsResult, VfsSetAttr};
+
+use super::dir::{dirent_node_type, DirEntry};
+use super::layout::*;
+use super::{Ext4FileSystem, FsInner};
+
+/// The maximum number of `..` followed to check whether a directory is moved
+/// into its own subtree.
+const MAX_DIR_DEPTH: usize = 4096;
+
+/// An inode in the ext4 filesystem.
+///
+/// It implements [`axfs_vfs::VfsNodeOps`] for all types of inodes.
+pub struct Ext4Node {
+    fs: Arc<Ext4FileSystem>,
+    ino: u32,
+}
+
+impl Ext4Node {
+    pub(super) fn new(fs: Arc<Ext4FileSystem>, ino: u32) -> Self {
+        Self { fs, ino }
+    }
+
+    /// Returns the node of inode `ino`, which is this node itself if it is.
+    fn node(self: &Arc<Self>, ino: u32) -> VfsNodeRef {
+        if ino == self.ino {
+            self.clone()
+        } else {
+            self.fs.node(ino)
+        }
+    }
+
+    /// Reads the inode, which must be a directory.
+    fn read_dir_inode(&self, fs: &mut FsInner) -> VfsResult<Inode> {
+        let inode = fs.read_inode(self.ino)?;
+        if inode.is_dir() {
+            Ok(inode)
+        } else {
+            Err(VfsError::NotADirectory)
+        }
+    }
+
+    /// Reads the inode, which must not be a directory.
+    fn read_file_inode(&self, fs: &mut FsInner) -> VfsResult<Inode> {
+        let inode = fs.read_inode(self.ino)?;
+        if inode.is_dir() {
+            Err(VfsError::IsADirectory)
+        } else {
+            Ok(inode)
+        }
+    }
+
+    /// Creates a node of `mode` at `path`, and initializes it by `init`.
+    fn create_node<F>(&self, path: &str, mode: u16, init: F) -> VfsResult
+    where
+        F: FnOnce(&mut FsInner, &mut Inode, u32) -> VfsResult,
+    {
+        let mut fs = self.fs.inner.lock();
+        fs.check_writable()?;
+        let (parent, name) = walk_parent(&mut fs, self.ino, path)?;
+        if matches!(name, "" | "." | "..") {
+            return Err(VfsError::AlreadyExists);
+        }
+        let mut dir = fs.read_inode(parent)?;
+        if dir.links_count() == 0 {
+            return Err(VfsError::NotFound); // removed but still opened
+        }
+        if fs.find_entry(&dir, name)?.is_some() {
+            return Err(VfsError::AlreadyExists);
+        }
+        let mut inode = fs.new_inode(parent, mode)?;
+        let res = init(&mut fs, &mut inode, parent)
+            .and_then(|_| fs.write_inode(&mut inode))
+            .and_then(|_| fs.add_entry(&mut dir, name, inode.ino, mode));
+        if let Err(e) = res {
+            fs.evict_inode(inode)?;
+            return Err(e);
+        }
+        if inode.is_dir() {
+            dir.set_links_count(dir.links_count().saturating_add(1));
+        }
+        fs.write_inode(&mut dir)
+    }
+}
+
+impl VfsNodeOps for Ext4Node {
+    fn open(&self) -> VfsResult {
+        self.fs.inner.lock().open_inode(self.ino);
+        Ok(())
+    }
+
+    fn release(&self) -> VfsResult {
+        self.fs.inner.lock().close_inode(self.ino)
+    }
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let mut fs = self.fs.inner.lock();
+        let inode = fs.read_inode(self.ino)?;
+        let mut blocks = inode.raw_blocks(&fs.sb);
+        if inode.has_flag(INODE_FLAG_HUGE_FILE) {
+            blocks *= fs.block_size as u64 / 512;
+        }
+        let mut attr = VfsNodeAttr::new(
+            VfsNodePerm::from_bits_truncate(inode.mode() & 0o777),
+            node_type(inode.mode()),
+            inode.size(),
+            blocks,
+        );
+        attr.set_nlink(inode.links_count() as u64);
+        attr.set_ino(self.ino as u64);
+        attr.set_owner(inode.uid(), inode.gid());
+        let [atime, ctime, mtime] = inode.times().map(|t| Duration::from_secs(t as u64));
+        attr.set_times(atime, mtime, ctime);
+        Ok(attr)
+    }
+
+    fn set_attr(&self, attr: &VfsSetAttr) -> VfsResult {
+        let mut fs = self.fs.inner.lock();
+        fs.check_writable()?;
+        let mut inode = fs.read_inode(self.ino)?;
+        if let Some(perm) = attr.perm {
+            inode.set_mode(inode.mode() & !0o777 | perm.bits());
+        }
+        if let Some(uid) = attr.uid {
+            inode.set_uid(uid);
+        }
+        if let Some(gid) = attr.gid {
+            inode.set_gid(gid);
+        }
+        // only seconds are stored, in 32 bits
+        if let Some(atime) = attr.atime {
+            inode.set_atime(atime.as_secs() as u32);
+        }
+        if let Some(mtime) = attr.mtime {
+            inode.set_mtime(mtime.as_secs() as u32);
+        }
+        inode.set_times(false, true, false, super::now());
+        fs.write_inode(&mut inode)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let mut fs = self.fs.inner.lock();
+        let inode = self.read_file_inode(&mut fs)?;
+        fs.read_data(&inode, offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let mut fs = self.fs.inner.lock();
+        fs.check_writable()?;
+        let mut inode = self.read_file_inode(&mut fs)?;
+        let res = fs.write_data(&mut inode, offset, buf);
+        // the blocks allocated before an error are still recorded
+        fs.write_inode(&mut inode)?;
+        res.map(|_| buf.len())
+    }
+
+    fn fsync(&self) -> VfsResult {
+        self.fs.inner.lock().flush()
+    }
+
+    fn truncate(&self, size: u64) -> VfsResult {
+        let mut fs = self.fs.inner.lock();
+        fs.check_writable()?;
+        let mut inode = self.read_file_inode(&mut fs)?;
+        let res = fs.set_data_size(&mut inode, size);
+        fs.write_inode(&mut inode)?;
+        res
+    }
+
+    fn readlink(&self) -> VfsResult<String> {
+        let mut fs = self.fs.inner.lock();
+        let inode = fs.read_inode(self.ino)?;
+        if inode.file_type() != S_IFLNK {
+            return Err(VfsError::InvalidInput);
+        }
+        let target = if fs.is_fast_symlink(&inode) {
+            let len = (inode.size() as usize).min(inode.i_block().len());
+            inode.i_block()[..len].to_vec()
+        } else {
+            let mut buf = alloc::vec![0; inode.size().min(fs.block_size as u64) as usize];
+            let len = fs.read_data(&inode, 0, &mut buf)?;
+            buf.truncate(len);
+            buf
+        };
+        String::from_utf8(target).map_err(|_| VfsError::InvalidData)
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        if self.ino == ROOT_INO {
+            return self.fs.parent.lock().clone();
+        }
+        let mut fs = self.fs.inner.lock();
+        let dir = self.read_dir_inode(&mut fs).ok()?;
+        let parent = fs.find_entry(&dir, "..").ok()??;
+        Some(self.fs.node(parent))
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        if self.ino == ROOT_INO && path.trim_matches('/') == ".." {
+            if let Some(parent) = self.fs.parent.lock().clone() {
+                return Ok(parent);
+            }
+        }
+        let ino = walk(&mut self.fs.inner.lock(), self.ino, path)?;
+        Ok(self.node(ino))
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let mut fs = self.fs.inner.lock();
+        let dir = self.read_dir_inode(&mut fs)?;
+        let entries = fs.read_dir_entries(&dir)?;
+        let mut count = 0;
+        for (
+            DirEntry {
+                ino,
+                name,
+                file_type,
+            },
+            out,
+        ) in entries.into_iter().skip(start_idx).zip(dirents.iter_mut())
+        {
+            let ty = match dirent_node_type(file_type) {
+                Some(ty) => ty,
+                None => node_type(fs.read_inode(ino)?.mode()),
+            };
+            *out = VfsDirEntry::new(&name, ty);
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        debug!("create {:?} at ext4: {}", ty, path);
+        let mode = match ty {
+            VfsNodeType::File => S_IFREG | VfsNodePerm::default_file().bits(),
+            VfsNodeType::Dir => S_IFDIR | VfsNodePerm::default_dir().bits(),
+            _ => return Err(VfsError::Unsupported),
+        };
+        let res = self.create_node(path, mode, |fs, inode, parent| {
+            if inode.is_dir() {
+                fs.init_dir(inode, parent)
+            } else {
+                Ok(())
+            }
+        });
+        match res {
+            Err(VfsError::AlreadyExists) if matches!(path.trim_matches('/'), "" | "." | "..") => {
+                Ok(()) // already exists
+            }
+            res => res,
+        }
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        debug!("remove at ext4: {}", path);
+        let mut fs = self.fs.inner.lock();
+        fs.check_writable()?;
+        let (parent, name) = walk_parent(&mut fs, self.ino, path)?;
+        if matches!(name, "" | "." | "..") {
+            return Err(VfsError::InvalidInput);
+        }
+        let mut dir = fs.read_inode(parent)?;
+        let ino = fs.find_entry(&dir, name)?.ok_or(VfsError::NotFound)?;
+        let inode = fs.read_inode(ino)?;
+        if inode.is_dir() && !fs.is_dir_empty(&inode)? {
+            return Err(VfsError::DirectoryNotEmpty);
+        }
+        fs.remove_entry(&mut dir, name)?;
+        if inode.is_dir() {
+            dir.set_links_count(dir.links_count().saturating_sub(1).max(2));
+        }
+        fs.write_inode(&mut dir)?;
+        fs.drop_link(ino)
+    }
+
+    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
+        debug!("rename at ext4: {} -> {}", src_path, dst_path);
+        let mut fs = self.fs.inner.lock();
+        fs.check_writable()?;
+        rename(&mut fs, self.ino, src_path, dst_path)
+    }
+
+    fn symlink(&self, path: &str, target: &str) -> VfsResult {
+        debug!("symlink at ext4: {} -> {}", path, target);
+        self.create_node(path, S_IFLNK | 0o777, |fs, inode, _| {
+            let target = target.as_bytes();
+            if target.len() < inode.i_block().len() {
+                inode.i_block_mut()[..target.len()].copy_from_slice(target);
+                inode.set_size(target.len() as u64);
+                Ok(())
+            } else if target.len() > fs.block_size {
+                Err(VfsError::InvalidInput)
+            } else {
+                if fs.sb.has_incompat(INCOMPAT_EXTENTS) {
+                    inode.set_flags(inode.flags() | INODE_FLAG_EXTENTS);
+                    super::inode::init_extent_root(inode);
+                }
+                fs.write_data(inode, 0, target)
+            }
+        })
+    }
+
+    fn link(&self, path: &str, node: &VfsNodeRef) -> VfsResult {
+        debug!("link at ext4: {}", path);
+        let Some(node) = node.as_any().downcast_ref::<Self>() else {
+            return Err(VfsError::CrossesDevices);
+        };
+        if !Arc::ptr_eq(&node.fs, &self.fs) {
+            return Err(VfsError::CrossesDevices);
+        }
+        let mut fs = self.fs.inner.lock();
+        fs.check_writable()?;
+        let mut inode = node.read_file_inode(&mut fs).map_err(|e| match e {
+            VfsError::IsADirectory => VfsError::PermissionDenied, // hard links to directories
+            e => e,
+        })?;
+        let (parent, name) = walk_parent(&mut fs, self.ino, path)?;
+        if matches!(name, "" | "." | "..") {
+            return Err(VfsError::AlreadyExists);
+        }
+        let mut dir = fs.read_inode(parent)?;
+        if fs.find_entry(&dir, name)?.is_some() {
+            return Err(VfsError::AlreadyExists);
+        }
+        fs.add_entry(&mut dir, name, inode.ino, inode.mode())?;
+        fs.write_inode(&mut dir)?;
+        inode.set_links_count(inode.links_count().saturating_add(1));
+        inode.set_times(false, true, false, super::now());
+        fs.write_inode(&mut inode)
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
+
+fn node_type(mode: u16) -> VfsNodeType {
+    match mode & S_IFMT {
+        0o010000 => VfsNodeType::Fifo,
+        0o020000 => VfsNodeType::CharDevice,
+        S_IFDIR => VfsNodeType::Dir,
+        0o060000 => VfsNodeType::BlockDevice,
+        S_IFLNK => VfsNodeType::SymLink,
+        0o140000 => VfsNodeType::Socket,
+        _ => VfsNodeType::File,
+    }
+}
+
+/// Looks up `path` relative to directory `start`, returns the inode number.
+fn walk(fs: &mut FsInner, start: u32, path: &str) -> VfsResult<u32> {
+    let mut ino = start;
+    for name in path.split('/').filter(|n| !n.is_empty() && *n != ".") {
+        let dir = fs.read_inode(ino)?;
+        if !dir.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        ino = fs.find_entry(&dir, name)?.ok_or(VfsError::NotFound)?;
+    }
+    Ok(ino)
+}
+
+/// Looks up the directory containing the last component of `path` relative
+/// to directory `start`, returns it with the name of the last component.
+fn walk_parent<'a>(fs: &mut FsInner, start: u32, path: &'a str) -> VfsResult<(u32, &'a str)> {
+    let path = path.trim_end_matches('/');
+    let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+    let parent = walk(fs, start, parent)?;
+    if fs.read_inode(parent)?.is_dir() {
+        Ok((parent, name))
+    } else {
+        Err(VfsError::NotADirectory)
+    }
+}
+
+/// Moves `src_path` to `dst_path`, both relative to directory `start`.
+///
+/// An existing node at `dst_path` is replaced, as long as it is of a
+/// compatible type (a file for a file, an empty directory for a directory).
+fn rename(fs: &mut FsInner, start: u32, src_path: &str, dst_path: &str) -> VfsResult {
+    let (src_dir, src_name) = walk_parent(fs, start, src_path)?;
+    let (dst_dir, dst_name) = walk_parent(fs, start, dst_path)?;
+    if [src_name, dst_name]
+        .iter()
+        .any(|n| matches!(*n, "" | "." | ".."))
+    {
+        return Err(VfsError::InvalidInput);
+    }
+    let src_ino = {
+        let dir = fs.read_inode(src_dir)?;
+        fs.find_entry(&dir, src_name)?.ok_or(VfsError::NotFound)?
+    };
+    let node = fs.read_inode(src_ino)?;
+    let target = {
+        let dir = fs.read_inode(dst_dir)?;
+        fs.find_entry(&dir, dst_name)?
+    };
+    if target == Some(src_ino) {
+        return Ok(()); // the same node, e.g. hard links
+    }
+
+    if node.is_dir() {
+        // a directory cannot be moved into its own subtree
+        let mut ino = dst_dir;
+        for _ in 0..MAX_DIR_DEPTH {
+            if ino == src_ino {
+                return Err(VfsError::InvalidInput);
+            } else if ino == ROOT_INO {
+                break;
+            }
+            let dir = fs.read_inode(ino)?;
+            ino = fs.find_entry(&dir, "..")?.ok_or(VfsError::InvalidData)?;
+        }
+    }
+    let moves_dir = node.is_dir() && src_dir != dst_dir;
+
+    let mut dir = fs.read_inode(dst_dir)?;
+    let mut links = dir.links_count() as i32 + moves_dir as i32;
+    if let Some(target) = target {
+        let target_inode = fs.read_inode(target)?;
+        match (node.is_dir(), target_inode.is_dir()) {
+            (true, true) if !fs.is_dir_empty(&target_inode)? => {
+                return Err(VfsError::DirectoryNotEmpty)
+            }
+            (true, false) => return Err(VfsError::NotADirectory),
+            (false, true) => return Err(VfsError::IsADirectory),
+            _ => {}
+        }
+        fs.replace_entry(&mut dir, dst_name, src_ino, node.mode())?;
+        links -= target_inode.is_dir() as i32;
+        dir.set_links_count(links.max(2) as u16);
+        fs.write_inode(&mut dir)?;
+        fs.drop_link(target)?;
+    } else {
+        fs.add_entry(&mut dir, dst_name, src_ino, node.mode())?;
+        dir.set_links_count(links.max(2) as u16);
+        fs.write_inode(&mut dir)?;
+    }
+
+    let mut dir = fs.read_inode(src_dir)?;
+    fs.remove_entry(&mut dir, src_name)?;
+    if moves_dir {
+        dir.set_links_count(dir.links_count().saturating_sub(1).max(2));
+    }
+    fs.write_inode(&mut dir)?;
+
+    if moves_dir {
+        let mut node = fs.read_inode(src_ino)?;
+        fs.replace_entry(&mut node, "..", dst_dir, S_IFDIR)?;
+        fs.write_inode(&mut node)?;
+    }
+    Ok(())
+}
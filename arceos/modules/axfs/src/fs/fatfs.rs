@@ -1,22 +1,59 @@
-use alloc::sync::Arc;
+//! The FAT filesystem, based on [rust-fatfs](https://github.com/rafalh/rust-fatfs).
+//!
+//! Timestamps of files are taken from [`axhal::time::wall_time`], which is
+//! only the real time if the `rtc` feature of `axhal` is enabled, and are
+//! clamped to the range that FAT can store (1980 to 2107). FAT has no
+//! permissions or owners, so everything is `0o755` and owned by `root`.
+//!
+//! The directory entries of opened files, the free cluster count of FAT32 and
+//! the block cache are written back on [`sync`](VfsOps::sync). The volume is
+//! also marked clean on [`umount`](VfsOps::umount), unless it was already
+//! dirty when mounted, so that `fsck` still checks it.
+
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
 use core::cell::UnsafeCell;
+use core::time::Duration;
 
-use axfs_vfs::{VfsDirEntry, VfsError, VfsNodePerm, VfsResult};
+use axfs_vfs::{FileSystemInfo, VfsDirEntry, VfsError, VfsNodePerm, VfsResult, VfsSetAttr};
 use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps};
 use axsync::Mutex;
-use fatfs::{Dir, File, LossyOemCpConverter, NullTimeProvider, Read, Seek, SeekFrom, Write};
+use fatfs::{Date, DateTime, Dir, DirEntry, FatType, File, LossyOemCpConverter, Time};
+use fatfs::{Read, Seek, SeekFrom, TimeProvider, Write};
 
 use crate::dev::Disk;
 
 const BLOCK_SIZE: usize = 512;
 
+type FatFile<'a> = File<'a, Disk, WallClock, LossyOemCpConverter>;
+type FatDir<'a> = Dir<'a, Disk, WallClock, LossyOemCpConverter>;
+type FatDirEntry<'a> = DirEntry<'a, Disk, WallClock, LossyOemCpConverter>;
+
 pub struct FatFileSystem {
-    inner: fatfs::FileSystem<Disk, NullTimeProvider, LossyOemCpConverter>,
+    // dropped before `inner`, which it borrows
     root_dir: UnsafeCell<Option<VfsNodeRef>>,
+    /// The opened files, whose directory entries are written back on sync.
+    files: Mutex<Vec<Weak<FileWrapper<'static>>>>,
+    /// Another cursor on the disk, to update the FSInfo sector and the boot
+    /// sector, which fatfs only does when it is dropped.
+    disk: Mutex<Disk>,
+    /// Whether the volume was marked dirty when mounted.
+    was_dirty: bool,
+    inner: fatfs::FileSystem<Disk, WallClock, LossyOemCpConverter>,
 }
 
-pub struct FileWrapper<'a>(Mutex<File<'a, Disk, NullTimeProvider, LossyOemCpConverter>>);
-pub struct DirWrapper<'a>(Dir<'a, Disk, NullTimeProvider, LossyOemCpConverter>);
+pub struct FileWrapper<'a> {
+    file: Mutex<FatFile<'a>>,
+    times: Mutex<Times>,
+}
+
+pub struct DirWrapper<'a> {
+    fs: &'a FatFileSystem,
+    dir: FatDir<'a>,
+    times: Times,
+}
 
 unsafe impl Sync for FatFileSystem {}
 unsafe impl Send for FatFileSystem {}
@@ -25,40 +62,97 @@ unsafe impl<'a> Sync for FileWrapper<'a> {}
 unsafe impl<'a> Send for DirWrapper<'a> {}
 unsafe impl<'a> Sync for DirWrapper<'a> {}
 
-impl FatFileSystem {
-    #[cfg(feature = "use-ramdisk")]
-    pub fn new(mut disk: Disk) -> Self {
-        let opts = fatfs::FormatVolumeOptions::new();
-        fatfs::format_volume(&mut disk, opts).expect("failed to format volume");
-        let inner = fatfs::FileSystem::new(disk, fatfs::FsOptions::new())
-            .expect("failed to initialize FAT filesystem");
+/// The timestamps of a file or directory, as stored in its directory entry.
+#[derive(Clone, Copy, Default)]
+struct Times {
+    atime: Duration,
+    mtime: Duration,
+    ctime: Duration,
+}
+
+impl Times {
+    fn of(entry: &FatDirEntry) -> Self {
         Self {
-            inner,
-            root_dir: UnsafeCell::new(None),
+            atime: date_to_duration(entry.accessed()),
+            mtime: date_time_to_duration(entry.modified()),
+            // FAT has no change time, use the creation time like Linux does
+            ctime: date_time_to_duration(entry.created()),
         }
     }
+}
 
-    #[cfg(not(feature = "use-ramdisk"))]
-    pub fn new(disk: Disk) -> Self {
-        let inner = fatfs::FileSystem::new(disk, fatfs::FsOptions::new())
-            .expect("failed to initialize FAT filesystem");
-        Self {
-            inner,
-            root_dir: UnsafeCell::new(None),
+impl FatFileSystem {
+    /// Opens the FAT filesystem on `disk`, which is formatted first if the
+    /// `use-ramdisk` feature is enabled.
+    pub fn open(disk: Disk) -> VfsResult<Arc<Self>> {
+        #[cfg(feature = "use-ramdisk")]
+        let disk = {
+            let mut disk = disk;
+            let opts = fatfs::FormatVolumeOptions::new();
+            fatfs::format_volume(&mut disk, opts).map_err(as_vfs_err)?;
+            disk
+        };
+        let raw_disk = disk.try_clone();
+        let opts = fatfs::FsOptions::new().time_provider(WallClock);
+        let inner = fatfs::FileSystem::new(disk, opts).map_err(as_vfs_err)?;
+        let was_dirty = inner.read_status_flags().map_err(as_vfs_err)?.dirty();
+        if was_dirty {
+            warn!("fatfs: the volume was not unmounted cleanly");
         }
+        let fs = Arc::new(Self {
+            root_dir: UnsafeCell::new(None),
+            files: Mutex::new(Vec::new()),
+            disk: Mutex::new(raw_disk),
+            was_dirty,
+            inner,
+        });
+        // SAFETY: the root directory only lives as long as `fs`, and `inner`
+        // is never moved out of the `Arc`.
+        let this: &'static Self = unsafe { &*Arc::as_ptr(&fs) };
+        let root_dir = this.new_dir(this.inner.root_dir(), Times::default());
+        unsafe { *this.root_dir.get() = Some(root_dir) };
+        Ok(fs)
     }
 
-    pub fn init(&'static self) {
-        // must be called before later operations
-        unsafe { *self.root_dir.get() = Some(Self::new_dir(self.inner.root_dir())) }
+    fn new_file(&'static self, file: FatFile<'static>, times: Times) -> Arc<FileWrapper<'static>> {
+        let file = Arc::new(FileWrapper {
+            file: Mutex::new(file),
+            times: Mutex::new(times),
+        });
+        let mut files = self.files.lock();
+        files.retain(|f| f.strong_count() > 0);
+        files.push(Arc::downgrade(&file));
+        file
     }
 
-    fn new_file(file: File<'_, Disk, NullTimeProvider, LossyOemCpConverter>) -> Arc<FileWrapper> {
-        Arc::new(FileWrapper(Mutex::new(file)))
+    fn new_dir(&'static self, dir: FatDir<'static>, times: Times) -> Arc<DirWrapper<'static>> {
+        Arc::new(DirWrapper {
+            fs: self,
+            dir,
+            times,
+        })
     }
 
-    fn new_dir(dir: Dir<'_, Disk, NullTimeProvider, LossyOemCpConverter>) -> Arc<DirWrapper> {
-        Arc::new(DirWrapper(dir))
+    /// Writes the directory entries of the opened files, the free cluster
+    /// count and the cached blocks back to the disk.
+    ///
+    /// The volume is also marked clean if `clean` is `true`, which must only
+    /// be done when it is not going to be modified anymore.
+    fn flush(&self, clean: bool) -> VfsResult {
+        let files: Vec<_> = self.files.lock().iter().filter_map(Weak::upgrade).collect();
+        for file in files {
+            file.file.lock().flush().map_err(as_vfs_err)?;
+        }
+        let fat_type = self.inner.fat_type();
+        let mut disk = self.disk.lock();
+        if fat_type == FatType::Fat32 {
+            let free_clusters = self.inner.stats().map_err(as_vfs_err)?.free_clusters();
+            write_fs_info(&mut disk, free_clusters)?;
+        }
+        if clean && !self.was_dirty {
+            clear_dirty_flag(&mut disk, fat_type)?;
+        }
+        disk.flush().map_err(|_| VfsError::Io)
     }
 }
 
@@ -66,29 +160,81 @@ impl VfsNodeOps for FileWrapper<'static> {
     axfs_vfs::impl_vfs_non_dir_default! {}
 
     fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
-        let size = self.0.lock().seek(SeekFrom::End(0)).map_err(as_vfs_err)?;
+        let size = self
+            .file
+            .lock()
+            .seek(SeekFrom::End(0))
+            .map_err(as_vfs_err)?;
         let blocks = (size + BLOCK_SIZE as u64 - 1) / BLOCK_SIZE as u64;
         // FAT fs doesn't support permissions, we just set everything to 755
         let perm = VfsNodePerm::from_bits_truncate(0o755);
-        Ok(VfsNodeAttr::new(perm, VfsNodeType::File, size, blocks))
+        let mut attr = VfsNodeAttr::new(perm, VfsNodeType::File, size, blocks);
+        let times = self.times.lock();
+        attr.set_times(times.atime, times.mtime, times.ctime);
+        Ok(attr)
+    }
+
+    fn set_attr(&self, attr: &VfsSetAttr) -> VfsResult {
+        if attr.perm.is_some() || attr.uid.is_some() || attr.gid.is_some() {
+            return Err(VfsError::Unsupported);
+        }
+        let mut file = self.file.lock();
+        let mut times = self.times.lock();
+        if let Some(atime) = attr.atime {
+            let date = duration_to_date_time(atime).date;
+            file.set_accessed(date);
+            times.atime = date_to_duration(date);
+        }
+        if let Some(mtime) = attr.mtime {
+            times.mtime = set_modified(&mut file, duration_to_date_time(mtime));
+        }
+        Ok(())
     }
 
     fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
-        let mut file = self.0.lock();
+        let mut file = self.file.lock();
         file.seek(SeekFrom::Start(offset)).map_err(as_vfs_err)?; // TODO: more efficient
         file.read(buf).map_err(as_vfs_err)
     }
 
     fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
-        let mut file = self.0.lock();
+        let mut file = self.file.lock();
         file.seek(SeekFrom::Start(offset)).map_err(as_vfs_err)?; // TODO: more efficient
-        file.write(buf).map_err(as_vfs_err)
+        let len = file.write(buf).map_err(as_vfs_err)?;
+        self.times.lock().mtime = set_modified(&mut file, WallClock.get_current_date_time());
+        Ok(len)
     }
 
     fn truncate(&self, size: u64) -> VfsResult {
-        let mut file = self.0.lock();
+        let mut file = self.file.lock();
         file.seek(SeekFrom::Start(size)).map_err(as_vfs_err)?; // TODO: more efficient
-        file.truncate().map_err(as_vfs_err)
+        file.truncate().map_err(as_vfs_err)?;
+        self.times.lock().mtime = set_modified(&mut file, WallClock.get_current_date_time());
+        Ok(())
+    }
+
+    fn fsync(&self) -> VfsResult {
+        self.file.lock().flush().map_err(as_vfs_err)
+    }
+}
+
+impl DirWrapper<'static> {
+    /// Finds the entry at the relative `path`, whose last name is compared
+    /// with both the long and the short names case-insensitively.
+    fn find_entry(&self, path: &str) -> VfsResult<FatDirEntry<'static>> {
+        let (parent, name) = match path.rsplit_once('/') {
+            Some((parent, name)) => (Some(self.dir.open_dir(parent).map_err(as_vfs_err)?), name),
+            None => (None, path),
+        };
+        for entry in parent.as_ref().unwrap_or(&self.dir).iter() {
+            let entry = entry.map_err(as_vfs_err)?;
+            if entry.file_name().eq_ignore_ascii_case(name)
+                || entry.short_file_name().eq_ignore_ascii_case(name)
+            {
+                return Ok(entry);
+            }
+        }
+        Err(VfsError::NotFound)
     }
 }
 
@@ -97,18 +243,21 @@ impl VfsNodeOps for DirWrapper<'static> {
 
     fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
         // FAT fs doesn't support permissions, we just set everything to 755
-        Ok(VfsNodeAttr::new(
+        let mut attr = VfsNodeAttr::new(
             VfsNodePerm::from_bits_truncate(0o755),
             VfsNodeType::Dir,
             BLOCK_SIZE as u64,
             1,
-        ))
+        );
+        let times = &self.times;
+        attr.set_times(times.atime, times.mtime, times.ctime);
+        Ok(attr)
     }
 
     fn parent(&self) -> Option<VfsNodeRef> {
-        self.0
+        self.dir
             .open_dir("..")
-            .map_or(None, |dir| Some(FatFileSystem::new_dir(dir)))
+            .map_or(None, |dir| Some(self.fs.new_dir(dir, Times::default())))
     }
 
     fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
@@ -121,13 +270,12 @@ impl VfsNodeOps for DirWrapper<'static> {
             return self.lookup(rest);
         }
 
-        // TODO: use `fatfs::Dir::find_entry`, but it's not public.
-        if let Ok(file) = self.0.open_file(path) {
-            Ok(FatFileSystem::new_file(file))
-        } else if let Ok(dir) = self.0.open_dir(path) {
-            Ok(FatFileSystem::new_dir(dir))
+        let entry = self.find_entry(path)?;
+        let times = Times::of(&entry);
+        if entry.is_dir() {
+            Ok(self.fs.new_dir(entry.to_dir(), times))
         } else {
-            Err(VfsError::NotFound)
+            Ok(self.fs.new_file(entry.to_file(), times))
         }
     }
 
@@ -143,11 +291,11 @@ impl VfsNodeOps for DirWrapper<'static> {
 
         match ty {
             VfsNodeType::File => {
-                self.0.create_file(path).map_err(as_vfs_err)?;
+                self.dir.create_file(path).map_err(as_vfs_err)?;
                 Ok(())
             }
             VfsNodeType::Dir => {
-                self.0.create_dir(path).map_err(as_vfs_err)?;
+                self.dir.create_dir(path).map_err(as_vfs_err)?;
                 Ok(())
             }
             _ => Err(VfsError::Unsupported),
@@ -161,11 +309,11 @@ impl VfsNodeOps for DirWrapper<'static> {
         if let Some(rest) = path.strip_prefix("./") {
             return self.remove(rest);
         }
-        self.0.remove(path).map_err(as_vfs_err)
+        self.dir.remove(path).map_err(as_vfs_err)
     }
 
     fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
-        let mut iter = self.0.iter().skip(start_idx);
+        let mut iter = self.dir.iter().skip(start_idx);
         for (i, out_entry) in dirents.iter_mut().enumerate() {
             let x = iter.next();
             match x {
@@ -192,17 +340,226 @@ impl VfsNodeOps for DirWrapper<'static> {
             src_path, dst_path
         );
 
-        self.0
-            .rename(src_path, &self.0, dst_path)
-            .map_err(as_vfs_err)
+        match self.dir.rename(src_path, &self.dir, dst_path) {
+            Err(fatfs::Error::AlreadyExists) if self.dir.open_file(dst_path).is_ok() => {
+                // fatfs does not replace an existing file. Move the old file
+                // aside first so that it can be restored if the rename fails,
+                // and only remove it once `dst_path` refers to the new file.
+                let tmp_path = (0..)
+                    .map(|i| format!("{}.~{}", dst_path, i))
+                    .find(|p| self.dir.open_file(p).is_err() && self.dir.open_dir(p).is_err())
+                    .unwrap();
+                self.dir
+                    .rename(dst_path, &self.dir, &tmp_path)
+                    .map_err(as_vfs_err)?;
+                if let Err(e) = self.dir.rename(src_path, &self.dir, dst_path) {
+                    self.dir
+                        .rename(&tmp_path, &self.dir, dst_path)
+                        .map_err(as_vfs_err)?;
+                    return Err(as_vfs_err(e));
+                }
+                if let Err(e) = self.dir.remove(&tmp_path) {
+                    warn!("failed to remove replaced file {}: {:?}", tmp_path, e);
+                }
+                Ok(())
+            }
+            res => res.map_err(as_vfs_err),
+        }
     }
 }
 
 impl VfsOps for FatFileSystem {
+    fn umount(&self) -> VfsResult {
+        self.flush(true)
+    }
+
+    fn sync(&self) -> VfsResult {
+        self.flush(false)
+    }
+
+    fn statfs(&self) -> VfsResult<FileSystemInfo> {
+        let stats = self.inner.stats().map_err(as_vfs_err)?;
+        Ok(FileSystemInfo {
+            block_size: stats.cluster_size() as u64,
+            blocks: stats.total_clusters() as u64,
+            blocks_free: stats.free_clusters() as u64,
+            // FAT has no inodes
+            files: 0,
+            files_free: 0,
+            name_max: 255,
+        })
+    }
+
+    fn label(&self) -> Option<String> {
+        // the label in the root directory takes precedence, like Linux does
+        let label = match self.inner.read_volume_label_from_root_dir() {
+            Ok(Some(label)) => label,
+            _ => self.inner.volume_label(),
+        };
+        let label = label.trim_end();
+        (!label.is_empty() && label != "NO NAME").then(|| label.into())
+    }
+
     fn root_dir(&self) -> VfsNodeRef {
         let root_dir = unsafe { (*self.root_dir.get()).as_ref().unwrap() };
         root_dir.clone()
     }
+
+    fn name(&self) -> &str {
+        "vfat"
+    }
+}
+
+/// The time provider of fatfs that reads the wall clock.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WallClock;
+
+impl TimeProvider for WallClock {
+    fn get_current_date(&self) -> Date {
+        self.get_current_date_time().date
+    }
+
+    fn get_current_date_time(&self) -> DateTime {
+        duration_to_date_time(axhal::time::wall_time())
+    }
+}
+
+/// The earliest time that FAT can store, 1980-01-01 00:00:00.
+const FAT_MIN_SECS: u64 = 315_532_800;
+/// The latest time that FAT can store, 2107-12-31 23:59:59.
+const FAT_MAX_SECS: u64 = 4_354_819_199;
+
+/// Converts the time since the Unix epoch to a FAT date and time, clamped to
+/// the range that FAT can store.
+fn duration_to_date_time(time: Duration) -> DateTime {
+    let secs = time.as_secs().clamp(FAT_MIN_SECS, FAT_MAX_SECS);
+    let millis = if secs == time.as_secs() {
+        time.subsec_millis() as u16
+    } else {
+        0
+    };
+    let (year, month, day) = civil_from_days(secs / 86400);
+    let secs = secs % 86400;
+    DateTime::new(
+        Date::new(year, month, day),
+        Time::new(
+            (secs / 3600) as u16,
+            (secs / 60 % 60) as u16,
+            (secs % 60) as u16,
+            millis,
+        ),
+    )
+}
+
+fn date_to_duration(date: Date) -> Duration {
+    Duration::from_secs(days_from_civil(date.year, date.month, date.day) * 86400)
+}
+
+fn date_time_to_duration(date_time: DateTime) -> Duration {
+    let time = date_time.time;
+    let secs = time.hour as u64 * 3600 + time.min as u64 * 60 + time.sec as u64;
+    date_to_duration(date_time.date)
+        + Duration::from_secs(secs)
+        + Duration::from_millis(time.millis as u64)
+}
+
+/// Sets the modification time of `file`, returns it as stored, which has a
+/// granularity of 2 seconds.
+fn set_modified(file: &mut FatFile, date_time: DateTime) -> Duration {
+    file.set_modified(date_time);
+    let time = date_time.time;
+    let stored = DateTime::new(
+        date_time.date,
+        Time::new(time.hour, time.min, time.sec & !1, 0),
+    );
+    date_time_to_duration(stored)
+}
+
+/// Returns the (year, month, day) of the days since the Unix epoch.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#civil_from_days>.
+fn civil_from_days(days: u64) -> (u16, u16, u16) {
+    let z = days + 719_468;
+    let era = z / 146_097;
+    let doe = z % 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = doy - (153 * mp + 2) / 5 + 1;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 };
+    let year = yoe + era * 400 + (month <= 2) as u64;
+    (year as u16, month as u16, day as u16)
+}
+
+/// Returns the days since the Unix epoch of the date, which must not be
+/// earlier than it.
+///
+/// See <http://howardhinnant.github.io/date_algorithms.html#days_from_civil>.
+fn days_from_civil(year: u16, month: u16, day: u16) -> u64 {
+    let y = year as u64 - (month <= 2) as u64;
+    let (m, d) = (month as u64, day as u64);
+    let era = y / 400;
+    let yoe = y % 400;
+    let doy = (153 * if m > 2 { m - 3 } else { m + 9 } + 2) / 5 + d - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Reads `buf` from `pos` of the disk.
+fn read_exact_at(disk: &mut Disk, pos: u64, buf: &mut [u8]) -> VfsResult {
+    disk.set_position(pos);
+    match Read::read(disk, buf) {
+        Ok(n) if n == buf.len() => Ok(()),
+        _ => Err(VfsError::Io),
+    }
+}
+
+/// Writes `buf` to `pos` of the disk.
+fn write_all_at(disk: &mut Disk, pos: u64, buf: &[u8]) -> VfsResult {
+    disk.set_position(pos);
+    match Write::write(disk, buf) {
+        Ok(n) if n == buf.len() => Ok(()),
+        _ => Err(VfsError::Io),
+    }
+}
+
+/// Updates the free cluster count in the FSInfo sector of FAT32.
+fn write_fs_info(disk: &mut Disk, free_clusters: u32) -> VfsResult {
+    const LEAD_SIG: u32 = 0x4161_5252;
+    const STRUC_SIG: u32 = 0x6141_7272;
+
+    let mut bpb = [0; 0x32];
+    read_exact_at(disk, 0, &mut bpb)?;
+    let bytes_per_sector = u16::from_le_bytes([bpb[0x0b], bpb[0x0c]]) as u64;
+    let fs_info_sector = u16::from_le_bytes([bpb[0x30], bpb[0x31]]) as u64;
+    let pos = fs_info_sector * bytes_per_sector;
+
+    let mut sig = [0; 4];
+    read_exact_at(disk, pos, &mut sig)?;
+    let lead_sig = u32::from_le_bytes(sig);
+    read_exact_at(disk, pos + 484, &mut sig)?;
+    let struc_sig = u32::from_le_bytes(sig);
+    if fs_info_sector == 0 || lead_sig != LEAD_SIG || struc_sig != STRUC_SIG {
+        warn!("fatfs: invalid FSInfo sector {}", fs_info_sector);
+        return Ok(());
+    }
+    write_all_at(disk, pos + 488, &free_clusters.to_le_bytes())
+}
+
+/// Clears the dirty flag in the boot sector.
+fn clear_dirty_flag(disk: &mut Disk, fat_type: FatType) -> VfsResult {
+    let pos = if fat_type == FatType::Fat32 {
+        0x41
+    } else {
+        0x25
+    };
+    let mut flags = [0];
+    read_exact_at(disk, pos, &mut flags)?;
+    if flags[0] & 1 != 0 {
+        flags[0] &= !1;
+        write_all_at(disk, pos, &flags)?;
+    }
+    Ok(())
 }
 
 impl fatfs::IoBase for Disk {
@@ -243,7 +600,7 @@ impl Write for Disk {
         Ok(write_len)
     }
     fn flush(&mut self) -> Result<(), Self::Error> {
-        Ok(())
+        Disk::flush(self).map_err(|_| ())
     }
 }
 
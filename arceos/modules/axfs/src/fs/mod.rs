@@ -1,13 +1,83 @@
-cfg_if::cfg_if! {
-    if #[cfg(feature = "myfs")] {
-        pub mod myfs;
-    } else if #[cfg(feature = "fatfs")] {
-        pub mod fatfs;
-    }
-}
+#[cfg(feature = "myfs")]
+pub mod myfs;
+
+#[cfg(feature = "fatfs")]
+pub mod fatfs;
+
+#[cfg(feature = "ext4fs")]
+pub mod ext4fs;
+
+#[cfg(feature = "ninepfs")]
+pub mod ninepfs;
 
 #[cfg(feature = "devfs")]
 pub use axfs_devfs as devfs;
 
 #[cfg(feature = "ramfs")]
 pub use axfs_ramfs as ramfs;
+
+#[cfg(any(feature = "devfs", feature = "procfs", feature = "sysfs"))]
+pub mod pseudofs;
+
+pub mod overlay;
+pub mod readonly;
+
+use alloc::sync::Arc;
+use axerrno::{ax_err, AxResult};
+use axfs_vfs::VfsOps;
+
+use crate::dev::Disk;
+
+/// Names of the built-in filesystem types, which can not be taken by custom
+/// filesystem types.
+#[cfg(feature = "myfs")]
+const BUILTIN_TYPES: &[&str] = &["vfat", "ext2", "ext3", "ext4", "9p"];
+
+/// Creates the filesystem of type `fs_type` on the device `dev`, which is a
+/// block device or partition, or the mount tag of a 9P device if `fs_type` is
+/// `9p`.
+pub(crate) fn new_device_fs(dev: &str, fs_type: Option<&str>) -> AxResult<Arc<dyn VfsOps>> {
+    #[cfg(feature = "ninepfs")]
+    if fs_type == Some("9p") {
+        return Ok(ninepfs::NinePFileSystem::open(dev)?);
+    }
+    let Some(disk) = crate::dev::find_disk(dev) else {
+        return ax_err!(NotFound, "device not found");
+    };
+    new_disk_fs(disk, fs_type)
+}
+
+/// Creates the filesystem of type `fs_type` on `disk`.
+///
+/// If `fs_type` is `None`, it is ext2/ext3/ext4 if the disk contains one and
+/// the `ext4fs` feature is enabled, or FAT if the `fatfs` feature is enabled.
+/// Custom filesystem types are only used when selected by name.
+#[allow(unused_variables)]
+pub(crate) fn new_disk_fs(disk: Disk, fs_type: Option<&str>) -> AxResult<Arc<dyn VfsOps>> {
+    match fs_type {
+        #[cfg(feature = "fatfs")]
+        Some("vfat") => Ok(fatfs::FatFileSystem::open(disk)?),
+        #[cfg(feature = "ext4fs")]
+        Some("ext2" | "ext3" | "ext4") => Ok(ext4fs::Ext4FileSystem::open(disk)?),
+        Some(name) => {
+            #[cfg(feature = "myfs")]
+            if let Some(fs_type) = myfs::find_filesystem(name) {
+                return fs_type.mount(alloc::boxed::Box::new(disk));
+            }
+            ax_err!(NotFound, "unknown filesystem type")
+        }
+        None => {
+            #[cfg(feature = "ext4fs")]
+            if ext4fs::Ext4FileSystem::probe(&disk) {
+                return Ok(ext4fs::Ext4FileSystem::open(disk)?);
+            }
+            cfg_if::cfg_if! {
+                if #[cfg(feature = "fatfs")] {
+                    Ok(fatfs::FatFileSystem::open(disk)?)
+                } else {
+                    ax_err!(Unsupported, "no filesystem for disks is enabled")
+                }
+            }
+        }
+    }
+}
@@ -1,16 +1,80 @@
-use crate::dev::Disk;
-use alloc::sync::Arc;
+use alloc::{boxed::Box, sync::Arc, vec::Vec};
+use core::marker::PhantomData;
+
+use axerrno::{ax_err, AxResult};
 use axfs_vfs::VfsOps;
+use axsync::Mutex;
+
+use crate::dev::BlockDevice;
+
+/// A custom filesystem type, which creates filesystems on block devices.
+///
+/// It is registered by [`register_filesystem`], and then selected by its name
+/// in the mount table or [`mount_device`](crate::api::mount_device).
+pub trait FileSystemType: Send + Sync {
+    /// The name of the filesystem type, e.g., `"myfs"`.
+    fn name(&self) -> &str;
+
+    /// Creates a new instance of the filesystem on `dev` with initialization.
+    fn mount(&self, dev: Box<dyn BlockDevice>) -> AxResult<Arc<dyn VfsOps>>;
+}
 
-/// The interface to define custom filesystems in user apps.
+/// The interface to define a custom filesystem in user apps.
+///
+/// It is mounted as the filesystem type named `myfs` once
+/// [`MyFileSystem`] with the implementation is registered, e.g.,
+/// `register_filesystem(Arc::new(MyFileSystem::<MyFileSystemIfImpl>::new()))`.
 #[crate_interface::def_interface]
 pub trait MyFileSystemIf {
-    /// Creates a new instance of the filesystem with initialization.
-    ///
-    /// TODO: use generic disk type
-    fn new_myfs(disk: Disk) -> Arc<dyn VfsOps>;
+    /// Creates a new instance of the filesystem on `disk` with initialization.
+    fn new_myfs(disk: Box<dyn BlockDevice>) -> Arc<dyn VfsOps>;
+}
+
+/// The filesystem type named `myfs`, which creates filesystems by the
+/// [`MyFileSystemIf`] implementation `T`.
+pub struct MyFileSystem<T>(PhantomData<fn() -> T>);
+
+impl<T: MyFileSystemIf> MyFileSystem<T> {
+    /// Creates the filesystem type.
+    pub const fn new() -> Self {
+        Self(PhantomData)
+    }
+}
+
+impl<T: MyFileSystemIf> Default for MyFileSystem<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: MyFileSystemIf> FileSystemType for MyFileSystem<T> {
+    fn name(&self) -> &str {
+        "myfs"
+    }
+
+    fn mount(&self, dev: Box<dyn BlockDevice>) -> AxResult<Arc<dyn VfsOps>> {
+        Ok(T::new_myfs(dev))
+    }
+}
+
+static FS_TYPES: Mutex<Vec<Arc<dyn FileSystemType>>> = Mutex::new(Vec::new());
+
+/// Registers a custom filesystem type.
+///
+/// To be used by the mount table, it must be registered before
+/// [`init_filesystems`](crate::init_filesystems). Returns
+/// [`AlreadyExists`](axerrno::AxError::AlreadyExists) if the name is taken by
+/// another registered or built-in filesystem type.
+pub fn register_filesystem(fs_type: Arc<dyn FileSystemType>) -> AxResult {
+    let mut fs_types = FS_TYPES.lock();
+    let name = fs_type.name();
+    if super::BUILTIN_TYPES.contains(&name) || fs_types.iter().any(|t| t.name() == name) {
+        return ax_err!(AlreadyExists, "filesystem type already registered");
+    }
+    fs_types.push(fs_type);
+    Ok(())
 }
 
-pub(crate) fn new_myfs(disk: Disk) -> Arc<dyn VfsOps> {
-    crate_interface::call_interface!(MyFileSystemIf::new_myfs(disk))
+pub(crate) fn find_filesystem(name: &str) -> Option<Arc<dyn FileSystemType>> {
+    FS_TYPES.lock().iter().find(|t| t.name() == name).cloned()
 }
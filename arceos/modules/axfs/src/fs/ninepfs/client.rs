@@ -0,0 +1,599 @@
+//! Messages of the 9P2000.L protocol, and the client that exchanges them over
+//! a 9P transport.
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec, vec::Vec};
+use core::time::Duration;
+
+use axdriver::prelude::NinePDriverOps;
+use axerrno::LinuxError;
+use axfs_vfs::{FileSystemInfo, VfsError, VfsNodeAttr, VfsNodePerm, VfsResult, VfsSetAttr};
+use axsync::Mutex;
+
+use super::node_type;
+
+/// The maximum size of messages requested in the version negotiation.
+const MAX_MESSAGE_SIZE: u32 = 0x10000 + IO_HEADER_SIZE;
+/// The size of the headers of `Tread`/`Rread` and `Twrite`/`Rwrite`, i.e.,
+/// `size[4] type[1] tag[2] fid[4] offset[8] count[4]`.
+const IO_HEADER_SIZE: u32 = 24;
+/// The maximum number of names in a `Twalk` message.
+const MAX_WALK_NAMES: usize = 16;
+
+const PROTOCOL_VERSION: &str = "9P2000.L";
+const NO_TAG: u16 = !0;
+const NO_FID: u32 = !0;
+
+const RLERROR: u8 = 7;
+const TSTATFS: u8 = 8;
+const TLOPEN: u8 = 12;
+const TLCREATE: u8 = 14;
+const TSYMLINK: u8 = 16;
+const TREADLINK: u8 = 22;
+const TGETATTR: u8 = 24;
+const TSETATTR: u8 = 26;
+const TREADDIR: u8 = 40;
+const TFSYNC: u8 = 50;
+const TLINK: u8 = 70;
+const TMKDIR: u8 = 72;
+const TRENAMEAT: u8 = 74;
+const TUNLINKAT: u8 = 76;
+const TVERSION: u8 = 100;
+const TATTACH: u8 = 104;
+const TWALK: u8 = 110;
+const TREAD: u8 = 116;
+const TWRITE: u8 = 118;
+const TCLUNK: u8 = 120;
+
+/// `request_mask` of `Tgetattr` for the fields of `struct stat`.
+const GETATTR_BASIC: u64 = 0x7ff;
+
+const SETATTR_MODE: u32 = 0x1;
+const SETATTR_UID: u32 = 0x2;
+const SETATTR_GID: u32 = 0x4;
+const SETATTR_SIZE: u32 = 0x8;
+const SETATTR_ATIME: u32 = 0x10;
+const SETATTR_MTIME: u32 = 0x20;
+const SETATTR_ATIME_SET: u32 = 0x80;
+const SETATTR_MTIME_SET: u32 = 0x100;
+
+/// Flags of `Tlopen` and `Tlcreate`, which are those of Linux `open`.
+pub(super) const O_RDONLY: u32 = 0;
+pub(super) const O_WRONLY: u32 = 1;
+const O_CREAT: u32 = 0o100;
+const O_EXCL: u32 = 0o200;
+
+/// Flag of `Tunlinkat` to remove a directory.
+pub(super) const AT_REMOVEDIR: u32 = 0x200;
+
+/// The `type` of [`Qid`]s of directories.
+pub(super) const QTDIR: u8 = 0x80;
+
+/// The errors of the server, which are Linux error numbers, to their
+/// [`VfsError`]s. Other errors are converted to [`VfsError::Io`].
+const ERRORS: &[(LinuxError, VfsError)] = &[
+    (LinuxError::EPERM, VfsError::PermissionDenied),
+    (LinuxError::ENOENT, VfsError::NotFound),
+    (LinuxError::EIO, VfsError::Io),
+    (LinuxError::ENOMEM, VfsError::NoMemory),
+    (LinuxError::EACCES, VfsError::PermissionDenied),
+    (LinuxError::EBUSY, VfsError::ResourceBusy),
+    (LinuxError::EEXIST, VfsError::AlreadyExists),
+    (LinuxError::EXDEV, VfsError::CrossesDevices),
+    (LinuxError::ENOTDIR, VfsError::NotADirectory),
+    (LinuxError::EISDIR, VfsError::IsADirectory),
+    (LinuxError::EINVAL, VfsError::InvalidInput),
+    (LinuxError::EFBIG, VfsError::StorageFull),
+    (LinuxError::ENOSPC, VfsError::StorageFull),
+    (LinuxError::EROFS, VfsError::ReadOnlyFilesystem),
+    (LinuxError::ENAMETOOLONG, VfsError::InvalidInput),
+    (LinuxError::ENOSYS, VfsError::Unsupported),
+    (LinuxError::ENOTEMPTY, VfsError::DirectoryNotEmpty),
+    (LinuxError::ELOOP, VfsError::FilesystemLoop),
+    (LinuxError::EOPNOTSUPP, VfsError::Unsupported),
+];
+
+/// The unique identifier of a file on the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(super) struct Qid {
+    pub ty: u8,
+    pub path: u64,
+}
+
+/// A request message being built.
+struct Request(Vec<u8>);
+
+impl Request {
+    fn new(ty: u8) -> Self {
+        let mut req = Self(Vec::with_capacity(64));
+        req.u32(0); // size, filled by `finish`
+        req.u8(ty);
+        req.u16(if ty == TVERSION { NO_TAG } else { 0 });
+        req
+    }
+
+    fn u8(&mut self, v: u8) -> &mut Self {
+        self.0.push(v);
+        self
+    }
+
+    fn u16(&mut self, v: u16) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn u32(&mut self, v: u32) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn u64(&mut self, v: u64) -> &mut Self {
+        self.0.extend_from_slice(&v.to_le_bytes());
+        self
+    }
+
+    fn str(&mut self, s: &str) -> &mut Self {
+        self.u16(s.len() as u16);
+        self.0.extend_from_slice(s.as_bytes());
+        self
+    }
+
+    fn ty(&self) -> u8 {
+        self.0[4]
+    }
+
+    fn finish(&mut self) -> &[u8] {
+        let size = self.0.len() as u32;
+        self.0[..4].copy_from_slice(&size.to_le_bytes());
+        &self.0
+    }
+}
+
+/// A reader of the fields of a reply message.
+struct Reply<'a>(&'a [u8]);
+
+impl<'a> Reply<'a> {
+    fn bytes(&mut self, len: usize) -> VfsResult<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(VfsError::InvalidData);
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> VfsResult<u8> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> VfsResult<u16> {
+        Ok(u16::from_le_bytes(self.bytes(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> VfsResult<u32> {
+        Ok(u32::from_le_bytes(self.bytes(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> VfsResult<u64> {
+        Ok(u64::from_le_bytes(self.bytes(8)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> VfsResult<&'a str> {
+        let len = self.u16()? as usize;
+        core::str::from_utf8(self.bytes(len)?).map_err(|_| VfsError::InvalidData)
+    }
+
+    fn qid(&mut self) -> VfsResult<Qid> {
+        let ty = self.u8()?;
+        let _version = self.u32()?;
+        let path = self.u64()?;
+        Ok(Qid { ty, path })
+    }
+
+    fn time(&mut self) -> VfsResult<Duration> {
+        let secs = self.u64()?;
+        let nanos = self.u64()?;
+        Ok(Duration::new(secs, nanos as u32))
+    }
+}
+
+struct ClientInner {
+    dev: Box<dyn NinePDriverOps>,
+    /// The buffer of replies, whose size is the maximum message size.
+    buf: Vec<u8>,
+    next_fid: u32,
+    free_fids: Vec<u32>,
+}
+
+/// A 9P2000.L client over a 9P transport, where the requests are sent one at a
+/// time.
+pub(super) struct Client {
+    mount_tag: String,
+    /// The maximum number of bytes in `Rread` and `Twrite`.
+    io_size: usize,
+    inner: Mutex<ClientInner>,
+}
+
+impl Client {
+    /// Negotiates the protocol version with the server over `dev`.
+    pub fn connect(mut dev: Box<dyn NinePDriverOps>) -> VfsResult<Self> {
+        let mount_tag = String::from(dev.mount_tag());
+        let mut buf = vec![0; MAX_MESSAGE_SIZE as usize];
+        let mut req = Request::new(TVERSION);
+        req.u32(MAX_MESSAGE_SIZE).str(PROTOCOL_VERSION);
+        let len = dev.transact(req.finish(), &mut buf).map_err(|e| {
+            warn!("9p: failed to send requests to {:?}: {:?}", mount_tag, e);
+            VfsError::Io
+        })?;
+        let mut reply = parse_reply(TVERSION, &buf[..len.min(buf.len())])?;
+        let msize = reply.u32()?;
+        if reply.str()? != PROTOCOL_VERSION {
+            warn!("9p: {:?} does not support {}", mount_tag, PROTOCOL_VERSION);
+            return Err(VfsError::Unsupported);
+        }
+        if msize <= IO_HEADER_SIZE || msize > MAX_MESSAGE_SIZE {
+            return Err(VfsError::InvalidData);
+        }
+        buf.truncate(msize as usize);
+        Ok(Self {
+            mount_tag,
+            io_size: (msize - IO_HEADER_SIZE) as usize,
+            inner: Mutex::new(ClientInner {
+                dev,
+                buf,
+                next_fid: 0,
+                free_fids: Vec::new(),
+            }),
+        })
+    }
+
+    pub fn mount_tag(&self) -> &str {
+        &self.mount_tag
+    }
+
+    /// Sends the request `req`, and parses its reply by `f`.
+    fn call<T>(&self, req: &mut Request, f: impl FnOnce(Reply) -> VfsResult<T>) -> VfsResult<T> {
+        let mut inner = self.inner.lock();
+        let ClientInner { dev, buf, .. } = &mut *inner;
+        let len = dev.transact(req.finish(), buf).map_err(|e| {
+            warn!(
+                "9p: failed to send requests to {:?}: {:?}",
+                self.mount_tag, e
+            );
+            VfsError::Io
+        })?;
+        f(parse_reply(req.ty(), &buf[..len.min(buf.len())])?)
+    }
+
+    /// Attaches to the root of the exported filesystem named `aname`.
+    pub fn attach(self: &Arc<Self>, aname: &str) -> VfsResult<(Fid, Qid)> {
+        let id = self.alloc_fid();
+        let mut req = Request::new(TATTACH);
+        req.u32(id).u32(NO_FID).str("root").str(aname).u32(0);
+        match self.call(&mut req, |mut r| r.qid()) {
+            Ok(qid) => Ok((self.new_fid(id), qid)),
+            Err(e) => {
+                self.inner.lock().free_fids.push(id);
+                Err(e)
+            }
+        }
+    }
+
+    fn alloc_fid(&self) -> u32 {
+        let mut inner = self.inner.lock();
+        inner.free_fids.pop().unwrap_or_else(|| {
+            inner.next_fid += 1;
+            inner.next_fid - 1
+        })
+    }
+
+    fn new_fid(self: &Arc<Self>, id: u32) -> Fid {
+        Fid {
+            client: self.clone(),
+            id,
+        }
+    }
+}
+
+/// Parses the header of the reply `buf` to the request of type `ty`.
+fn parse_reply(ty: u8, buf: &[u8]) -> VfsResult<Reply> {
+    let mut reply = Reply(buf);
+    let size = reply.u32()? as usize;
+    if size > buf.len() {
+        return Err(VfsError::InvalidData);
+    }
+    reply.0 = &buf[4..size];
+    let reply_ty = reply.u8()?;
+    let _tag = reply.u16()?;
+    if reply_ty == RLERROR {
+        let ecode = reply.u32()? as i32;
+        let err = ERRORS.iter().find(|(e, _)| *e as i32 == ecode);
+        Err(err.map_or(VfsError::Io, |&(_, e)| e))
+    } else if reply_ty != ty + 1 {
+        Err(VfsError::InvalidData)
+    } else {
+        Ok(reply)
+    }
+}
+
+/// A file identifier, which refers to a file on the server. It is clunked when
+/// dropped.
+pub(super) struct Fid {
+    client: Arc<Client>,
+    id: u32,
+}
+
+impl Fid {
+    pub fn client(&self) -> &Arc<Client> {
+        &self.client
+    }
+
+    fn request(&self, ty: u8) -> Request {
+        let mut req = Request::new(ty);
+        req.u32(self.id);
+        req
+    }
+
+    /// Walks `names` from this file to a new fid, returns it with the qid of
+    /// the last name, or `None` if `names` is empty.
+    pub fn walk(&self, names: &[&str]) -> VfsResult<(Fid, Option<Qid>)> {
+        let client = &self.client;
+        let id = client.alloc_fid();
+        let mut fid: Option<Fid> = None;
+        let mut qid = None;
+        let mut chunks = names.chunks(MAX_WALK_NAMES);
+        let first = chunks.next().unwrap_or_default();
+        for names in core::iter::once(first).chain(chunks) {
+            // walk from the new fid once it is created
+            let mut req = Request::new(TWALK);
+            req.u32(if fid.is_some() { id } else { self.id });
+            req.u32(id).u16(names.len() as u16);
+            for name in names {
+                req.str(name);
+            }
+            let res = client.call(&mut req, |mut r| {
+                let count = r.u16()? as usize;
+                if count < names.len() {
+                    return Err(VfsError::NotFound);
+                }
+                let mut last = None;
+                for _ in 0..count {
+                    last = Some(r.qid()?);
+                }
+                Ok(last)
+            });
+            match res {
+                Ok(last) => {
+                    qid = last.or(qid);
+                    fid.get_or_insert_with(|| client.new_fid(id));
+                }
+                Err(e) => {
+                    if fid.is_none() {
+                        client.inner.lock().free_fids.push(id);
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        Ok((fid.unwrap(), qid))
+    }
+
+    /// Opens the file with the Linux `flags`, after which it can not be walked
+    /// from.
+    pub fn open(&self, flags: u32) -> VfsResult {
+        let mut req = self.request(TLOPEN);
+        req.u32(flags);
+        self.client.call(&mut req, |_| Ok(()))
+    }
+
+    /// Creates a file of `name` with `mode` in this directory, which becomes
+    /// the new file opened for writing.
+    pub fn create(&self, name: &str, mode: u32) -> VfsResult {
+        let mut req = self.request(TLCREATE);
+        req.str(name)
+            .u32(O_WRONLY | O_CREAT | O_EXCL)
+            .u32(mode)
+            .u32(0);
+        self.client.call(&mut req, |_| Ok(()))
+    }
+
+    /// Creates a directory of `name` with `mode` in this directory.
+    pub fn mkdir(&self, name: &str, mode: u32) -> VfsResult {
+        let mut req = self.request(TMKDIR);
+        req.str(name).u32(mode).u32(0);
+        self.client.call(&mut req, |_| Ok(()))
+    }
+
+    /// Creates a symbolic link of `name` to `target` in this directory.
+    pub fn symlink(&self, name: &str, target: &str) -> VfsResult {
+        let mut req = self.request(TSYMLINK);
+        req.str(name).str(target).u32(0);
+        self.client.call(&mut req, |_| Ok(()))
+    }
+
+    /// Creates a hard link of `name` to `file` in this directory.
+    pub fn link(&self, name: &str, file: &Fid) -> VfsResult {
+        let mut req = self.request(TLINK);
+        req.u32(file.id).str(name);
+        self.client.call(&mut req, |_| Ok(()))
+    }
+
+    /// Renames `name` in this directory to `new_name` in the directory
+    /// `new_dir`.
+    pub fn rename(&self, name: &str, new_dir: &Fid, new_name: &str) -> VfsResult {
+        let mut req = self.request(TRENAMEAT);
+        req.str(name).u32(new_dir.id).str(new_name);
+        self.client.call(&mut req, |_| Ok(()))
+    }
+
+    /// Removes `name` in this directory, which must be a directory if `flags`
+    /// contains [`AT_REMOVEDIR`].
+    pub fn unlink(&self, name: &str, flags: u32) -> VfsResult {
+        let mut req = self.request(TUNLINKAT);
+        req.str(name).u32(flags);
+        self.client.call(&mut req, |_| Ok(()))
+    }
+
+    pub fn readlink(&self) -> VfsResult<String> {
+        self.client
+            .call(&mut self.request(TREADLINK), |mut r| Ok(r.str()?.into()))
+    }
+
+    pub fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let mut req = self.request(TGETATTR);
+        req.u64(GETATTR_BASIC);
+        self.client.call(&mut req, |mut r| {
+            let _valid = r.u64()?;
+            let qid = r.qid()?;
+            let mode = r.u32()?;
+            let (uid, gid) = (r.u32()?, r.u32()?);
+            let nlink = r.u64()?;
+            let _rdev = r.u64()?;
+            let size = r.u64()?;
+            let _blksize = r.u64()?;
+            let blocks = r.u64()?;
+            let (atime, mtime, ctime) = (r.time()?, r.time()?, r.time()?);
+            let perm = VfsNodePerm::from_bits_truncate(mode as u16);
+            let mut attr = VfsNodeAttr::new(perm, node_type(mode), size, blocks);
+            attr.set_nlink(nlink);
+            attr.set_ino(qid.path);
+            attr.set_owner(uid, gid);
+            attr.set_times(atime, mtime, ctime);
+            Ok(attr)
+        })
+    }
+
+    /// Changes the attributes given in `attr`, and the size to `size` if it is
+    /// not `None`.
+    pub fn set_attr(&self, attr: &VfsSetAttr, size: Option<u64>) -> VfsResult {
+        let fields = [
+            (SETATTR_MODE, attr.perm.is_some()),
+            (SETATTR_UID, attr.uid.is_some()),
+            (SETATTR_GID, attr.gid.is_some()),
+            (SETATTR_SIZE, size.is_some()),
+            (SETATTR_ATIME | SETATTR_ATIME_SET, attr.atime.is_some()),
+            (SETATTR_MTIME | SETATTR_MTIME_SET, attr.mtime.is_some()),
+        ];
+        let valid = fields
+            .iter()
+            .filter(|(_, set)| *set)
+            .fold(0, |v, (f, _)| v | f);
+        let mut req = self.request(TSETATTR);
+        req.u32(valid)
+            .u32(attr.perm.map_or(0, |p| p.bits() as u32))
+            .u32(attr.uid.unwrap_or_default())
+            .u32(attr.gid.unwrap_or_default())
+            .u64(size.unwrap_or_default());
+        for time in [attr.atime, attr.mtime] {
+            let time = time.unwrap_or_default();
+            req.u64(time.as_secs()).u64(time.subsec_nanos() as u64);
+        }
+        self.client.call(&mut req, |_| Ok(()))
+    }
+
+    /// Reads the opened file at `offset` into `buf`, until `buf` is full or
+    /// the end of the file is reached.
+    pub fn read(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let mut read = 0;
+        while read < buf.len() {
+            let count = (buf.len() - read).min(self.client.io_size);
+            let mut req = self.request(TREAD);
+            req.u64(offset + read as u64).u32(count as u32);
+            let len = self.client.call(&mut req, |mut r| {
+                let len = r.u32()? as usize;
+                let data = r.bytes(len.min(count))?;
+                buf[read..read + data.len()].copy_from_slice(data);
+                Ok(data.len())
+            })?;
+            read += len;
+            if len < count {
+                break;
+            }
+        }
+        Ok(read)
+    }
+
+    /// Writes `buf` to the opened file at `offset`.
+    pub fn write(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let mut written = 0;
+        while written < buf.len() {
+            let data = &buf[written..(buf.len()).min(written + self.client.io_size)];
+            let mut req = self.request(TWRITE);
+            req.u64(offset + written as u64).u32(data.len() as u32);
+            req.0.extend_from_slice(data);
+            let len = self.client.call(&mut req, |mut r| Ok(r.u32()? as usize))?;
+            if len == 0 || len > data.len() {
+                return Err(VfsError::WriteZero);
+            }
+            written += len;
+        }
+        Ok(written)
+    }
+
+    pub fn fsync(&self) -> VfsResult {
+        let mut req = self.request(TFSYNC);
+        req.u32(0); // datasync
+        self.client.call(&mut req, |_| Ok(()))
+    }
+
+    /// Reads the entries of the opened directory starting from `offset`, and
+    /// passes each entry to `f` as `(name, type, offset of the next entry)`
+    /// until it returns `false`. Returns whether the end is reached.
+    pub fn read_dir(
+        &self,
+        offset: u64,
+        mut f: impl FnMut(&str, u8, u64) -> bool,
+    ) -> VfsResult<bool> {
+        let mut req = self.request(TREADDIR);
+        req.u64(offset).u32(self.client.io_size as u32);
+        self.client.call(&mut req, |mut r| {
+            let len = r.u32()? as usize;
+            let mut entries = Reply(r.bytes(len)?);
+            if entries.0.is_empty() {
+                return Ok(true);
+            }
+            while !entries.0.is_empty() {
+                let _qid = entries.qid()?;
+                let next = entries.u64()?;
+                let ty = entries.u8()?;
+                let name = entries.str()?;
+                if !f(name, ty, next) {
+                    break;
+                }
+            }
+            Ok(false)
+        })
+    }
+
+    pub fn statfs(&self) -> VfsResult<FileSystemInfo> {
+        self.client.call(&mut self.request(TSTATFS), |mut r| {
+            let _fs_type = r.u32()?;
+            let block_size = r.u32()? as u64;
+            let blocks = r.u64()?;
+            let _blocks_free = r.u64()?;
+            let blocks_avail = r.u64()?;
+            let files = r.u64()?;
+            let files_free = r.u64()?;
+            let _fsid = r.u64()?;
+            let name_max = r.u32()? as u64;
+            Ok(FileSystemInfo {
+                block_size,
+                blocks,
+                blocks_free: blocks_avail,
+                files,
+                files_free,
+                name_max,
+            })
+        })
+    }
+}
+
+impl Drop for Fid {
+    fn drop(&mut self) {
+        let mut req = self.request(TCLUNK);
+        if let Err(e) = self.client.call(&mut req, |_| Ok(())) {
+            warn!("9p: failed to clunk fid {}: {:?}", self.id, e);
+        }
+        // the fid is released by the server even if it returns an error
+        self.client.inner.lock().free_fids.push(self.id);
+    }
+}
@@ -0,0 +1,127 @@
+//! The 9P2000.L client filesystem, which shares directories of the host with
+//! the guest, e.g., by QEMU `-fsdev local,...` and `-device virtio-9p-...`.
+//!
+//! The 9P devices are registered by [`register_ninep_device`], then each
+//! exported directory is mounted by its mount tag, either in the mount table
+//! with the filesystem type `9p` or automatically on `/<tag>`.
+//!
+//! Some limitations:
+//!
+//! - Requests are sent one at a time, and the maximum message size is 64 KiB.
+//! - All files are accessed as `root`, so the permissions are enforced by the
+//!   server as the user it runs as (e.g., `security_model=none` of QEMU).
+//! - Nothing is cached, so every operation makes at least one round trip.
+
+mod client;
+mod node;
+
+use alloc::{boxed::Box, string::String, sync::Arc, vec::Vec};
+
+use axdriver::prelude::NinePDriverOps;
+use axerrno::{ax_err, AxResult};
+use axfs_vfs::{FileSystemInfo, VfsError, VfsNodeRef, VfsNodeType, VfsOps, VfsResult};
+use axsync::Mutex;
+
+use self::client::{Client, Qid};
+use self::node::NinePNode;
+
+/// The clients of the registered 9P devices.
+static CLIENTS: Mutex<Vec<Arc<Client>>> = Mutex::new(Vec::new());
+
+/// Registers a 9P device, whose exported directory can then be mounted by its
+/// mount tag.
+///
+/// To be mounted by the mount table or automatically, it must be registered
+/// before [`init_filesystems`](crate::init_filesystems). Returns
+/// [`AlreadyExists`](axerrno::AxError::AlreadyExists) if the mount tag is
+/// taken by another registered device.
+pub fn register_ninep_device(dev: impl NinePDriverOps + 'static) -> AxResult {
+    let client = Client::connect(Box::new(dev))?;
+    let mut clients = CLIENTS.lock();
+    if clients.iter().any(|c| c.mount_tag() == client.mount_tag()) {
+        return ax_err!(AlreadyExists, "9p mount tag already registered");
+    }
+    info!("9p: register device {:?}", client.mount_tag());
+    clients.push(Arc::new(client));
+    Ok(())
+}
+
+/// Returns the mount tags of the registered 9P devices.
+pub(crate) fn mount_tags() -> Vec<String> {
+    CLIENTS
+        .lock()
+        .iter()
+        .map(|c| c.mount_tag().into())
+        .collect()
+}
+
+/// The state shared by all nodes of a mounted filesystem.
+struct Session {
+    root_qid: Qid,
+    /// The parent of the mount point.
+    parent: Mutex<Option<VfsNodeRef>>,
+}
+
+/// The directory exported by a 9P device that implements
+/// [`axfs_vfs::VfsOps`].
+pub struct NinePFileSystem {
+    session: Arc<Session>,
+    root: Arc<NinePNode>,
+}
+
+impl NinePFileSystem {
+    /// Attaches to the directory exported by the registered 9P device of
+    /// `mount_tag`.
+    pub fn open(mount_tag: &str) -> VfsResult<Arc<Self>> {
+        let client = CLIENTS
+            .lock()
+            .iter()
+            .find(|c| c.mount_tag() == mount_tag)
+            .cloned()
+            .ok_or(VfsError::NotFound)?;
+        let (fid, root_qid) = client.attach("")?;
+        let session = Arc::new(Session {
+            root_qid,
+            parent: Mutex::new(None),
+        });
+        let root = Arc::new(NinePNode::new(session.clone(), fid, root_qid));
+        Ok(Arc::new(Self { session, root }))
+    }
+}
+
+impl VfsOps for NinePFileSystem {
+    fn mount(&self, _path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        *self.session.parent.lock() = mount_point.parent();
+        Ok(())
+    }
+
+    fn statfs(&self) -> VfsResult<FileSystemInfo> {
+        self.root.fid().statfs()
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        self.root.clone()
+    }
+
+    fn name(&self) -> &str {
+        "9p"
+    }
+}
+
+/// Returns the node type of the Linux file `mode`.
+fn node_type(mode: u32) -> VfsNodeType {
+    match (mode >> 12) & 0o17 {
+        0o1 => VfsNodeType::Fifo,
+        0o2 => VfsNodeType::CharDevice,
+        0o4 => VfsNodeType::Dir,
+        0o6 => VfsNodeType::BlockDevice,
+        0o12 => VfsNodeType::SymLink,
+        0o14 => VfsNodeType::Socket,
+        _ => VfsNodeType::File,
+    }
+}
+
+/// Returns the node type of the Linux directory entry type `DT_*`.
+fn dirent_type(ty: u8) -> VfsNodeType {
+    node_type((ty as u32) << 12)
+}
@@ -0,0 +1,251 @@
+//! Files on the server as VFS nodes.
+
+use alloc::{string::String, sync::Arc, vec::Vec};
+
+use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodePerm, VfsNodeRef, VfsNodeType};
+use axfs_vfs::{VfsError, VfsResult, VfsSetAttr};
+use axsync::Mutex;
+
+use super::client::{Fid, Qid, AT_REMOVEDIR, O_RDONLY, O_WRONLY, QTDIR};
+use super::Session;
+
+/// A file on the server, which is referred to by a fid that is never opened,
+/// so other files can be walked to from it.
+///
+/// It implements [`axfs_vfs::VfsNodeOps`] for all types of files.
+pub struct NinePNode {
+    session: Arc<Session>,
+    fid: Fid,
+    qid: Qid,
+    /// The fids opened for reading and for writing on demand, as a fid can
+    /// only be opened once.
+    opened: [Mutex<Option<Fid>>; 2],
+    /// The index and the offset of the next entry of the directory to read,
+    /// to continue sequential [`read_dir`](VfsNodeOps::read_dir)s.
+    dir_pos: Mutex<(usize, u64)>,
+}
+
+impl NinePNode {
+    pub(super) fn new(session: Arc<Session>, fid: Fid, qid: Qid) -> Self {
+        Self {
+            session,
+            fid,
+            qid,
+            opened: [Mutex::new(None), Mutex::new(None)],
+            dir_pos: Mutex::new((0, 0)),
+        }
+    }
+
+    pub(super) fn fid(&self) -> &Fid {
+        &self.fid
+    }
+
+    fn is_dir(&self) -> bool {
+        self.qid.ty & QTDIR != 0
+    }
+
+    fn is_root(&self) -> bool {
+        self.qid == self.session.root_qid
+    }
+
+    /// Returns the node at the relative path `names`.
+    fn walk(&self, names: &[&str]) -> VfsResult<Arc<Self>> {
+        let (fid, qid) = self.fid.walk(names)?;
+        let qid = qid.unwrap_or(self.qid);
+        Ok(Arc::new(Self::new(self.session.clone(), fid, qid)))
+    }
+
+    /// Walks to the directory containing the last component of `path`,
+    /// returns its fid with the name of the last component.
+    fn walk_parent<'a>(&self, path: &'a str) -> VfsResult<(Fid, &'a str)> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        let (fid, qid) = self.fid.walk(&components(parent))?;
+        if qid.unwrap_or(self.qid).ty & QTDIR != 0 {
+            Ok((fid, name))
+        } else {
+            Err(VfsError::NotADirectory)
+        }
+    }
+
+    /// Calls `f` with the fid opened for writing if `write` is `true`, or for
+    /// reading otherwise.
+    fn with_opened<T>(&self, write: bool, f: impl FnOnce(&Fid) -> VfsResult<T>) -> VfsResult<T> {
+        let mut opened = self.opened[write as usize].lock();
+        if opened.is_none() {
+            let (fid, _) = self.fid.walk(&[])?;
+            fid.open(if write { O_WRONLY } else { O_RDONLY })?;
+            *opened = Some(fid);
+        }
+        f(opened.as_ref().unwrap())
+    }
+}
+
+impl VfsNodeOps for NinePNode {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        self.fid.get_attr()
+    }
+
+    fn set_attr(&self, attr: &VfsSetAttr) -> VfsResult {
+        self.fid.set_attr(attr, None)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        if self.is_dir() {
+            return Err(VfsError::IsADirectory);
+        }
+        self.with_opened(false, |fid| fid.read(offset, buf))
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        if self.is_dir() {
+            return Err(VfsError::IsADirectory);
+        }
+        self.with_opened(true, |fid| fid.write(offset, buf))
+    }
+
+    fn fsync(&self) -> VfsResult {
+        match &*self.opened[1].lock() {
+            Some(fid) => fid.fsync(),
+            None => Ok(()), // nothing written
+        }
+    }
+
+    fn truncate(&self, size: u64) -> VfsResult {
+        if self.is_dir() {
+            return Err(VfsError::IsADirectory);
+        }
+        self.fid.set_attr(&VfsSetAttr::default(), Some(size))
+    }
+
+    fn readlink(&self) -> VfsResult<String> {
+        self.fid.readlink()
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        if self.is_root() {
+            self.session.parent.lock().clone()
+        } else if self.is_dir() {
+            self.walk(&[".."]).ok().map(|n| n as VfsNodeRef)
+        } else {
+            None
+        }
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        if self.is_root() && path.trim_matches('/') == ".." {
+            if let Some(parent) = self.session.parent.lock().clone() {
+                return Ok(parent);
+            }
+        }
+        let names = components(path);
+        if names.is_empty() {
+            return Ok(self);
+        }
+        Ok(self.walk(&names)?)
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        if !self.is_dir() {
+            return Err(VfsError::NotADirectory);
+        }
+        let mut pos = self.dir_pos.lock();
+        if start_idx < pos.0 {
+            *pos = (0, 0); // rewind
+        }
+        self.with_opened(false, |fid| {
+            let mut count = 0;
+            while count < dirents.len() {
+                let end = fid.read_dir(pos.1, |name, ty, next| {
+                    if count == dirents.len() {
+                        return false;
+                    }
+                    if pos.0 >= start_idx {
+                        dirents[count] = VfsDirEntry::new(name, super::dirent_type(ty));
+                        count += 1;
+                    }
+                    *pos = (pos.0 + 1, next);
+                    true
+                })?;
+                if end {
+                    break;
+                }
+            }
+            Ok(count)
+        })
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        debug!("create {:?} at 9p: {}", ty, path);
+        let (dir, name) = self.walk_parent(path)?;
+        match ty {
+            _ if matches!(name, "" | "." | "..") => Err(VfsError::AlreadyExists),
+            // the fid of the directory becomes the opened new file
+            VfsNodeType::File => dir.create(name, VfsNodePerm::default_file().bits() as u32),
+            VfsNodeType::Dir => dir.mkdir(name, VfsNodePerm::default_dir().bits() as u32),
+            _ => Err(VfsError::Unsupported),
+        }
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        debug!("remove at 9p: {}", path);
+        let (dir, name) = self.walk_parent(path)?;
+        if matches!(name, "" | "." | "..") {
+            return Err(VfsError::InvalidInput);
+        }
+        let (_, qid) = dir.walk(&[name])?;
+        let flags = match qid {
+            Some(qid) if qid.ty & QTDIR != 0 => AT_REMOVEDIR,
+            _ => 0,
+        };
+        dir.unlink(name, flags)
+    }
+
+    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
+        debug!("rename at 9p: {} -> {}", src_path, dst_path);
+        let (src_dir, src_name) = self.walk_parent(src_path)?;
+        let (dst_dir, dst_name) = self.walk_parent(dst_path)?;
+        if [src_name, dst_name]
+            .iter()
+            .any(|n| matches!(*n, "" | "." | ".."))
+        {
+            return Err(VfsError::InvalidInput);
+        }
+        src_dir.rename(src_name, &dst_dir, dst_name)
+    }
+
+    fn symlink(&self, path: &str, target: &str) -> VfsResult {
+        debug!("symlink at 9p: {} -> {}", path, target);
+        let (dir, name) = self.walk_parent(path)?;
+        if matches!(name, "" | "." | "..") {
+            return Err(VfsError::AlreadyExists);
+        }
+        dir.symlink(name, target)
+    }
+
+    fn link(&self, path: &str, node: &VfsNodeRef) -> VfsResult {
+        debug!("link at 9p: {}", path);
+        let Some(node) = node.as_any().downcast_ref::<Self>() else {
+            return Err(VfsError::CrossesDevices);
+        };
+        if !Arc::ptr_eq(node.fid.client(), self.fid.client()) {
+            return Err(VfsError::CrossesDevices);
+        }
+        let (dir, name) = self.walk_parent(path)?;
+        if matches!(name, "" | "." | "..") {
+            return Err(VfsError::AlreadyExists);
+        }
+        dir.link(name, &node.fid)
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
+
+/// Splits `path` into the names to walk, where `.` is skipped.
+fn components(path: &str) -> Vec<&str> {
+    path.split('/')
+        .filter(|n| !n.is_empty() && *n != ".")
+        .collect()
+}
@@ -0,0 +1,537 @@
+//! An overlay filesystem, which merges a read-only lower filesystem with a
+//! writable upper one.
+//!
+//! Files are looked up in the upper filesystem first, then in the lower one.
+//! Directories existing in both are merged. The lower filesystem is never
+//! modified: a file or directory is copied up to the upper filesystem before
+//! it is modified, and removed files of the lower filesystem are hidden by
+//! whiteouts.
+//!
+//! Whiteouts are stored as empty files named `.wh.<name>` in the upper
+//! directory (like aufs), so the upper filesystem may be any writable one.
+//! Such names are hidden, and can not be created in the overlay.
+
+use alloc::collections::BTreeMap;
+use alloc::{format, string::String, sync::Arc, sync::Weak, vec, vec::Vec};
+use axerrno::ax_err;
+use axfs_vfs::{path::canonicalize, VfsNodeType, VfsOps, VfsResult, VfsSetAttr};
+use axfs_vfs::{FileSystemInfo, VfsDirEntry, VfsError, VfsNodeAttr, VfsNodeOps, VfsNodeRef};
+use axsync::Mutex;
+
+/// Prefix of the names of whiteouts.
+const WHITEOUT_PREFIX: &str = ".wh.";
+
+/// Serializes copy-ups, so that a file is copied only once.
+static COPY_UP_LOCK: Mutex<()> = Mutex::new(());
+
+/// A filesystem merging a read-only `lower` filesystem with a writable `upper`
+/// filesystem, see the [module-level documentation](self).
+///
+/// It can be used to make an immutable image (e.g., FAT or initramfs)
+/// writable, with the changes kept in a [`RamFileSystem`] by
+/// [`OverlayFs::with_ramfs`].
+///
+/// Renaming a directory of the lower filesystem fails with
+/// [`CrossesDevices`](axerrno::AxError::CrossesDevices), like on Linux without
+/// `redirect_dir`, so that applications copy it instead.
+///
+/// [`RamFileSystem`]: axfs_ramfs::RamFileSystem
+pub struct OverlayFs {
+    lower: Arc<dyn VfsOps>,
+    upper: Arc<dyn VfsOps>,
+    root: Arc<OverlayNode>,
+}
+
+/// A node of [`OverlayFs`], which is a file or directory of the upper
+/// filesystem, the lower filesystem, or both.
+struct OverlayNode {
+    this: Weak<OverlayNode>,
+    /// The parent directory and the name in it, or `None` for the root.
+    parent: Option<(Arc<OverlayNode>, String)>,
+    /// The parent of the mount point, only used by the root.
+    mount_parent: Mutex<Option<VfsNodeRef>>,
+    /// The node in the upper filesystem, which is looked up again if it is
+    /// `None`, as it may be copied up through other nodes of the same path.
+    upper: Mutex<Option<VfsNodeRef>>,
+    /// The node in the lower filesystem, which has the same type if the upper
+    /// one exists.
+    lower: Option<VfsNodeRef>,
+}
+
+impl OverlayFs {
+    /// Creates an overlay of the `lower` filesystem, whose changes are written
+    /// to the `upper` filesystem.
+    ///
+    /// The `upper` filesystem is usually empty, or an upper filesystem of the
+    /// same lower one before.
+    pub fn new(lower: Arc<dyn VfsOps>, upper: Arc<dyn VfsOps>) -> Self {
+        let root = OverlayNode::new(None, Some(upper.root_dir()), Some(lower.root_dir()));
+        Self { lower, upper, root }
+    }
+
+    /// Creates an overlay of the `lower` filesystem, whose changes are kept in
+    /// memory by a [`RamFileSystem`](axfs_ramfs::RamFileSystem) like the one
+    /// on `/tmp`.
+    #[cfg(feature = "ramfs")]
+    pub fn with_ramfs(lower: Arc<dyn VfsOps>) -> Self {
+        Self::new(lower, crate::mounts::ramfs())
+    }
+}
+
+impl VfsOps for OverlayFs {
+    fn mount(&self, _path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        *self.root.mount_parent.lock() = mount_point.parent();
+        Ok(())
+    }
+
+    fn umount(&self) -> VfsResult {
+        self.upper.umount()?;
+        self.lower.umount()
+    }
+
+    fn statfs(&self) -> VfsResult<FileSystemInfo> {
+        self.upper.statfs()
+    }
+
+    fn sync(&self) -> VfsResult {
+        self.upper.sync()?;
+        self.lower.sync()
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        self.root.clone()
+    }
+
+    fn name(&self) -> &str {
+        "overlay"
+    }
+}
+
+impl OverlayNode {
+    fn new(
+        parent: Option<(Arc<OverlayNode>, String)>,
+        upper: Option<VfsNodeRef>,
+        lower: Option<VfsNodeRef>,
+    ) -> Arc<Self> {
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            parent,
+            mount_parent: Mutex::new(None),
+            upper: Mutex::new(upper),
+            lower,
+        })
+    }
+
+    fn arc(&self) -> Arc<Self> {
+        self.this.upgrade().unwrap()
+    }
+
+    /// Returns the node in the upper filesystem if it exists.
+    fn upper(&self) -> Option<VfsNodeRef> {
+        let mut upper = self.upper.lock();
+        if upper.is_none() {
+            let (parent, name) = self.parent.as_ref()?;
+            *upper = parent.upper()?.lookup(name).ok();
+        }
+        upper.clone()
+    }
+
+    /// Returns the node in the upper filesystem if it exists, or the one in
+    /// the lower filesystem.
+    fn current(&self) -> VfsNodeRef {
+        self.upper().or_else(|| self.lower.clone()).unwrap()
+    }
+
+    fn is_dir(&self) -> VfsResult<bool> {
+        Ok(self.current().get_attr()?.is_dir())
+    }
+
+    /// Looks up the entry `name` of this directory.
+    fn lookup_child(&self, name: &str) -> VfsResult<Arc<Self>> {
+        if !self.is_dir()? {
+            return ax_err!(NotADirectory);
+        }
+        if name.starts_with(WHITEOUT_PREFIX) {
+            return ax_err!(NotFound);
+        }
+        let upper_dir = self.upper();
+        let upper = match upper_dir.clone().map(|dir| dir.lookup(name)) {
+            Some(Ok(node)) => Some(node),
+            None | Some(Err(VfsError::NotFound)) => None,
+            Some(Err(e)) => return Err(e),
+        };
+        let mut lower = match &self.lower {
+            Some(dir) if !has_whiteout(upper_dir.as_ref(), name) => {
+                match dir.clone().lookup(name) {
+                    Ok(node) => Some(node),
+                    Err(VfsError::NotFound | VfsError::NotADirectory) => None,
+                    Err(e) => return Err(e),
+                }
+            }
+            _ => None,
+        };
+        if let (Some(upper), Some(node)) = (&upper, &lower) {
+            if upper.get_attr()?.file_type() != node.get_attr()?.file_type() {
+                lower = None;
+            }
+        }
+        if upper.is_none() && lower.is_none() {
+            return ax_err!(NotFound);
+        }
+        Ok(Self::new(Some((self.arc(), name.into())), upper, lower))
+    }
+
+    /// Looks up the node at `path` relative to this directory.
+    fn lookup_path(&self, path: &str) -> VfsResult<Arc<Self>> {
+        let mut node = self.arc();
+        for name in path.split('/') {
+            node = match name {
+                "" | "." => node,
+                ".." => match &node.parent {
+                    Some((parent, _)) => parent.clone(),
+                    None => return ax_err!(NotFound),
+                },
+                _ => node.lookup_child(name)?,
+            };
+        }
+        Ok(node)
+    }
+
+    /// Looks up the parent directory of `path` relative to this directory,
+    /// and returns it with the last component of `path`.
+    fn lookup_parent<'a>(&self, path: &'a str) -> VfsResult<(Arc<Self>, &'a str)> {
+        let path = path.trim_end_matches('/');
+        let (parent, name) = path.rsplit_once('/').unwrap_or(("", path));
+        if matches!(name, "" | "." | "..") {
+            return ax_err!(InvalidInput);
+        }
+        let parent = self.lookup_path(parent)?;
+        if !parent.is_dir()? {
+            return ax_err!(NotADirectory);
+        }
+        Ok((parent, name))
+    }
+
+    /// Returns the entries of this directory, merged from both filesystems.
+    fn entries(&self) -> VfsResult<BTreeMap<String, VfsNodeType>> {
+        let upper = self.upper();
+        let mut entries = BTreeMap::new();
+        if let Some(lower) = &self.lower {
+            for (name, ty) in read_all(lower)? {
+                if !has_whiteout(upper.as_ref(), &name) {
+                    entries.insert(name, ty);
+                }
+            }
+        }
+        if let Some(upper) = &upper {
+            for (name, ty) in read_all(upper)? {
+                if !name.starts_with(WHITEOUT_PREFIX) {
+                    entries.insert(name, ty);
+                }
+            }
+        }
+        Ok(entries)
+    }
+
+    /// Copies the node to the upper filesystem, with its parent directories,
+    /// if it only exists in the lower filesystem. Returns the upper node.
+    fn copy_up(&self) -> VfsResult<VfsNodeRef> {
+        if let Some(upper) = self.upper() {
+            return Ok(upper);
+        }
+        // the root always exists in the upper filesystem
+        let (parent, name) = self.parent.as_ref().unwrap();
+        let upper_dir = parent.copy_up()?;
+        let lower = self.lower.as_ref().unwrap();
+        let attr = lower.get_attr()?;
+
+        let _guard = COPY_UP_LOCK.lock();
+        if let Some(upper) = self.upper() {
+            return Ok(upper); // copied up through other nodes
+        }
+        match attr.file_type() {
+            VfsNodeType::Dir => upper_dir.create(name, VfsNodeType::Dir)?,
+            VfsNodeType::File => upper_dir.create(name, VfsNodeType::File)?,
+            VfsNodeType::SymLink => upper_dir.symlink(name, &lower.readlink()?)?,
+            _ => {
+                return ax_err!(
+                    Unsupported,
+                    "only files, directories and symlinks can be copied"
+                )
+            }
+        }
+        let upper = upper_dir.clone().lookup(name)?;
+        if let Err(e) = copy_contents(lower, &upper, &attr) {
+            upper_dir.remove(name).ok();
+            return Err(e);
+        }
+        *self.upper.lock() = Some(upper.clone());
+        Ok(upper)
+    }
+}
+
+impl VfsNodeOps for OverlayNode {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let mut attr = self.current().get_attr()?;
+        // keep the inode numbers of the filesystems apart, and those of the
+        // lower nodes unchanged by copy-ups. If the lower filesystem has no
+        // inode numbers (e.g., FAT reports 0), the number of a node changes
+        // to that of its upper copy when it is copied up.
+        let lower_ino = match &self.lower {
+            Some(lower) => lower.get_attr()?.ino(),
+            None => 0,
+        };
+        if lower_ino != 0 {
+            attr.set_ino((lower_ino << 1) | 1);
+        } else {
+            attr.set_ino(attr.ino() << 1);
+        }
+        Ok(attr)
+    }
+
+    fn set_attr(&self, attr: &VfsSetAttr) -> VfsResult {
+        self.copy_up()?.set_attr(attr)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        self.current().read_at(offset, buf)
+    }
+
+    fn write_at(&self, offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        self.copy_up()?.write_at(offset, buf)
+    }
+
+    fn fsync(&self) -> VfsResult {
+        match self.upper() {
+            Some(upper) => upper.fsync(),
+            None => Ok(()),
+        }
+    }
+
+    fn truncate(&self, size: u64) -> VfsResult {
+        self.copy_up()?.truncate(size)
+    }
+
+    fn readlink(&self) -> VfsResult<String> {
+        self.current().readlink()
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        match &self.parent {
+            Some((parent, _)) => Some(parent.clone()),
+            None => self.mount_parent.lock().clone(),
+        }
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        Ok(self.lookup_path(path)?)
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        let (dir, name) = self.lookup_parent(path)?;
+        match dir.lookup_child(name) {
+            Ok(_) => return Ok(()), // already exists
+            Err(VfsError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        check_name(name)?;
+        dir.copy_up()?.create(name, ty)
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        let (dir, name) = self.lookup_parent(path)?;
+        let node = dir.lookup_child(name)?;
+        let is_dir = node.is_dir()?;
+        if is_dir && !node.entries()?.is_empty() {
+            return ax_err!(DirectoryNotEmpty);
+        }
+        let upper_dir = dir.copy_up()?;
+        if let Some(upper) = node.upper() {
+            if is_dir {
+                remove_whiteouts(&upper)?;
+            }
+            upper_dir.remove(name)?;
+        }
+        if node.lower.is_some() {
+            upper_dir.create(&format!("{WHITEOUT_PREFIX}{name}"), VfsNodeType::File)?;
+        }
+        Ok(())
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let entries = self.entries()?;
+        let mut entries = entries.iter().skip(start_idx.max(2) - 2);
+        for (i, ent) in dirents.iter_mut().enumerate() {
+            match i + start_idx {
+                0 => *ent = VfsDirEntry::new(".", VfsNodeType::Dir),
+                1 => *ent = VfsDirEntry::new("..", VfsNodeType::Dir),
+                _ => {
+                    if let Some((name, &ty)) = entries.next() {
+                        *ent = VfsDirEntry::new(name, ty);
+                    } else {
+                        return Ok(i);
+                    }
+                }
+            }
+        }
+        Ok(dirents.len())
+    }
+
+    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
+        if canonicalize(src_path) == canonicalize(dst_path) {
+            self.lookup_path(src_path)?;
+            return Ok(());
+        }
+        let (src_dir, src_name) = self.lookup_parent(src_path)?;
+        let (dst_dir, dst_name) = self.lookup_parent(dst_path)?;
+        let src = src_dir.lookup_child(src_name)?;
+        let src_is_dir = src.is_dir()?;
+        if src_is_dir && src.lower.is_some() {
+            return ax_err!(
+                CrossesDevices,
+                "directories of the lower filesystem can not be renamed"
+            );
+        }
+        let dst = match dst_dir.lookup_child(dst_name) {
+            Ok(dst) => Some(dst),
+            Err(VfsError::NotFound) => None,
+            Err(e) => return Err(e),
+        };
+        if let Some(dst) = &dst {
+            let dst_is_dir = dst.is_dir()?;
+            if src_is_dir && !dst_is_dir {
+                return ax_err!(NotADirectory);
+            } else if !src_is_dir && dst_is_dir {
+                return ax_err!(IsADirectory);
+            } else if dst_is_dir && !dst.entries()?.is_empty() {
+                return ax_err!(DirectoryNotEmpty);
+            }
+        }
+        check_name(dst_name)?;
+
+        src.copy_up()?;
+        let dst_upper_dir = dst_dir.copy_up()?;
+        if let Some(upper) = dst.as_ref().and_then(|dst| dst.upper()) {
+            if upper.get_attr()?.is_dir() {
+                remove_whiteouts(&upper)?;
+            }
+        }
+        // both directories are in the upper filesystem now
+        self.copy_up()?.rename(src_path, dst_path)?;
+        if dst.is_some_and(|dst| dst.lower.is_some()) {
+            let whiteout = format!("{WHITEOUT_PREFIX}{dst_name}");
+            dst_upper_dir.create(&whiteout, VfsNodeType::File)?;
+        }
+        if src.lower.is_some() {
+            let whiteout = format!("{WHITEOUT_PREFIX}{src_name}");
+            src_dir.copy_up()?.create(&whiteout, VfsNodeType::File)?;
+        }
+        Ok(())
+    }
+
+    fn symlink(&self, path: &str, target: &str) -> VfsResult {
+        let (dir, name) = self.lookup_parent(path)?;
+        match dir.lookup_child(name) {
+            Ok(_) => return ax_err!(AlreadyExists),
+            Err(VfsError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        check_name(name)?;
+        dir.copy_up()?.symlink(name, target)
+    }
+
+    fn link(&self, path: &str, node: &VfsNodeRef) -> VfsResult {
+        let Some(node) = node.as_any().downcast_ref::<OverlayNode>() else {
+            return ax_err!(CrossesDevices);
+        };
+        let (dir, name) = self.lookup_parent(path)?;
+        match dir.lookup_child(name) {
+            Ok(_) => return ax_err!(AlreadyExists),
+            Err(VfsError::NotFound) => {}
+            Err(e) => return Err(e),
+        }
+        check_name(name)?;
+        let upper = node.copy_up()?;
+        dir.copy_up()?.link(name, &upper)
+    }
+
+    fn as_any(&self) -> &dyn core::any::Any {
+        self
+    }
+}
+
+/// Checks that `name` can be created in the overlay.
+fn check_name(name: &str) -> VfsResult {
+    if name.starts_with(WHITEOUT_PREFIX) {
+        ax_err!(InvalidInput, "names of whiteouts are reserved")
+    } else {
+        Ok(())
+    }
+}
+
+/// Whether the entry `name` of the lower directory is hidden by a whiteout in
+/// the upper directory `upper_dir`.
+fn has_whiteout(upper_dir: Option<&VfsNodeRef>, name: &str) -> bool {
+    upper_dir.is_some_and(|dir| {
+        dir.clone()
+            .lookup(&format!("{WHITEOUT_PREFIX}{name}"))
+            .is_ok()
+    })
+}
+
+/// Removes the whiteouts in the upper directory `dir`, before it is removed.
+fn remove_whiteouts(dir: &VfsNodeRef) -> VfsResult {
+    for (name, _) in read_all(dir)? {
+        if name.starts_with(WHITEOUT_PREFIX) {
+            dir.remove(&name)?;
+        }
+    }
+    Ok(())
+}
+
+/// Reads all entries of the directory `dir` but `.` and `..`.
+fn read_all(dir: &VfsNodeRef) -> VfsResult<Vec<(String, VfsNodeType)>> {
+    let mut entries = Vec::new();
+    let mut dirents: [VfsDirEntry; 16] = core::array::from_fn(|_| VfsDirEntry::default());
+    let mut idx = 0;
+    loop {
+        let n = dir.read_dir(idx, &mut dirents)?;
+        if n == 0 {
+            break;
+        }
+        idx += n;
+        for ent in &dirents[..n] {
+            let name = String::from_utf8_lossy(ent.name_as_bytes());
+            if name != "." && name != ".." {
+                entries.push((name.into_owned(), ent.entry_type()));
+            }
+        }
+    }
+    Ok(entries)
+}
+
+/// Copies the contents and attributes of the lower node `lower`, whose
+/// attributes are `attr`, to the new upper node `upper`.
+fn copy_contents(lower: &VfsNodeRef, upper: &VfsNodeRef, attr: &VfsNodeAttr) -> VfsResult {
+    if attr.is_file() {
+        let mut buf = vec![0; 16 * 1024];
+        let mut offset = 0;
+        loop {
+            let n = lower.read_at(offset, &mut buf)?;
+            if n == 0 {
+                break;
+            }
+            upper.write_at(offset, &buf[..n])?;
+            offset += n as u64;
+        }
+    }
+    // the permissions of symbolic links can not be changed
+    let perm = Some(attr.perm()).filter(|_| !attr.is_symlink());
+    upper.set_attr(&VfsSetAttr {
+        perm,
+        uid: Some(attr.uid()),
+        gid: Some(attr.gid()),
+        atime: Some(attr.atime()),
+        mtime: Some(attr.mtime()),
+    })
+}
@@ -0,0 +1,374 @@
+//! A pseudo filesystem whose contents are generated on access.
+//!
+//! Unlike a ramfs, nothing is stored in the filesystem: regular files produce
+//! their contents each time they are read and pass the written data to a
+//! callback, and directories can list entries that are generated on the fly
+//! (e.g., one directory per task). It is used to implement devfs, procfs and
+//! sysfs.
+
+// Symbolic links and generated directories are only used for tasks.
+#![cfg_attr(not(feature = "multitask"), allow(dead_code))]
+
+use alloc::{boxed::Box, collections::BTreeMap, string::String, sync::Arc, sync::Weak, vec::Vec};
+use axfs_vfs::{VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps};
+use axfs_vfs::{VfsError, VfsNodePerm, VfsResult};
+use axsync::Mutex;
+use lazyinit::LazyInit;
+
+type FileGenerator = Box<dyn Fn() -> String + Send + Sync>;
+type FileSetter = Box<dyn Fn(&str) -> VfsResult + Send + Sync>;
+type DirGenerator = Box<dyn Fn(&Arc<PseudoDir>) -> Vec<(String, VfsNodeRef)> + Send + Sync>;
+
+/// A pseudo filesystem that implements [`axfs_vfs::VfsOps`].
+pub struct PseudoFileSystem {
+    name: &'static str,
+    parent: LazyInit<VfsNodeRef>,
+    root: Arc<PseudoDir>,
+}
+
+impl PseudoFileSystem {
+    /// Create a new empty pseudo filesystem with the given type name (e.g.,
+    /// `"proc"`).
+    pub fn new(name: &'static str) -> Self {
+        Self {
+            name,
+            parent: LazyInit::new(),
+            root: PseudoDir::new(None),
+        }
+    }
+
+    /// Returns the root directory node in [`Arc<PseudoDir>`](PseudoDir).
+    pub fn root_dir_node(&self) -> Arc<PseudoDir> {
+        self.root.clone()
+    }
+}
+
+impl VfsOps for PseudoFileSystem {
+    fn mount(&self, _path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        if let Some(parent) = mount_point.parent() {
+            if !self.parent.is_inited() {
+                self.parent.init_once(parent);
+            }
+            self.root.set_parent(self.parent.get());
+        } else {
+            self.root.set_parent(None);
+        }
+        Ok(())
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        self.root.clone()
+    }
+
+    fn name(&self) -> &str {
+        self.name
+    }
+}
+
+/// A file whose contents are generated each time it is read.
+///
+/// It is read-only unless a setter is given, which is called with the written
+/// data on each write.
+pub struct PseudoFile {
+    generator: FileGenerator,
+    setter: Option<FileSetter>,
+}
+
+impl PseudoFile {
+    /// Create a new read-only file whose contents are produced by `generator`.
+    pub fn new(generator: impl Fn() -> String + Send + Sync + 'static) -> Arc<Self> {
+        Arc::new(Self {
+            generator: Box::new(generator),
+            setter: None,
+        })
+    }
+
+    /// Create a new writable file whose contents are produced by `generator`.
+    ///
+    /// The data of each write is passed to `setter` as a string, with leading
+    /// and trailing whitespace removed. The write fails with the error returned
+    /// by `setter`.
+    pub fn new_rw(
+        generator: impl Fn() -> String + Send + Sync + 'static,
+        setter: impl Fn(&str) -> VfsResult + Send + Sync + 'static,
+    ) -> Arc<Self> {
+        Arc::new(Self {
+            generator: Box::new(generator),
+            setter: Some(Box::new(setter)),
+        })
+    }
+}
+
+impl VfsNodeOps for PseudoFile {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        let mode = if self.setter.is_some() { 0o644 } else { 0o444 };
+        // Like Linux, the size is unknown until the file is read.
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::from_bits_truncate(mode),
+            VfsNodeType::File,
+            0,
+            0,
+        ))
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        let content = (self.generator)();
+        let start = content.len().min(offset as usize);
+        let end = content.len().min(start + buf.len());
+        let src = &content.as_bytes()[start..end];
+        buf[..src.len()].copy_from_slice(src);
+        Ok(src.len())
+    }
+
+    fn write_at(&self, _offset: u64, buf: &[u8]) -> VfsResult<usize> {
+        let setter = self.setter.as_ref().ok_or(VfsError::PermissionDenied)?;
+        let value = core::str::from_utf8(buf).map_err(|_| VfsError::InvalidData)?;
+        setter(value.trim())?;
+        Ok(buf.len())
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        // writable files accept truncation, so that they can be opened with
+        // `O_TRUNC` (e.g., by `fs::write`).
+        match self.setter {
+            Some(_) => Ok(()),
+            None => Err(VfsError::PermissionDenied),
+        }
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+/// A symbolic link whose target is generated each time it is resolved.
+pub struct PseudoSymlink {
+    generator: FileGenerator,
+}
+
+impl PseudoSymlink {
+    /// Create a new symbolic link whose target is produced by `generator`.
+    pub fn new(generator: impl Fn() -> String + Send + Sync + 'static) -> Arc<Self> {
+        Arc::new(Self {
+            generator: Box::new(generator),
+        })
+    }
+}
+
+impl VfsNodeOps for PseudoSymlink {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(VfsNodeAttr::new_symlink((self.generator)().len() as _))
+    }
+
+    fn readlink(&self) -> VfsResult<String> {
+        Ok((self.generator)())
+    }
+
+    axfs_vfs::impl_vfs_non_dir_default! {}
+}
+
+/// A directory of the pseudo filesystem.
+///
+/// It holds a fixed set of entries added by [`PseudoDir::add`], and optionally
+/// a generator set by [`PseudoDir::set_generator`].
+pub struct PseudoDir {
+    this: Weak<PseudoDir>,
+    parent: Mutex<Weak<dyn VfsNodeOps>>,
+    children: Mutex<BTreeMap<String, VfsNodeRef>>,
+    generator: LazyInit<DirGenerator>,
+}
+
+impl PseudoDir {
+    /// Create a new directory with the given parent.
+    pub fn new(parent: Option<&VfsNodeRef>) -> Arc<Self> {
+        let parent = parent.map_or(Weak::<Self>::new() as _, Arc::downgrade);
+        Arc::new_cyclic(|this| Self {
+            this: this.clone(),
+            parent: Mutex::new(parent),
+            children: Mutex::new(BTreeMap::new()),
+            generator: LazyInit::new(),
+        })
+    }
+
+    /// Set the generator that produces extra entries each time the directory
+    /// is accessed.
+    ///
+    /// The generator is passed the directory itself, which can be used as the
+    /// parent of the generated subdirectories.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the generator has already been set.
+    pub fn set_generator(
+        &self,
+        generator: impl Fn(&Arc<PseudoDir>) -> Vec<(String, VfsNodeRef)> + Send + Sync + 'static,
+    ) {
+        self.generator.init_once(Box::new(generator));
+    }
+
+    pub(super) fn set_parent(&self, parent: Option<&VfsNodeRef>) {
+        *self.parent.lock() = parent.map_or(Weak::<Self>::new() as _, Arc::downgrade);
+    }
+
+    /// Create a subdirectory at this directory.
+    pub fn mkdir(self: &Arc<Self>, name: &str) -> Arc<Self> {
+        let parent = self.clone() as VfsNodeRef;
+        let node = Self::new(Some(&parent));
+        self.children.lock().insert(name.into(), node.clone());
+        node
+    }
+
+    /// Returns the subdirectory `name` of this directory, creating it if it
+    /// does not exist.
+    pub fn get_or_mkdir(self: &Arc<Self>, name: &str) -> VfsResult<Arc<Self>> {
+        let node = self.children.lock().get(name).cloned();
+        match node {
+            Some(node) => node
+                .as_any()
+                .downcast_ref::<Self>()
+                .and_then(|dir| dir.this.upgrade())
+                .ok_or(VfsError::NotADirectory),
+            None => Ok(self.mkdir(name)),
+        }
+    }
+
+    /// Add a node to this directory.
+    pub fn add(&self, name: &str, node: VfsNodeRef) {
+        self.children.lock().insert(name.into(), node);
+    }
+
+    /// Add a node at `path` relative to this directory, creating the missing
+    /// parent directories.
+    ///
+    /// Returns [`AlreadyExists`](VfsError::AlreadyExists) if `path` exists, or
+    /// [`InvalidInput`](VfsError::InvalidInput) if `path` is empty or contains
+    /// `..`.
+    pub fn add_path(self: &Arc<Self>, path: &str, node: VfsNodeRef) -> VfsResult {
+        let mut comps = path.split('/').filter(|c| !c.is_empty() && *c != ".");
+        let Some(name) = comps.next_back() else {
+            return Err(VfsError::InvalidInput);
+        };
+        if name == ".." || comps.clone().any(|c| c == "..") {
+            return Err(VfsError::InvalidInput);
+        }
+
+        let mut dir = self.clone();
+        for comp in comps {
+            dir = dir.get_or_mkdir(comp)?;
+        }
+        if dir.child(name).is_ok() {
+            return Err(VfsError::AlreadyExists);
+        }
+        dir.add(name, node);
+        Ok(())
+    }
+
+    /// Returns all entries of this directory, including the generated ones.
+    fn entries(&self) -> Vec<(String, VfsNodeRef)> {
+        let mut entries: Vec<_> = self
+            .children
+            .lock()
+            .iter()
+            .map(|(name, node)| (name.clone(), node.clone()))
+            .collect();
+        if let (Some(generator), Some(this)) = (self.generator.get(), self.this.upgrade()) {
+            entries.extend(generator(&this));
+        }
+        entries
+    }
+
+    fn child(&self, name: &str) -> VfsResult<VfsNodeRef> {
+        if let Some(node) = self.children.lock().get(name) {
+            return Ok(node.clone());
+        }
+        self.entries()
+            .into_iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, node)| node)
+            .ok_or(VfsError::NotFound)
+    }
+}
+
+impl VfsNodeOps for PseudoDir {
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        Ok(VfsNodeAttr::new(
+            VfsNodePerm::from_bits_truncate(0o555),
+            VfsNodeType::Dir,
+            0,
+            0,
+        ))
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        self.parent.lock().upgrade()
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let (name, rest) = split_path(path);
+        let node = match name {
+            "" | "." => Ok(self.clone() as VfsNodeRef),
+            ".." => self.parent().ok_or(VfsError::NotFound),
+            _ => self.child(name),
+        }?;
+
+        if let Some(rest) = rest {
+            node.lookup(rest)
+        } else {
+            Ok(node)
+        }
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        let entries = self.entries();
+        let mut entries = entries.iter().skip(start_idx.max(2) - 2);
+        for (i, ent) in dirents.iter_mut().enumerate() {
+            match i + start_idx {
+                0 => *ent = VfsDirEntry::new(".", VfsNodeType::Dir),
+                1 => *ent = VfsDirEntry::new("..", VfsNodeType::Dir),
+                _ => {
+                    if let Some((name, node)) = entries.next() {
+                        *ent = VfsDirEntry::new(name, node.get_attr()?.file_type());
+                    } else {
+                        return Ok(i);
+                    }
+                }
+            }
+        }
+        Ok(dirents.len())
+    }
+
+    fn create(&self, path: &str, ty: VfsNodeType) -> VfsResult {
+        let (name, rest) = split_path(path);
+        if let Some(rest) = rest {
+            match name {
+                "" | "." => self.create(rest, ty),
+                ".." => self.parent().ok_or(VfsError::NotFound)?.create(rest, ty),
+                _ => self.child(name)?.create(rest, ty),
+            }
+        } else if name.is_empty() || name == "." || name == ".." {
+            Ok(()) // already exists
+        } else {
+            Err(VfsError::PermissionDenied) // do not support to create nodes dynamically
+        }
+    }
+
+    fn remove(&self, path: &str) -> VfsResult {
+        let (name, rest) = split_path(path);
+        if let Some(rest) = rest {
+            match name {
+                "" | "." => self.remove(rest),
+                ".." => self.parent().ok_or(VfsError::NotFound)?.remove(rest),
+                _ => self.child(name)?.remove(rest),
+            }
+        } else {
+            Err(VfsError::PermissionDenied) // do not support to remove nodes dynamically
+        }
+    }
+
+    axfs_vfs::impl_vfs_dir_default! {}
+}
+
+fn split_path(path: &str) -> (&str, Option<&str>) {
+    let trimmed_path = path.trim_start_matches('/');
+    trimmed_path.find('/').map_or((trimmed_path, None), |n| {
+        (&trimmed_path[..n], Some(&trimmed_path[n + 1..]))
+    })
+}
@@ -0,0 +1,152 @@
+//! A wrapper which makes a filesystem read-only.
+
+use alloc::{string::String, sync::Arc, sync::Weak};
+use axerrno::ax_err;
+use axfs_vfs::{FileSystemInfo, VfsDirEntry, VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType};
+use axfs_vfs::{VfsOps, VfsResult, VfsSetAttr, VfsWatcher};
+
+/// A filesystem whose nodes reject all modifications with
+/// [`ReadOnlyFilesystem`](axerrno::AxError::ReadOnlyFilesystem).
+pub struct ReadOnlyFs {
+    inner: Arc<dyn VfsOps>,
+    root: VfsNodeRef,
+}
+
+/// A node of [`ReadOnlyFs`].
+struct ReadOnlyNode {
+    inner: VfsNodeRef,
+    /// The root directory of the wrapped filesystem, whose parent is outside
+    /// the filesystem and therefore not wrapped.
+    fs_root: VfsNodeRef,
+}
+
+impl ReadOnlyFs {
+    pub fn new(inner: Arc<dyn VfsOps>) -> Self {
+        let fs_root = inner.root_dir();
+        let root = ReadOnlyNode::wrap(fs_root.clone(), &fs_root);
+        Self { inner, root }
+    }
+}
+
+impl VfsOps for ReadOnlyFs {
+    fn mount(&self, path: &str, mount_point: VfsNodeRef) -> VfsResult {
+        self.inner.mount(path, mount_point)
+    }
+
+    fn umount(&self) -> VfsResult {
+        self.inner.umount()
+    }
+
+    fn format(&self) -> VfsResult {
+        ax_err!(ReadOnlyFilesystem)
+    }
+
+    fn statfs(&self) -> VfsResult<FileSystemInfo> {
+        self.inner.statfs()
+    }
+
+    fn sync(&self) -> VfsResult {
+        self.inner.sync()
+    }
+
+    fn label(&self) -> Option<String> {
+        self.inner.label()
+    }
+
+    fn root_dir(&self) -> VfsNodeRef {
+        self.root.clone()
+    }
+
+    fn name(&self) -> &str {
+        self.inner.name()
+    }
+}
+
+impl ReadOnlyNode {
+    fn wrap(inner: VfsNodeRef, fs_root: &VfsNodeRef) -> VfsNodeRef {
+        Arc::new(Self {
+            inner,
+            fs_root: fs_root.clone(),
+        })
+    }
+}
+
+impl VfsNodeOps for ReadOnlyNode {
+    fn open(&self) -> VfsResult {
+        self.inner.open()
+    }
+
+    fn release(&self) -> VfsResult {
+        self.inner.release()
+    }
+
+    fn get_attr(&self) -> VfsResult<VfsNodeAttr> {
+        self.inner.get_attr()
+    }
+
+    fn set_attr(&self, _attr: &VfsSetAttr) -> VfsResult {
+        ax_err!(ReadOnlyFilesystem)
+    }
+
+    fn read_at(&self, offset: u64, buf: &mut [u8]) -> VfsResult<usize> {
+        self.inner.read_at(offset, buf)
+    }
+
+    fn write_at(&self, _offset: u64, _buf: &[u8]) -> VfsResult<usize> {
+        ax_err!(ReadOnlyFilesystem)
+    }
+
+    fn fsync(&self) -> VfsResult {
+        Ok(())
+    }
+
+    fn truncate(&self, _size: u64) -> VfsResult {
+        ax_err!(ReadOnlyFilesystem)
+    }
+
+    fn readlink(&self) -> VfsResult<String> {
+        self.inner.readlink()
+    }
+
+    fn parent(&self) -> Option<VfsNodeRef> {
+        let parent = self.inner.parent()?;
+        if Arc::ptr_eq(&self.inner, &self.fs_root) {
+            Some(parent)
+        } else {
+            Some(Self::wrap(parent, &self.fs_root))
+        }
+    }
+
+    fn lookup(self: Arc<Self>, path: &str) -> VfsResult<VfsNodeRef> {
+        let node = self.inner.clone().lookup(path)?;
+        Ok(Self::wrap(node, &self.fs_root))
+    }
+
+    fn create(&self, _path: &str, _ty: VfsNodeType) -> VfsResult {
+        ax_err!(ReadOnlyFilesystem)
+    }
+
+    fn remove(&self, _path: &str) -> VfsResult {
+        ax_err!(ReadOnlyFilesystem)
+    }
+
+    fn read_dir(&self, start_idx: usize, dirents: &mut [VfsDirEntry]) -> VfsResult<usize> {
+        self.inner.read_dir(start_idx, dirents)
+    }
+
+    fn rename(&self, _src_path: &str, _dst_path: &str) -> VfsResult {
+        ax_err!(ReadOnlyFilesystem)
+    }
+
+    fn symlink(&self, _path: &str, _target: &str) -> VfsResult {
+        ax_err!(ReadOnlyFilesystem)
+    }
+
+    fn link(&self, _path: &str, _node: &VfsNodeRef) -> VfsResult {
+        ax_err!(ReadOnlyFilesystem)
+    }
+
+    fn watch(&self, watcher: Weak<dyn VfsWatcher>) -> VfsResult {
+        self.inner.watch(watcher)
+    }
+}
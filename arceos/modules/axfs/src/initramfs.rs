@@ -0,0 +1,173 @@
+//! Initial RAM filesystem unpacked from a [cpio] archive.
+//!
+//! The archive is in the "newc" format (created by `cpio -o -H newc`), and is
+//! embedded in the kernel image from the file which the `AX_INITRAMFS`
+//! environment variable points to at build time. The archive can also be
+//! replaced by [`set_archive`], e.g., with the one passed by the bootloader.
+//!
+//! If the archive is not empty and no device is mounted on `/` explicitly,
+//! it is unpacked into a ramfs which becomes the root filesystem, so that no
+//! block device is needed. The archive itself is never modified, while the
+//! unpacked files can be changed in memory.
+//!
+//! [cpio]: https://www.kernel.org/doc/html/latest/driver-api/early-userspace/buffer-format.html
+
+use alloc::{collections::BTreeMap, format, sync::Arc};
+use axerrno::{ax_err, AxError, AxResult};
+use axfs_vfs::{VfsNodeRef, VfsNodeType, VfsOps};
+use axsync::Mutex;
+
+use crate::fs::ramfs::RamFileSystem;
+
+static EMBEDDED_ARCHIVE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/initramfs.cpio"));
+static ARCHIVE: Mutex<&'static [u8]> = Mutex::new(EMBEDDED_ARCHIVE);
+
+const HEADER_LEN: usize = 110;
+const TRAILER: &str = "TRAILER!!!";
+
+const S_IFMT: u32 = 0o170000;
+const S_IFDIR: u32 = 0o040000;
+const S_IFREG: u32 = 0o100000;
+const S_IFLNK: u32 = 0o120000;
+
+/// Returns the archive to be unpacked as the root filesystem, which is empty
+/// if there is none.
+pub fn archive() -> &'static [u8] {
+    *ARCHIVE.lock()
+}
+
+/// Replaces the archive embedded in the kernel image with `archive`.
+///
+/// It only takes effect if called before the filesystems are initialized.
+pub fn set_archive(archive: &'static [u8]) {
+    *ARCHIVE.lock() = archive;
+}
+
+/// An entry in the archive.
+struct Entry<'a> {
+    /// Device and inode numbers, which identify hard links to the same file.
+    id: (u32, u32, u32),
+    mode: u32,
+    nlink: u32,
+    name: &'a str,
+    data: &'a [u8],
+}
+
+const fn align4(n: usize) -> usize {
+    (n + 3) & !3
+}
+
+/// Parses the entry at `pos`, returns it and the position of the next entry.
+fn parse_entry(archive: &[u8], pos: usize) -> AxResult<(Entry<'_>, usize)> {
+    let header = archive
+        .get(pos..pos + HEADER_LEN)
+        .ok_or(AxError::InvalidData)?;
+    if &header[..6] != b"070701" && &header[..6] != b"070702" {
+        return ax_err!(InvalidData, "invalid cpio magic");
+    }
+    let field = |idx: usize| {
+        core::str::from_utf8(&header[6 + idx * 8..14 + idx * 8])
+            .ok()
+            .and_then(|s| u32::from_str_radix(s, 16).ok())
+            .ok_or(AxError::InvalidData)
+    };
+
+    let name_start = pos + HEADER_LEN;
+    let name_end = name_start + field(11)? as usize;
+    let data_start = align4(name_end);
+    let data_end = data_start + field(6)? as usize;
+    let name = match archive.get(name_start..name_end) {
+        Some([name @ .., 0]) => core::str::from_utf8(name).map_err(|_| AxError::InvalidData)?,
+        _ => return ax_err!(InvalidData, "invalid cpio entry name"),
+    };
+    let data = archive
+        .get(data_start..data_end)
+        .ok_or(AxError::InvalidData)?;
+    let entry = Entry {
+        id: (field(7)?, field(8)?, field(0)?),
+        mode: field(1)?,
+        nlink: field(4)?,
+        name,
+        data,
+    };
+    Ok((entry, align4(data_end)))
+}
+
+/// Creates the missing parent directories of `path`.
+fn create_parents(root: &VfsNodeRef, path: &str) -> AxResult {
+    let Some((parent, _)) = path.rsplit_once('/') else {
+        return Ok(());
+    };
+    match root.clone().lookup(parent) {
+        Ok(_) => Ok(()),
+        Err(AxError::NotFound) => {
+            create_parents(root, parent)?;
+            root.create(parent, VfsNodeType::Dir)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Unpacks the cpio `archive` in the "newc" format into the directory `root`.
+///
+/// Directories, regular files, symbolic links and hard links are created,
+/// while device nodes, FIFOs and sockets are skipped. Ownership, permissions
+/// and timestamps in the archive are ignored.
+pub fn unpack(archive: &[u8], root: &VfsNodeRef) -> AxResult {
+    let mut linked_files: BTreeMap<_, VfsNodeRef> = BTreeMap::new();
+    let mut pos = 0;
+    while pos < archive.len() {
+        let (entry, next) = parse_entry(archive, pos)?;
+        pos = next;
+        if entry.name == TRAILER {
+            break;
+        }
+        // keep all entries inside `root`, even with `..` in their names
+        let path = axfs_vfs::path::canonicalize(&format!("/{}", entry.name));
+        let path = path.trim_start_matches('/');
+        if path.is_empty() {
+            continue;
+        }
+        debug!("initramfs: unpack {:?} ({:o})", path, entry.mode);
+
+        create_parents(root, path)?;
+        match entry.mode & S_IFMT {
+            S_IFDIR => match root.create(path, VfsNodeType::Dir) {
+                Err(AxError::AlreadyExists) => {}
+                res => res?,
+            },
+            S_IFREG => {
+                // the data of hard linked files is in the last entry of them
+                let file = match linked_files.get(&entry.id) {
+                    Some(file) => {
+                        root.link(path, file)?;
+                        file.clone()
+                    }
+                    None => {
+                        root.create(path, VfsNodeType::File)?;
+                        root.clone().lookup(path)?
+                    }
+                };
+                if !entry.data.is_empty() {
+                    file.write_at(0, entry.data)?;
+                }
+                if entry.nlink > 1 {
+                    linked_files.insert(entry.id, file);
+                }
+            }
+            S_IFLNK => {
+                let target = core::str::from_utf8(entry.data).map_err(|_| AxError::InvalidData)?;
+                root.symlink(path, target)?;
+            }
+            _ => warn!("initramfs: skip special file {:?}", path),
+        }
+    }
+    Ok(())
+}
+
+/// Creates a ramfs with the contents of `archive`.
+pub(crate) fn new_initramfs(archive: &[u8]) -> AxResult<Arc<RamFileSystem>> {
+    let fs = crate::mounts::ramfs();
+    unpack(archive, &fs.root_dir())?;
+    Ok(fs)
+}
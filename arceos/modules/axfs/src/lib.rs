@@ -4,20 +4,49 @@
 //!
 //! # Cargo Features
 //!
-//! - `fatfs`: Use [FAT] as the main filesystem and mount it on `/`. This feature
+//! - `fatfs`: Use [FAT] as the main filesystem and mount it on `/`. File times
+//!    are only correct if the `rtc` feature of `axhal` is enabled. This feature
 //!    is **enabled** by default.
-//! - `devfs`: Mount [`axfs_devfs::DeviceFileSystem`] on `/dev`. This feature is
-//!    **enabled** by default.
-//! - `ramfs`: Mount [`axfs_ramfs::RamFileSystem`] on `/tmp`. This feature is
-//!    **enabled** by default.
-//! - `myfs`: Allow users to define their custom filesystems to override the
-//!    default. In this case, [`MyFileSystemIf`] is required to be implemented
-//!    to create and initialize other filesystems. This feature is **disabled** by
-//!    by default, but it will override other filesystem selection features if
-//!    both are enabled.
+//! - `ext4fs`: Use [ext2/ext3/ext4][ext4] as the main filesystem if the disk
+//!    contains one (otherwise FAT is used if `fatfs` is enabled). Journals are
+//!    not replayed, so filesystems that need recovery are mounted read-only.
+//!    This feature is **disabled** by default.
+//! - `devfs`: Mount a devfs on `/dev`, which provides the console, random and
+//!    block devices, and where drivers can register their own devices by
+//!    [`devfs::register_device`]. This feature is **enabled** by default.
+//! - `ramfs`: Mount [`axfs_ramfs::RamFileSystem`] on `/tmp`, whose file
+//!    contents are stored in pages allocated from [`axalloc`] on demand, and
+//!    limited to half of the physical memory. This feature is **enabled** by
+//!    default.
+//! - `procfs`: Mount a procfs on `/proc`, whose files are generated from the
+//!    kernel state on read. This feature is **enabled** by default.
+//! - `sysfs`: Mount a sysfs on `/sys`, where other modules can register
+//!    attributes by [`sysfs::register_attr`]. This feature is **enabled** by
+//!    default.
+//! - `multitask`: Provide `/proc/self` and `/proc/<tid>` for each task in
+//!    procfs, and write back the [block cache](cache) periodically.
+//! - `irq`: Provide IRQ counts in `/proc/interrupts`.
+//! - `initramfs`: Use the [initramfs] embedded in the kernel image as the root
+//!    filesystem if no device is mounted on `/`. This feature is **disabled**
+//!    by default.
+//! - `myfs`: Allow users to define their custom filesystem types and register
+//!    them by [`register_filesystem`] under their names. They are mounted
+//!    alongside the built-in filesystems, by the mount table (see
+//!    [`init_filesystems_with`]) or [`api::mount_device`]. A filesystem
+//!    defined by [`MyFileSystemIf`] is registered as the `myfs` type by
+//!    [`MyFileSystem`]. This feature is **disabled** by default.
+//! - `ninepfs`: Mount directories shared by the host over [9P2000.L][9p]
+//!    devices, which are registered by [`register_ninep_device`] and mounted by
+//!    their mount tags (see [`init_filesystems_with`]). This feature is
+//!    **disabled** by default.
 //!
 //! [FAT]: https://en.wikipedia.org/wiki/File_Allocation_Table
+//! [ext4]: https://en.wikipedia.org/wiki/Ext4
+//! [9p]: https://github.com/chaos/diod/blob/master/protocol.md
+//! [`register_filesystem`]: fops::register_filesystem
 //! [`MyFileSystemIf`]: fops::MyFileSystemIf
+//! [`MyFileSystem`]: fops::MyFileSystem
+//! [`register_ninep_device`]: fops::register_ninep_device
 
 #![cfg_attr(all(not(test), not(doc)), no_std)]
 #![feature(doc_auto_cfg)]
@@ -28,19 +57,162 @@ extern crate alloc;
 
 mod dev;
 mod fs;
+mod lock;
 mod mounts;
+mod notify;
+mod partition;
 mod root;
 
 pub mod api;
+pub mod cache;
+#[cfg(feature = "devfs")]
+pub mod devfs;
 pub mod fops;
+#[cfg(feature = "initramfs")]
+pub mod initramfs;
+#[cfg(feature = "sysfs")]
+pub mod sysfs;
 
+use alloc::{sync::Arc, vec::Vec};
 use axdriver::{prelude::*, AxDeviceContainer};
+use axerrno::AxResult;
+use axfs_vfs::VfsOps;
 
 /// Initializes filesystems by block devices.
-pub fn init_filesystems(mut blk_devs: AxDeviceContainer<AxBlockDevice>) {
+///
+/// The devices and partitions are mounted according to
+/// `axconfig::FS_MOUNTS`, see [`init_filesystems_with`].
+pub fn init_filesystems(blk_devs: AxDeviceContainer<AxBlockDevice>) {
+    init_filesystems_with(blk_devs, axconfig::FS_MOUNTS);
+}
+
+/// Initializes filesystems by block devices, which are mounted according to
+/// `mounts`.
+///
+/// `mounts` is a comma-separated list of `device:path[:type[:options]]`, e.g.,
+/// `"vda0p1:/,vda1:/mnt/data:myfs,vda2:/mnt/rom::ro"`, where `vdaN` is the
+/// `N`-th block device and `vdaNpM` is its `M`-th partition in the MBR or GPT
+/// partition table. `type` is the name of a built-in (`vfat`, `ext4`, etc.)
+/// or [custom](fops::register_filesystem) filesystem type, and the
+/// filesystem type is detected if it is omitted or empty. The filesystem is
+/// mounted read-only if the options are `ro`, or as the lower layer of an
+/// [overlay](fops::OverlayFs) whose changes are kept in memory if they are
+/// `overlay` (requires the `ramfs` feature). If no device is mounted on `/`, the
+/// [initramfs] is used if it is enabled and not empty. Otherwise, the first
+/// partition of the first device, or the whole device if it is not
+/// partitioned, is used.
+///
+/// With the `ninepfs` feature, `device` is the mount tag of a
+/// [registered](fops::register_ninep_device) 9P device if `type` is `9p`,
+/// e.g., `"host:/mnt/host:9p"`. The 9P devices not in `mounts` are mounted on
+/// `/<tag>`.
+///
+/// Note that `axdriver` probes at most one block device unless its `dyn`
+/// feature is enabled.
+pub fn init_filesystems_with(blk_devs: AxDeviceContainer<AxBlockDevice>, mounts: &str) {
     info!("Initialize filesystems...");
 
-    let dev = blk_devs.take_one().expect("No block device found!");
-    info!("  use block device 0: {:?}", dev.device_name());
-    self::root::init_rootfs(self::dev::Disk::new(dev));
+    let disks = self::dev::probe_disks(blk_devs);
+
+    let mut table = Vec::new();
+    for entry in mounts.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+        let mut fields = entry.split(':').map(str::trim);
+        let fields = [(); 5].map(|_| fields.next());
+        match fields {
+            [Some(dev), Some(path), fs_type, opts @ (None | Some("ro" | "rw" | "overlay")), None]
+                if path.starts_with('/')
+                    && (opts != Some("overlay") || cfg!(feature = "ramfs")) =>
+            {
+                let fs_type = fs_type.filter(|t| !t.is_empty());
+                table.push((dev, path, fs_type, opts))
+            }
+            _ => warn!("  invalid mount entry: {:?}", entry),
+        }
+    }
+
+    let (root_fs, root_ro) = match table.iter().find(|(_, path, ..)| *path == "/") {
+        Some(&(dev, _, fs_type, opts)) => {
+            info!("  use {} as the root filesystem", dev);
+            open_mount_entry(dev, fs_type, opts)
+                .unwrap_or_else(|e| panic!("failed to mount {} on /: {:?}", dev, e))
+        }
+        #[cfg(feature = "initramfs")]
+        None if !self::initramfs::archive().is_empty() => {
+            info!("  use the initramfs as the root filesystem");
+            let fs: Arc<dyn axfs_vfs::VfsOps> =
+                self::initramfs::new_initramfs(self::initramfs::archive())
+                    .expect("failed to unpack the initramfs");
+            (fs, false)
+        }
+        None => {
+            assert!(!disks.is_empty(), "No block device found!");
+            let dev = disks
+                .iter()
+                .map(|(name, _)| name.as_str())
+                .find(|name| name.starts_with("vda0p"))
+                .unwrap_or("vda0");
+            info!("  use {} as the root filesystem", dev);
+            open_mount_entry(dev, None, None)
+                .unwrap_or_else(|e| panic!("failed to mount {} on /: {:?}", dev, e))
+        }
+    };
+    self::root::init_rootfs(root_fs, root_ro);
+
+    #[cfg(feature = "devfs")]
+    self::devfs::register_block_devices(disks);
+
+    for &(dev, path, fs_type, opts) in table.iter().filter(|(_, path, ..)| *path != "/") {
+        let res = open_mount_entry(dev, fs_type, opts)
+            .and_then(|(fs, read_only)| self::root::mount(path, fs, read_only));
+        match res {
+            Ok(()) => info!("  mount {} on {}", dev, path),
+            Err(e) => warn!("  failed to mount {} on {}: {:?}", dev, path, e),
+        }
+    }
+
+    #[cfg(feature = "ninepfs")]
+    for tag in self::fs::ninepfs::mount_tags() {
+        let in_table = table
+            .iter()
+            .any(|&(dev, _, fs_type, _)| dev == tag && fs_type == Some("9p"));
+        if in_table || tag.is_empty() || tag.contains('/') {
+            continue;
+        }
+        let path = alloc::format!("/{}", tag);
+        let res =
+            fs::new_device_fs(&tag, Some("9p")).and_then(|fs| self::root::mount(&path, fs, false));
+        match res {
+            Ok(()) => info!("  mount 9p {} on {}", tag, path),
+            Err(e) => warn!("  failed to mount 9p {} on {}: {:?}", tag, path, e),
+        }
+    }
+
+    #[cfg(feature = "multitask")]
+    self::cache::spawn_writeback_task();
+}
+
+/// Opens the filesystem of type `fs_type` on the device `dev` for a mount
+/// entry with the options `opts`. Returns the filesystem to mount, and whether
+/// it is mounted read-only.
+fn open_mount_entry(
+    dev: &str,
+    fs_type: Option<&str>,
+    opts: Option<&str>,
+) -> AxResult<(Arc<dyn VfsOps>, bool)> {
+    let fs = fs::new_device_fs(dev, fs_type)?;
+    match opts {
+        #[cfg(feature = "ramfs")]
+        Some("overlay") => Ok((Arc::new(fops::OverlayFs::with_ramfs(fs)), false)),
+        _ => Ok((fs, opts == Some("ro"))),
+    }
+}
+
+/// Writes all filesystems back to their devices and marks them cleanly
+/// unmounted, for an orderly shutdown.
+///
+/// It is called by `axruntime` when the system terminates. The filesystems
+/// must not be modified afterwards.
+pub fn shutdown_filesystems() {
+    info!("Shutdown filesystems...");
+    self::root::umount_all();
 }
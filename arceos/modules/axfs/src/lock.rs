@@ -0,0 +1,286 @@
+//! Advisory file locks.
+//!
+//! Like on Linux, there are two independent kinds of locks: whole-file locks
+//! by [`FileLocks::flock`], and byte-range record locks by
+//! [`FileLocks::set_lock`]. Both are owned by the opened
+//! [`File`](crate::fops::File) (like the open file description locks of
+//! Linux), so different opens of the same file conflict with each other even
+//! in the same task, and they are released when the file is closed.
+//!
+//! Locks are tracked per inode, which is identified by the filesystem and the
+//! inode number. On filesystems without inode numbers (e.g., FAT), it is
+//! identified by the node object instead, so the file may be opened as
+//! different inodes.
+
+use alloc::{collections::BTreeMap, sync::Arc, vec::Vec};
+use core::ops::Range;
+use core::sync::atomic::{AtomicU64, Ordering};
+
+use axerrno::{ax_err, AxResult};
+use axfs_vfs::{VfsNodeAttr, VfsNodeRef, VfsOps};
+use axsync::Mutex;
+
+/// The type of an advisory lock.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum LockKind {
+    /// A shared (read) lock, which can be held by multiple owners at a time.
+    Shared,
+    /// An exclusive (write) lock, which can only be held by one owner.
+    Exclusive,
+}
+
+/// A byte-range lock held on a file.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecordLock {
+    /// The type of the lock.
+    pub kind: LockKind,
+    /// The locked bytes. It ends at `u64::MAX` if the lock extends to the end
+    /// of the file, however the file grows.
+    pub range: Range<u64>,
+    owner: u64,
+}
+
+/// The identity of an inode, which is used as the key of the lock table.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub(crate) struct InodeKey {
+    /// Address of the filesystem, or 0 if `ino` is the address of the node.
+    fs: usize,
+    ino: u64,
+}
+
+/// Locks held on an inode.
+#[derive(Default)]
+struct InodeLocks {
+    /// Owners of the whole-file locks, and the types of their locks.
+    flocks: Vec<(u64, LockKind)>,
+    records: Vec<RecordLock>,
+}
+
+static LOCKS: Mutex<BTreeMap<InodeKey, InodeLocks>> = Mutex::new(BTreeMap::new());
+
+/// Incremented whenever locks are released, so that waiters can try again.
+static GENERATION: AtomicU64 = AtomicU64::new(0);
+
+#[cfg(feature = "multitask")]
+static WAIT_QUEUE: axtask::WaitQueue = axtask::WaitQueue::new();
+
+impl InodeKey {
+    /// Returns the identity of `node` on `fs`, whose attributes are `attr`.
+    pub(crate) fn new(fs: &Arc<dyn VfsOps>, node: &VfsNodeRef, attr: &VfsNodeAttr) -> Self {
+        if attr.ino() != 0 {
+            Self {
+                fs: Arc::as_ptr(fs) as *const () as usize,
+                ino: attr.ino(),
+            }
+        } else {
+            Self {
+                fs: 0,
+                ino: Arc::as_ptr(node) as *const () as usize as u64,
+            }
+        }
+    }
+}
+
+impl LockKind {
+    fn conflicts_with(self, other: Self) -> bool {
+        self == Self::Exclusive || other == Self::Exclusive
+    }
+}
+
+impl RecordLock {
+    fn conflicts_with(&self, kind: LockKind, range: &Range<u64>, owner: u64) -> bool {
+        self.owner != owner
+            && self.kind.conflicts_with(kind)
+            && self.range.start < range.end
+            && range.start < self.range.end
+    }
+}
+
+impl InodeLocks {
+    fn is_empty(&self) -> bool {
+        self.flocks.is_empty() && self.records.is_empty()
+    }
+
+    /// Replaces the record locks of `owner` in `range` with a lock of `kind`,
+    /// or removes them if `kind` is `None`. Locks partially in the range are
+    /// split.
+    fn set_record(&mut self, owner: u64, kind: Option<LockKind>, range: Range<u64>) {
+        let mut records = Vec::with_capacity(self.records.len() + 2);
+        for lock in self.records.drain(..) {
+            if lock.owner != owner || lock.range.end <= range.start || range.end <= lock.range.start
+            {
+                records.push(lock);
+                continue;
+            }
+            if lock.range.start < range.start {
+                records.push(RecordLock {
+                    range: lock.range.start..range.start,
+                    ..lock.clone()
+                });
+            }
+            if range.end < lock.range.end {
+                records.push(RecordLock {
+                    range: range.end..lock.range.end,
+                    ..lock
+                });
+            }
+        }
+        if let Some(kind) = kind {
+            records.push(RecordLock { kind, range, owner });
+        }
+        self.records = records;
+    }
+}
+
+/// Calls `f` with the locks held on the inode `key`.
+fn with_locks<R>(key: InodeKey, f: impl FnOnce(&mut InodeLocks) -> R) -> R {
+    let mut table = LOCKS.lock();
+    let locks = table.entry(key).or_default();
+    let ret = f(locks);
+    if locks.is_empty() {
+        table.remove(&key);
+    }
+    ret
+}
+
+/// Wakes up the tasks waiting for locks to be released.
+fn notify_released() {
+    GENERATION.fetch_add(1, Ordering::Release);
+    #[cfg(feature = "multitask")]
+    WAIT_QUEUE.notify_all(true);
+}
+
+/// Waits until some locks are released after the generation `gen`.
+#[cfg(feature = "multitask")]
+fn wait_released(gen: u64) -> AxResult {
+    WAIT_QUEUE.wait_until(|| GENERATION.load(Ordering::Acquire) != gen);
+    Ok(())
+}
+
+#[cfg(not(feature = "multitask"))]
+fn wait_released(_gen: u64) -> AxResult {
+    ax_err!(WouldBlock, "no other task can release the lock")
+}
+
+/// Acquires a lock on the inode `key` by `try_lock`, which returns whether
+/// the lock is acquired. If it is not, and `wait` is true, it is tried again
+/// whenever locks are released.
+fn acquire(key: InodeKey, wait: bool, try_lock: impl Fn(&mut InodeLocks) -> bool) -> AxResult {
+    loop {
+        let gen = GENERATION.load(Ordering::Acquire);
+        if with_locks(key, &try_lock) {
+            return Ok(());
+        }
+        if !wait {
+            return ax_err!(WouldBlock);
+        }
+        wait_released(gen)?;
+    }
+}
+
+/// A handle to the advisory locks of an opened [`File`](crate::fops::File),
+/// obtained by [`File::locks`](crate::fops::File::locks).
+///
+/// It can be used without holding the file, e.g., to wait for a lock while
+/// other tasks keep using the file. However, it must not be used after the
+/// file is closed, otherwise the locks acquired are never released.
+#[derive(Clone, Copy, Debug)]
+pub struct FileLocks {
+    key: InodeKey,
+    owner: u64,
+}
+
+impl FileLocks {
+    /// Creates a new lock owner for the opened `node` on `fs`.
+    pub(crate) fn new(fs: &Arc<dyn VfsOps>, node: &VfsNodeRef, attr: &VfsNodeAttr) -> Self {
+        static NEXT_OWNER: AtomicU64 = AtomicU64::new(1);
+        Self {
+            key: InodeKey::new(fs, node, attr),
+            owner: NEXT_OWNER.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    /// Places a whole-file lock of `kind`, or removes it if `kind` is `None`.
+    ///
+    /// A lock already held is converted to the new type. Like on Linux, the
+    /// conversion is not atomic: the old lock is released before waiting for
+    /// the new one. If the lock is held by others, it waits for them if
+    /// `wait` is true, or returns [`WouldBlock`](axerrno::AxError::WouldBlock)
+    /// otherwise.
+    pub fn flock(&self, kind: Option<LockKind>, wait: bool) -> AxResult {
+        let owner = self.owner;
+        let released = with_locks(self.key, |locks| {
+            let len = locks.flocks.len();
+            locks.flocks.retain(|&(o, _)| o != owner);
+            locks.flocks.len() != len
+        });
+        if released {
+            notify_released();
+        }
+        let Some(kind) = kind else {
+            return Ok(());
+        };
+        acquire(self.key, wait, |locks| {
+            if locks.flocks.iter().any(|&(_, k)| k.conflicts_with(kind)) {
+                return false;
+            }
+            locks.flocks.push((owner, kind));
+            true
+        })
+    }
+
+    /// Places a record lock of `kind` on `range`, or removes the locks in it
+    /// if `kind` is `None`.
+    ///
+    /// Locks already held in the range are replaced, and split if they are
+    /// partially in it. If a conflicting lock is held by others, it waits for
+    /// them if `wait` is true, or returns
+    /// [`WouldBlock`](axerrno::AxError::WouldBlock) otherwise. Deadlocks are
+    /// not detected.
+    pub fn set_lock(&self, kind: Option<LockKind>, range: Range<u64>, wait: bool) -> AxResult {
+        if range.is_empty() {
+            return ax_err!(InvalidInput);
+        }
+        let owner = self.owner;
+        let Some(kind) = kind else {
+            with_locks(self.key, |locks| locks.set_record(owner, None, range));
+            notify_released();
+            return Ok(());
+        };
+        acquire(self.key, wait, |locks| {
+            if (locks.records.iter()).any(|l| l.conflicts_with(kind, &range, owner)) {
+                return false;
+            }
+            locks.set_record(owner, Some(kind), range.clone());
+            true
+        })?;
+        // the lock may be downgraded or shrunk
+        notify_released();
+        Ok(())
+    }
+
+    /// Returns the first lock held by others which prevents placing a record
+    /// lock of `kind` on `range`, or `None` if it can be placed.
+    pub fn get_lock(&self, kind: LockKind, range: Range<u64>) -> Option<RecordLock> {
+        with_locks(self.key, |locks| {
+            let mut records = locks.records.iter();
+            records
+                .find(|l| l.conflicts_with(kind, &range, self.owner))
+                .cloned()
+        })
+    }
+
+    /// Releases all locks held through the file.
+    pub(crate) fn release_all(&self) {
+        let owner = self.owner;
+        let released = with_locks(self.key, |locks| {
+            let len = locks.flocks.len() + locks.records.len();
+            locks.flocks.retain(|&(o, _)| o != owner);
+            locks.records.retain(|l| l.owner != owner);
+            locks.flocks.len() + locks.records.len() != len
+        });
+        if released {
+            notify_released();
+        }
+    }
+}
@@ -1,80 +1,283 @@
 use alloc::sync::Arc;
-use axfs_vfs::{VfsNodeType, VfsOps, VfsResult};
 
 use crate::fs;
 
 #[cfg(feature = "devfs")]
-pub(crate) fn devfs() -> Arc<fs::devfs::DeviceFileSystem> {
-    let null = fs::devfs::NullDev;
-    let zero = fs::devfs::ZeroDev;
-    let bar = fs::devfs::ZeroDev;
-    let devfs = fs::devfs::DeviceFileSystem::new();
-    let foo_dir = devfs.mkdir("foo");
-    devfs.add("null", Arc::new(null));
-    devfs.add("zero", Arc::new(zero));
-    foo_dir.add("bar", Arc::new(bar));
+pub(crate) fn devfs() -> Arc<fs::pseudofs::PseudoFileSystem> {
+    use crate::devfs::{ConsoleDev, RandomDev, RtcDev};
+
+    let devfs = fs::pseudofs::PseudoFileSystem::new("devtmpfs");
+    let dev_root = devfs.root_dir_node();
+    dev_root.add("null", Arc::new(fs::devfs::NullDev));
+    dev_root.add("zero", Arc::new(fs::devfs::ZeroDev));
+    dev_root.add("console", Arc::new(ConsoleDev));
+    dev_root.add("tty", Arc::new(ConsoleDev));
+    dev_root.add("random", Arc::new(RandomDev));
+    dev_root.add("urandom", Arc::new(RandomDev));
+    dev_root.add("rtc", Arc::new(RtcDev));
     Arc::new(devfs)
 }
 
+/// Allocates the pages of ramfs files from the page allocator of [`axalloc`].
+#[cfg(all(feature = "ramfs", target_os = "none", not(test)))]
+struct RamfsPageAlloc;
+
+#[cfg(all(feature = "ramfs", target_os = "none", not(test)))]
+impl fs::ramfs::PageAlloc for RamfsPageAlloc {
+    fn alloc_page(&self) -> Option<core::ptr::NonNull<u8>> {
+        use fs::ramfs::PAGE_SIZE;
+        let vaddr = axalloc::global_allocator().alloc_pages(1, PAGE_SIZE).ok()?;
+        unsafe { core::ptr::write_bytes(vaddr as *mut u8, 0, PAGE_SIZE) };
+        core::ptr::NonNull::new(vaddr as *mut u8)
+    }
+
+    unsafe fn dealloc_page(&self, page: core::ptr::NonNull<u8>) {
+        axalloc::global_allocator().dealloc_pages(page.as_ptr() as usize, 1)
+    }
+}
+
 #[cfg(feature = "ramfs")]
 pub(crate) fn ramfs() -> Arc<fs::ramfs::RamFileSystem> {
-    Arc::new(fs::ramfs::RamFileSystem::new())
+    let opts = fs::ramfs::RamFsOptions {
+        clock: axhal::time::wall_time,
+        // at most half of the physical memory as in Linux, 0 if unknown
+        max_bytes: (axconfig::PHYS_MEMORY_SIZE / 2) as u64,
+        max_inodes: (axconfig::PHYS_MEMORY_SIZE / 2 / fs::ramfs::PAGE_SIZE) as u64,
+        // the page allocator is only initialized on bare-metal targets
+        #[cfg(all(target_os = "none", not(test)))]
+        page_alloc: &RamfsPageAlloc,
+        ..Default::default()
+    };
+    Arc::new(fs::ramfs::RamFileSystem::with_options(opts))
 }
 
 #[cfg(feature = "procfs")]
-pub(crate) fn procfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
-    let procfs = fs::ramfs::RamFileSystem::new();
-    let proc_root = procfs.root_dir();
+pub(crate) fn procfs() -> Arc<fs::pseudofs::PseudoFileSystem> {
+    use fs::pseudofs::{PseudoFile, PseudoFileSystem};
+
+    let procfs = PseudoFileSystem::new("proc");
+    let proc_root = procfs.root_dir_node();
+
+    proc_root.add("meminfo", PseudoFile::new(procfs_gen::meminfo));
+    proc_root.add("uptime", PseudoFile::new(procfs_gen::uptime));
+    proc_root.add("mounts", PseudoFile::new(procfs_gen::mounts));
+    proc_root.add("interrupts", PseudoFile::new(procfs_gen::interrupts));
 
     // Create /proc/sys/net/core/somaxconn
-    proc_root.create("sys", VfsNodeType::Dir)?;
-    proc_root.create("sys/net", VfsNodeType::Dir)?;
-    proc_root.create("sys/net/core", VfsNodeType::Dir)?;
-    proc_root.create("sys/net/core/somaxconn", VfsNodeType::File)?;
-    let file_somaxconn = proc_root.clone().lookup("./sys/net/core/somaxconn")?;
-    file_somaxconn.write_at(0, b"4096\n")?;
+    let sys_dir = proc_root.mkdir("sys");
+    let core_dir = sys_dir.mkdir("net").mkdir("core");
+    core_dir.add("somaxconn", PseudoFile::new(|| "4096\n".into()));
 
     // Create /proc/sys/vm/overcommit_memory
-    proc_root.create("sys/vm", VfsNodeType::Dir)?;
-    proc_root.create("sys/vm/overcommit_memory", VfsNodeType::File)?;
-    let file_over = proc_root.clone().lookup("./sys/vm/overcommit_memory")?;
-    file_over.write_at(0, b"0\n")?;
+    let vm_dir = sys_dir.mkdir("vm");
+    vm_dir.add("overcommit_memory", PseudoFile::new(|| "0\n".into()));
+
+    // Create /proc/self and /proc/<tid>
+    use fs::pseudofs::PseudoSymlink;
+    #[cfg(feature = "multitask")]
+    {
+        proc_root.add(
+            "self",
+            PseudoSymlink::new(|| alloc::format!("{}", axtask::current().id().as_u64())),
+        );
+        let task_dirs = procfs_gen::TaskDirs::new();
+        proc_root.set_generator(move |proc_root| task_dirs.get(proc_root));
+    }
+    #[cfg(not(feature = "multitask"))]
+    {
+        // only the `main` task exists, with the same ID as reported by `getpid`
+        let parent = proc_root.clone() as axfs_vfs::VfsNodeRef;
+        let main_dir = procfs_gen::task_dir(&parent, || Some(("main".into(), 2, "R (running)")));
+        proc_root.add("2", main_dir);
+        proc_root.add("self", PseudoSymlink::new(|| "2".into()));
+    }
+
+    Arc::new(procfs)
+}
+
+/// Generators of the procfs file contents.
+#[cfg(feature = "procfs")]
+mod procfs_gen {
+    use alloc::{format, string::String};
+    use core::fmt::Write;
+
+    use {
+        crate::fs::pseudofs::{PseudoDir, PseudoFile},
+        alloc::sync::Arc,
+        axfs_vfs::VfsNodeRef,
+    };
 
-    // Create /proc/self/stat
-    proc_root.create("self", VfsNodeType::Dir)?;
-    proc_root.create("self/stat", VfsNodeType::File)?;
+    #[cfg(feature = "multitask")]
+    use {
+        alloc::{collections::BTreeMap, vec::Vec},
+        axsync::Mutex,
+        axtask::TaskState,
+    };
 
-    Ok(Arc::new(procfs))
+    pub fn meminfo() -> String {
+        use axhal::mem::PAGE_SIZE_4K;
+
+        let allocator = axalloc::global_allocator();
+        let free = allocator.available_pages() * PAGE_SIZE_4K;
+        let total = allocator.used_pages() * PAGE_SIZE_4K + free;
+        // free memory in the byte allocator can also be used.
+        let available = free + allocator.available_bytes();
+
+        let mut s = String::new();
+        for (key, bytes) in [
+            ("MemTotal", total),
+            ("MemFree", free),
+            ("MemAvailable", available),
+        ] {
+            writeln!(s, "{:<15} {:>8} kB", format!("{key}:"), bytes / 1024).unwrap();
+        }
+        s
+    }
+
+    pub fn uptime() -> String {
+        let now = axhal::time::monotonic_time();
+        // idle time is not tracked.
+        format!("{}.{:02} 0.00\n", now.as_secs(), now.subsec_millis() / 10)
+    }
+
+    pub fn mounts() -> String {
+        let mut s = String::new();
+        for (path, fs, read_only) in crate::root::mount_table() {
+            let opts = if read_only { "ro" } else { "rw" };
+            writeln!(s, "{0} {1} {0} {2} 0 0", fs.name(), path, opts).unwrap();
+        }
+        s
+    }
+
+    pub fn interrupts() -> String {
+        #[allow(unused_mut)]
+        let mut s = String::new();
+        #[cfg(feature = "irq")]
+        for (irq_num, count) in axhal::irq::irq_counts() {
+            writeln!(s, "{:>4}: {:>10}", irq_num, count).unwrap();
+        }
+        s
+    }
+
+    /// The name, ID and state (as in `/proc/<tid>/status`) of a task, or
+    /// [`None`] if the task is gone.
+    type TaskInfo = Option<(String, u64, &'static str)>;
+
+    /// Creates a `/proc/<tid>` directory, whose files are generated from the
+    /// task information returned by `info`.
+    pub fn task_dir(
+        parent: &VfsNodeRef,
+        info: impl Fn() -> TaskInfo + Send + Sync + 'static,
+    ) -> Arc<PseudoDir> {
+        let info = Arc::new(info);
+        let dir = PseudoDir::new(Some(parent));
+        let stat_info = info.clone();
+        // only the leading fields are provided
+        dir.add(
+            "stat",
+            PseudoFile::new(move || {
+                stat_info().map_or_else(String::new, |(name, tid, state)| {
+                    format!("{} ({}) {}\n", tid, name, &state[..1])
+                })
+            }),
+        );
+        dir.add(
+            "status",
+            PseudoFile::new(move || {
+                info().map_or_else(String::new, |(name, tid, state)| {
+                    format!("Name:\t{}\nState:\t{}\nTid:\t{}\n", name, state, tid)
+                })
+            }),
+        );
+        dir
+    }
+
+    /// The generator of the `/proc/<tid>` directories.
+    ///
+    /// The directory of a task is created on the first lookup and reused
+    /// until the task is recycled.
+    #[cfg(feature = "multitask")]
+    pub struct TaskDirs(Mutex<BTreeMap<u64, Arc<PseudoDir>>>);
+
+    #[cfg(feature = "multitask")]
+    impl TaskDirs {
+        pub fn new() -> Self {
+            Self(Mutex::new(BTreeMap::new()))
+        }
+
+        pub fn get(&self, proc_root: &Arc<PseudoDir>) -> Vec<(String, VfsNodeRef)> {
+            let parent = proc_root.clone() as VfsNodeRef;
+            let mut dirs = self.0.lock();
+            let mut old_dirs = core::mem::take(&mut *dirs);
+            axtask::all_tasks()
+                .into_iter()
+                .map(|task| {
+                    let tid = task.id().as_u64();
+                    let dir = old_dirs.remove(&tid).unwrap_or_else(|| {
+                        let weak = Arc::downgrade(&task);
+                        task_dir(&parent, move || {
+                            let task = weak.upgrade()?;
+                            let state = match task.state() {
+                                TaskState::Running => "R (running)",
+                                TaskState::Ready => "R (runnable)",
+                                TaskState::Blocked => "S (sleeping)",
+                                TaskState::Exited => "Z (zombie)",
+                            };
+                            Some((task.name().into(), task.id().as_u64(), state))
+                        })
+                    });
+                    dirs.insert(tid, dir.clone());
+                    (format!("{}", tid), dir as _)
+                })
+                .collect()
+        }
+    }
 }
 
 #[cfg(feature = "sysfs")]
-pub(crate) fn sysfs() -> VfsResult<Arc<fs::ramfs::RamFileSystem>> {
-    let sysfs = fs::ramfs::RamFileSystem::new();
-    let sys_root = sysfs.root_dir();
-
-    // Create /sys/kernel/mm/transparent_hugepage/enabled
-    sys_root.create("kernel", VfsNodeType::Dir)?;
-    sys_root.create("kernel/mm", VfsNodeType::Dir)?;
-    sys_root.create("kernel/mm/transparent_hugepage", VfsNodeType::Dir)?;
-    sys_root.create("kernel/mm/transparent_hugepage/enabled", VfsNodeType::File)?;
-    let file_hp = sys_root
-        .clone()
-        .lookup("./kernel/mm/transparent_hugepage/enabled")?;
-    file_hp.write_at(0, b"always [madvise] never\n")?;
+pub(crate) fn sysfs() -> Arc<fs::pseudofs::PseudoFileSystem> {
+    use fs::pseudofs::{PseudoFile, PseudoFileSystem};
+
+    let sysfs = PseudoFileSystem::new("sysfs");
+    let sys_root = sysfs.root_dir_node();
 
     // Create /sys/devices/system/clocksource/clocksource0/current_clocksource
-    sys_root.create("devices", VfsNodeType::Dir)?;
-    sys_root.create("devices/system", VfsNodeType::Dir)?;
-    sys_root.create("devices/system/clocksource", VfsNodeType::Dir)?;
-    sys_root.create("devices/system/clocksource/clocksource0", VfsNodeType::Dir)?;
-    sys_root.create(
-        "devices/system/clocksource/clocksource0/current_clocksource",
-        VfsNodeType::File,
-    )?;
-    let file_cc = sys_root
-        .clone()
-        .lookup("devices/system/clocksource/clocksource0/current_clocksource")?;
-    file_cc.write_at(0, b"tsc\n")?;
-
-    Ok(Arc::new(sysfs))
+    let cs_dir = sys_root
+        .mkdir("devices")
+        .mkdir("system")
+        .mkdir("clocksource")
+        .mkdir("clocksource0");
+    cs_dir.add("current_clocksource", PseudoFile::new(|| "tsc\n".into()));
+
+    // Create /sys/fs/block_cache/{capacity,stats}
+    let cache_dir = sys_root.mkdir("fs").mkdir("block_cache");
+    cache_dir.add(
+        "capacity",
+        PseudoFile::new_rw(
+            || alloc::format!("{}\n", crate::cache::capacity()),
+            |s| {
+                let blocks = s.parse().map_err(|_| axerrno::AxError::InvalidInput)?;
+                if crate::cache::set_capacity(blocks) {
+                    Ok(())
+                } else {
+                    Err(axerrno::AxError::InvalidInput)
+                }
+            },
+        ),
+    );
+    cache_dir.add(
+        "stats",
+        PseudoFile::new(|| {
+            let stats = crate::cache::stats();
+            alloc::format!(
+                "hits {}\nmisses {}\ncached {}\ndirty {}\n",
+                stats.hits,
+                stats.misses,
+                stats.cached,
+                stats.dirty
+            )
+        }),
+    );
+
+    Arc::new(sysfs)
 }
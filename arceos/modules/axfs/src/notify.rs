@@ -0,0 +1,236 @@
+//! Change notification of files and directories.
+//!
+//! A [`Watcher`] watches files and directories for changes, and queues the
+//! changes as [`WatchEvent`]s, like inotify on Linux. A watched directory
+//! reports the changes of its entries, with their names in the events.
+//!
+//! Changes are reported by filesystems, so watches can only be added on
+//! filesystems supporting [`VfsNodeOps::watch`](axfs_vfs::VfsNodeOps::watch)
+//! (currently ramfs).
+
+use alloc::{collections::BTreeMap, collections::VecDeque, string::String, sync::Arc};
+use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU32, Ordering};
+
+use axerrno::{ax_err, AxResult};
+use axfs_vfs::{VfsEvent, VfsWatcher};
+use axsync::{spin::SpinNoIrq, Mutex};
+use cap_access::Cap;
+
+use crate::fops::Credentials;
+use crate::lock::InodeKey;
+
+/// Types of changes to watch, and of the changes in [`WatchEvent`]s.
+pub type WatchMask = axfs_vfs::VfsEventMask;
+
+/// The maximum number of events queued in a [`Watcher`]. Further events are
+/// dropped, and reported by a [`Q_OVERFLOW`](WatchMask::Q_OVERFLOW) event
+/// after the queued ones.
+pub const MAX_QUEUED_EVENTS: usize = 16384;
+
+/// A change to a watched file or directory.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct WatchEvent {
+    /// The watch descriptor returned by [`Watcher::add_watch`], or -1 for
+    /// [`Q_OVERFLOW`](WatchMask::Q_OVERFLOW) events.
+    pub wd: i32,
+    /// Type of the change.
+    pub mask: WatchMask,
+    /// A number which is the same for the [`MOVED_FROM`](WatchMask::MOVED_FROM)
+    /// and [`MOVED_TO`](WatchMask::MOVED_TO) events of a rename, or 0.
+    pub cookie: u32,
+    /// Name of the changed entry in the watched directory, or empty if the
+    /// watched node itself is changed.
+    pub name: String,
+}
+
+/// Events of a [`Watcher`] waiting to be read.
+struct EventQueue {
+    /// It is locked by filesystems when reporting changes, so it must not
+    /// sleep.
+    events: SpinNoIrq<VecDeque<WatchEvent>>,
+    #[cfg(feature = "multitask")]
+    wait_queue: axtask::WaitQueue,
+}
+
+/// A watch on a node, which is registered to its filesystem.
+struct Watch {
+    wd: i32,
+    mask: AtomicU32,
+    /// Whether the watch is removed as the node was removed.
+    removed: AtomicBool,
+    queue: Arc<EventQueue>,
+}
+
+/// A watcher of changes to files and directories.
+///
+/// Watches are removed when the watcher is dropped.
+pub struct Watcher {
+    queue: Arc<EventQueue>,
+    watches: Mutex<BTreeMap<InodeKey, Arc<Watch>>>,
+    next_wd: AtomicI32,
+}
+
+impl EventQueue {
+    fn push(&self, event: WatchEvent) {
+        let mut events = self.events.lock();
+        // merge identical events not read yet, like Linux
+        if events.back() == Some(&event) {
+            return;
+        }
+        if events.len() < MAX_QUEUED_EVENTS {
+            events.push_back(event);
+        } else if events
+            .back()
+            .is_some_and(|e| e.mask != WatchMask::Q_OVERFLOW)
+        {
+            // one slot beyond the limit is reserved for the overflow event
+            events.push_back(WatchEvent {
+                wd: -1,
+                mask: WatchMask::Q_OVERFLOW,
+                cookie: 0,
+                name: String::new(),
+            });
+        } else {
+            return;
+        }
+        drop(events);
+        #[cfg(feature = "multitask")]
+        self.wait_queue.notify_all(false);
+    }
+}
+
+impl Watch {
+    fn push(&self, mask: WatchMask, event: &VfsEvent) {
+        self.queue.push(WatchEvent {
+            wd: self.wd,
+            mask,
+            cookie: event.cookie,
+            name: event.name.into(),
+        })
+    }
+}
+
+impl VfsWatcher for Watch {
+    fn notify(&self, event: &VfsEvent) {
+        if self.removed.load(Ordering::Acquire) {
+            return;
+        }
+        let mask = WatchMask::from_bits_truncate(self.mask.load(Ordering::Relaxed));
+        if mask.intersects(event.mask - WatchMask::IS_DIR) {
+            self.push(event.mask, event);
+        }
+        if event.mask.contains(WatchMask::DELETE_SELF) {
+            self.removed.store(true, Ordering::Release);
+            self.push(WatchMask::IGNORED, &VfsEvent { name: "", ..*event });
+        }
+    }
+}
+
+impl Watcher {
+    /// Creates a new watcher without watches.
+    pub fn new() -> Self {
+        Self {
+            queue: Arc::new(EventQueue {
+                events: SpinNoIrq::new(VecDeque::new()),
+                #[cfg(feature = "multitask")]
+                wait_queue: axtask::WaitQueue::new(),
+            }),
+            watches: Mutex::new(BTreeMap::new()),
+            next_wd: AtomicI32::new(1),
+        }
+    }
+
+    /// Watches the changes of `mask` to the file or directory at `path`, on
+    /// behalf of the user `cred`, who must have the read permission on it.
+    /// Returns the watch descriptor in the events of the node.
+    ///
+    /// If the node is already watched, the mask of the watch is replaced, and
+    /// the same watch descriptor is returned. Returns
+    /// [`Unsupported`](axerrno::AxError::Unsupported) if the filesystem does
+    /// not report changes.
+    pub fn add_watch(&self, path: &str, mask: WatchMask, cred: &Credentials) -> AxResult<i32> {
+        let node = crate::root::lookup(None, path, cred)?;
+        let attr = node.get_attr()?;
+        cred.check_access(&attr, Cap::READ)?;
+        let mount = crate::root::mount_point_of(path)?;
+        let key = InodeKey::new(crate::root::fs_of(mount.as_ref()), &node, &attr);
+
+        let mut watches = self.watches.lock();
+        if let Some(watch) = watches.get(&key) {
+            if !watch.removed.load(Ordering::Acquire) {
+                watch.mask.store(mask.bits(), Ordering::Relaxed);
+                return Ok(watch.wd);
+            }
+        }
+        let watch = Arc::new(Watch {
+            wd: self.next_wd.fetch_add(1, Ordering::Relaxed),
+            mask: AtomicU32::new(mask.bits()),
+            removed: AtomicBool::new(false),
+            queue: self.queue.clone(),
+        });
+        node.watch(Arc::downgrade(&watch) as _)?;
+        watches.insert(key, watch.clone());
+        Ok(watch.wd)
+    }
+
+    /// Removes the watch `wd`, and queues an
+    /// [`IGNORED`](WatchMask::IGNORED) event of it.
+    ///
+    /// Returns [`InvalidInput`](axerrno::AxError::InvalidInput) if there is no
+    /// such watch, or it is already removed as the node was removed.
+    pub fn remove_watch(&self, wd: i32) -> AxResult {
+        let mut watches = self.watches.lock();
+        let Some((&key, _)) = watches.iter().find(|(_, w)| w.wd == wd) else {
+            return ax_err!(InvalidInput);
+        };
+        let watch = watches.remove(&key).unwrap();
+        if watch.removed.swap(true, Ordering::AcqRel) {
+            return ax_err!(InvalidInput);
+        }
+        let event = VfsEvent {
+            mask: WatchMask::IGNORED,
+            name: "",
+            cookie: 0,
+        };
+        watch.push(WatchMask::IGNORED, &event);
+        Ok(())
+    }
+
+    /// Whether there are events to read.
+    pub fn has_events(&self) -> bool {
+        !self.queue.events.lock().is_empty()
+    }
+
+    /// Takes the queued events in order, while `f` accepts them. Returns the
+    /// number of events taken.
+    pub fn read_events(&self, mut f: impl FnMut(&WatchEvent) -> bool) -> usize {
+        let mut events = self.queue.events.lock();
+        let mut count = 0;
+        while events.front().is_some_and(&mut f) {
+            events.pop_front();
+            count += 1;
+        }
+        count
+    }
+
+    /// Waits until there are events to read.
+    ///
+    /// Without the `multitask` feature, it returns
+    /// [`WouldBlock`](axerrno::AxError::WouldBlock) if there are no events, as
+    /// no other task can make changes.
+    pub fn wait_events(&self) -> AxResult {
+        #[cfg(feature = "multitask")]
+        self.queue.wait_queue.wait_until(|| self.has_events());
+        if self.has_events() {
+            Ok(())
+        } else {
+            ax_err!(WouldBlock)
+        }
+    }
+}
+
+impl Default for Watcher {
+    fn default() -> Self {
+        Self::new()
+    }
+}
@@ -0,0 +1,122 @@
+//! Parsing of MBR and GPT partition tables.
+//!
+//! Only the primary partitions of MBR are supported, logical partitions in
+//! extended partitions are ignored. The checksums of GPT are not verified.
+
+use alloc::vec::Vec;
+use axdriver::prelude::DevResult;
+
+use crate::dev::Disk;
+
+const BLOCK_SIZE: usize = 512;
+
+const MBR_ENTRY_OFFSET: usize = 0x1be;
+const MBR_ENTRY_SIZE: usize = 16;
+const MBR_TYPE_GPT_PROTECTIVE: u8 = 0xee;
+const MBR_TYPES_EXTENDED: [u8; 3] = [0x05, 0x0f, 0x85];
+const GPT_SIGNATURE: &[u8] = b"EFI PART";
+
+/// A partition on a disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Partition {
+    /// The partition number, starting from 1.
+    pub number: usize,
+    /// The first block of the partition.
+    pub start: u64,
+    /// The number of blocks in the partition.
+    pub num_blocks: u64,
+}
+
+/// Returns the partitions on `disk`, or an empty list if it does not have a
+/// partition table.
+pub(crate) fn parse(disk: &Disk) -> DevResult<Vec<Partition>> {
+    let mut disk = disk.try_clone();
+    let num_blocks = disk.size() / BLOCK_SIZE as u64;
+    if num_blocks == 0 {
+        return Ok(Vec::new());
+    }
+    let mbr = read_block(&mut disk, 0)?;
+    if mbr[510..512] != [0x55, 0xaa] || is_boot_sector(&mbr) {
+        return Ok(Vec::new());
+    }
+
+    let mut parts = Vec::new();
+    for i in 0..4 {
+        let entry = &mbr[MBR_ENTRY_OFFSET + i * MBR_ENTRY_SIZE..][..MBR_ENTRY_SIZE];
+        let (status, ty) = (entry[0], entry[4]);
+        let start = u32::from_le_bytes(entry[8..12].try_into().unwrap()) as u64;
+        let len = u32::from_le_bytes(entry[12..16].try_into().unwrap()) as u64;
+        if status != 0 && status != 0x80 {
+            return Ok(Vec::new()); // not an MBR
+        }
+        if ty == MBR_TYPE_GPT_PROTECTIVE {
+            return parse_gpt(&mut disk, num_blocks);
+        }
+        if ty == 0 || len == 0 || MBR_TYPES_EXTENDED.contains(&ty) {
+            continue;
+        }
+        if start == 0 || start + len > num_blocks {
+            return Ok(Vec::new()); // not an MBR
+        }
+        parts.push(Partition {
+            number: i + 1,
+            start,
+            num_blocks: len,
+        });
+    }
+    Ok(parts)
+}
+
+fn parse_gpt(disk: &mut Disk, num_blocks: u64) -> DevResult<Vec<Partition>> {
+    let header = read_block(disk, 1)?;
+    if &header[..8] != GPT_SIGNATURE {
+        warn!("invalid GPT header");
+        return Ok(Vec::new());
+    }
+    let entry_lba = u64::from_le_bytes(header[72..80].try_into().unwrap());
+    let num_entries = u32::from_le_bytes(header[80..84].try_into().unwrap()) as usize;
+    let entry_size = u32::from_le_bytes(header[84..88].try_into().unwrap()) as usize;
+    if !(128..=BLOCK_SIZE).contains(&entry_size) || BLOCK_SIZE % entry_size != 0 {
+        warn!("unsupported GPT entry size: {}", entry_size);
+        return Ok(Vec::new());
+    }
+
+    let entries_per_block = BLOCK_SIZE / entry_size;
+    let mut parts = Vec::new();
+    let mut block = [0; BLOCK_SIZE];
+    for i in 0..num_entries {
+        if i % entries_per_block == 0 {
+            block = read_block(disk, entry_lba + (i / entries_per_block) as u64)?;
+        }
+        let entry = &block[(i % entries_per_block) * entry_size..][..entry_size];
+        if entry[..16].iter().all(|&b| b == 0) {
+            continue; // unused entry
+        }
+        let first = u64::from_le_bytes(entry[32..40].try_into().unwrap());
+        let last = u64::from_le_bytes(entry[40..48].try_into().unwrap());
+        if first == 0 || first > last || last >= num_blocks {
+            warn!("invalid GPT entry {}: {}..={}", i, first, last);
+            continue;
+        }
+        parts.push(Partition {
+            number: i + 1,
+            start: first,
+            num_blocks: last - first + 1,
+        });
+    }
+    Ok(parts)
+}
+
+/// Whether the first block is the boot sector of a filesystem (e.g., FAT)
+/// rather than an MBR, as both end with `0x55aa`.
+fn is_boot_sector(block: &[u8; BLOCK_SIZE]) -> bool {
+    matches!(block[0], 0xeb | 0xe9)
+        && (&block[0x36..0x39] == b"FAT" || &block[0x52..0x55] == b"FAT")
+}
+
+fn read_block(disk: &mut Disk, block_id: u64) -> DevResult<[u8; BLOCK_SIZE]> {
+    let mut buf = [0; BLOCK_SIZE];
+    disk.set_position(block_id * BLOCK_SIZE as u64);
+    disk.read_one(&mut buf)?;
+    Ok(buf)
+}
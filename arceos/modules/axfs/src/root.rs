@@ -1,73 +1,207 @@
 //! Root directory of the filesystem
-//!
-//! TODO: it doesn't work very well if the mount points have containment relationships.
 
-use alloc::{string::String, sync::Arc, vec::Vec};
+use alloc::{format, string::String, sync::Arc, sync::Weak, vec::Vec};
 use axerrno::{ax_err, AxError, AxResult};
-use axfs_vfs::{VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps, VfsResult};
+use axfs_vfs::{FileSystemInfo, VfsNodeAttr, VfsNodeOps, VfsNodeRef, VfsNodeType, VfsOps};
+use axfs_vfs::{VfsResult, VfsWatcher};
 use axsync::Mutex;
+use cap_access::Cap;
 use lazyinit::LazyInit;
 
-use crate::{api::FileType, fs, mounts};
+use crate::fops::{Credentials, SetAttr};
+use crate::fs::readonly::ReadOnlyFs;
+use crate::{api::FileType, mounts};
 
 static CURRENT_DIR_PATH: Mutex<String> = Mutex::new(String::new());
 static CURRENT_DIR: LazyInit<Mutex<VfsNodeRef>> = LazyInit::new();
 
-struct MountPoint {
-    path: &'static str,
+/// A filesystem mounted at some path of the root directory.
+///
+/// Opened files and directories hold a reference to the mount point they
+/// belong to, so that a filesystem in use cannot be unmounted.
+pub(crate) struct MountPoint {
+    path: String,
     fs: Arc<dyn VfsOps>,
+    read_only: bool,
 }
 
 struct RootDirectory {
     main_fs: Arc<dyn VfsOps>,
-    mounts: Vec<MountPoint>,
+    main_read_only: bool,
+    mounts: Mutex<Vec<Arc<MountPoint>>>,
 }
 
 static ROOT_DIR: LazyInit<Arc<RootDirectory>> = LazyInit::new();
 
+/// Wraps `fs` to reject modifications if `read_only` is true.
+fn wrap_fs(fs: Arc<dyn VfsOps>, read_only: bool) -> Arc<dyn VfsOps> {
+    if read_only {
+        Arc::new(ReadOnlyFs::new(fs))
+    } else {
+        fs
+    }
+}
+
 impl MountPoint {
-    pub fn new(path: &'static str, fs: Arc<dyn VfsOps>) -> Self {
-        Self { path, fs }
+    pub fn new(path: String, fs: Arc<dyn VfsOps>, read_only: bool) -> Self {
+        Self {
+            path,
+            fs: wrap_fs(fs, read_only),
+            read_only,
+        }
     }
 }
 
-impl Drop for MountPoint {
-    fn drop(&mut self) {
-        self.fs.umount().ok();
+/// Matches `path` (relative to `/`) against the absolute mount point path
+/// `mp` component by component. Returns the remaining path if `path` is
+/// located in the mount point.
+///
+/// Empty and `.` components of `path` are skipped, so `./dev//null` matches
+/// `/dev` with `/null` remaining, but `/tmpfoo` does not match `/tmp`.
+fn strip_mount_point<'a>(path: &'a str, mp: &str) -> Option<&'a str> {
+    let mut rest = path;
+    for comp in mp.split('/').filter(|c| !c.is_empty()) {
+        loop {
+            rest = rest.trim_start_matches('/');
+            match rest.strip_prefix('.') {
+                Some(r) if r.is_empty() || r.starts_with('/') => rest = r,
+                _ => break,
+            }
+        }
+        let end = rest.find('/').unwrap_or(rest.len());
+        if &rest[..end] != comp {
+            return None;
+        }
+        rest = &rest[end..];
     }
+    Some(rest)
+}
+
+/// Whether the canonical absolute `path` is `mp` itself or located under it.
+fn is_under_mount_point(path: &str, mp: &str) -> bool {
+    path == mp || (path.starts_with(mp) && path.as_bytes().get(mp.len()) == Some(&b'/'))
 }
 
 impl RootDirectory {
-    pub const fn new(main_fs: Arc<dyn VfsOps>) -> Self {
+    pub fn new(main_fs: Arc<dyn VfsOps>, read_only: bool) -> Self {
         Self {
-            main_fs,
-            mounts: Vec::new(),
+            main_fs: wrap_fs(main_fs, read_only),
+            main_read_only: read_only,
+            mounts: Mutex::new(Vec::new()),
         }
     }
 
-    pub fn mount(&mut self, path: &'static str, fs: Arc<dyn VfsOps>) -> AxResult {
+    pub fn mount(&self, path: &str, fs: Arc<dyn VfsOps>, read_only: bool) -> AxResult {
+        if !path.starts_with('/') {
+            return ax_err!(InvalidInput, "mount path must start with '/'");
+        }
+        let path = axfs_vfs::path::canonicalize(path);
         if path == "/" {
             return ax_err!(InvalidInput, "cannot mount root filesystem");
         }
+
+        let mut mounts = self.mounts.lock();
+        if mounts.iter().any(|mp| mp.path == path) {
+            return ax_err!(InvalidInput, "mount point already exists");
+        }
+        // create the mount point in the filesystem that contains it (may be
+        // another mounted filesystem) if it does not exist
+        let (parent_fs, rest_path) = Self::find_mounted_fs(&self.main_fs, &mounts, &path[1..]);
+        let parent_root = parent_fs.root_dir();
+        let mount_point = match parent_root.clone().lookup(rest_path) {
+            Ok(node) => node,
+            Err(AxError::NotFound) => {
+                parent_root.create(rest_path, FileType::Dir)?;
+                parent_root.lookup(rest_path)?
+            }
+            Err(e) => return Err(e),
+        };
+        if !mount_point.get_attr()?.is_dir() {
+            return ax_err!(NotADirectory, "mount point is not a directory");
+        }
+        fs.mount(&path, mount_point)?;
+        mounts.push(Arc::new(MountPoint::new(path, fs, read_only)));
+        Ok(())
+    }
+
+    pub fn umount(&self, path: &str) -> AxResult {
         if !path.starts_with('/') {
             return ax_err!(InvalidInput, "mount path must start with '/'");
         }
-        if self.mounts.iter().any(|mp| mp.path == path) {
-            return ax_err!(InvalidInput, "mount point already exists");
+        let path = axfs_vfs::path::canonicalize(path);
+        if path == "/" {
+            return ax_err!(PermissionDenied, "cannot unmount root filesystem");
+        }
+
+        let mut mounts = self.mounts.lock();
+        let idx = mounts
+            .iter()
+            .position(|mp| mp.path == path)
+            .ok_or(AxError::InvalidInput)?;
+        if mounts
+            .iter()
+            .any(|mp| mp.path != path && is_under_mount_point(&mp.path, &path))
+        {
+            return ax_err!(ResourceBusy, "filesystem has nested mount points");
+        }
+        if Arc::strong_count(&mounts[idx]) > 1 {
+            return ax_err!(ResourceBusy, "filesystem has opened files");
+        }
+        if is_under_mount_point(CURRENT_DIR_PATH.lock().trim_end_matches('/'), &path) {
+            return ax_err!(ResourceBusy, "filesystem contains the current directory");
+        }
+        mounts[idx].fs.umount()?;
+        mounts.remove(idx);
+        if let Err(e) = crate::cache::flush_all() {
+            warn!("failed to write back the block cache on umount: {:?}", e);
         }
-        // create the mount point in the main filesystem if it does not exist
-        self.main_fs.root_dir().create(path, FileType::Dir)?;
-        fs.mount(path, self.main_fs.root_dir().lookup(path)?)?;
-        self.mounts.push(MountPoint::new(path, fs));
         Ok(())
     }
 
-    pub fn _umount(&mut self, path: &str) {
-        self.mounts.retain(|mp| mp.path != path);
+    pub fn contains(&self, path: &str) -> bool {
+        self.mounts.lock().iter().any(|mp| mp.path == path)
     }
 
-    pub fn contains(&self, path: &str) -> bool {
-        self.mounts.iter().any(|mp| mp.path == path)
+    /// Whether any filesystem is mounted at or under the canonical absolute
+    /// `path`.
+    pub fn has_mount_points_under(&self, path: &str) -> bool {
+        self.mounts
+            .lock()
+            .iter()
+            .any(|mp| is_under_mount_point(&mp.path, path))
+    }
+
+    /// Returns the mount point that the canonical absolute `path` is located
+    /// in, or `None` if it is on the main filesystem.
+    pub fn mount_point_of(&self, path: &str) -> Option<Arc<MountPoint>> {
+        self.mounts
+            .lock()
+            .iter()
+            .filter(|mp| is_under_mount_point(path, &mp.path))
+            .max_by_key(|mp| mp.path.len())
+            .cloned()
+    }
+
+    /// Finds the filesystem that has the longest mounted path match.
+    ///
+    /// TODO: more efficient, e.g. trie
+    fn find_mounted_fs<'a>(
+        main_fs: &Arc<dyn VfsOps>,
+        mounts: &[Arc<MountPoint>],
+        path: &'a str,
+    ) -> (Arc<dyn VfsOps>, &'a str) {
+        let mut matched: Option<(&Arc<MountPoint>, &'a str)> = None;
+        for mp in mounts {
+            if let Some(rest) = strip_mount_point(path, &mp.path) {
+                if matched.map_or(true, |(m, _)| mp.path.len() > m.path.len()) {
+                    matched = Some((mp, rest));
+                }
+            }
+        }
+        match matched {
+            Some((mp, rest)) => (mp.fs.clone(), rest), // matched a mount point
+            None => (main_fs.clone(), path),           // not matched any mount point
+        }
     }
 
     fn lookup_mounted_fs<F, T>(&self, path: &str, f: F) -> AxResult<T>
@@ -80,24 +214,9 @@ impl RootDirectory {
             return self.lookup_mounted_fs(rest, f);
         }
 
-        let mut idx = 0;
-        let mut max_len = 0;
-
-        // Find the filesystem that has the longest mounted path match
-        // TODO: more efficient, e.g. trie
-        for (i, mp) in self.mounts.iter().enumerate() {
-            // skip the first '/'
-            if path.starts_with(&mp.path[1..]) && mp.path.len() - 1 > max_len {
-                max_len = mp.path.len() - 1;
-                idx = i;
-            }
-        }
-
-        if max_len == 0 {
-            f(self.main_fs.clone(), path) // not matched any mount point
-        } else {
-            f(self.mounts[idx].fs.clone(), &path[max_len..]) // matched at `idx`
-        }
+        // do not hold the lock of the mount table during the operation
+        let (fs, rest_path) = Self::find_mounted_fs(&self.main_fs, &self.mounts.lock(), path);
+        f(fs, rest_path)
     }
 }
 
@@ -132,51 +251,66 @@ impl VfsNodeOps for RootDirectory {
         })
     }
 
-    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
-        self.lookup_mounted_fs(src_path, |fs, rest_path| {
+    fn symlink(&self, path: &str, target: &str) -> VfsResult {
+        self.lookup_mounted_fs(path, |fs, rest_path| {
             if rest_path.is_empty() {
-                ax_err!(PermissionDenied) // cannot rename mount points
+                ax_err!(AlreadyExists)
             } else {
-                fs.root_dir().rename(rest_path, dst_path)
+                fs.root_dir().symlink(rest_path, target)
             }
         })
     }
-}
 
-pub(crate) fn init_rootfs(disk: crate::dev::Disk) {
-    cfg_if::cfg_if! {
-        if #[cfg(feature = "myfs")] { // override the default filesystem
-            let main_fs = fs::myfs::new_myfs(disk);
-        } else if #[cfg(feature = "fatfs")] {
-            static FAT_FS: LazyInit<Arc<fs::fatfs::FatFileSystem>> = LazyInit::new();
-            FAT_FS.init_once(Arc::new(fs::fatfs::FatFileSystem::new(disk)));
-            FAT_FS.init();
-            let main_fs = FAT_FS.clone();
-        }
+    fn link(&self, path: &str, node: &VfsNodeRef) -> VfsResult {
+        self.lookup_mounted_fs(path, |fs, rest_path| {
+            if rest_path.is_empty() {
+                ax_err!(AlreadyExists)
+            } else {
+                fs.root_dir().link(rest_path, node)
+            }
+        })
+    }
+
+    fn rename(&self, src_path: &str, dst_path: &str) -> VfsResult {
+        self.lookup_mounted_fs(src_path, |src_fs, src_rest| {
+            self.lookup_mounted_fs(dst_path, |dst_fs, dst_rest| {
+                if src_rest.is_empty() || dst_rest.is_empty() {
+                    ax_err!(PermissionDenied) // cannot rename mount points
+                } else if !Arc::ptr_eq(&src_fs, &dst_fs) {
+                    ax_err!(CrossesDevices)
+                } else {
+                    src_fs.root_dir().rename(src_rest, dst_rest)
+                }
+            })
+        })
+    }
+
+    fn watch(&self, watcher: Weak<dyn VfsWatcher>) -> VfsResult {
+        self.main_fs.root_dir().watch(watcher)
     }
+}
 
-    let mut root_dir = RootDirectory::new(main_fs);
+pub(crate) fn init_rootfs(main_fs: Arc<dyn VfsOps>, read_only: bool) {
+    let root_dir = RootDirectory::new(main_fs, read_only);
 
     #[cfg(feature = "devfs")]
     root_dir
-        .mount("/dev", mounts::devfs())
+        .mount("/dev", crate::devfs::init(mounts::devfs()), false)
         .expect("failed to mount devfs at /dev");
 
     #[cfg(feature = "ramfs")]
     root_dir
-        .mount("/tmp", mounts::ramfs())
+        .mount("/tmp", mounts::ramfs(), false)
         .expect("failed to mount ramfs at /tmp");
 
-    // Mount another ramfs as procfs
     #[cfg(feature = "procfs")]
     root_dir // should not fail
-        .mount("/proc", mounts::procfs().unwrap())
+        .mount("/proc", mounts::procfs(), false)
         .expect("fail to mount procfs at /proc");
 
-    // Mount another ramfs as sysfs
     #[cfg(feature = "sysfs")]
     root_dir // should not fail
-        .mount("/sys", mounts::sysfs().unwrap())
+        .mount("/sys", crate::sysfs::init(mounts::sysfs()), false)
         .expect("fail to mount sysfs at /sys");
 
     ROOT_DIR.init_once(Arc::new(root_dir));
@@ -184,11 +318,282 @@ pub(crate) fn init_rootfs(disk: crate::dev::Disk) {
     *CURRENT_DIR_PATH.lock() = "/".into();
 }
 
-fn parent_node_of(dir: Option<&VfsNodeRef>, path: &str) -> VfsNodeRef {
-    if path.starts_with('/') {
-        ROOT_DIR.clone()
-    } else {
-        dir.cloned().unwrap_or_else(|| CURRENT_DIR.lock().clone())
+/// Maximum number of symbolic links followed during one path resolution,
+/// the same as Linux.
+const MAX_SYMLINK_FOLLOWS: usize = 40;
+
+/// A directory reached during path resolution.
+enum Walker {
+    /// Canonical absolute path of the directory, with no symbolic links in it.
+    /// Nodes are looked up from the root directory, so that mount points are
+    /// crossed.
+    Path(String),
+    /// The directory node itself, for paths relative to an opened directory.
+    Node(VfsNodeRef),
+}
+
+impl Walker {
+    fn start(dir: Option<&VfsNodeRef>, path: &str) -> Self {
+        if path.starts_with('/') {
+            Self::Path("/".into())
+        } else if let Some(dir) = dir {
+            Self::Node(dir.clone())
+        } else {
+            Self::Path(axfs_vfs::path::canonicalize(&CURRENT_DIR_PATH.lock()))
+        }
+    }
+
+    /// Returns the canonical absolute path of the child `name`, or of the
+    /// directory itself if `name` is empty.
+    fn abs_path(&self, name: &str) -> Option<String> {
+        match self {
+            Self::Path(path) if name.is_empty() => Some(path.clone()),
+            Self::Path(path) if path == "/" => Some(format!("/{name}")),
+            Self::Path(path) => Some(format!("{path}/{name}")),
+            Self::Node(_) => None,
+        }
+    }
+
+    fn node(&self) -> AxResult<VfsNodeRef> {
+        match self {
+            Self::Path(path) => ROOT_DIR.clone().lookup(path),
+            Self::Node(node) => Ok(node.clone()),
+        }
+    }
+
+    fn lookup(&self, name: &str) -> AxResult<VfsNodeRef> {
+        match self.abs_path(name) {
+            Some(path) => ROOT_DIR.clone().lookup(&path),
+            None => self.node()?.lookup(name),
+        }
+    }
+
+    fn create(&self, name: &str, ty: VfsNodeType) -> AxResult {
+        match self.abs_path(name) {
+            Some(path) => ROOT_DIR.create(&path, ty),
+            None => self.node()?.create(name, ty),
+        }
+    }
+
+    fn remove(&self, name: &str) -> AxResult {
+        match self.abs_path(name) {
+            Some(path) => ROOT_DIR.remove(&path),
+            None => self.node()?.remove(name),
+        }
+    }
+
+    fn symlink(&self, name: &str, target: &str) -> AxResult {
+        match self.abs_path(name) {
+            Some(path) => ROOT_DIR.symlink(&path, target),
+            None => self.node()?.symlink(name, target),
+        }
+    }
+
+    fn link(&self, name: &str, node: &VfsNodeRef) -> AxResult {
+        match self.abs_path(name) {
+            Some(path) => ROOT_DIR.link(&path, node),
+            None => self.node()?.link(name, node),
+        }
+    }
+
+    /// Moves to the child directory `name`, whose node is `node`.
+    fn enter(&mut self, name: &str, node: VfsNodeRef) {
+        *self = match self.abs_path(name) {
+            Some(path) => Self::Path(path),
+            None => Self::Node(node),
+        };
+    }
+
+    /// Moves to the parent directory.
+    fn leave(&mut self) -> AxResult {
+        match self {
+            Self::Path(path) if path == "/" => ax_err!(NotFound),
+            Self::Path(path) => {
+                let idx = path.rfind('/').unwrap_or(0);
+                path.truncate(idx.max(1));
+                Ok(())
+            }
+            Self::Node(node) => {
+                *node = node.parent().ok_or(AxError::NotFound)?;
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Pushes the components of `path` onto the stack `comps` in reverse order,
+/// so that the first component is popped first. Empty and `.` components are
+/// skipped.
+fn push_components(comps: &mut Vec<String>, path: &str) {
+    comps.extend(
+        path.rsplit('/')
+            .filter(|c| !c.is_empty() && *c != ".")
+            .map(String::from),
+    );
+}
+
+/// Resolves `path` to the directory containing its last component, and the
+/// name of the last component. The name is empty if `path` refers to the
+/// directory itself (e.g. `/` or `a/..`).
+///
+/// Symbolic links are followed in all components but the last one, which is
+/// followed only if `follow` is true or `path` ends with `/`. The last
+/// component is not required to exist.
+///
+/// `cred` must have the search permission of all directories walked through.
+fn resolve(
+    dir: Option<&VfsNodeRef>,
+    path: &str,
+    follow: bool,
+    cred: &Credentials,
+) -> AxResult<(Walker, String)> {
+    let follow = follow || path.ends_with('/');
+    let mut walker = Walker::start(dir, path);
+    let mut comps = Vec::new();
+    push_components(&mut comps, path);
+    let check_search =
+        |walker: &Walker| cred.check_access(&walker.node()?.get_attr()?, Cap::EXECUTE);
+    check_search(&walker)?;
+
+    let mut follows = 0;
+    while let Some(name) = comps.pop() {
+        if name == ".." {
+            walker.leave()?;
+            check_search(&walker)?;
+            continue;
+        }
+        let is_last = comps.is_empty();
+        if is_last && !follow {
+            return Ok((walker, name));
+        }
+        let node = match walker.lookup(&name) {
+            Ok(node) => node,
+            Err(AxError::NotFound) if is_last => return Ok((walker, name)),
+            Err(e) => return Err(e),
+        };
+        let attr = node.get_attr()?;
+        if attr.is_symlink() {
+            follows += 1;
+            if follows > MAX_SYMLINK_FOLLOWS {
+                return ax_err!(FilesystemLoop);
+            }
+            let target = node.readlink()?;
+            if target.starts_with('/') {
+                walker = Walker::Path("/".into());
+                check_search(&walker)?;
+            }
+            push_components(&mut comps, &target);
+        } else if is_last {
+            return Ok((walker, name));
+        } else if attr.is_dir() {
+            cred.check_access(&attr, Cap::EXECUTE)?;
+            walker.enter(&name, node);
+        } else {
+            return ax_err!(NotADirectory);
+        }
+    }
+    Ok((walker, String::new()))
+}
+
+/// Resolves `path` relative to the current directory to a canonical absolute
+/// path. See [`resolve`] for how symbolic links are followed.
+fn resolve_abs(path: &str, follow: bool, cred: &Credentials) -> AxResult<String> {
+    let (parent, name) = resolve(None, path, follow, cred)?;
+    parent.abs_path(&name).ok_or(AxError::BadState)
+}
+
+/// Returns whether the last components of two resolved paths are located in
+/// the same filesystem.
+///
+/// Paths relative to an opened directory never cross mount points, so they
+/// are always considered to be in the same filesystem.
+fn on_same_fs(a: &(Walker, String), b: &(Walker, String)) -> bool {
+    match (a.0.abs_path(&a.1), b.0.abs_path(&b.1)) {
+        (Some(a), Some(b)) => match (ROOT_DIR.mount_point_of(&a), ROOT_DIR.mount_point_of(&b)) {
+            (Some(a), Some(b)) => Arc::ptr_eq(&a, &b),
+            (a, b) => a.is_none() && b.is_none(),
+        },
+        _ => true,
+    }
+}
+
+/// Checks that `cred` can add or remove entries in the directory `parent`.
+fn check_modify(parent: &Walker, cred: &Credentials) -> AxResult {
+    cred.check_access(&parent.node()?.get_attr()?, Cap::WRITE | Cap::EXECUTE)
+}
+
+/// Returns the mount paths, filesystems and whether they are read-only, in
+/// the order they were mounted, starting with the main filesystem at `/`.
+pub(crate) fn mount_table() -> Vec<(String, Arc<dyn VfsOps>, bool)> {
+    let root = &ROOT_DIR;
+    let mut table = Vec::from([("/".into(), root.main_fs.clone(), root.main_read_only)]);
+    let mounts = ROOT_DIR.mounts.lock();
+    table.extend(
+        mounts
+            .iter()
+            .map(|mp| (mp.path.clone(), mp.fs.clone(), mp.read_only)),
+    );
+    table
+}
+
+pub(crate) fn mount(path: &str, fs: Arc<dyn VfsOps>, read_only: bool) -> AxResult {
+    ROOT_DIR.mount(path, fs, read_only)
+}
+
+pub(crate) fn umount(path: &str) -> AxResult {
+    ROOT_DIR.umount(path)
+}
+
+/// Returns the mount point that `path` (relative to the current directory)
+/// is located in, or `None` if it is on the main filesystem.
+///
+/// Symbolic links in `path` are followed.
+pub(crate) fn mount_point_of(path: &str) -> AxResult<Option<Arc<MountPoint>>> {
+    let path = resolve_abs(path, true, &Credentials::root())?;
+    Ok(ROOT_DIR.mount_point_of(&path))
+}
+
+/// Whether the filesystem mounted at `mount`, or the main filesystem if it is
+/// `None`, is mounted read-only.
+pub(crate) fn is_read_only(mount: Option<&Arc<MountPoint>>) -> bool {
+    mount.map_or(ROOT_DIR.main_read_only, |mp| mp.read_only)
+}
+
+/// Returns the filesystem mounted at `mount`, or the main filesystem if it is
+/// `None`.
+pub(crate) fn fs_of(mount: Option<&Arc<MountPoint>>) -> &Arc<dyn VfsOps> {
+    mount.map_or(&ROOT_DIR.main_fs, |mp| &mp.fs)
+}
+
+/// Gets the attributes of the filesystem mounted at `mount`, or the main
+/// filesystem if it is `None`.
+pub(crate) fn statfs(mount: Option<&Arc<MountPoint>>) -> AxResult<FileSystemInfo> {
+    fs_of(mount).statfs()
+}
+
+/// Writes the cached data and metadata of all mounted filesystems back to
+/// their devices.
+pub(crate) fn sync_all() -> AxResult {
+    for (_, fs, _) in mount_table() {
+        fs.sync()?;
+    }
+    crate::cache::flush_all()
+}
+
+/// Unmounts all filesystems for shutdown, in the reverse order they were
+/// mounted. They are kept in the mount table, since other tasks may still be
+/// using them.
+pub(crate) fn umount_all() {
+    if !ROOT_DIR.is_inited() {
+        return;
+    }
+    for (path, fs, _) in mount_table().into_iter().rev() {
+        if let Err(e) = fs.umount() {
+            warn!("failed to unmount {} on shutdown: {:?}", path, e);
+        }
+    }
+    if let Err(e) = crate::cache::flush_all() {
+        warn!("failed to write back the block cache on shutdown: {:?}", e);
     }
 }
 
@@ -201,11 +606,40 @@ pub(crate) fn absolute_path(path: &str) -> AxResult<String> {
     }
 }
 
-pub(crate) fn lookup(dir: Option<&VfsNodeRef>, path: &str) -> AxResult<VfsNodeRef> {
+/// Looks up the node at `path`, following symbolic links.
+pub(crate) fn lookup(
+    dir: Option<&VfsNodeRef>,
+    path: &str,
+    cred: &Credentials,
+) -> AxResult<VfsNodeRef> {
+    lookup_at(dir, path, true, cred)
+}
+
+/// Looks up the node at `path`. If the last component is a symbolic link,
+/// the link itself is returned.
+pub(crate) fn lookup_no_follow(
+    dir: Option<&VfsNodeRef>,
+    path: &str,
+    cred: &Credentials,
+) -> AxResult<VfsNodeRef> {
+    lookup_at(dir, path, false, cred)
+}
+
+fn lookup_at(
+    dir: Option<&VfsNodeRef>,
+    path: &str,
+    follow: bool,
+    cred: &Credentials,
+) -> AxResult<VfsNodeRef> {
     if path.is_empty() {
         return ax_err!(NotFound);
     }
-    let node = parent_node_of(dir, path).lookup(path)?;
+    let (parent, name) = resolve(dir, path, follow, cred)?;
+    let node = if name.is_empty() {
+        parent.node()?
+    } else {
+        parent.lookup(&name)?
+    };
     if path.ends_with('/') && !node.get_attr()?.is_dir() {
         ax_err!(NotADirectory)
     } else {
@@ -213,38 +647,66 @@ pub(crate) fn lookup(dir: Option<&VfsNodeRef>, path: &str) -> AxResult<VfsNodeRe
     }
 }
 
-pub(crate) fn create_file(dir: Option<&VfsNodeRef>, path: &str) -> AxResult<VfsNodeRef> {
+pub(crate) fn create_file(
+    dir: Option<&VfsNodeRef>,
+    path: &str,
+    cred: &Credentials,
+) -> AxResult<VfsNodeRef> {
     if path.is_empty() {
         return ax_err!(NotFound);
     } else if path.ends_with('/') {
         return ax_err!(NotADirectory);
     }
-    let parent = parent_node_of(dir, path);
-    parent.create(path, VfsNodeType::File)?;
-    parent.lookup(path)
+    let (parent, name) = resolve(dir, path, true, cred)?;
+    if name.is_empty() {
+        return ax_err!(AlreadyExists);
+    }
+    check_modify(&parent, cred)?;
+    parent.create(&name, VfsNodeType::File)?;
+    let node = parent.lookup(&name)?;
+    set_owner(&node, cred);
+    Ok(node)
 }
 
-pub(crate) fn create_dir(dir: Option<&VfsNodeRef>, path: &str) -> AxResult {
-    match lookup(dir, path) {
+pub(crate) fn create_dir(dir: Option<&VfsNodeRef>, path: &str, cred: &Credentials) -> AxResult {
+    match lookup_no_follow(dir, path, cred) {
         Ok(_) => ax_err!(AlreadyExists),
-        Err(AxError::NotFound) => parent_node_of(dir, path).create(path, VfsNodeType::Dir),
+        Err(AxError::NotFound) => {
+            let (parent, name) = resolve(dir, path, false, cred)?;
+            check_modify(&parent, cred)?;
+            parent.create(&name, VfsNodeType::Dir)?;
+            set_owner(&parent.lookup(&name)?, cred);
+            Ok(())
+        }
         Err(e) => Err(e),
     }
 }
 
-pub(crate) fn remove_file(dir: Option<&VfsNodeRef>, path: &str) -> AxResult {
-    let node = lookup(dir, path)?;
-    let attr = node.get_attr()?;
-    if attr.is_dir() {
+/// Makes the user of `cred` own the new node, if it is not root. Filesystems
+/// without ownership are left unchanged.
+fn set_owner(node: &VfsNodeRef, cred: &Credentials) {
+    if cred.uid != 0 || cred.gid != 0 {
+        let attr = SetAttr {
+            uid: Some(cred.uid),
+            gid: Some(cred.gid),
+            ..Default::default()
+        };
+        node.set_attr(&attr).ok();
+    }
+}
+
+pub(crate) fn remove_file(dir: Option<&VfsNodeRef>, path: &str, cred: &Credentials) -> AxResult {
+    let node = lookup_no_follow(dir, path, cred)?;
+    if node.get_attr()?.is_dir() {
         ax_err!(IsADirectory)
-    } else if !attr.perm().owner_writable() {
-        ax_err!(PermissionDenied)
     } else {
-        parent_node_of(dir, path).remove(path)
+        let (parent, name) = resolve(dir, path, false, cred)?;
+        check_modify(&parent, cred)?;
+        parent.remove(&name)
     }
 }
 
-pub(crate) fn remove_dir(dir: Option<&VfsNodeRef>, path: &str) -> AxResult {
+pub(crate) fn remove_dir(dir: Option<&VfsNodeRef>, path: &str, cred: &Credentials) -> AxResult {
     if path.is_empty() {
         return ax_err!(NotFound);
     }
@@ -258,27 +720,87 @@ pub(crate) fn remove_dir(dir: Option<&VfsNodeRef>, path: &str) -> AxResult {
     {
         return ax_err!(InvalidInput);
     }
-    if ROOT_DIR.contains(&absolute_path(path)?) {
-        return ax_err!(PermissionDenied);
+
+    let node = lookup_no_follow(dir, path, cred)?;
+    if !node.get_attr()?.is_dir() {
+        return ax_err!(NotADirectory);
+    }
+    let (parent, name) = resolve(dir, path, false, cred)?;
+    check_modify(&parent, cred)?;
+    match parent.abs_path(&name) {
+        Some(abs_path) if ROOT_DIR.contains(&abs_path) => ax_err!(PermissionDenied),
+        _ => parent.remove(&name),
     }
+}
 
-    let node = lookup(dir, path)?;
-    let attr = node.get_attr()?;
-    if !attr.is_dir() {
-        ax_err!(NotADirectory)
-    } else if !attr.perm().owner_writable() {
-        ax_err!(PermissionDenied)
-    } else {
-        parent_node_of(dir, path).remove(path)
+/// Creates a symbolic link at `path` which points to `target`.
+pub(crate) fn create_symlink(
+    dir: Option<&VfsNodeRef>,
+    target: &str,
+    path: &str,
+    cred: &Credentials,
+) -> AxResult {
+    if target.is_empty() {
+        return ax_err!(NotFound);
     }
+    match lookup_no_follow(dir, path, cred) {
+        Ok(_) => ax_err!(AlreadyExists),
+        Err(AxError::NotFound) => {
+            let (parent, name) = resolve(dir, path, false, cred)?;
+            check_modify(&parent, cred)?;
+            parent.symlink(&name, target)
+        }
+        Err(e) => Err(e),
+    }
+}
+
+/// Creates a hard link at `new` which refers to the same node as `old`.
+///
+/// Symbolic links are not followed at `old`, so a link to a symbolic link
+/// is created in that case.
+pub(crate) fn create_link(
+    dir: Option<&VfsNodeRef>,
+    old: &str,
+    new: &str,
+    cred: &Credentials,
+) -> AxResult {
+    let node = lookup_no_follow(dir, old, cred)?;
+    if node.get_attr()?.is_dir() {
+        return ax_err!(PermissionDenied, "cannot create hard links to directories");
+    }
+    match lookup_no_follow(dir, new, cred) {
+        Ok(_) => return ax_err!(AlreadyExists),
+        Err(AxError::NotFound) => {}
+        Err(e) => return Err(e),
+    }
+    let old = resolve(dir, old, false, cred)?;
+    let new = resolve(dir, new, false, cred)?;
+    if !on_same_fs(&old, &new) {
+        return ax_err!(CrossesDevices);
+    }
+    check_modify(&new.0, cred)?;
+    new.0.link(&new.1, &node)
+}
+
+/// Reads the target of the symbolic link at `path`.
+pub(crate) fn read_link(
+    dir: Option<&VfsNodeRef>,
+    path: &str,
+    cred: &Credentials,
+) -> AxResult<String> {
+    lookup_no_follow(dir, path, cred)?.readlink()
 }
 
 pub(crate) fn current_dir() -> AxResult<String> {
     Ok(CURRENT_DIR_PATH.lock().clone())
 }
 
-pub(crate) fn set_current_dir(path: &str) -> AxResult {
-    let mut abs_path = absolute_path(path)?;
+pub(crate) fn set_current_dir(path: &str, cred: &Credentials) -> AxResult {
+    if path.is_empty() {
+        return ax_err!(NotFound);
+    }
+    // resolve symbolic links, so that the current directory path is canonical
+    let mut abs_path = resolve_abs(path, true, cred)?;
     if !abs_path.ends_with('/') {
         abs_path += "/";
     }
@@ -288,23 +810,43 @@ pub(crate) fn set_current_dir(path: &str) -> AxResult {
         return Ok(());
     }
 
-    let node = lookup(None, &abs_path)?;
+    let node = lookup(None, &abs_path, cred)?;
     let attr = node.get_attr()?;
     if !attr.is_dir() {
-        ax_err!(NotADirectory)
-    } else if !attr.perm().owner_executable() {
-        ax_err!(PermissionDenied)
-    } else {
-        *CURRENT_DIR.lock() = node;
-        *CURRENT_DIR_PATH.lock() = abs_path;
-        Ok(())
+        return ax_err!(NotADirectory);
     }
+    cred.check_access(&attr, Cap::EXECUTE)?;
+    *CURRENT_DIR.lock() = node;
+    *CURRENT_DIR_PATH.lock() = abs_path;
+    Ok(())
 }
 
-pub(crate) fn rename(old: &str, new: &str) -> AxResult {
-    if parent_node_of(None, new).lookup(new).is_ok() {
-        warn!("dst file already exist, now remove it");
-        remove_file(None, new)?;
+/// Renames `old` to `new`, replacing `new` if it already exists.
+///
+/// Symbolic links are not followed at the last components. Both paths must
+/// be located in the same filesystem, otherwise
+/// [`CrossesDevices`](AxError::CrossesDevices) is returned.
+pub(crate) fn rename(old: &str, new: &str, cred: &Credentials) -> AxResult {
+    if old.is_empty() || new.is_empty() {
+        return ax_err!(NotFound);
+    }
+    let is_dot = |path: &str| {
+        matches!(
+            path.trim_end_matches('/').rsplit('/').next(),
+            Some("." | "..")
+        )
+    };
+    if is_dot(old) || is_dot(new) {
+        return ax_err!(InvalidInput);
+    }
+    let old = resolve(None, old, false, cred)?;
+    let new = resolve(None, new, false, cred)?;
+    check_modify(&old.0, cred)?;
+    check_modify(&new.0, cred)?;
+    let old_path = old.0.abs_path(&old.1).ok_or(AxError::BadState)?;
+    let new_path = new.0.abs_path(&new.1).ok_or(AxError::BadState)?;
+    if ROOT_DIR.has_mount_points_under(&old_path) {
+        return ax_err!(ResourceBusy, "cannot rename directories with mount points");
     }
-    parent_node_of(None, old).rename(old, new)
+    ROOT_DIR.rename(&old_path, &new_path)
 }
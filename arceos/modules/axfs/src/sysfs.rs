@@ -0,0 +1,67 @@
+//! Kernel attributes exposed in sysfs (mounted on `/sys`).
+//!
+//! Other modules register attributes with a getter and an optional setter,
+//! which are called when the attribute file is read or written respectively.
+//! This allows kernel tunables to be inspected and changed at runtime, e.g.:
+//!
+//! ```no_run
+//! use core::sync::atomic::{AtomicUsize, Ordering};
+//!
+//! static VALUE: AtomicUsize = AtomicUsize::new(0);
+//!
+//! axfs::sysfs::register_attr_rw(
+//!     "kernel/foo/value",
+//!     || format!("{}\n", VALUE.load(Ordering::Relaxed)),
+//!     |s| {
+//!         let v = s.parse().map_err(|_| axerrno::AxError::InvalidInput)?;
+//!         VALUE.store(v, Ordering::Relaxed);
+//!         Ok(())
+//!     },
+//! )
+//! .unwrap();
+//! ```
+
+use alloc::{string::String, sync::Arc};
+use axerrno::{ax_err, AxResult};
+use axfs_vfs::VfsNodeRef;
+use lazyinit::LazyInit;
+
+use crate::fs::pseudofs::{PseudoFile, PseudoFileSystem};
+
+static SYSFS: LazyInit<Arc<PseudoFileSystem>> = LazyInit::new();
+
+pub(crate) fn init(sysfs: Arc<PseudoFileSystem>) -> Arc<PseudoFileSystem> {
+    SYSFS.init_once(sysfs).clone()
+}
+
+fn add_attr(path: &str, node: VfsNodeRef) -> AxResult {
+    let Some(sysfs) = SYSFS.get() else {
+        return ax_err!(BadState, "sysfs is not initialized");
+    };
+    sysfs.root_dir_node().add_path(path, node)
+}
+
+/// Registers a read-only attribute at `path` (relative to `/sys`), whose
+/// contents are produced by `getter` each time it is read.
+///
+/// Missing parent directories are created. Returns
+/// [`AlreadyExists`](axerrno::AxError::AlreadyExists) if `path` exists.
+pub fn register_attr(path: &str, getter: impl Fn() -> String + Send + Sync + 'static) -> AxResult {
+    add_attr(path, PseudoFile::new(getter))
+}
+
+/// Registers a writable attribute at `path` (relative to `/sys`).
+///
+/// The contents are produced by `getter` each time it is read, and the data
+/// written to it is passed to `setter` with leading and trailing whitespace
+/// removed. The error returned by `setter` is returned to the writer.
+///
+/// Missing parent directories are created. Returns
+/// [`AlreadyExists`](axerrno::AxError::AlreadyExists) if `path` exists.
+pub fn register_attr_rw(
+    path: &str,
+    getter: impl Fn() -> String + Send + Sync + 'static,
+    setter: impl Fn(&str) -> AxResult + Send + Sync + 'static,
+) -> AxResult {
+    add_attr(path, PseudoFile::new_rw(getter, setter))
+}
@@ -1,8 +1,13 @@
+use std::sync::Arc;
+
 use axfs::api as fs;
+use axfs::{cache, devfs};
+use axfs_devfs::ZeroDev;
+use axfs_ramfs::RamFileSystem;
 use axio as io;
 
 use fs::{File, FileType, OpenOptions};
-use io::{prelude::*, Error, Result};
+use io::{prelude::*, Error, Result, SeekFrom};
 
 macro_rules! assert_err {
     ($expr: expr) => {
@@ -168,6 +173,8 @@ fn test_devfs_ramfs() -> Result<()> {
     const N: usize = 32;
     let mut buf = [1; N];
 
+    devfs::register_device("foo/bar", Arc::new(ZeroDev))?;
+
     // list '/' and check if /dev and /tmp exist
     let dirents = fs::read_dir("././//.//")?
         .map(|e| e.unwrap().file_name())
@@ -192,6 +199,13 @@ fn test_devfs_ramfs() -> Result<()> {
     assert!(file.write_all(&buf).is_ok());
     assert_eq!(buf, [0; N]);
 
+    // no input on the console, a non-blocking read does not wait
+    let mut opts = axfs::fops::OpenOptions::new();
+    opts.read(true);
+    opts.nonblocking(true);
+    let mut console = axfs::fops::File::open("/dev/console", &opts)?;
+    assert_err!(console.read(&mut buf), WouldBlock);
+
     // list /dev
     let dirents = fs::read_dir("/dev")?
         .map(|e| e.unwrap().file_name())
@@ -252,6 +266,385 @@ fn test_devfs_ramfs() -> Result<()> {
     Ok(())
 }
 
+fn test_devfs() -> Result<()> {
+    let mut buf = [0; 32];
+
+    for fname in ["/dev/console", "/dev/tty", "/dev/random", "/dev/rtc"] {
+        assert_eq!(fs::metadata(fname)?.file_type(), FileType::CharDevice);
+    }
+
+    // random bytes never repeat in practice
+    let mut file = File::open("/dev/urandom")?;
+    assert_eq!(file.read(&mut buf)?, 32);
+    let mut buf2 = [0; 32];
+    assert_eq!(file.read(&mut buf2)?, 32);
+    assert_ne!(buf, buf2);
+    assert_eq!(fs::write("/dev/random", "entropy"), Ok(()));
+
+    let secs = fs::read_to_string("/dev/rtc")?;
+    assert!(secs.trim_end().parse::<u64>().is_ok());
+    assert_err!(fs::write("/dev/rtc", "0"), PermissionDenied);
+
+    // raw block device of the root filesystem
+    let md = fs::metadata("/dev/vda0")?;
+    assert_eq!(md.file_type(), FileType::BlockDevice);
+    let mut file = File::open("/dev/vda0")?;
+    let len = file.read(&mut buf)?;
+    assert_eq!(len as u64, md.len().min(32));
+    let mut file = File::open("/dev/vda0")?;
+    file.seek(SeekFrom::Start(md.len()))?;
+    assert_eq!(file.read(&mut buf)?, 0);
+
+    // device registration
+    devfs::register_device("/misc//zero2", Arc::new(ZeroDev))?;
+    assert_eq!(File::open("/dev/misc/zero2")?.read(&mut buf)?, 32);
+    assert_eq!(buf, [0; 32]);
+    assert_err!(
+        devfs::register_device("misc/zero2", Arc::new(ZeroDev)),
+        AlreadyExists
+    );
+    assert_err!(
+        devfs::register_device("null/x", Arc::new(ZeroDev)),
+        NotADirectory
+    );
+    assert_err!(
+        devfs::register_device("..", Arc::new(ZeroDev)),
+        InvalidInput
+    );
+    assert!(fs::read_to_string("/proc/mounts")?.contains("devtmpfs /dev devtmpfs "));
+
+    println!("test_devfs() OK!");
+    Ok(())
+}
+
+fn test_block_cache() -> Result<()> {
+    const BLOCK_SIZE: u64 = 512;
+    let old_capacity = cache::capacity();
+    assert!(!cache::set_capacity(0));
+    assert!(cache::set_capacity(4));
+    assert_eq!(fs::read_to_string("/sys/fs/block_cache/capacity")?, "4\n");
+
+    // use the last 8 blocks of the root device, and restore them at the end
+    let mut file = File::options().read(true).write(true).open("/dev/vda0")?;
+    let base = file.metadata()?.len() - 8 * BLOCK_SIZE;
+    let mut saved = vec![0; 8 * BLOCK_SIZE as usize];
+    file.seek(SeekFrom::Start(base))?;
+    file.read_exact(&mut saved)?;
+
+    let stats = cache::stats();
+    file.seek(SeekFrom::Start(base + BLOCK_SIZE - 3))?;
+    file.write_all(b"cached!")?; // across two blocks
+    file.seek(SeekFrom::Start(base + BLOCK_SIZE - 3))?;
+    let mut buf = [0; 7];
+    file.read_exact(&mut buf)?;
+    assert_eq!(&buf, b"cached!");
+    let new_stats = cache::stats();
+    assert!(new_stats.hits >= stats.hits + 2);
+    assert!(new_stats.cached <= 4);
+    assert!(new_stats.dirty >= 2);
+
+    // evict the dirty blocks, then read them back from the device
+    for i in 2..8 {
+        file.seek(SeekFrom::Start(base + i * BLOCK_SIZE))?;
+        file.read_exact(&mut buf)?;
+    }
+    assert!(cache::stats().misses >= new_stats.misses + 6);
+    file.seek(SeekFrom::Start(base + BLOCK_SIZE - 3))?;
+    file.read_exact(&mut buf)?;
+    assert_eq!(&buf, b"cached!");
+
+    file.seek(SeekFrom::Start(base))?;
+    file.write_all(&saved)?;
+    file.flush()?;
+    assert_eq!(cache::stats().dirty, 0);
+    cache::drop_all()?;
+    assert_eq!(cache::stats().cached, 0);
+    file.seek(SeekFrom::Start(base))?;
+    file.read_exact(&mut buf)?;
+    assert_eq!(buf, saved[..7]);
+    let stats = fs::read_to_string("/sys/fs/block_cache/stats")?;
+    assert!(stats.starts_with("hits "));
+    assert!(stats.contains("\ndirty 0\n"));
+
+    assert_err!(fs::write("/sys/fs/block_cache/capacity", "0"), InvalidInput);
+    assert_eq!(
+        fs::write("/sys/fs/block_cache/capacity", format!("{}", old_capacity)),
+        Ok(())
+    );
+    assert_eq!(cache::capacity(), old_capacity);
+
+    println!("test_block_cache() OK!");
+    Ok(())
+}
+
+fn test_mount_umount() -> Result<()> {
+    println!("test mount and umount:");
+
+    // nested mount points
+    fs::mount("/mnt", Arc::new(RamFileSystem::new()))?;
+    fs::mount("/mnt//usb/", Arc::new(RamFileSystem::new()))?;
+    assert_err!(
+        fs::mount("/mnt/usb", Arc::new(RamFileSystem::new())),
+        InvalidInput
+    );
+    assert_err!(fs::mount("/", Arc::new(RamFileSystem::new())), InvalidInput);
+    assert_err!(
+        fs::mount("mnt2", Arc::new(RamFileSystem::new())),
+        InvalidInput
+    );
+    assert_eq!(fs::write("/mnt/a.txt", "mnt"), Ok(()));
+    assert_eq!(fs::write("/mnt/./usb//b.txt", "usb"), Ok(()));
+    let dirents = fs::read_dir("/mnt")?
+        .map(|e| e.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert!(dirents.contains(&"a.txt".into()));
+    assert!(dirents.contains(&"usb".into()));
+    assert_err!(fs::metadata("/mnt/b.txt"), NotFound);
+    assert_err!(fs::metadata("/mnt/usb/a.txt"), NotFound);
+    assert_eq!(fs::read_to_string(".//mnt/usb/b.txt")?, "usb");
+
+    // mount points are matched by path components
+    assert_eq!(fs::create_dir("/tmpfoo"), Ok(()));
+    assert_eq!(fs::write("/tmpfoo/c.txt", "c"), Ok(()));
+    assert_err!(fs::metadata("/tmp/c.txt"), NotFound);
+    assert_eq!(fs::remove_file("/tmpfoo/c.txt"), Ok(()));
+    assert_eq!(fs::remove_dir("/tmpfoo"), Ok(()));
+
+    // busy filesystems cannot be unmounted
+    assert_err!(fs::umount("/mnt"), ResourceBusy);
+    let file = File::open("/mnt/usb/b.txt")?;
+    assert_err!(fs::umount("/mnt/usb"), ResourceBusy);
+    drop(file);
+    fs::set_current_dir("/mnt/usb")?;
+    assert_err!(fs::umount("/mnt/usb"), ResourceBusy);
+    fs::set_current_dir("/")?;
+
+    assert_eq!(fs::umount("/mnt/usb/"), Ok(()));
+    assert_err!(fs::metadata("/mnt/usb/b.txt"), NotFound);
+    assert_eq!(fs::read_to_string("/mnt/a.txt")?, "mnt");
+    assert_eq!(fs::umount("/mnt"), Ok(()));
+    assert_err!(fs::metadata("/mnt/a.txt"), NotFound);
+    assert_err!(fs::umount("/mnt"), InvalidInput);
+    assert_err!(fs::umount("/"), PermissionDenied);
+    assert_eq!(fs::remove_dir("/mnt"), Ok(()));
+
+    println!("test_mount_umount() OK!");
+    Ok(())
+}
+
+fn test_symlink_hardlink() -> Result<()> {
+    println!("test symbolic and hard links:");
+
+    // symbolic links
+    fs::create_dir("/tmp/dir")?;
+    fs::write("/tmp/dir/file.txt", "link")?;
+    fs::symlink("dir/file.txt", "/tmp/rel-link")?;
+    fs::symlink("/tmp/dir", "/tmp/abs-link")?;
+    fs::symlink("/very/long", "/tmp/fat-link")?;
+    assert_eq!(fs::read_link("/tmp/rel-link")?, "dir/file.txt");
+    assert_eq!(fs::read_to_string("/tmp/rel-link")?, "link");
+    assert_eq!(fs::read_to_string("tmp/abs-link//file.txt")?, "link");
+    assert!(fs::metadata("/tmp/abs-link")?.is_dir());
+    assert!(fs::symlink_metadata("/tmp/abs-link")?.is_symlink());
+    assert!(fs::read_dir("/tmp/fat-link/")?.count() > 0);
+    assert_err!(fs::read_link("/tmp/dir"), InvalidInput);
+    assert_err!(fs::symlink("dir", "/tmp/abs-link"), AlreadyExists);
+
+    // the current directory follows symbolic links
+    fs::set_current_dir("/tmp/abs-link")?;
+    assert_eq!(fs::current_dir()?, "/tmp/dir/");
+    assert_eq!(fs::read_to_string("../rel-link")?, "link");
+    fs::set_current_dir("/")?;
+
+    // dangling links and loops
+    fs::symlink("nothing", "/tmp/dangling")?;
+    assert_err!(fs::metadata("/tmp/dangling"), NotFound);
+    fs::write("/tmp/dangling", "created")?;
+    assert_eq!(fs::read_to_string("/tmp/nothing")?, "created");
+    fs::symlink("loop", "/tmp/loop")?;
+    assert_err!(fs::metadata("/tmp/loop"), FilesystemLoop);
+
+    // removing a link does not remove its target
+    assert_eq!(fs::remove_file("/tmp/abs-link"), Ok(()));
+    assert!(fs::metadata("/tmp/dir")?.is_dir());
+    for name in ["rel-link", "fat-link", "dangling", "nothing", "loop"] {
+        fs::remove_file(&format!("/tmp/{}", name))?;
+    }
+
+    // hard links
+    fs::hard_link("/tmp/dir/file.txt", "/tmp/hard.txt")?;
+    assert_eq!(fs::metadata("/tmp/hard.txt")?.nlink(), 2);
+    fs::write("/tmp/hard.txt", "changed")?;
+    assert_eq!(fs::read_to_string("/tmp/dir/file.txt")?, "changed");
+    fs::remove_file("/tmp/dir/file.txt")?;
+    assert_eq!(fs::metadata("/tmp/hard.txt")?.nlink(), 1);
+    assert_eq!(fs::read_to_string("/tmp/hard.txt")?, "changed");
+    assert_err!(fs::hard_link("/tmp/dir", "/tmp/dir2"), PermissionDenied);
+    assert_err!(fs::hard_link("/tmp/hard.txt", "/short.txt"), AlreadyExists);
+    assert_err!(fs::hard_link("/tmp/hard.txt", "/hard.txt"), CrossesDevices);
+    fs::remove_file("/tmp/hard.txt")?;
+    fs::remove_dir("/tmp/dir")?;
+
+    println!("test_symlink_hardlink() OK!");
+    Ok(())
+}
+
+fn test_rename() -> Result<()> {
+    println!("test rename:");
+
+    // move files and directories across directories
+    fs::create_dir("/tmp/a")?;
+    fs::create_dir("/tmp/b")?;
+    fs::write("/tmp/a/f1", "f1")?;
+    fs::write("/tmp/b/f2", "f2")?;
+    assert_eq!(fs::rename("/tmp/a/f1", "/tmp/b/x"), Ok(()));
+    assert_err!(fs::metadata("/tmp/a/f1"), NotFound);
+    assert_err!(fs::metadata("/tmp/a/x"), NotFound);
+    assert_eq!(fs::read_to_string("/tmp/b/x")?, "f1");
+    assert_eq!(fs::rename("/tmp/b/x", "tmp/b/f2"), Ok(()));
+    assert_eq!(fs::read_to_string("/tmp/b/f2")?, "f1");
+    assert_eq!(fs::rename("/tmp/b", "/tmp/a/b"), Ok(()));
+    assert_eq!(fs::read_to_string("/tmp/a/b/../b/f2")?, "f1");
+
+    // error cases
+    assert_err!(fs::rename("/tmp/a", "/tmp/a/b/a"), InvalidInput);
+    assert_err!(fs::rename("/tmp/a/b/.", "/tmp/c"), InvalidInput);
+    assert_err!(fs::rename("/tmp/a/b/f2", "/f2"), CrossesDevices);
+    assert_err!(fs::rename("/tmp", "/tmp2"), ResourceBusy);
+    fs::mount("/tmp/a/mnt", Arc::new(RamFileSystem::new()))?;
+    assert_err!(fs::rename("/tmp/a", "/tmp/c"), ResourceBusy);
+    fs::umount("/tmp/a/mnt")?;
+    fs::remove_dir("/tmp/a/mnt")?;
+
+    fs::remove_file("/tmp/a/b/f2")?;
+    fs::remove_dir("/tmp/a/b")?;
+    fs::remove_dir("/tmp/a")?;
+
+    println!("test_rename() OK!");
+    Ok(())
+}
+
+fn test_procfs() -> Result<()> {
+    println!("test procfs:");
+
+    let meminfo = fs::read_to_string("/proc/meminfo")?;
+    let keys = meminfo
+        .lines()
+        .map(|l| l.split(':').next().unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(keys, ["MemTotal", "MemFree", "MemAvailable"]);
+    assert!(meminfo.lines().all(|l| l.ends_with(" kB")));
+
+    let uptime = fs::read_to_string("/proc/uptime")?;
+    let (up, idle) = uptime.trim_end().split_once(' ').unwrap();
+    assert!(up.parse::<f64>().is_ok() && idle.parse::<f64>().is_ok());
+
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    assert!(mounts.lines().any(|l| l.starts_with("proc /proc proc ")));
+    assert!(mounts.lines().any(|l| l.starts_with("ramfs /tmp ramfs ")));
+    fs::mount("/mnt", Arc::new(RamFileSystem::new()))?;
+    assert!(fs::read_to_string("/proc/mounts")?.contains(" /mnt "));
+    fs::umount("/mnt")?;
+    assert!(!fs::read_to_string("/proc/mounts")?.contains(" /mnt "));
+    fs::remove_dir("/mnt")?;
+
+    assert!(fs::metadata("/proc/interrupts")?.is_file());
+    assert_eq!(
+        fs::read_to_string("/proc/sys/net/core/somaxconn")?,
+        "4096\n"
+    );
+    assert_eq!(fs::read_to_string("/proc/sys/vm/overcommit_memory")?, "0\n");
+
+    // read in small chunks
+    let mut file = File::open("/proc/meminfo")?;
+    let mut buf = [0; 7];
+    let mut content = Vec::new();
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        content.extend_from_slice(&buf[..n]);
+    }
+    assert!(content.starts_with(b"MemTotal:"));
+
+    // procfs is read-only
+    assert_err!(fs::write("/proc/meminfo", "0"), PermissionDenied);
+    assert_err!(fs::write("/proc/foo", "0"), PermissionDenied);
+    assert_err!(fs::create_dir("/proc/foo"));
+    assert_err!(fs::remove_file("/proc/uptime"));
+
+    let tid = fs::read_link("/proc/self")?;
+    let status = fs::read_to_string(&format!("/proc/{}/status", tid))?;
+    assert!(status.contains(&format!("Tid:\t{}\n", tid)));
+    assert_eq!(fs::read_to_string("/proc/self/status")?, status);
+    let stat = fs::read_to_string("/proc/self/stat")?;
+    assert!(stat.starts_with(&format!("{} (", tid)));
+    let dirents = fs::read_dir("/proc")?
+        .map(|e| e.unwrap().file_name())
+        .collect::<Vec<_>>();
+    assert!(dirents.contains(&tid));
+    assert!(dirents.contains(&"meminfo".into()));
+    assert!(fs::metadata("/proc/self/../meminfo")?.is_file());
+
+    println!("test_procfs() OK!");
+    Ok(())
+}
+
+fn test_sysfs() -> Result<()> {
+    use axfs::sysfs;
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    println!("test sysfs:");
+
+    static VALUE: AtomicUsize = AtomicUsize::new(10);
+    sysfs::register_attr_rw(
+        "/test/foo//value",
+        || format!("{}\n", VALUE.load(Ordering::Relaxed)),
+        |s| {
+            let v = s.parse().map_err(|_| Error::InvalidInput)?;
+            VALUE.store(v, Ordering::Relaxed);
+            Ok(())
+        },
+    )?;
+    sysfs::register_attr("test/foo/ro", || "ro\n".into())?;
+    assert_err!(
+        sysfs::register_attr("test/foo/ro", String::new),
+        AlreadyExists
+    );
+    assert_err!(
+        sysfs::register_attr("test/foo/ro/x", String::new),
+        NotADirectory
+    );
+    assert_err!(sysfs::register_attr("test/../x", String::new), InvalidInput);
+    assert_err!(sysfs::register_attr("/", String::new), InvalidInput);
+
+    assert_eq!(fs::read_to_string("/sys/test/foo/value")?, "10\n");
+    assert_eq!(fs::write("/sys/test/foo/value", "42\n"), Ok(()));
+    assert_eq!(VALUE.load(Ordering::Relaxed), 42);
+    assert_eq!(fs::read_to_string("/sys/test/foo/value")?, "42\n");
+    assert_err!(fs::write("/sys/test/foo/value", "abc"), InvalidInput);
+    assert_eq!(fs::read_to_string("/sys/test/foo/value")?, "42\n");
+
+    assert_eq!(fs::read_to_string("/sys/test/foo/ro")?, "ro\n");
+    assert_err!(fs::write("/sys/test/foo/ro", "x"), PermissionDenied);
+    assert!(fs::metadata("/sys/test/foo/value")?
+        .permissions()
+        .owner_writable());
+    assert!(!fs::metadata("/sys/test/foo/ro")?
+        .permissions()
+        .owner_writable());
+    assert!(fs::metadata("/sys/test/foo")?.is_dir());
+    assert_eq!(
+        fs::read_to_string("/sys/devices/system/clocksource/clocksource0/current_clocksource")?,
+        "tsc\n"
+    );
+    assert!(fs::read_to_string("/proc/mounts")?.contains("sysfs /sys sysfs "));
+
+    println!("test_sysfs() OK!");
+    Ok(())
+}
+
 pub fn test_all() {
     test_read_write_file().expect("test_read_write_file() failed");
     test_read_dir().expect("test_read_dir() failed");
@@ -259,4 +652,11 @@ pub fn test_all() {
     test_create_file_dir().expect("test_create_file_dir() failed");
     test_remove_file_dir().expect("test_remove_file_dir() failed");
     test_devfs_ramfs().expect("test_devfs_ramfs() failed");
+    test_devfs().expect("test_devfs() failed");
+    test_block_cache().expect("test_block_cache() failed");
+    test_mount_umount().expect("test_mount_umount() failed");
+    test_symlink_hardlink().expect("test_symlink_hardlink() failed");
+    test_rename().expect("test_rename() failed");
+    test_procfs().expect("test_procfs() failed");
+    test_sysfs().expect("test_sysfs() failed");
 }
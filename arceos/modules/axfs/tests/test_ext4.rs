@@ -0,0 +1,296 @@
+#![cfg(feature = "ext4fs")]
+
+mod test_common;
+
+use axdriver::AxDeviceContainer;
+use axdriver_block::ramdisk::RamDisk;
+use std::time::Duration;
+
+use axfs::api::{self as fs, File, Permissions};
+use axio::{Read, Result, Seek, SeekFrom, Write};
+
+const IMG_PATH: &str = "resources/ext4.img";
+const EXT2_IMG_PATH: &str = "resources/ext2.img";
+
+fn load_image(path: &str) -> std::io::Result<Vec<u8>> {
+    let path = std::env::current_dir()?.join(path);
+    println!("Loading disk image from {:?} ...", path);
+    let data = std::fs::read(path)?;
+    println!("size = {} bytes", data.len());
+    Ok(data)
+}
+
+/// Puts the images into the primary partitions of an MBR disk, aligned to
+/// 1 MiB as `fdisk` does.
+fn make_mbr_disk(images: &[&[u8]]) -> RamDisk {
+    const ALIGN: usize = 1 << 20;
+    let mut data = vec![0; ALIGN];
+    for (i, img) in images.iter().enumerate() {
+        let start = data.len() / 512;
+        let entry = &mut data[0x1be + i * 16..][..16];
+        entry[4] = 0x83; // Linux
+        entry[8..12].copy_from_slice(&(start as u32).to_le_bytes());
+        entry[12..16].copy_from_slice(&((img.len() / 512) as u32).to_le_bytes());
+        data.extend_from_slice(img);
+        data.resize(data.len().next_multiple_of(ALIGN), 0);
+    }
+    // an extended partition, whose logical partitions are not supported
+    data[0x1be + 3 * 16 + 4] = 0x05;
+    data[0x1be + 3 * 16 + 8..0x1be + 3 * 16 + 16].copy_from_slice(&[1, 0, 0, 0, 1, 0, 0, 0]);
+    data[510..512].copy_from_slice(&[0x55, 0xaa]);
+    RamDisk::from(&data)
+}
+
+fn make_disk() -> std::io::Result<RamDisk> {
+    let (ext4, ext2) = (load_image(IMG_PATH)?, load_image(EXT2_IMG_PATH)?);
+    Ok(make_mbr_disk(&[&ext4, &ext2]))
+}
+
+fn test_partitions() -> Result<()> {
+    // the first partition is used as the root filesystem
+    assert_eq!(fs::metadata("/dev/vda0p1")?.len(), 8 << 20);
+    assert_eq!(fs::metadata("/dev/vda0p2")?.len(), 4 << 20);
+    assert_eq!(fs::metadata("/dev/vda0")?.len(), 13 << 20);
+    assert!(fs::metadata("/dev/vda0p3").is_err());
+    assert!(fs::metadata("/dev/vda0p4").is_err());
+
+    // partitions are views of the device
+    let mut part = File::open("/dev/vda0p1")?;
+    let mut dev = File::open("/dev/vda0")?;
+    let (mut buf1, mut buf2) = ([0; 512], [0; 512]);
+    part.seek(SeekFrom::Start(1024))?; // the superblock
+    part.read_exact(&mut buf1)?;
+    dev.seek(SeekFrom::Start((1 << 20) + 1024))?;
+    dev.read_exact(&mut buf2)?;
+    assert_eq!(buf1, buf2);
+    assert_eq!(&buf1[0x38..0x3a], &[0x53, 0xef]); // the ext4 magic
+
+    println!("test_partitions() OK!");
+    Ok(())
+}
+
+fn test_inode_attrs() -> Result<()> {
+    let short = fs::metadata("/short.txt")?;
+    let long = fs::metadata("/long.txt")?;
+    assert!(short.ino() > 2 && long.ino() > 2);
+    assert_ne!(short.ino(), long.ino());
+    assert_eq!(fs::metadata("/")?.ino(), 2);
+    assert_eq!(long.len(), 14000);
+    assert!(long.blocks() >= 14000 / 512);
+    assert_eq!(fs::metadata("/very")?.nlink(), 3); // `.`, `..` of `long` and the entry in `/`
+    assert_eq!(fs::read_link("/short-link")?, "very/long/path/test.txt");
+    assert!(fs::read_to_string("/short-link")?.starts_with("Rust is cool!\n"));
+
+    fs::hard_link("/long.txt", "/very/long.txt")?;
+    assert_eq!(fs::metadata("/very/long.txt")?.ino(), long.ino());
+    assert_eq!(fs::metadata("/long.txt")?.nlink(), 2);
+    fs::remove_file("/long.txt")?;
+    assert_eq!(fs::metadata("/very/long.txt")?.nlink(), 1);
+    fs::rename("/very/long.txt", "/long.txt")?;
+    assert_eq!(fs::read("/long.txt")?.len(), 14000);
+
+    let target = "very/long/path/".repeat(5) + "test.txt"; // stored in a block
+    fs::symlink(&target, "/long-link")?;
+    assert_eq!(fs::read_link("/long-link")?, target);
+    assert!(fs::symlink_metadata("/long-link")?.blocks() > 0);
+    fs::remove_file("/long-link")?;
+
+    println!("test_inode_attrs() OK!");
+    Ok(())
+}
+
+fn test_sparse_file() -> Result<()> {
+    const BLOCK: u64 = 1024;
+    let fname = "/sparse.bin";
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open(fname)?;
+    // every other block, so that each block is a separate extent
+    for i in 0..64 {
+        file.seek(SeekFrom::Start(i * 2 * BLOCK + 7))?;
+        file.write_all(&[i as u8 + 1; 3])?;
+    }
+    assert_eq!(file.metadata()?.len(), 126 * BLOCK + 10);
+    for i in 0..64 {
+        let mut buf = [0xff; 5];
+        file.seek(SeekFrom::Start(i * 2 * BLOCK + 5))?;
+        file.read_exact(&mut buf)?;
+        let v = i as u8 + 1;
+        assert_eq!(buf, [0, 0, v, v, v]);
+    }
+    let mut hole = vec![0xff; BLOCK as usize];
+    file.seek(SeekFrom::Start(BLOCK))?;
+    file.read_exact(&mut hole)?;
+    assert!(hole.iter().all(|&b| b == 0));
+
+    // shrink and extend again
+    file.set_len(10 * BLOCK + 8)?;
+    assert_eq!(file.metadata()?.len(), 10 * BLOCK + 8);
+    file.set_len(12 * BLOCK)?;
+    let mut buf = [0xff; 4];
+    file.seek(SeekFrom::Start(10 * BLOCK + 7))?;
+    file.read_exact(&mut buf)?;
+    assert_eq!(buf, [6, 0, 0, 0]);
+    drop(file);
+
+    let blocks = fs::metadata(fname)?.blocks();
+    fs::remove_file(fname)?;
+    assert!(blocks > 0);
+
+    println!("test_sparse_file() OK!");
+    Ok(())
+}
+
+fn test_statfs_attrs() -> Result<()> {
+    let info = fs::statfs("/")?;
+    assert_eq!((info.block_size, info.blocks), (1024, 8192));
+    assert!(info.blocks_free > 0 && info.blocks_free < info.blocks);
+    assert!(info.files_free > 0 && info.files_free < info.files);
+    fs::write("/statfs.txt", [1; 4096])?;
+    let new_info = fs::statfs("/")?;
+    assert!(new_info.blocks_free <= info.blocks_free - 4);
+    assert_eq!(new_info.files_free, info.files_free - 1);
+    fs::remove_file("/statfs.txt")?;
+    assert_eq!(fs::statfs("/")?.blocks_free, info.blocks_free);
+
+    let fname = "/short.txt";
+    fs::set_permissions(fname, Permissions::from_bits_truncate(0o640))?;
+    fs::chown(fname, Some(100_000), Some(0))?;
+    fs::set_times(fname, Some(Duration::from_secs(1)), None)?;
+    let meta = fs::metadata(fname)?;
+    assert_eq!(meta.permissions().bits(), 0o640);
+    assert!(meta.is_file());
+    assert_eq!((meta.uid(), meta.gid()), (100_000, 0));
+    assert_eq!(meta.accessed(), Duration::from_secs(1));
+    assert!(meta.changed() > Duration::from_secs(1));
+    File::open(fname)?.set_modified(Duration::from_secs(2))?;
+    assert_eq!(fs::metadata(fname)?.modified(), Duration::from_secs(2));
+    fs::set_permissions(fname, Permissions::from_bits_truncate(0o644))?;
+    fs::chown(fname, Some(0), None)?;
+
+    println!("test_statfs_attrs() OK!");
+    Ok(())
+}
+
+fn test_remove_opened() -> Result<()> {
+    let files_free = fs::statfs("/")?.files_free;
+    let mut file = File::options()
+        .read(true)
+        .write(true)
+        .create_new(true)
+        .open("/opened.txt")?;
+    file.write_all(b"still here")?;
+    fs::remove_file("/opened.txt")?;
+    assert!(fs::metadata("/opened.txt").is_err());
+    assert_eq!(file.metadata()?.nlink(), 0);
+
+    // the inode is not reused until the file is closed
+    fs::write("/other.txt", "other")?;
+    assert_ne!(fs::metadata("/other.txt")?.ino(), file.metadata()?.ino());
+    file.write_all(b" and there")?;
+    let mut buf = String::new();
+    file.seek(SeekFrom::Start(0))?;
+    file.read_to_string(&mut buf)?;
+    assert_eq!(buf, "still here and there");
+    assert_eq!(fs::read_to_string("/other.txt")?, "other");
+    assert_eq!(fs::statfs("/")?.files_free, files_free - 2);
+    drop(file);
+    assert_eq!(fs::statfs("/")?.files_free, files_free - 1);
+    fs::remove_file("/other.txt")?;
+
+    // the same for directories
+    fs::create_dir("/opened")?;
+    let dir = File::open("/opened")?;
+    fs::remove_dir("/opened")?;
+    assert!(fs::write("/opened/new.txt", "new").is_err());
+    assert_eq!(fs::statfs("/")?.files_free, files_free - 1);
+    drop(dir);
+    assert_eq!(fs::statfs("/")?.files_free, files_free);
+
+    println!("test_remove_opened() OK!");
+    Ok(())
+}
+
+/// Tests ext2 on the second partition, whose files are mapped by block maps
+/// instead of extent trees.
+fn test_ext2() -> Result<()> {
+    fs::mount_device("vda0p2", "/ext2", None, false)?;
+    assert!(fs::read_to_string("/proc/mounts")?.contains("ext2 /ext2 ext2 rw "));
+    assert_eq!(fs::statfs("/ext2")?.blocks, 4096);
+    assert!(fs::read_to_string("/ext2/very/long/path/test.txt")?.starts_with("Rust is cool!\n"));
+    assert_eq!(fs::read("/ext2/long.txt")?.len(), 14000);
+
+    // with 1 KiB blocks, the file needs double indirect blocks
+    let blocks_free = fs::statfs("/ext2")?.blocks_free;
+    let data = (0..300 * 1024).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+    fs::write("/ext2/big.bin", &data)?;
+    assert_eq!(fs::read("/ext2/big.bin")?, data);
+    let meta = fs::metadata("/ext2/big.bin")?;
+    assert!(meta.blocks() > 300 * 2);
+    let file = File::options().write(true).open("/ext2/big.bin")?;
+    file.set_len(20 * 1024)?;
+    drop(file);
+    assert_eq!(fs::read("/ext2/big.bin")?, &data[..20 * 1024]);
+    fs::remove_file("/ext2/big.bin")?;
+    assert_eq!(fs::statfs("/ext2")?.blocks_free, blocks_free);
+
+    fs::umount("/ext2")?;
+    fs::remove_dir("/ext2")?;
+
+    println!("test_ext2() OK!");
+    Ok(())
+}
+
+fn test_large_dir() -> Result<()> {
+    fs::create_dir("/many")?;
+    for i in 0..200 {
+        fs::write(
+            &format!("/many/file-with-a-long-name-{:03}", i),
+            format!("{}", i),
+        )?;
+    }
+    assert!(fs::metadata("/many")?.len() > 1024);
+    assert_eq!(fs::read_dir("/many")?.count(), 200);
+    assert_eq!(
+        fs::read_to_string("/many/file-with-a-long-name-123")?,
+        "123"
+    );
+    for i in (0..200).step_by(2) {
+        fs::remove_file(&format!("/many/file-with-a-long-name-{:03}", i))?;
+    }
+    assert_eq!(fs::read_dir("/many")?.count(), 100);
+    assert_eq!(
+        fs::remove_dir("/many").err(),
+        Some(axio::Error::DirectoryNotEmpty)
+    );
+    for i in (1..200).step_by(2) {
+        fs::remove_file(&format!("/many/file-with-a-long-name-{:03}", i))?;
+    }
+    fs::remove_dir("/many")?;
+
+    println!("test_large_dir() OK!");
+    Ok(())
+}
+
+#[test]
+fn test_ext4() {
+    println!("Testing ext4 with ramdisk ...");
+
+    let disk = make_disk().expect("failed to load disk image");
+    axtask::init_scheduler(); // call this to use `axsync::Mutex`.
+    axfs::init_filesystems(AxDeviceContainer::from_one(disk));
+    assert!(fs::read_to_string("/proc/mounts")
+        .unwrap()
+        .contains(" / ext4 "));
+
+    test_common::test_all();
+    test_partitions().expect("test_partitions() failed");
+    test_inode_attrs().expect("test_inode_attrs() failed");
+    test_sparse_file().expect("test_sparse_file() failed");
+    test_large_dir().expect("test_large_dir() failed");
+    test_statfs_attrs().expect("test_statfs_attrs() failed");
+    test_remove_opened().expect("test_remove_opened() failed");
+    test_ext2().expect("test_ext2() failed");
+}
@@ -1,9 +1,13 @@
-#![cfg(not(feature = "myfs"))]
+#![cfg(feature = "fatfs")]
 
 mod test_common;
 
+use std::time::Duration;
+
 use axdriver::AxDeviceContainer;
 use axdriver_block::ramdisk::RamDisk;
+use axfs::api as fs;
+use axio::Result;
 
 const IMG_PATH: &str = "resources/fat16.img";
 
@@ -24,4 +28,32 @@ fn test_fatfs() {
     axfs::init_filesystems(AxDeviceContainer::from_one(disk));
 
     test_common::test_all();
+    test_fat_features().expect("test_fat_features() failed");
+}
+
+fn test_fat_features() -> Result<()> {
+    let fname = "/fat-features.txt";
+    let info = fs::statfs("/")?;
+    assert!(info.blocks > 0 && info.blocks_free <= info.blocks);
+    fs::write(fname, vec![0xa5; 64 * 1024])?;
+    assert!(fs::statfs("/")?.blocks_free < info.blocks_free);
+
+    // the modification time has a granularity of 2 seconds
+    let mtime = Duration::from_secs(1_000_000_000);
+    fs::set_times(fname, None, Some(mtime))?;
+    assert_eq!(fs::metadata(fname)?.modified(), mtime);
+    fs::set_times(fname, None, Some(mtime + Duration::from_secs(1)))?;
+    assert_eq!(fs::metadata(fname)?.modified(), mtime);
+    // times before 1980 are clamped
+    fs::set_times(fname, None, Some(Duration::ZERO))?;
+    assert_eq!(fs::metadata(fname)?.modified().as_secs(), 315_532_800);
+
+    let label = fs::volume_label("/")?.expect("no volume label");
+    assert!(label.eq_ignore_ascii_case("Test!"));
+
+    fs::sync()?;
+    assert_eq!(fs::read(fname)?.len(), 64 * 1024);
+    fs::remove_file(fname)?;
+    assert_eq!(fs::statfs("/")?.blocks_free, info.blocks_free);
+    Ok(())
 }
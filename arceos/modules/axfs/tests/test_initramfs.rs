@@ -0,0 +1,97 @@
+#![cfg(feature = "initramfs")]
+
+use axdriver::AxDeviceContainer;
+use axfs::api as fs;
+use axfs_ramfs::RamFileSystem;
+use axfs_vfs::VfsOps;
+use axio::Result;
+
+const ARCHIVE_PATH: &str = "resources/initramfs.img";
+
+fn load_archive() -> std::io::Result<&'static [u8]> {
+    let path = std::env::current_dir()?.join(ARCHIVE_PATH);
+    println!("Loading initramfs from {:?} ...", path);
+    let data = std::fs::read(path)?;
+    println!("size = {} bytes", data.len());
+    Ok(data.leak())
+}
+
+fn test_unpacked_files() -> Result<()> {
+    assert_eq!(
+        fs::read_to_string("/long.txt")?,
+        "Rust is cool!\n".repeat(1000)
+    );
+    assert_eq!(fs::read_to_string("/short.txt")?, "Rust is cool!\n");
+    assert_eq!(
+        fs::read_to_string("/very-long-dir-name/very-long-file-name.txt")?,
+        "Rust is cool!\n"
+    );
+    assert_eq!(fs::read_link("/short-link")?, "very/long/path/test.txt");
+    assert_eq!(fs::read_to_string("/short-link")?, "Rust is cool!\n");
+
+    // hard links share the data in the last entry of them
+    assert_eq!(
+        fs::read_to_string("/very/short-hard-link.txt")?,
+        "Rust is cool!\n"
+    );
+    assert_eq!(fs::metadata("/short.txt")?.nlink(), 2);
+    fs::write("/short.txt", "Hello, initramfs!\n")?;
+    assert_eq!(
+        fs::read_to_string("/very/short-hard-link.txt")?,
+        "Hello, initramfs!\n"
+    );
+
+    // FIFOs are skipped
+    assert_eq!(fs::metadata("/fifo").err(), Some(axio::Error::NotFound));
+
+    println!("test_unpacked_files() OK!");
+    Ok(())
+}
+
+fn test_root_dir() -> Result<()> {
+    let mounts = fs::read_to_string("/proc/mounts")?;
+    assert!(mounts.starts_with("ramfs / ramfs "));
+    assert_eq!(
+        fs::metadata("/dev/null")?.file_type(),
+        fs::FileType::CharDevice
+    );
+    assert!(fs::metadata("/dev/vda0").is_err());
+
+    fs::create_dir("/new-dir")?;
+    fs::write("/new-dir/new-file.txt", "new file")?;
+    assert_eq!(fs::read_to_string("/new-dir/new-file.txt")?, "new file");
+    fs::remove_file("/new-dir/new-file.txt")?;
+    fs::remove_dir("/new-dir")?;
+
+    println!("test_root_dir() OK!");
+    Ok(())
+}
+
+fn test_bad_archive() -> Result<()> {
+    let root = RamFileSystem::new().root_dir();
+    let archive = load_archive().unwrap();
+    assert_eq!(
+        axfs::initramfs::unpack(&archive[..200], &root).err(),
+        Some(axio::Error::InvalidData)
+    );
+    assert_eq!(
+        axfs::initramfs::unpack(&archive[4..], &root).err(),
+        Some(axio::Error::InvalidData)
+    );
+
+    println!("test_bad_archive() OK!");
+    Ok(())
+}
+
+#[test]
+fn test_initramfs() {
+    println!("Testing initramfs without block devices ...");
+
+    axfs::initramfs::set_archive(load_archive().expect("failed to load the archive"));
+    axtask::init_scheduler(); // call this to use `axsync::Mutex`.
+    axfs::init_filesystems(AxDeviceContainer::default());
+
+    test_unpacked_files().expect("test_unpacked_files() failed");
+    test_root_dir().expect("test_root_dir() failed");
+    test_bad_archive().expect("test_bad_archive() failed");
+}
@@ -0,0 +1,514 @@
+#![cfg(feature = "ninepfs")]
+
+mod test_common;
+
+use std::collections::HashMap;
+use std::fs as host;
+use std::io::ErrorKind;
+use std::os::unix::fs::{DirBuilderExt, FileExt, MetadataExt, OpenOptionsExt};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+
+use axdriver::prelude::{BaseDriverOps, DevResult, DeviceType, NinePDriverOps};
+use axdriver::AxDeviceContainer;
+use axdriver_block::ramdisk::RamDisk;
+use axfs::api::{self as fs, File, Permissions};
+use axfs::fops::register_ninep_device;
+use axio::{Result, Write};
+
+/// A 9P2000.L server exporting a directory of the host, which handles the
+/// requests synchronously as a 9P transport.
+struct MockServer {
+    tag: &'static str,
+    root: PathBuf,
+    fids: HashMap<u32, FidState>,
+}
+
+struct FidState {
+    path: PathBuf,
+    file: Option<host::File>,
+}
+
+/// A reader of the fields of a request message.
+struct Fields<'a>(&'a [u8]);
+
+impl<'a> Fields<'a> {
+    fn bytes(&mut self, len: usize) -> &'a [u8] {
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+        bytes
+    }
+
+    fn u8(&mut self) -> u8 {
+        self.bytes(1)[0]
+    }
+
+    fn u16(&mut self) -> u16 {
+        u16::from_le_bytes(self.bytes(2).try_into().unwrap())
+    }
+
+    fn u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.bytes(4).try_into().unwrap())
+    }
+
+    fn u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.bytes(8).try_into().unwrap())
+    }
+
+    fn str(&mut self) -> &'a str {
+        let len = self.u16() as usize;
+        std::str::from_utf8(self.bytes(len)).unwrap()
+    }
+}
+
+fn put_str(out: &mut Vec<u8>, s: &str) {
+    out.extend_from_slice(&(s.len() as u16).to_le_bytes());
+    out.extend_from_slice(s.as_bytes());
+}
+
+fn put_qid(out: &mut Vec<u8>, path: &Path) -> std::io::Result<()> {
+    let meta = host::symlink_metadata(path)?;
+    let ty = if meta.is_dir() {
+        0x80
+    } else if meta.is_symlink() {
+        0x02
+    } else {
+        0
+    };
+    out.push(ty);
+    out.extend_from_slice(&0u32.to_le_bytes());
+    out.extend_from_slice(&meta.ino().to_le_bytes());
+    Ok(())
+}
+
+fn put_time(out: &mut Vec<u8>, secs: i64, nsecs: i64) {
+    out.extend_from_slice(&(secs as u64).to_le_bytes());
+    out.extend_from_slice(&(nsecs as u64).to_le_bytes());
+}
+
+fn to_time(secs: u64, nsecs: u64) -> SystemTime {
+    SystemTime::UNIX_EPOCH + Duration::new(secs, nsecs as u32)
+}
+
+impl MockServer {
+    fn new(tag: &'static str) -> Self {
+        let root = std::env::temp_dir().join(format!("axfs-9p-{}-{}", tag, std::process::id()));
+        let _ = host::remove_dir_all(&root);
+        host::create_dir_all(&root).unwrap();
+        Self {
+            tag,
+            root,
+            fids: HashMap::new(),
+        }
+    }
+
+    fn path(&self, fid: u32) -> std::io::Result<PathBuf> {
+        match self.fids.get(&fid) {
+            Some(state) => Ok(state.path.clone()),
+            None => Err(ErrorKind::InvalidInput.into()),
+        }
+    }
+
+    fn file(&self, fid: u32) -> std::io::Result<&host::File> {
+        match self.fids.get(&fid).and_then(|s| s.file.as_ref()) {
+            Some(file) => Ok(file),
+            None => Err(ErrorKind::InvalidInput.into()),
+        }
+    }
+
+    /// Handles the request of `ty` with the fields `req`, and writes the fields
+    /// of its reply to `out`.
+    fn handle(&mut self, ty: u8, mut req: Fields, out: &mut Vec<u8>) -> std::io::Result<()> {
+        match ty {
+            // Tversion
+            100 => {
+                let msize = req.u32().min(8192 + 24);
+                out.extend_from_slice(&msize.to_le_bytes());
+                put_str(out, req.str());
+            }
+            // Tattach
+            104 => {
+                let fid = req.u32();
+                put_qid(out, &self.root)?;
+                let path = self.root.clone();
+                self.fids.insert(fid, FidState { path, file: None });
+            }
+            // Twalk
+            110 => {
+                let (fid, new_fid) = (req.u32(), req.u32());
+                let mut path = self.path(fid)?;
+                let count = req.u16();
+                let mut qids = Vec::new();
+                for i in 0..count {
+                    let name = req.str();
+                    let next = match name {
+                        ".." if path == self.root => path.clone(),
+                        ".." => path.parent().unwrap().to_path_buf(),
+                        _ => path.join(name),
+                    };
+                    if let Err(e) = put_qid(&mut qids, &next) {
+                        if i == 0 {
+                            return Err(e);
+                        }
+                        out.extend_from_slice(&i.to_le_bytes());
+                        out.extend_from_slice(&qids);
+                        return Ok(());
+                    }
+                    path = next;
+                }
+                out.extend_from_slice(&count.to_le_bytes());
+                out.extend_from_slice(&qids);
+                self.fids.insert(new_fid, FidState { path, file: None });
+            }
+            // Tlopen
+            12 => {
+                let (fid, flags) = (req.u32(), req.u32());
+                let path = self.path(fid)?;
+                put_qid(out, &path)?;
+                out.extend_from_slice(&0u32.to_le_bytes());
+                if !path.is_dir() {
+                    let file = host::OpenOptions::new()
+                        .read(flags & 3 != 1)
+                        .write(flags & 3 != 0)
+                        .open(&path)?;
+                    self.fids.get_mut(&fid).unwrap().file = Some(file);
+                }
+            }
+            // Tlcreate
+            14 => {
+                let (fid, name, _flags, mode) = (req.u32(), req.str(), req.u32(), req.u32());
+                let path = self.path(fid)?.join(name);
+                let file = host::OpenOptions::new()
+                    .write(true)
+                    .create_new(true)
+                    .mode(mode)
+                    .open(&path)?;
+                put_qid(out, &path)?;
+                out.extend_from_slice(&0u32.to_le_bytes());
+                let file = Some(file);
+                self.fids.insert(fid, FidState { path, file });
+            }
+            // Tmkdir
+            72 => {
+                let (fid, name, mode) = (req.u32(), req.str(), req.u32());
+                let path = self.path(fid)?.join(name);
+                host::DirBuilder::new().mode(mode).create(&path)?;
+                put_qid(out, &path)?;
+            }
+            // Tsymlink
+            16 => {
+                let (fid, name, target) = (req.u32(), req.str(), req.str());
+                let path = self.path(fid)?.join(name);
+                std::os::unix::fs::symlink(target, &path)?;
+                put_qid(out, &path)?;
+            }
+            // Tlink
+            70 => {
+                let (dir_fid, fid, name) = (req.u32(), req.u32(), req.str());
+                host::hard_link(self.path(fid)?, self.path(dir_fid)?.join(name))?;
+            }
+            // Trenameat
+            74 => {
+                let (fid, name, new_fid, new_name) = (req.u32(), req.str(), req.u32(), req.str());
+                let new_path = self.path(new_fid)?.join(new_name);
+                host::rename(self.path(fid)?.join(name), new_path)?;
+            }
+            // Tunlinkat
+            76 => {
+                let (fid, name, flags) = (req.u32(), req.str(), req.u32());
+                let path = self.path(fid)?.join(name);
+                if flags & 0x200 != 0 {
+                    host::remove_dir(path)?;
+                } else {
+                    host::remove_file(path)?;
+                }
+            }
+            // Treadlink
+            22 => {
+                let target = host::read_link(self.path(req.u32())?)?;
+                put_str(out, target.to_str().unwrap());
+            }
+            // Tgetattr
+            24 => {
+                let path = self.path(req.u32())?;
+                let meta = host::symlink_metadata(&path)?;
+                out.extend_from_slice(&0x7ffu64.to_le_bytes());
+                put_qid(out, &path)?;
+                for v in [meta.mode(), meta.uid(), meta.gid()] {
+                    out.extend_from_slice(&v.to_le_bytes());
+                }
+                let fields = [meta.nlink(), meta.rdev(), meta.size()];
+                for v in fields.into_iter().chain([meta.blksize(), meta.blocks()]) {
+                    out.extend_from_slice(&v.to_le_bytes());
+                }
+                put_time(out, meta.atime(), meta.atime_nsec());
+                put_time(out, meta.mtime(), meta.mtime_nsec());
+                put_time(out, meta.ctime(), meta.ctime_nsec());
+            }
+            // Tsetattr
+            26 => {
+                let path = self.path(req.u32())?;
+                let valid = req.u32();
+                let (mode, uid, gid, size) = (req.u32(), req.u32(), req.u32(), req.u64());
+                let atime = to_time(req.u64(), req.u64());
+                let mtime = to_time(req.u64(), req.u64());
+                if valid & 0x1 != 0 {
+                    let perm = std::os::unix::fs::PermissionsExt::from_mode(mode);
+                    host::set_permissions(&path, perm)?;
+                }
+                if valid & 0x6 != 0 {
+                    let uid = (valid & 0x2 != 0).then_some(uid);
+                    let gid = (valid & 0x4 != 0).then_some(gid);
+                    std::os::unix::fs::lchown(&path, uid, gid)?;
+                }
+                if valid & 0x8 != 0 {
+                    host::OpenOptions::new()
+                        .write(true)
+                        .open(&path)?
+                        .set_len(size)?;
+                }
+                if valid & 0x30 != 0 {
+                    let mut times = host::FileTimes::new();
+                    if valid & 0x10 != 0 {
+                        times = times.set_accessed(atime);
+                    }
+                    if valid & 0x20 != 0 {
+                        times = times.set_modified(mtime);
+                    }
+                    host::File::open(&path)?.set_times(times)?;
+                }
+            }
+            // Tread
+            116 => {
+                let (fid, offset, count) = (req.u32(), req.u64(), req.u32());
+                let mut buf = vec![0; count as usize];
+                let len = self.file(fid)?.read_at(&mut buf, offset)?;
+                out.extend_from_slice(&(len as u32).to_le_bytes());
+                out.extend_from_slice(&buf[..len]);
+            }
+            // Twrite
+            118 => {
+                let (fid, offset, count) = (req.u32(), req.u64(), req.u32());
+                let data = req.bytes(count as usize);
+                let len = self.file(fid)?.write_at(data, offset)?;
+                out.extend_from_slice(&(len as u32).to_le_bytes());
+            }
+            // Tfsync
+            50 => {
+                if let Ok(file) = self.file(req.u32()) {
+                    file.sync_all()?;
+                }
+            }
+            // Treaddir
+            40 => {
+                let (fid, offset, count) = (req.u32(), req.u64(), req.u32());
+                let dir = self.path(fid)?;
+                let mut names = vec![".".to_string(), "..".to_string()];
+                let mut entries = host::read_dir(&dir)?
+                    .map(|e| Ok(e?.file_name().into_string().unwrap()))
+                    .collect::<std::io::Result<Vec<_>>>()?;
+                entries.sort();
+                names.extend(entries);
+                let mut data = Vec::new();
+                for (i, name) in names.iter().enumerate().skip(offset as usize) {
+                    let path = match name.as_str() {
+                        "." => dir.clone(),
+                        ".." => dir.parent().unwrap().to_path_buf(),
+                        _ => dir.join(name),
+                    };
+                    let mut entry = Vec::new();
+                    put_qid(&mut entry, &path)?;
+                    entry.extend_from_slice(&(i as u64 + 1).to_le_bytes());
+                    let mode = host::symlink_metadata(&path)?.mode();
+                    entry.push((mode >> 12) as u8 & 0xf);
+                    put_str(&mut entry, name);
+                    if data.len() + entry.len() > count as usize {
+                        break;
+                    }
+                    data.extend_from_slice(&entry);
+                }
+                out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+                out.extend_from_slice(&data);
+            }
+            // Tstatfs
+            8 => {
+                out.extend_from_slice(&0x01021997u32.to_le_bytes()); // V9FS_MAGIC
+                out.extend_from_slice(&4096u32.to_le_bytes());
+                for v in [1000u64, 500, 400, 100, 50, 0] {
+                    out.extend_from_slice(&v.to_le_bytes());
+                }
+                out.extend_from_slice(&255u32.to_le_bytes());
+            }
+            // Tclunk
+            120 => {
+                self.fids.remove(&req.u32());
+            }
+            _ => return Err(ErrorKind::Unsupported.into()),
+        }
+        Ok(())
+    }
+}
+
+impl BaseDriverOps for MockServer {
+    fn device_name(&self) -> &str {
+        "mock-9p"
+    }
+
+    fn device_type(&self) -> DeviceType {
+        DeviceType::Char
+    }
+}
+
+impl NinePDriverOps for MockServer {
+    fn mount_tag(&self) -> &str {
+        self.tag
+    }
+
+    fn transact(&mut self, req: &[u8], resp: &mut [u8]) -> DevResult<usize> {
+        let mut fields = Fields(&req[4..]);
+        let (ty, tag) = (fields.u8(), fields.u16());
+        let mut out = Vec::new();
+        let reply_ty = match self.handle(ty, fields, &mut out) {
+            Ok(()) => ty + 1,
+            Err(e) => {
+                out.clear();
+                let ecode = e.raw_os_error().unwrap_or(5); // EIO
+                out.extend_from_slice(&(ecode as u32).to_le_bytes());
+                7 // Rlerror
+            }
+        };
+        let size = (out.len() + 7) as u32;
+        resp[..4].copy_from_slice(&size.to_le_bytes());
+        resp[4] = reply_ty;
+        resp[5..7].copy_from_slice(&tag.to_le_bytes());
+        resp[7..size as usize].copy_from_slice(&out);
+        Ok(size as usize)
+    }
+}
+
+fn create_init_files(root: &Path) -> std::io::Result<()> {
+    host::write(root.join("short.txt"), "Rust is cool!\n")?;
+    host::write(root.join("long.txt"), "Rust is cool!\n".repeat(100))?;
+    host::create_dir_all(root.join("very-long-dir-name"))?;
+    host::write(
+        root.join("very-long-dir-name/very-long-file-name.txt"),
+        "Rust is cool!\n",
+    )?;
+    host::create_dir_all(root.join("very/long/path"))?;
+    host::write(root.join("very/long/path/test.txt"), "Rust is cool!\n")?;
+    Ok(())
+}
+
+fn test_host_files(host_root: &Path) -> Result<()> {
+    // mounted automatically by the mount tag
+    assert!(fs::read_to_string("/proc/mounts")?.contains("9p /host 9p rw "));
+    host::write(host_root.join("from-host.txt"), "from host\n").unwrap();
+    assert_eq!(fs::read_to_string("/host/from-host.txt")?, "from host\n");
+    fs::write("/host/from-guest.txt", "from guest\n")?;
+    let data = host::read_to_string(host_root.join("from-guest.txt")).unwrap();
+    assert_eq!(data, "from guest\n");
+
+    // large files are split into multiple messages
+    let data = (0..20000).map(|i| i as u8).collect::<Vec<_>>();
+    let mut file = File::create_new("/host/large.bin")?;
+    file.write_all(&data)?;
+    drop(file);
+    assert_eq!(host::read(host_root.join("large.bin")).unwrap(), data);
+    assert_eq!(fs::read("/host/large.bin")?, data);
+
+    // directories with more entries than a message
+    fs::create_dir("/host/dir")?;
+    for i in 0..200 {
+        fs::write(&format!("/host/dir/file-with-a-long-name-{:03}", i), "")?;
+    }
+    let names = fs::read_dir("/host/dir")?
+        .map(|e| e.map(|e| e.file_name()))
+        .collect::<Result<Vec<_>>>()?;
+    assert_eq!(names.len(), 200);
+    assert!(names
+        .iter()
+        .enumerate()
+        .all(|(i, name)| name.ends_with(&format!("-{:03}", i))));
+    for name in names {
+        fs::remove_file(&format!("/host/dir/{}", name))?;
+    }
+
+    // attributes
+    fs::set_permissions(
+        "/host/from-guest.txt",
+        Permissions::from_bits_truncate(0o600),
+    )?;
+    fs::set_times("/host/from-guest.txt", None, Some(Duration::from_secs(1)))?;
+    let meta = fs::metadata("/host/from-guest.txt")?;
+    let host_meta = host::metadata(host_root.join("from-guest.txt")).unwrap();
+    assert_eq!(meta.permissions().bits(), 0o600);
+    assert_eq!(meta.modified(), Duration::from_secs(1));
+    assert_eq!(meta.ino(), host_meta.ino());
+    assert_eq!(meta.len(), 11);
+    let info = fs::statfs("/host/dir")?;
+    assert_eq!(
+        (info.block_size, info.blocks_free, info.name_max),
+        (4096, 400, 255)
+    );
+
+    // `..` of the root leaves the filesystem
+    fs::write("/host/../outside.txt", "outside\n")?;
+    assert!(!host_root.join("outside.txt").exists());
+    fs::remove_file("/outside.txt")?;
+
+    // renames and links within the filesystem
+    fs::rename("/host/from-host.txt", "/host/dir/renamed.txt")?;
+    assert!(host_root.join("dir/renamed.txt").exists());
+    fs::hard_link("/host/dir/renamed.txt", "/host/linked.txt")?;
+    assert_eq!(fs::metadata("/host/linked.txt")?.nlink(), 2);
+    fs::symlink("dir/renamed.txt", "/host/symlink")?;
+    assert_eq!(fs::read_to_string("/host/symlink")?, "from host\n");
+    let res = fs::hard_link("/host/linked.txt", "/tmp/linked.txt");
+    assert_eq!(res.err(), Some(axio::Error::CrossesDevices));
+    let res = fs::remove_dir("/host/dir");
+    assert_eq!(res.err(), Some(axio::Error::DirectoryNotEmpty));
+
+    // mounted again by the mount tag
+    fs::mount_device("host", "/mnt-host", Some("9p"), true)?;
+    assert_eq!(fs::read_to_string("/mnt-host/linked.txt")?, "from host\n");
+    let res = fs::write("/mnt-host/new.txt", "");
+    assert_eq!(res.err(), Some(axio::Error::ReadOnlyFilesystem));
+    fs::umount("/mnt-host")?;
+    let res = fs::mount_device("none", "/mnt-host", Some("9p"), false);
+    assert_eq!(res.err(), Some(axio::Error::NotFound));
+    fs::remove_dir("/mnt-host")?;
+
+    // mount tags are unique
+    let res = register_ninep_device(MockServer::new("host"));
+    assert_eq!(res.err(), Some(axio::Error::AlreadyExists));
+
+    println!("test_host_files() OK!");
+    Ok(())
+}
+
+#[test]
+fn test_ninepfs() {
+    println!("Testing 9p with mock servers ...");
+
+    let root = MockServer::new("rootfs");
+    let host = MockServer::new("host");
+    let (root_path, host_path) = (root.root.clone(), host.root.clone());
+    create_init_files(&root_path).expect("failed to create init files");
+
+    axtask::init_scheduler(); // call this to use `axsync::Mutex`.
+    register_ninep_device(root).unwrap();
+    register_ninep_device(host).unwrap();
+    axfs::init_filesystems_with(
+        AxDeviceContainer::from_one(RamDisk::new(256 * 512)), // dummy disk, only used by /dev/vda0.
+        "rootfs:/:9p",
+    );
+    assert!(fs::read_to_string("/proc/mounts")
+        .unwrap()
+        .contains("9p / 9p rw "));
+
+    test_common::test_all();
+    test_host_files(&host_path).expect("test_host_files() failed");
+
+    let _ = host::remove_dir_all(root_path);
+    let _ = host::remove_dir_all(host_path);
+}
@@ -2,21 +2,41 @@
 
 mod test_common;
 
+use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 
 use axdriver::AxDeviceContainer;
 use axdriver_block::ramdisk::RamDisk;
-use axfs::api::{self as fs, File};
-use axfs::fops::{Disk, MyFileSystemIf};
+use axfs::api::{self as fs, File, Permissions};
+use axfs::fops::{self, register_filesystem, BlockDevice, Credentials, FileSystemType, LockKind};
+use axfs::fops::{MyFileSystem, MyFileSystemIf};
+use axfs::fops::{OverlayFs, WatchEvent, WatchMask, Watcher, MAX_QUEUED_EVENTS};
 use axfs_ramfs::RamFileSystem;
-use axfs_vfs::VfsOps;
-use axio::{Result, Write};
+use axfs_vfs::{VfsNodePerm, VfsNodeType, VfsOps, VfsSetAttr};
+use axio::{Read, Result, Seek, SeekFrom, Write};
+
+/// The number of blocks of the device which the last ramfs is mounted on.
+static MOUNTED_BLOCKS: AtomicU64 = AtomicU64::new(0);
+
+struct RamFsType;
+
+impl FileSystemType for RamFsType {
+    fn name(&self) -> &str {
+        "ramfs"
+    }
+
+    fn mount(&self, dev: Box<dyn BlockDevice>) -> Result<Arc<dyn VfsOps>> {
+        MOUNTED_BLOCKS.store(dev.num_blocks(), Ordering::Relaxed);
+        Ok(Arc::new(RamFileSystem::new()))
+    }
+}
 
 struct MyFileSystemIfImpl;
 
 #[crate_interface::impl_interface]
 impl MyFileSystemIf for MyFileSystemIfImpl {
-    fn new_myfs(_disk: Disk) -> Arc<dyn VfsOps> {
+    fn new_myfs(_disk: Box<dyn BlockDevice>) -> Arc<dyn VfsOps> {
         Arc::new(RamFileSystem::new())
     }
 }
@@ -41,16 +61,616 @@ fn create_init_files() -> Result<()> {
     Ok(())
 }
 
+/// Creates a disk of 256 blocks with a GPT partition table, which has two
+/// partitions of 64 blocks starting from block 34 and 98.
+fn make_gpt_disk() -> RamDisk {
+    let mut data = vec![0; 256 * 512];
+    // protective MBR
+    data[0x1be + 4] = 0xee;
+    data[0x1be + 8..0x1be + 12].copy_from_slice(&1u32.to_le_bytes());
+    data[0x1be + 12..0x1be + 16].copy_from_slice(&255u32.to_le_bytes());
+    data[510..512].copy_from_slice(&[0x55, 0xaa]);
+    // GPT header
+    let header = &mut data[512..1024];
+    header[..8].copy_from_slice(b"EFI PART");
+    header[72..80].copy_from_slice(&2u64.to_le_bytes());
+    header[80..84].copy_from_slice(&128u32.to_le_bytes());
+    header[84..88].copy_from_slice(&128u32.to_le_bytes());
+    // GPT entries
+    for (i, first) in [34u64, 98].into_iter().enumerate() {
+        let entry = &mut data[1024 + i * 128..][..128];
+        entry[..16].fill(0xaa); // partition type GUID
+        entry[32..40].copy_from_slice(&first.to_le_bytes());
+        entry[40..48].copy_from_slice(&(first + 63).to_le_bytes());
+    }
+    RamDisk::from(&data)
+}
+
+fn test_partitions() -> Result<()> {
+    // mounted by the mount table
+    assert!(fs::read_to_string("/proc/mounts")?.contains(" /data "));
+    fs::write("/data/test.txt", "partition 2\n")?;
+    assert_eq!(fs::read_to_string("/data/test.txt")?, "partition 2\n");
+
+    assert_eq!(fs::metadata("/dev/vda0")?.len(), 256 * 512);
+    assert_eq!(fs::metadata("/dev/vda0p1")?.len(), 64 * 512);
+    assert_eq!(fs::metadata("/dev/vda0p2")?.len(), 64 * 512);
+    assert!(fs::metadata("/dev/vda0p3").is_err());
+
+    // partitions are views of the device
+    let mut part = File::options().read(true).write(true).open("/dev/vda0p2")?;
+    part.write_all(b"raw data")?;
+    part.seek(SeekFrom::Start(64 * 512))?;
+    assert_eq!(part.write(b"out of range").ok(), None);
+    let mut dev = File::open("/dev/vda0")?;
+    dev.seek(SeekFrom::Start(98 * 512))?;
+    let mut buf = [0; 8];
+    dev.read_exact(&mut buf)?;
+    assert_eq!(&buf, b"raw data");
+    Ok(())
+}
+
+fn test_fs_types() -> Result<()> {
+    assert_eq!(
+        register_filesystem(Arc::new(RamFsType)).err(),
+        Some(axio::Error::AlreadyExists)
+    );
+
+    fs::mount_device("vda0p2", "/custom", Some("ramfs"), false)?;
+    assert_eq!(MOUNTED_BLOCKS.load(Ordering::Relaxed), 64);
+    fs::write("/custom/test.txt", "custom\n")?;
+    assert!(fs::read_to_string("/proc/mounts")?.contains("ramfs /custom "));
+    fs::umount("/custom")?;
+
+    register_filesystem(Arc::new(MyFileSystem::<MyFileSystemIfImpl>::new()))?;
+    fs::mount_device("vda0p2", "/custom", Some("myfs"), false)?;
+    fs::write("/custom/test.txt", "myfs\n")?;
+    assert_eq!(fs::read_to_string("/custom/test.txt")?, "myfs\n");
+    fs::umount("/custom")?;
+
+    let res = fs::mount_device("vda0p2", "/custom", Some("none"), false);
+    assert_eq!(res.err(), Some(axio::Error::NotFound));
+    let res = fs::mount_device("vda9", "/custom", Some("ramfs"), false);
+    assert_eq!(res.err(), Some(axio::Error::NotFound));
+
+    println!("test_fs_types() OK!");
+    Ok(())
+}
+
+fn test_attrs() -> Result<()> {
+    let fname = "/tmp/attrs.txt";
+    fs::write(fname, "attrs")?;
+    let meta = fs::metadata(fname)?;
+    assert!(meta.ino() > 1);
+    assert_eq!((meta.uid(), meta.gid()), (0, 0));
+    assert_eq!(meta.permissions().bits(), 0o666);
+    assert_eq!(meta.modified(), meta.changed());
+
+    fs::set_permissions(fname, Permissions::from_bits_truncate(0o640))?;
+    fs::chown(fname, Some(1000), None)?;
+    fs::set_times(fname, Some(Duration::from_secs(1)), None)?;
+    let meta = fs::metadata(fname)?;
+    assert_eq!(meta.permissions().bits(), 0o640);
+    assert_eq!((meta.uid(), meta.gid()), (1000, 0));
+    assert_eq!(meta.accessed(), Duration::from_secs(1));
+
+    let file = File::open(fname)?;
+    file.set_modified(Duration::from_secs(2))?;
+    assert_eq!(file.metadata()?.modified(), Duration::from_secs(2));
+    assert_eq!(file.metadata()?.ino(), meta.ino());
+    fs::remove_file(fname)?;
+
+    println!("test_attrs() OK!");
+    Ok(())
+}
+
+fn test_sparse_file() -> Result<()> {
+    const FAR: u64 = 1 << 32;
+    let fname = "/tmp/sparse.bin";
+    let mut file = File::create_new(fname)?;
+    file.seek(SeekFrom::Start(FAR))?;
+    file.write_all(b"far away")?;
+    let meta = file.metadata()?;
+    assert_eq!(meta.len(), FAR + 8);
+    assert_eq!(meta.blocks(), 4096 / 512);
+
+    let mut buf = [0xff; 16];
+    file.seek(SeekFrom::Start(FAR - 8))?;
+    file.read_exact(&mut buf)?;
+    assert_eq!(&buf, b"\0\0\0\0\0\0\0\0far away");
+    file.set_len(4)?;
+    assert_eq!(file.metadata()?.blocks(), 0);
+    drop(file);
+    fs::remove_file(fname)?;
+
+    println!("test_sparse_file() OK!");
+    Ok(())
+}
+
+fn test_statfs() -> Result<()> {
+    let info = fs::statfs("/tmp")?;
+    assert_eq!(info.block_size, 4096);
+    assert!(info.blocks > 0 && info.files > 0);
+    fs::write("/tmp/statfs.txt", [0; 10000])?;
+    let new_info = fs::statfs("/tmp/statfs.txt")?;
+    assert_eq!(new_info.blocks_free, info.blocks_free - 3);
+    assert_eq!(new_info.files_free, info.files_free - 1);
+    fs::remove_file("/tmp/statfs.txt")?;
+    assert_eq!(fs::statfs("/tmp/.")?.blocks_free, info.blocks_free);
+
+    // the root is unlimited, which never looks full, and procfs does not
+    // support it
+    let root_info = fs::statfs("/very")?;
+    assert!(root_info.blocks_free > 0 && root_info.blocks_free <= root_info.blocks);
+    assert_eq!(fs::statfs("/proc").err(), Some(axio::Error::Unsupported));
+    assert_eq!(fs::statfs("/none").err(), Some(axio::Error::NotFound));
+
+    println!("test_statfs() OK!");
+    Ok(())
+}
+
+fn test_credentials() -> Result<()> {
+    fs::create_dir("/tmp/private")?;
+    fs::write("/tmp/private/secret.txt", "secret")?;
+    fs::write("/tmp/shared.txt", "shared")?;
+    fs::set_permissions("/tmp/private", Permissions::from_bits_truncate(0o700))?;
+    fs::set_permissions("/tmp/shared.txt", Permissions::from_bits_truncate(0o640))?;
+    fs::chown("/tmp/shared.txt", Some(1000), Some(100))?;
+
+    let open = |path: &str, cred: &Credentials, write: bool| {
+        let mut opts = fops::OpenOptions::new();
+        opts.read(true);
+        opts.write(write);
+        opts.credentials(cred.clone());
+        fops::File::open(path, &opts).map(drop)
+    };
+    let owner = Credentials::new(1000, 1000, vec![]);
+    let member = Credentials::new(1001, 1001, vec![100]);
+    let other = Credentials::new(1002, 1002, vec![]);
+
+    // owner, group and other classes
+    open("/tmp/shared.txt", &owner, true)?;
+    open("/tmp/shared.txt", &member, false)?;
+    let res = open("/tmp/shared.txt", &member, true);
+    assert_eq!(res.err(), Some(axio::Error::PermissionDenied));
+    let res = open("/tmp/shared.txt", &other, false);
+    assert_eq!(res.err(), Some(axio::Error::PermissionDenied));
+
+    // search permission of the directories on the path
+    open("/tmp/private/secret.txt", &Credentials::root(), false)?;
+    let res = open("/tmp/private/secret.txt", &owner, false);
+    assert_eq!(res.err(), Some(axio::Error::PermissionDenied));
+
+    // files created with credentials are owned by the caller
+    fs::create_dir("/tmp/home")?;
+    fs::chown("/tmp/home", Some(1000), Some(1000))?;
+    let mut opts = fops::OpenOptions::new();
+    opts.read(true);
+    opts.execute(true);
+    opts.credentials(owner.clone());
+    let dir = fops::Directory::open_dir("/tmp", &opts)?;
+    let res = dir.create_file("owned.txt");
+    assert_eq!(res.err(), Some(axio::Error::PermissionDenied));
+    dir.create_file("home/owned.txt")?;
+    let meta = fs::metadata("/tmp/home/owned.txt")?;
+    assert_eq!((meta.uid(), meta.gid()), (1000, 1000));
+    let res = dir.remove_file("private/secret.txt");
+    assert_eq!(res.err(), Some(axio::Error::PermissionDenied));
+    let res = fops::File::open("/tmp/home/owned.txt", &opts);
+    assert_eq!(res.err(), Some(axio::Error::PermissionDenied));
+
+    // only the owner can change the permission and the timestamps, and only
+    // the superuser can change the owner
+    let chmod = fops::SetAttr {
+        perm: Some(Permissions::from_bits_truncate(0o600)),
+        ..Default::default()
+    };
+    let chown = |uid, gid| fops::SetAttr {
+        uid,
+        gid,
+        ..Default::default()
+    };
+    let utime = fops::SetAttr {
+        mtime: Some(Duration::from_secs(1)),
+        ..Default::default()
+    };
+    let mut opts = fops::OpenOptions::new();
+    opts.read(true);
+    opts.credentials(owner.clone());
+    let file = fops::File::open("/tmp/home/owned.txt", &opts)?;
+    file.set_attr(&chmod)?;
+    file.set_attr(&utime)?;
+    file.set_attr(&chown(Some(1000), Some(1000)))?;
+    let res = file.set_attr(&chown(Some(1002), None));
+    assert_eq!(res.err(), Some(axio::Error::PermissionDenied));
+    let res = file.set_attr(&chown(None, Some(100)));
+    assert_eq!(res.err(), Some(axio::Error::PermissionDenied));
+    dir.set_attr(&utime).unwrap_err();
+    opts.credentials(member.clone());
+    opts.read(false);
+    opts.write(true);
+    fs::set_permissions(
+        "/tmp/home/owned.txt",
+        Permissions::from_bits_truncate(0o666),
+    )?;
+    let file = fops::File::open("/tmp/home/owned.txt", &opts)?;
+    for attr in [chmod, utime, chown(None, Some(100))] {
+        assert_eq!(
+            file.set_attr(&attr).err(),
+            Some(axio::Error::PermissionDenied)
+        );
+    }
+    opts.credentials(Credentials::new(1000, 1000, vec![100]));
+    let file = fops::File::open("/tmp/home/owned.txt", &opts)?;
+    file.set_attr(&chown(None, Some(1000)))?;
+    file.set_attr(&chown(None, Some(100)))?;
+    drop(dir);
+
+    // the mode of a created file does not restrict the creator
+    let mut opts = fops::OpenOptions::new();
+    opts.write(true);
+    opts.create_new(true);
+    opts.mode(0o400);
+    opts.credentials(owner.clone());
+    fops::File::open("/tmp/home/created.txt", &opts)?;
+    let meta = fs::metadata("/tmp/home/created.txt")?;
+    assert_eq!(meta.permissions().bits(), 0o400);
+    let res = open("/tmp/home/created.txt", &owner, true);
+    assert_eq!(res.err(), Some(axio::Error::PermissionDenied));
+    fs::remove_file("/tmp/home/created.txt")?;
+
+    // the superuser can access any file, but only execute files with an
+    // execute bit
+    fs::set_permissions("/tmp/home", Permissions::from_bits_truncate(0o000))?;
+    fs::set_permissions(
+        "/tmp/home/owned.txt",
+        Permissions::from_bits_truncate(0o000),
+    )?;
+    open("/tmp/home/owned.txt", &Credentials::root(), true)?;
+    let mut opts = fops::OpenOptions::new();
+    opts.execute(true);
+    let res = fops::File::open("/tmp/home/owned.txt", &opts);
+    assert_eq!(res.err(), Some(axio::Error::PermissionDenied));
+    fs::set_permissions(
+        "/tmp/home/owned.txt",
+        Permissions::from_bits_truncate(0o100),
+    )?;
+    fops::File::open("/tmp/home/owned.txt", &opts)?;
+    fs::remove_file("/tmp/home/owned.txt")?;
+    fs::remove_dir("/tmp/home")?;
+    fs::remove_file("/tmp/shared.txt")?;
+    fs::remove_file("/tmp/private/secret.txt")?;
+    fs::remove_dir("/tmp/private")?;
+
+    println!("test_credentials() OK!");
+    Ok(())
+}
+
+fn test_read_only_mount() -> Result<()> {
+    let ramfs = Arc::new(RamFileSystem::new());
+    ramfs
+        .root_dir()
+        .create("file.txt", axfs_vfs::VfsNodeType::File)?;
+    fs::create_dir("/rom")?;
+    fs::mount_read_only("/rom", ramfs)?;
+    assert!(fs::read_to_string("/proc/mounts")?.contains("ramfs /rom ramfs ro "));
+
+    assert_eq!(fs::read_to_string("/rom/file.txt")?, "");
+    let res = File::options().write(true).open("/rom/file.txt");
+    assert_eq!(res.err(), Some(axio::Error::ReadOnlyFilesystem));
+    let res = fs::write("/rom/new.txt", "new");
+    assert_eq!(res.err(), Some(axio::Error::ReadOnlyFilesystem));
+    let res = fs::create_dir("/rom/dir");
+    assert_eq!(res.err(), Some(axio::Error::ReadOnlyFilesystem));
+    let res = fs::remove_file("/rom/file.txt");
+    assert_eq!(res.err(), Some(axio::Error::ReadOnlyFilesystem));
+    let res = fs::set_permissions("/rom/file.txt", Permissions::from_bits_truncate(0o777));
+    assert_eq!(res.err(), Some(axio::Error::ReadOnlyFilesystem));
+    // `..` leaves the read-only filesystem
+    fs::write("/rom/../rw.txt", "rw")?;
+    fs::remove_file("/rw.txt")?;
+
+    fs::umount("/rom")?;
+    fs::remove_dir("/rom")?;
+
+    println!("test_read_only_mount() OK!");
+    Ok(())
+}
+
+fn test_file_locks() -> Result<()> {
+    let fname = "/tmp/locks.txt";
+    fs::write(fname, "locks")?;
+    let (f1, f2) = (File::open(fname)?, File::open(fname)?);
+    assert_eq!(f2.try_lock_shared().err(), None);
+
+    // whole-file locks
+    f1.lock_shared()?;
+    assert_eq!(f2.try_lock().err(), Some(axio::Error::WouldBlock));
+    f1.unlock()?;
+    f2.lock()?;
+    assert_eq!(f1.try_lock_shared().err(), Some(axio::Error::WouldBlock));
+    drop(f2); // released on close
+    f1.try_lock()?;
+    f1.unlock()?;
+
+    // record locks are independent of whole-file locks
+    let mut opts = fops::OpenOptions::new();
+    opts.read(true);
+    opts.write(true);
+    let (r1, r2) = (
+        fops::File::open(fname, &opts)?,
+        fops::File::open(fname, &opts)?,
+    );
+    let (l1, l2) = (r1.locks(), r2.locks());
+    l1.flock(Some(LockKind::Exclusive), false)?;
+    l2.set_lock(Some(LockKind::Exclusive), 0..u64::MAX, false)?;
+    l2.set_lock(None, 10..20, true)?; // split into 0..10 and 20..
+    l1.set_lock(Some(LockKind::Exclusive), 10..20, false)?;
+    let res = l1.set_lock(Some(LockKind::Shared), 5..15, false);
+    assert_eq!(res.err(), Some(axio::Error::WouldBlock));
+    let lock = l1.get_lock(LockKind::Shared, 15..100).unwrap();
+    assert_eq!((lock.kind, lock.range), (LockKind::Exclusive, 20..u64::MAX));
+    assert_eq!(l2.get_lock(LockKind::Shared, 0..10), None);
+    // shared locks only conflict with exclusive ones
+    l2.set_lock(Some(LockKind::Shared), 20..30, false)?;
+    l1.set_lock(Some(LockKind::Shared), 25..26, false)?;
+    assert!(l1.get_lock(LockKind::Exclusive, 20..30).is_some());
+    drop(r2);
+    assert_eq!(l1.get_lock(LockKind::Exclusive, 0..u64::MAX), None);
+    // a cloned file has its own locks, and keeps the file open
+    let r3 = r1.try_clone()?;
+    assert!(r3.readable() && r3.writable());
+    opts.write(false);
+    assert!(!fops::File::open(fname, &opts)?.writable());
+    let res = r3.locks().flock(Some(LockKind::Shared), false);
+    assert_eq!(res.err(), Some(axio::Error::WouldBlock));
+    drop(r1);
+    let mut buf = [0; 5];
+    assert_eq!(r3.read_at(0, &mut buf)?, 5);
+    assert_eq!(&buf, b"locks");
+    r3.locks().flock(Some(LockKind::Shared), false)?;
+    drop(r3);
+    drop(f1);
+    fs::remove_file(fname)?;
+
+    println!("test_file_locks() OK!");
+    Ok(())
+}
+
+fn test_watch() -> Result<()> {
+    let watcher = Watcher::new();
+    let root = Credentials::root();
+    let take_events = || {
+        let mut events = Vec::new();
+        watcher.read_events(|e| {
+            events.push((e.wd, e.mask, e.name.clone()));
+            true
+        });
+        events
+    };
+    fs::create_dir("/tmp/watched")?;
+    let all = WatchMask::all();
+    let wd = watcher.add_watch("/tmp/watched", all, &root)?;
+    let tmp_wd = watcher.add_watch("/tmp", WatchMask::CREATE, &root)?;
+    assert_eq!(watcher.add_watch("/tmp/watched/", all, &root)?, wd);
+    assert!(!watcher.has_events());
+
+    fs::write("/tmp/watched/a", "watch")?;
+    fs::create_dir("/tmp/watched/dir")?;
+    fs::rename("/tmp/watched/a", "/tmp/watched/b")?;
+    fs::remove_dir("/tmp/watched/dir")?;
+    fs::write("/tmp/b", "not watched")?;
+    watcher.wait_events()?;
+    let mut events = Vec::new();
+    watcher.read_events(|e| {
+        events.push(e.clone());
+        true
+    });
+    let cookie = events[3].cookie;
+    assert_ne!(cookie, 0);
+    let event = |mask, cookie, name: &str| WatchEvent {
+        wd,
+        mask,
+        cookie,
+        name: name.into(),
+    };
+    assert_eq!(
+        events,
+        [
+            event(WatchMask::CREATE, 0, "a"),
+            event(WatchMask::MODIFY, 0, "a"),
+            event(WatchMask::CREATE | WatchMask::IS_DIR, 0, "dir"),
+            event(WatchMask::MOVED_FROM, cookie, "a"),
+            event(WatchMask::MOVED_TO, cookie, "b"),
+            event(WatchMask::DELETE | WatchMask::IS_DIR, 0, "dir"),
+            WatchEvent {
+                wd: tmp_wd,
+                mask: WatchMask::CREATE,
+                cookie: 0,
+                name: "b".into(),
+            },
+        ]
+    );
+
+    // events are read while they are accepted
+    fs::write("/tmp/watched/b", "watch")?;
+    fs::remove_file("/tmp/watched/b")?;
+    assert_eq!(watcher.read_events(|e| e.mask == WatchMask::MODIFY), 1);
+    assert_eq!(take_events(), [(wd, WatchMask::DELETE, "b".into())]);
+
+    // the watch is removed with the directory
+    fs::remove_dir("/tmp/watched")?;
+    assert_eq!(
+        take_events(),
+        [
+            (wd, WatchMask::DELETE_SELF, String::new()),
+            (wd, WatchMask::IGNORED, String::new()),
+        ]
+    );
+    assert_eq!(
+        watcher.remove_watch(wd).err(),
+        Some(axio::Error::InvalidInput)
+    );
+    watcher.remove_watch(tmp_wd)?;
+    assert_eq!(take_events(), [(tmp_wd, WatchMask::IGNORED, String::new())]);
+    fs::remove_file("/tmp/b")?;
+    assert!(!watcher.has_events());
+
+    // overflow of the event queue
+    fs::create_dir("/tmp/flood")?;
+    let mask = WatchMask::CREATE | WatchMask::DELETE;
+    let wd = watcher.add_watch("/tmp/flood", mask, &root)?;
+    let flood = |count: usize| -> Result<()> {
+        for _ in 0..count {
+            fs::write("/tmp/flood/f", "")?;
+            fs::remove_file("/tmp/flood/f")?;
+        }
+        Ok(())
+    };
+    flood(MAX_QUEUED_EVENTS / 2 + 1)?;
+    assert_eq!(watcher.read_events(|e| e.mask == WatchMask::CREATE), 1);
+    flood(1)?;
+    let events = take_events();
+    assert_eq!(events.len(), MAX_QUEUED_EVENTS);
+    let deleted = (wd, WatchMask::DELETE, "f".into());
+    assert_eq!(events[MAX_QUEUED_EVENTS - 2], deleted);
+    let overflow = (-1, WatchMask::Q_OVERFLOW, String::new());
+    assert_eq!(events[MAX_QUEUED_EVENTS - 1], overflow);
+    flood(1)?;
+    let created = (wd, WatchMask::CREATE, "f".into());
+    assert_eq!(take_events(), [created, deleted]);
+    watcher.remove_watch(wd)?;
+    take_events();
+    fs::remove_dir("/tmp/flood")?;
+
+    // filesystems without change notification
+    let res = watcher.add_watch("/proc", all, &root);
+    assert_eq!(res.err(), Some(axio::Error::Unsupported));
+
+    println!("test_watch() OK!");
+    Ok(())
+}
+
+fn test_overlay() -> Result<()> {
+    // mounted by the mount table
+    assert!(fs::read_to_string("/proc/mounts")?.contains("overlay /ovl overlay rw "));
+    fs::write("/ovl/test.txt", "overlay\n")?;
+    assert_eq!(fs::read_to_string("/ovl/test.txt")?, "overlay\n");
+
+    let lower = Arc::new(RamFileSystem::new());
+    let lower_root = lower.root_dir();
+    for dir in ["dir", "dir/sub"] {
+        lower_root.create(dir, VfsNodeType::Dir)?;
+    }
+    for (fname, contents) in [("dir/a.txt", "lower a"), ("b.txt", "lower b")] {
+        lower_root.create(fname, VfsNodeType::File)?;
+        lower_root
+            .clone()
+            .lookup(fname)?
+            .write_at(0, contents.as_bytes())?;
+    }
+    lower_root.symlink("link", "b.txt")?;
+    let perm = VfsNodePerm::from_bits_truncate(0o640);
+    let attr = VfsSetAttr {
+        perm: Some(perm),
+        ..Default::default()
+    };
+    lower_root.clone().lookup("dir/a.txt")?.set_attr(&attr)?;
+    let read_lower = |fname: &str| -> Result<String> {
+        let mut buf = [0; 64];
+        let len = lower_root.clone().lookup(fname)?.read_at(0, &mut buf)?;
+        Ok(String::from_utf8_lossy(&buf[..len]).into_owned())
+    };
+    let read_dir = |path: &str| -> Result<Vec<String>> {
+        fs::read_dir(path)?
+            .map(|entry| entry.map(|e| e.file_name()))
+            .collect()
+    };
+    let upper = Arc::new(RamFileSystem::new());
+    fs::mount("/overlay", Arc::new(OverlayFs::new(lower.clone(), upper)))?;
+
+    // files are copied up when modified
+    assert_eq!(fs::read_to_string("/overlay/link")?, "lower b");
+    let ino = fs::metadata("/overlay/dir/a.txt")?.ino();
+    fs::write("/overlay/dir/a.txt", "upper a")?;
+    assert_eq!(fs::read_to_string("/overlay/dir/a.txt")?, "upper a");
+    assert_eq!(read_lower("dir/a.txt")?, "lower a");
+    let meta = fs::metadata("/overlay/dir/a.txt")?;
+    assert_eq!((meta.ino(), meta.permissions().bits()), (ino, 0o640));
+
+    // directories are merged
+    fs::write("/overlay/dir/new.txt", "new")?;
+    assert_eq!(read_dir("/overlay/dir")?, ["a.txt", "new.txt", "sub"]);
+    assert!(lower_root.clone().lookup("dir/new.txt").is_err());
+
+    // removed files are hidden by whiteouts
+    fs::remove_file("/overlay/b.txt")?;
+    assert_eq!(read_dir("/overlay")?, ["dir", "link"]);
+    assert_eq!(
+        fs::metadata("/overlay/link/").err(),
+        Some(axio::Error::NotFound)
+    );
+    assert_eq!(read_lower("b.txt")?, "lower b");
+    let res = fs::write("/overlay/.wh.b.txt", "");
+    assert_eq!(res.err(), Some(axio::Error::InvalidInput));
+    fs::write("/overlay/b.txt", "upper b")?;
+    assert_eq!(fs::read_to_string("/overlay/link")?, "upper b");
+
+    // renames
+    let res = fs::rename("/overlay/dir", "/overlay/dir2");
+    assert_eq!(res.err(), Some(axio::Error::CrossesDevices));
+    fs::rename("/overlay/link", "/overlay/dir/link")?;
+    fs::rename("/overlay/dir/link", "/overlay/b.txt")?;
+    assert_eq!(read_dir("/overlay")?, ["b.txt", "dir"]);
+    assert_eq!(fs::read_link("/overlay/b.txt")?, "b.txt");
+    assert_eq!(
+        fs::read_link("/overlay/link").err(),
+        Some(axio::Error::NotFound)
+    );
+
+    // a removed directory is empty when created again
+    let res = fs::remove_dir("/overlay/dir");
+    assert_eq!(res.err(), Some(axio::Error::DirectoryNotEmpty));
+    for fname in ["a.txt", "new.txt"] {
+        fs::remove_file(&format!("/overlay/dir/{fname}"))?;
+    }
+    fs::remove_dir("/overlay/dir/sub")?;
+    fs::remove_dir("/overlay/dir")?;
+    fs::create_dir("/overlay/dir")?;
+    assert!(read_dir("/overlay/dir")?.is_empty());
+    assert_eq!(read_lower("dir/a.txt")?, "lower a");
+
+    fs::umount("/overlay")?;
+    fs::remove_dir("/overlay")?;
+
+    println!("test_overlay() OK!");
+    Ok(())
+}
+
 #[test]
 fn test_ramfs() {
     println!("Testing ramfs ...");
 
     axtask::init_scheduler(); // call this to use `axsync::Mutex`.
-    axfs::init_filesystems(AxDeviceContainer::from_one(RamDisk::default())); // dummy disk, actually not used.
+    register_filesystem(Arc::new(RamFsType)).unwrap();
+    axfs::init_filesystems_with(
+        AxDeviceContainer::from_one(make_gpt_disk()), // dummy disk, only used by /dev/vda0*.
+        "vda0p1:/:ramfs, vda0p2:/data:ramfs, vda0:/ovl:ramfs:overlay",
+    );
 
     if let Err(e) = create_init_files() {
         log::warn!("failed to create init files: {:?}", e);
     }
 
     test_common::test_all();
+    test_partitions().expect("test_partitions() failed");
+    test_fs_types().expect("test_fs_types() failed");
+    test_attrs().expect("test_attrs() failed");
+    test_sparse_file().expect("test_sparse_file() failed");
+    test_statfs().expect("test_statfs() failed");
+    test_credentials().expect("test_credentials() failed");
+    test_read_only_mount().expect("test_read_only_mount() failed");
+    test_file_locks().expect("test_file_locks() failed");
+    test_watch().expect("test_watch() failed");
+    test_overlay().expect("test_overlay() failed");
 }
@@ -8,7 +8,9 @@ mod trap;
 use core::arch::asm;
 
 use memory_addr::{MemoryAddr, PhysAddr, VirtAddr};
-use x86::{controlregs, msr, tlb};
+#[cfg(target_os = "none")]
+use x86::tlb;
+use x86::{controlregs, msr};
 use x86_64::instructions::interrupts;
 
 pub use self::context::{ExtendedState, FxsaveArea, TaskContext, TrapFrame};
@@ -80,8 +82,13 @@ pub unsafe fn write_page_table_root(root_paddr: PhysAddr) {
 ///
 /// If `vaddr` is [`None`], flushes the entire TLB. Otherwise, flushes the TLB
 /// entry that maps the given virtual address.
+///
+/// It does nothing on hosted targets (e.g., in unit tests), where the TLB is
+/// not accessible.
 #[inline]
+#[cfg_attr(not(target_os = "none"), allow(unused_variables))]
 pub fn flush_tlb(vaddr: Option<VirtAddr>) {
+    #[cfg(target_os = "none")]
     if let Some(vaddr) = vaddr {
         unsafe { tlb::flush(vaddr.into()) }
     } else {
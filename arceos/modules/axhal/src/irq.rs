@@ -1,5 +1,7 @@
 //! Interrupt management.
 
+use core::sync::atomic::{AtomicUsize, Ordering};
+
 use handler_table::HandlerTable;
 
 use crate::platform::irq::{dispatch_irq, MAX_IRQ_COUNT};
@@ -12,10 +14,39 @@ pub type IrqHandler = handler_table::Handler;
 
 static IRQ_HANDLER_TABLE: HandlerTable<MAX_IRQ_COUNT> = HandlerTable::new();
 
+/// Number of times each IRQ has been dispatched.
+static IRQ_COUNTS: [AtomicUsize; MAX_IRQ_COUNT] = [const { AtomicUsize::new(0) }; MAX_IRQ_COUNT];
+
+/// Returns the number of times the given IRQ has been dispatched.
+pub fn irq_count(irq_num: usize) -> usize {
+    IRQ_COUNTS
+        .get(irq_num)
+        .map_or(0, |count| count.load(Ordering::Relaxed))
+}
+
+/// Returns an iterator over `(irq_num, count)` of the IRQs that have been
+/// dispatched at least once, in ascending order of the IRQ numbers.
+pub fn irq_counts() -> impl Iterator<Item = (usize, usize)> {
+    IRQ_COUNTS
+        .iter()
+        .enumerate()
+        .map(|(irq_num, count)| (irq_num, count.load(Ordering::Relaxed)))
+        .filter(|&(_, count)| count > 0)
+}
+
+/// Increases the dispatch count of the given IRQ.
+#[allow(dead_code)]
+pub(crate) fn record_irq(irq_num: usize) {
+    if let Some(count) = IRQ_COUNTS.get(irq_num) {
+        count.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
 /// Platform-independent IRQ dispatching.
 #[allow(dead_code)]
 pub(crate) fn dispatch_irq_common(irq_num: usize) {
     trace!("IRQ {}", irq_num);
+    record_irq(irq_num);
     if !IRQ_HANDLER_TABLE.handle(irq_num) {
         warn!("Unhandled IRQ {}", irq_num);
     }
@@ -1,8 +1,46 @@
 pub use super::platform::misc::*;
 
+use core::sync::atomic::{AtomicBool, Ordering};
+
 use kspin::SpinNoIrq;
+use linkme::distributed_slice as def_terminate_hook;
 use crate::time;
 
+pub use linkme::distributed_slice as register_terminate_hook;
+
+/// A slice of functions called before the system terminates, e.g., to write
+/// back the filesystems.
+#[def_terminate_hook]
+pub static TERMINATE_HOOKS: [fn()];
+
+/// Calls the [terminate hooks](TERMINATE_HOOKS) if they have not been called.
+///
+/// The hooks may block, e.g., on sleeping locks, so it must be called when
+/// blocking is allowed, such as before the run queue is locked on exit.
+pub fn run_terminate_hooks() {
+    static TERMINATING: AtomicBool = AtomicBool::new(false);
+    if !TERMINATING.swap(true, Ordering::AcqRel) {
+        for hook in TERMINATE_HOOKS {
+            hook();
+        }
+    }
+}
+
+/// Shutdown the whole system, including all CPUs, after calling the
+/// [terminate hooks](TERMINATE_HOOKS) once.
+///
+/// See [`run_terminate_hooks`] for when the hooks can be called.
+pub fn terminate() -> ! {
+    run_terminate_hooks();
+    super::platform::misc::terminate()
+}
+
+/// Shutdown the whole system immediately, without calling the terminate
+/// hooks, e.g., on panic.
+pub fn abort() -> ! {
+    super::platform::misc::terminate()
+}
+
 static PARK_MILLER_LEHMER_SEED: SpinNoIrq<u32> = SpinNoIrq::new(0);
 const RAND_MAX: u64 = 2_147_483_647;
 
@@ -69,6 +69,7 @@ pub fn dispatch_irq(scause: usize) {
         scause,
         @TIMER => {
             trace!("IRQ: timer");
+            crate::irq::record_irq(S_TIMER - INTC_IRQ_BASE);
             TIMER_HANDLER();
         },
         @EXT => crate::irq::dispatch_irq_common(0), // TODO: get IRQ number from PLIC
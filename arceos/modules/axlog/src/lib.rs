@@ -260,3 +260,30 @@ pub fn set_max_level(level: &str) {
         .unwrap_or(LevelFilter::Off);
     log::set_max_level(lf);
 }
+
+/// Set the maximum log level, like [`set_max_level`].
+///
+/// Unlike [`set_max_level`], it returns `false` and leaves the level unchanged
+/// if `level` is invalid.
+pub fn try_set_max_level(level: &str) -> bool {
+    match LevelFilter::from_str(level) {
+        Ok(lf) => {
+            log::set_max_level(lf);
+            true
+        }
+        Err(_) => false,
+    }
+}
+
+/// Get the maximum log level, as one of `off`, `error`, `warn`, `info`,
+/// `debug`, `trace`.
+pub fn max_level() -> &'static str {
+    match log::max_level() {
+        LevelFilter::Off => "off",
+        LevelFilter::Error => "error",
+        LevelFilter::Warn => "warn",
+        LevelFilter::Info => "info",
+        LevelFilter::Debug => "debug",
+        LevelFilter::Trace => "trace",
+    }
+}
@@ -1,24 +1,39 @@
 use core::fmt;
 
+#[cfg(feature = "fs")]
+use crate::backend::FileMapping;
+use crate::backend::{split_huge_pages, Backend, SharedPages};
+#[cfg(feature = "swap")]
+use crate::backend::{swap_in_page, swap_out_page};
+use crate::mapping_err_to_ax_err;
+use crate::paging_err_to_ax_err;
+#[cfg(feature = "swap")]
+use crate::swap::{self, SwapState};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 use axerrno::{ax_err, AxError, AxResult};
 use axhal::{
     mem::phys_to_virt,
     paging::{MappingFlags, PageTable},
 };
 use memory_addr::{
-    is_aligned_4k, pa, MemoryAddr, PageIter4K, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K,
+    is_aligned_4k, pa, va, MemoryAddr, PageIter4K, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K,
 };
 use memory_set::{MemoryArea, MemorySet};
-use crate::backend::Backend;
-use crate::paging_err_to_ax_err;
-use crate::mapping_err_to_ax_err;
-use alloc::vec::Vec;
 
 /// The virtual memory address space.
 pub struct AddrSpace {
     va_range: VirtAddrRange,
     areas: MemorySet<Backend>,
     pt: PageTable,
+    /// The start of the heap, see [`init_heap`](Self::init_heap).
+    heap_start: VirtAddr,
+    /// The program break, i.e., the end of the heap.
+    brk: VirtAddr,
+    /// The ages of the pages that can be swapped out, and the pages swapped
+    /// out.
+    #[cfg(feature = "swap")]
+    swap: SwapState,
 }
 
 impl AddrSpace {
@@ -53,12 +68,42 @@ impl AddrSpace {
             .contains_range(VirtAddrRange::from_start_size(start, size))
     }
 
+    /// Checks if the given address range overlaps any mapping (excluding the
+    /// linear mappings by [`map_linear`](Self::map_linear)).
+    pub fn overlaps(&self, start: VirtAddr, size: usize) -> bool {
+        self.areas
+            .overlaps(VirtAddrRange::from_start_size(start, size))
+    }
+
+    /// Checks if the given address range is fully covered by mappings
+    /// (excluding the linear mappings by [`map_linear`](Self::map_linear)).
+    pub fn is_mapped(&self, start: VirtAddr, size: usize) -> bool {
+        let (mut addr, end) = (start, start + size);
+        for area in self.areas.iter() {
+            if addr >= end {
+                break;
+            }
+            if area.end() <= addr {
+                continue;
+            }
+            if area.start() > addr {
+                return false; // a hole at `addr`
+            }
+            addr = area.end();
+        }
+        addr >= end
+    }
+
     /// Creates a new empty address space.
     pub fn new_empty(base: VirtAddr, size: usize) -> AxResult<Self> {
         Ok(Self {
             va_range: VirtAddrRange::from_start_size(base, size),
             areas: MemorySet::new(),
             pt: PageTable::try_new().map_err(|_| AxError::NoMemory)?,
+            heap_start: base,
+            brk: base,
+            #[cfg(feature = "swap")]
+            swap: SwapState::default(),
         })
     }
 
@@ -77,6 +122,53 @@ impl AddrSpace {
         Ok(())
     }
 
+    /// Creates a copy of the address space, e.g., for `fork`.
+    ///
+    /// The allocated frames are not copied, but shared by both address spaces
+    /// and mapped read-only, with their reference counts increased. A frame is
+    /// copied when either writes to it, and the copy is private to the writer
+    /// (copy-on-write). Shared file mappings still share the file pages. The
+    /// huge pages of allocation mappings are split into 4K pages to be shared.
+    /// The pages swapped out are shared in the swap area likewise.
+    ///
+    /// The kernel mappings (outside the address space) are copied as they are.
+    pub fn clone_cow(&mut self) -> AxResult<Self> {
+        let mut aspace = Self::new_empty(self.base(), self.size())?;
+        aspace.heap_start = self.heap_start;
+        aspace.brk = self.brk;
+        #[cfg(feature = "swap")]
+        {
+            aspace.swap = self.swap.clone_cow();
+        }
+        let kernel_range = VirtAddrRange::from_start_size(
+            va!(axconfig::KERNEL_ASPACE_BASE),
+            axconfig::KERNEL_ASPACE_SIZE,
+        );
+        if !self.va_range.overlaps(kernel_range) {
+            aspace
+                .pt
+                .copy_from(&self.pt, kernel_range.start, kernel_range.size());
+        }
+
+        for area in self.areas.iter() {
+            let backend = area.backend();
+            let new_area = MemoryArea::new(
+                area.start(),
+                area.size(),
+                area.flags(),
+                backend.clone_lazy(),
+            );
+            aspace
+                .areas
+                .map(new_area, &mut aspace.pt, false)
+                .map_err(mapping_err_to_ax_err)?;
+            if !backend.share_pages(area.start(), area.size(), &mut self.pt, &mut aspace.pt) {
+                return ax_err!(NoMemory, "failed to share the pages");
+            }
+        }
+        Ok(aspace)
+    }
+
     /// Finds a free area that can accommodate the given size.
     ///
     /// The search starts from the given hint address, and the area should be within the given limit range.
@@ -94,7 +186,8 @@ impl AddrSpace {
     /// Add a new linear mapping.
     ///
     /// The mapping is linear, i.e., `start_vaddr` is mapped to `start_paddr`,
-    /// and `start_vaddr + size` is mapped to `start_paddr + size`.
+    /// and `start_vaddr + size` is mapped to `start_paddr + size`. Huge pages
+    /// are used where both addresses are aligned to them.
     ///
     /// The `flags` parameter indicates the mapping permissions and attributes.
     ///
@@ -106,6 +199,19 @@ impl AddrSpace {
         start_paddr: PhysAddr,
         size: usize,
         flags: MappingFlags,
+    ) -> AxResult {
+        self.map_linear_with(start_vaddr, start_paddr, size, flags, true)
+    }
+
+    /// Same as [`map_linear`](Self::map_linear), but huge pages are used only
+    /// if `allow_huge` is `true`.
+    pub(crate) fn map_linear_with(
+        &mut self,
+        start_vaddr: VirtAddr,
+        start_paddr: PhysAddr,
+        size: usize,
+        flags: MappingFlags,
+        allow_huge: bool,
     ) -> AxResult {
         if !self.contains_range(start_vaddr, size) {
             return ax_err!(InvalidInput, "address out of range");
@@ -121,7 +227,7 @@ impl AddrSpace {
                 |va| pa!(va.as_usize() - offset),
                 size,
                 flags,
-                false, // allow_huge
+                allow_huge,
                 false, // flush_tlb_by_page
             )
             .map_err(paging_err_to_ax_err)?
@@ -158,6 +264,130 @@ impl AddrSpace {
         Ok(())
     }
 
+    /// Add a new allocation mapping that opts in to huge pages.
+    ///
+    /// It is the same as [`map_alloc`](Self::map_alloc), except that 2M pages
+    /// are used where the addresses are aligned to them, unless the
+    /// [`HugePagePolicy`](crate::HugePagePolicy) is `Never`. If a huge frame
+    /// cannot be allocated, 4K pages are used instead.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    pub fn map_alloc_huge(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        populate: bool,
+    ) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let area = MemoryArea::new(start, size, flags, Backend::new_alloc_huge(populate));
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        Ok(())
+    }
+
+    /// Add a new shared anonymous mapping.
+    ///
+    /// The zero-filled pages are allocated on demand, and are shared with the
+    /// copies of this address space by [`clone_cow`](Self::clone_cow) rather
+    /// than copied on write. See [`Backend`] for more details about the
+    /// mapping backends.
+    ///
+    /// The `flags` parameter indicates the mapping permissions and attributes.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    pub fn map_shared(&mut self, start: VirtAddr, size: usize, flags: MappingFlags) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let backend = Backend::new_shared(Arc::new(SharedPages::new()), start);
+        let area = MemoryArea::new(start, size, flags, backend);
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        Ok(())
+    }
+
+    /// Add a new file mapping, where `start` is mapped to `offset` of the
+    /// file.
+    ///
+    /// The pages are read from the file on demand. If `shared` is `true`, the
+    /// changes are written back to the file on [`msync`](Self::msync) or
+    /// unmapping, otherwise they are private to this mapping (copy-on-write).
+    /// See [`Backend`] for more details about the mapping backends.
+    ///
+    /// The `flags` parameter indicates the mapping permissions and attributes.
+    ///
+    /// Returns an error if the address range is out of the address space, or
+    /// the address range or `offset` is not aligned.
+    #[cfg(feature = "fs")]
+    pub fn map_file(
+        &mut self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        file: Arc<FileMapping>,
+        offset: u64,
+        shared: bool,
+    ) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) || !is_aligned_4k(offset as usize) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let backend = Backend::new_file(file, start, offset, shared);
+        let area = MemoryArea::new(start, size, flags, backend);
+        self.areas
+            .map(area, &mut self.pt, false)
+            .map_err(mapping_err_to_ax_err)?;
+        Ok(())
+    }
+
+    /// Writes the changes in the shared file mappings within the specified
+    /// virtual address range back to the files.
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned, or it fails to write the files.
+    #[cfg(feature = "fs")]
+    pub fn msync(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let end = start + size;
+        for area in self.areas.iter() {
+            if !matches!(area.backend(), Backend::File { shared: true, .. }) {
+                continue;
+            }
+            let (sync_start, sync_end) = (area.start().max(start), area.end().min(end));
+            if sync_start >= sync_end {
+                continue;
+            }
+            let sync_size = sync_end.as_usize() - sync_start.as_usize();
+            area.backend()
+                .write_back_file(sync_start, sync_size, &mut self.pt)?;
+        }
+        Ok(())
+    }
+
     /// Removes mappings within the specified virtual address range.
     ///
     /// Returns an error if the address range is out of the address space or not
@@ -170,23 +400,144 @@ impl AddrSpace {
             return ax_err!(InvalidInput, "address not aligned");
         }
 
-        self.pt
-            .unmap_region(start, size, true)
-            .map_err(paging_err_to_ax_err)?
-            .ignore();
+        let range = VirtAddrRange::from_start_size(start, size);
+        if self.areas.overlaps(range) {
+            self.areas
+                .unmap(start, size, &mut self.pt)
+                .map_err(mapping_err_to_ax_err)?;
+            #[cfg(feature = "swap")]
+            self.swap.forget(start, start + size);
+        } else {
+            // mappings without areas, e.g., by `map_linear`
+            if !split_huge_pages(&mut self.pt, start, start + size) {
+                return ax_err!(NoMemory, "failed to split the huge pages");
+            }
+            self.pt
+                .unmap_region(start, size, true)
+                .map_err(paging_err_to_ax_err)?
+                .ignore();
+        }
+        Ok(())
+    }
+
+    /// Drops the pages within the specified virtual address range, which are
+    /// mapped again on the next access, e.g., for `madvise(MADV_DONTNEED)`.
+    ///
+    /// The dropped pages of allocation mappings are freed and reallocated
+    /// zero-filled, and those of file mappings are read from the files again
+    /// (the changes of shared file mappings are written back first, while the
+    /// private changes are lost).
+    ///
+    /// Returns an error if the address range is out of the address space or not
+    /// aligned.
+    pub fn discard(&mut self, start: VirtAddr, size: usize) -> AxResult {
+        if !self.contains_range(start, size) {
+            return ax_err!(InvalidInput, "address out of range");
+        }
+        if !start.is_aligned_4k() || !is_aligned_4k(size) {
+            return ax_err!(InvalidInput, "address not aligned");
+        }
+
+        let end = start + size;
+        let dropped: Vec<_> = self
+            .areas
+            .iter()
+            .filter_map(|area| {
+                let (drop_start, drop_end) = (area.start().max(start), area.end().min(end));
+                let size = drop_end.as_usize().saturating_sub(drop_start.as_usize());
+                (size > 0).then(|| {
+                    MemoryArea::new(drop_start, size, area.flags(), area.backend().clone_lazy())
+                })
+            })
+            .collect();
+        if dropped.is_empty() {
+            return Ok(());
+        }
+        self.areas
+            .unmap(start, size, &mut self.pt)
+            .map_err(mapping_err_to_ax_err)?;
+        #[cfg(feature = "swap")]
+        self.swap.forget(start, end);
+        for area in dropped {
+            self.areas
+                .map(area, &mut self.pt, false)
+                .map_err(mapping_err_to_ax_err)?;
+        }
+        Ok(())
+    }
+
+    /// Sets the start of the heap, e.g., to the end of the loaded program, and
+    /// resets the program break to it.
+    ///
+    /// `start` is aligned up to the page size. The heap is grown and shrunk by
+    /// [`set_brk`](Self::set_brk), nothing is mapped here.
+    pub fn init_heap(&mut self, start: VirtAddr) {
+        self.heap_start = start.align_up_4k();
+        self.brk = self.heap_start;
+    }
+
+    /// Returns the program break, i.e., the end of the heap.
+    pub const fn brk(&self) -> VirtAddr {
+        self.brk
+    }
+
+    /// Moves the program break to `brk`, which grows or shrinks the heap.
+    ///
+    /// The pages of the grown heap are allocated on demand (readable and
+    /// writable by the user), and those of the shrunk heap are freed.
+    ///
+    /// Returns an error if `brk` is below the start of the heap (set by
+    /// [`init_heap`](Self::init_heap)) or out of the address space, or the
+    /// grown heap would overlap other mappings.
+    pub fn set_brk(&mut self, brk: VirtAddr) -> AxResult {
+        if brk < self.heap_start || !self.va_range.contains(brk) {
+            return ax_err!(InvalidInput, "program break out of range");
+        }
+        let (old_end, new_end) = (self.brk.align_up_4k(), brk.align_up_4k());
+        if new_end > old_end {
+            let size = new_end.as_usize() - old_end.as_usize();
+            let flags = MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER;
+            self.map_alloc(old_end, size, flags, false)?;
+        } else if new_end < old_end {
+            let size = old_end.as_usize() - new_end.as_usize();
+            if self.overlaps(new_end, size) {
+                self.unmap(new_end, size)?;
+            }
+        }
+        self.brk = brk;
+        Ok(())
+    }
+
+    /// Swaps in the pages in `[start, end)` that are swapped out, so that the
+    /// kernel can access them.
+    #[cfg(feature = "swap")]
+    fn swap_in_range(&mut self, start: VirtAddr, end: VirtAddr) -> AxResult {
+        for (page, slot) in self.swap.swapped_range(start.align_down_4k(), end) {
+            let Some(flags) = self.areas.find(page).map(|area| area.flags()) else {
+                continue;
+            };
+            if !swap_in_page(page, slot, flags, &mut self.pt) {
+                return ax_err!(NoMemory, "failed to swap in the page");
+            }
+            self.swap.clear_swapped(page);
+            self.swap.touch(page);
+        }
         Ok(())
     }
 
     /// To process data in this area with the given function.
     ///
-    /// Now it supports reading and writing data in the given interval.
-    fn process_area_data<F>(&self, start: VirtAddr, size: usize, mut f: F) -> AxResult
+    /// Now it supports reading and writing data in the given interval. The
+    /// pages swapped out are swapped in first.
+    fn process_area_data<F>(&mut self, start: VirtAddr, size: usize, mut f: F) -> AxResult
     where
         F: FnMut(VirtAddr, usize, usize),
     {
         if !self.contains_range(start, size) {
             return ax_err!(InvalidInput, "address out of range");
         }
+        #[cfg(feature = "swap")]
+        self.swap_in_range(start, start + size)?;
         let mut cnt = 0;
         // If start is aligned to 4K, start_align_down will be equal to start_align_up.
         let end_align_up = (start + size).align_up_4k();
@@ -217,7 +568,7 @@ impl AddrSpace {
     ///
     /// * `start` - The start virtual address to read.
     /// * `buf` - The buffer to store the data.
-    pub fn read(&self, start: VirtAddr, buf: &mut [u8]) -> AxResult {
+    pub fn read(&mut self, start: VirtAddr, buf: &mut [u8]) -> AxResult {
         self.process_area_data(start, buf.len(), |src, offset, read_size| unsafe {
             core::ptr::copy_nonoverlapping(src.as_ptr(), buf.as_mut_ptr().add(offset), read_size);
         })
@@ -229,7 +580,7 @@ impl AddrSpace {
     ///
     /// * `start_vaddr` - The start virtual address to write.
     /// * `buf` - The buffer to write to the address space.
-    pub fn write(&self, start: VirtAddr, buf: &[u8]) -> AxResult {
+    pub fn write(&mut self, start: VirtAddr, buf: &[u8]) -> AxResult {
         self.process_area_data(start, buf.len(), |dst, offset, write_size| unsafe {
             core::ptr::copy_nonoverlapping(buf.as_ptr().add(offset), dst.as_mut_ptr(), write_size);
         })
@@ -247,10 +598,22 @@ impl AddrSpace {
             return ax_err!(InvalidInput, "address not aligned");
         }
 
-        self.pt
-            .protect_region(start, size, flags, true)
-            .map_err(paging_err_to_ax_err)?
-            .ignore();
+        let range = VirtAddrRange::from_start_size(start, size);
+        if self.areas.overlaps(range) {
+            // the backends keep some pages read-only, e.g., to copy on write
+            self.areas
+                .protect(start, size, |_| Some(flags), &mut self.pt)
+                .map_err(mapping_err_to_ax_err)?;
+        } else {
+            // mappings without areas, e.g., by `map_linear`
+            if !split_huge_pages(&mut self.pt, start, start + size) {
+                return ax_err!(NoMemory, "failed to split the huge pages");
+            }
+            self.pt
+                .protect_region(start, size, flags, true)
+                .map_err(paging_err_to_ax_err)?
+                .ignore();
+        }
         Ok(())
     }
 
@@ -264,25 +627,108 @@ impl AddrSpace {
         if !self.va_range.contains(vaddr) {
             return false;
         }
+        #[cfg(feature = "swap")]
+        if swap::should_reclaim() {
+            self.reclaim(swap::SWAP_CLUSTER);
+        }
         if let Some(area) = self.areas.find(vaddr) {
             let orig_flags = area.flags();
             if orig_flags.contains(access_flags) {
-                return area
-                    .backend()
-                    .handle_page_fault(vaddr, orig_flags, &mut self.pt);
+                let area_range = VirtAddrRange::from_start_size(area.start(), area.size());
+                #[cfg(feature = "swap")]
+                if area.backend().is_swappable() && orig_flags.contains(MappingFlags::USER) {
+                    let page = vaddr.align_down_4k();
+                    if let Some(slot) = self.swap.swapped(page) {
+                        if !swap_in_page(page, slot, orig_flags, &mut self.pt) {
+                            return false;
+                        }
+                        self.swap.clear_swapped(page);
+                    } else if !area.backend().handle_page_fault(
+                        vaddr,
+                        access_flags,
+                        orig_flags,
+                        area_range,
+                        &mut self.pt,
+                    ) {
+                        return false;
+                    }
+                    // the huge pages are not swapped out
+                    if self
+                        .pt
+                        .query(page)
+                        .is_ok_and(|(_, _, size)| !size.is_huge())
+                    {
+                        self.swap.touch(page);
+                    }
+                    return true;
+                }
+                return area.backend().handle_page_fault(
+                    vaddr,
+                    access_flags,
+                    orig_flags,
+                    area_range,
+                    &mut self.pt,
+                );
             }
         }
         false
     }
 
+    /// Swaps out at most `max_pages` of the oldest pages that can be swapped
+    /// out to the swap area (see [`swapon`](crate::swapon)), and returns the
+    /// number of pages swapped out.
+    ///
+    /// It is called on page faults when the free memory is low, and can also
+    /// be called to reclaim the memory of other address spaces.
+    #[cfg(feature = "swap")]
+    pub fn reclaim(&mut self, max_pages: usize) -> usize {
+        if !swap::has_free_slots() {
+            return 0;
+        }
+        let mut count = 0;
+        // the pages not swapped out (e.g., the shared ones) are young again
+        let mut kept = Vec::new();
+        while count < max_pages && kept.len() < max_pages {
+            let Some(page) = self.swap.pop_oldest() else {
+                break;
+            };
+            match swap_out_page(page, &mut self.pt) {
+                Some(slot) => {
+                    self.swap.set_swapped(page, slot);
+                    count += 1;
+                }
+                None => kept.push(page),
+            }
+        }
+        for page in kept {
+            self.swap.touch(page);
+        }
+        if count > 0 {
+            swap::drop_cached();
+        }
+        debug!("reclaim: {} pages swapped out", count);
+        count
+    }
+
+    /// Returns the slices of kernel memory that `[vaddr, vaddr + len)` of the
+    /// address space is mapped to, where the range must be within one
+    /// mapping.
+    ///
+    /// The pages swapped out are swapped in first. Returns `None` if the range
+    /// is not within one mapping, or some pages are not mapped (e.g., not
+    /// allocated yet).
     pub fn translated_byte_buffer(
-        &self,
+        &mut self,
         vaddr: VirtAddr,
         len: usize,
     ) -> Option<Vec<&'static mut [u8]>> {
         if !self.va_range.contains(vaddr) {
             return None;
         }
+        #[cfg(feature = "swap")]
+        if self.swap_in_range(vaddr, vaddr + len).is_err() {
+            return None;
+        }
         if let Some(area) = self.areas.find(vaddr) {
             if len > area.size() {
                 warn!(
@@ -305,7 +751,7 @@ impl AddrSpace {
 
             let mut v = Vec::new();
             while start < end {
-                let (start_paddr, _, page_size) = self.page_table().query(start).unwrap();
+                let (start_paddr, _, page_size) = self.page_table().query(start).ok()?;
                 let mut end_va = start.align_down(page_size) + page_size.into();
                 end_va = end_va.min(end);
 
@@ -324,6 +770,13 @@ impl AddrSpace {
     }
 }
 
+impl Drop for AddrSpace {
+    fn drop(&mut self) {
+        // free the frames, and write back the shared file mappings
+        self.areas.clear(&mut self.pt).ok();
+    }
+}
+
 impl fmt::Debug for AddrSpace {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         f.debug_struct("AddrSpace")
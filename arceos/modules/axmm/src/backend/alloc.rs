@@ -1,11 +1,20 @@
+use alloc::collections::BTreeMap;
+
 use axalloc::global_allocator;
 use axhal::mem::{phys_to_virt, virt_to_phys};
 use axhal::paging::{MappingFlags, PageSize, PageTable};
-use memory_addr::{PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+use kspin::SpinNoIrq;
+use memory_addr::{MemoryAddr, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
+
+use super::{split_huge_pages, Backend};
+use crate::HugePagePolicy;
 
-use super::Backend;
+/// The reference counts of the frames shared by multiple mappings, e.g., by
+/// [`AddrSpace::clone_cow`](crate::AddrSpace::clone_cow). The frames not in it
+/// are referenced once.
+static FRAME_REFS: SpinNoIrq<BTreeMap<PhysAddr, usize>> = SpinNoIrq::new(BTreeMap::new());
 
-fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
+pub(super) fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
     let vaddr = VirtAddr::from(global_allocator().alloc_pages(1, PAGE_SIZE_4K).ok()?);
     if zeroed {
         unsafe { core::ptr::write_bytes(vaddr.as_mut_ptr(), 0, PAGE_SIZE_4K) };
@@ -14,15 +23,201 @@ fn alloc_frame(zeroed: bool) -> Option<PhysAddr> {
     Some(paddr)
 }
 
-fn dealloc_frame(frame: PhysAddr) {
+pub(super) fn dealloc_frame(frame: PhysAddr) {
     let vaddr = phys_to_virt(frame);
     global_allocator().dealloc_pages(vaddr.as_usize(), 1);
 }
 
+/// Allocates a zeroed huge frame of `page_size`.
+///
+/// When the huge page is split, the 4K frames of it are deallocated one by one
+/// by [`unref_frame`].
+fn alloc_huge_frame(page_size: PageSize) -> Option<PhysAddr> {
+    let size: usize = page_size.into();
+    let num_pages = size / PAGE_SIZE_4K;
+    let vaddr = VirtAddr::from(global_allocator().alloc_pages(num_pages, size).ok()?);
+    let paddr = virt_to_phys(vaddr);
+    if !paddr.is_aligned(size) {
+        global_allocator().dealloc_pages(vaddr.as_usize(), num_pages);
+        return None;
+    }
+    unsafe { core::ptr::write_bytes(vaddr.as_mut_ptr(), 0, size) };
+    Some(paddr)
+}
+
+fn dealloc_huge_frame(frame: PhysAddr, page_size: PageSize) {
+    let vaddr = phys_to_virt(frame);
+    global_allocator().dealloc_pages(vaddr.as_usize(), usize::from(page_size) / PAGE_SIZE_4K);
+}
+
+/// Whether huge pages are used for an allocation mapping, by the current
+/// [`HugePagePolicy`] and whether the mapping opts in to them.
+pub(super) fn use_huge_pages(huge: bool) -> bool {
+    match crate::huge_page_policy() {
+        HugePagePolicy::Always => true,
+        HugePagePolicy::Madvise => huge,
+        HugePagePolicy::Never => false,
+    }
+}
+
+/// Adds a reference to the allocated frame, which is then shared.
+pub(super) fn ref_frame(frame: PhysAddr) {
+    *FRAME_REFS.lock().entry(frame).or_insert(1) += 1;
+}
+
+/// Removes a reference to the allocated frame, and deallocates it if it is
+/// the last one.
+pub(super) fn unref_frame(frame: PhysAddr) {
+    let mut refs = FRAME_REFS.lock();
+    match refs.get_mut(&frame) {
+        Some(count) if *count > 2 => *count -= 1,
+        Some(_) => {
+            refs.remove(&frame);
+        }
+        None => {
+            drop(refs);
+            dealloc_frame(frame);
+        }
+    }
+}
+
+/// Whether the allocated frame is referenced more than once.
+pub(super) fn is_frame_shared(frame: PhysAddr) -> bool {
+    FRAME_REFS.lock().contains_key(&frame)
+}
+
+/// Resolves a write fault on the read-only page at `vaddr` mapped to `frame`
+/// that may be shared, by copying it if it is still shared, or by making it
+/// writable otherwise.
+pub(super) fn copy_on_write(
+    vaddr: VirtAddr,
+    frame: PhysAddr,
+    flags: MappingFlags,
+    pt: &mut PageTable,
+) -> bool {
+    if !is_frame_shared(frame) {
+        return pt.protect(vaddr, flags).map(|(_, tlb)| tlb.flush()).is_ok();
+    }
+    let Some(copy) = alloc_frame(false) else {
+        return false;
+    };
+    unsafe {
+        core::ptr::copy_nonoverlapping(
+            phys_to_virt(frame).as_ptr(),
+            phys_to_virt(copy).as_mut_ptr(),
+            PAGE_SIZE_4K,
+        )
+    };
+    match pt.remap(vaddr, copy, flags) {
+        Ok((_, tlb)) => {
+            tlb.flush();
+            unref_frame(frame);
+            true
+        }
+        Err(_) => {
+            dealloc_frame(copy);
+            false
+        }
+    }
+}
+
+/// Swaps out the 4K page at `vaddr` if its frame is not shared, i.e., writes
+/// it to the swap area, unmaps it and frees the frame.
+///
+/// Returns the slot of the page in the swap area, or `None` if the page is
+/// not swapped out.
+#[cfg(feature = "swap")]
+pub(crate) fn swap_out_page(vaddr: VirtAddr, pt: &mut PageTable) -> Option<usize> {
+    let (frame, flags, page_size) = pt.query(vaddr).ok()?;
+    if page_size.is_huge() || is_frame_shared(frame) {
+        return None;
+    }
+    // unmap it first, so that it is not changed while being written
+    let (_, _, tlb) = pt.unmap(vaddr).ok()?;
+    tlb.flush();
+    let data = unsafe { core::slice::from_raw_parts(phys_to_virt(frame).as_ptr(), PAGE_SIZE_4K) };
+    match crate::swap::write_slot(data) {
+        Ok(slot) => {
+            dealloc_frame(frame);
+            Some(slot)
+        }
+        Err(_) => {
+            if let Ok(tlb) = pt.map(vaddr, frame, PageSize::Size4K, flags) {
+                tlb.flush();
+            }
+            None
+        }
+    }
+}
+
+/// Swaps in the page at `vaddr` from `slot` of the swap area, i.e., reads it
+/// into a new frame and maps it with `flags`.
+#[cfg(feature = "swap")]
+pub(crate) fn swap_in_page(
+    vaddr: VirtAddr,
+    slot: usize,
+    flags: MappingFlags,
+    pt: &mut PageTable,
+) -> bool {
+    let Some(frame) = alloc_frame(false) else {
+        return false;
+    };
+    let buf =
+        unsafe { core::slice::from_raw_parts_mut(phys_to_virt(frame).as_mut_ptr(), PAGE_SIZE_4K) };
+    if crate::swap::read_slot(slot, buf).is_ok() {
+        if let Ok(tlb) = pt.map(vaddr, frame, PageSize::Size4K, flags) {
+            tlb.flush();
+            return true;
+        }
+    }
+    dealloc_frame(frame);
+    false
+}
+
+/// Allocates a huge frame lazily and maps it to the 2M page containing
+/// `vaddr`, if the page is within `range` and there is no 4K page table for
+/// it.
+fn map_huge_page(
+    vaddr: VirtAddr,
+    flags: MappingFlags,
+    pt: &mut PageTable,
+    range: VirtAddrRange,
+) -> bool {
+    let start = vaddr.align_down(PageSize::Size2M);
+    let huge_range = VirtAddrRange::from_start_size(start, PageSize::Size2M.into());
+    if !range.contains_range(huge_range) {
+        return false;
+    }
+    let Some(frame) = alloc_huge_frame(PageSize::Size2M) else {
+        return false;
+    };
+    match pt.map(start, frame, PageSize::Size2M, flags) {
+        Ok(tlb) => {
+            tlb.flush();
+            true
+        }
+        Err(_) => {
+            dealloc_huge_frame(frame, PageSize::Size2M);
+            false
+        }
+    }
+}
+
 impl Backend {
     /// Creates a new allocation mapping backend.
     pub const fn new_alloc(populate: bool) -> Self {
-        Self::Alloc { populate }
+        Self::Alloc {
+            populate,
+            huge: false,
+        }
+    }
+
+    /// Creates a new allocation mapping backend that opts in to huge pages.
+    pub const fn new_alloc_huge(populate: bool) -> Self {
+        Self::Alloc {
+            populate,
+            huge: true,
+        }
     }
 
     pub(crate) fn map_alloc(
@@ -32,17 +227,43 @@ impl Backend {
         flags: MappingFlags,
         pt: &mut PageTable,
         populate: bool,
+        huge: bool,
     ) -> bool {
         debug!(
-            "map_alloc: [{:#x}, {:#x}) {:?} (populate={})",
+            "map_alloc: [{:#x}, {:#x}) {:?} (populate={}, huge={})",
             start,
             start + size,
             flags,
-            populate
+            populate,
+            huge
         );
+        let end = start + size;
+        // the 2M-aligned range where huge pages can be mapped
+        let (huge_start, huge_end) = if use_huge_pages(huge) {
+            (
+                start.align_up(PageSize::Size2M),
+                end.align_down(PageSize::Size2M),
+            )
+        } else {
+            (end, end)
+        };
         if populate {
             // allocate all possible physical frames for populated mapping.
-            for addr in PageIter4K::new(start, start + size).unwrap() {
+            let mut addr = start;
+            while addr < end {
+                if addr >= huge_start && addr < huge_end {
+                    if let Some(frame) = alloc_huge_frame(PageSize::Size2M) {
+                        match pt.map(addr, frame, PageSize::Size2M, flags) {
+                            Ok(tlb) => {
+                                tlb.ignore();
+                                addr += PageSize::Size2M.into();
+                                continue;
+                            }
+                            // e.g., the 4K page table is there, use 4K pages
+                            Err(_) => dealloc_huge_frame(frame, PageSize::Size2M),
+                        }
+                    }
+                }
                 if let Some(frame) = alloc_frame(true) {
                     if let Ok(tlb) = pt.map(addr, frame, PageSize::Size4K, flags) {
                         tlb.ignore(); // TLB flush on map is unnecessary, as there are no outdated mappings.
@@ -50,14 +271,24 @@ impl Backend {
                         return false;
                     }
                 }
+                addr += PAGE_SIZE_4K;
             }
             true
         } else {
-            // Map to a empty entry for on-demand mapping.
-            let flags = MappingFlags::empty();
-            pt.map_region(start, |_| 0.into(), size, flags, false, false)
-                .map(|tlb| tlb.ignore())
-                .is_ok()
+            // Map to a empty entry for on-demand mapping. The range of huge
+            // pages is left unmapped without page tables, so a huge page can
+            // be mapped on demand.
+            let mut map_empty = |start: VirtAddr, end: VirtAddr| {
+                let (size, flags) = (end.as_usize() - start.as_usize(), MappingFlags::empty());
+                pt.map_region(start, |_| 0.into(), size, flags, false, false)
+                    .map(|tlb| tlb.ignore())
+                    .is_ok()
+            };
+            if huge_start < huge_end {
+                map_empty(start, huge_start) && map_empty(huge_end, end)
+            } else {
+                map_empty(start, end)
+            }
         }
     }
 
@@ -69,18 +300,57 @@ impl Backend {
         _populate: bool,
     ) -> bool {
         debug!("unmap_alloc: [{:#x}, {:#x})", start, start + size);
-        for addr in PageIter4K::new(start, start + size).unwrap() {
+        let end = start + size;
+        if !split_huge_pages(pt, start, end) {
+            return false;
+        }
+        let mut addr = start;
+        while addr < end {
             if let Ok((frame, page_size, tlb)) = pt.unmap(addr) {
                 // Deallocate the physical frame if there is a mapping in the
                 // page table.
+                tlb.flush();
                 if page_size.is_huge() {
-                    return false;
+                    dealloc_huge_frame(frame, page_size);
+                } else {
+                    unref_frame(frame);
                 }
-                tlb.flush();
-                dealloc_frame(frame);
+                addr += page_size.into();
             } else {
                 // Deallocation is needn't if the page is not mapped.
+                addr += PAGE_SIZE_4K;
+            }
+        }
+        true
+    }
+
+    pub(crate) fn protect_alloc(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        new_flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        let end = start + size;
+        if !split_huge_pages(pt, start, end) {
+            return false;
+        }
+        let mut addr = start;
+        while addr < end {
+            let Ok((frame, _, page_size)) = pt.query(addr) else {
+                addr += PAGE_SIZE_4K;
+                continue; // not allocated yet
+            };
+            // keep the shared frames read-only to copy them on write
+            let mut flags = new_flags;
+            if is_frame_shared(frame) {
+                flags.remove(MappingFlags::WRITE);
+            }
+            match pt.protect(addr, flags) {
+                Ok((_, tlb)) => tlb.flush(),
+                Err(_) => return false,
             }
+            addr += page_size.into();
         }
         true
     }
@@ -88,19 +358,45 @@ impl Backend {
     pub(crate) fn handle_page_fault_alloc(
         &self,
         vaddr: VirtAddr,
+        access_flags: MappingFlags,
         orig_flags: MappingFlags,
         pt: &mut PageTable,
         populate: bool,
+        huge_range: Option<VirtAddrRange>,
     ) -> bool {
-        if populate {
+        if let Ok((frame, flags, _)) = pt.query(vaddr.align_down_4k()) {
+            // Copy the shared frame on write.
+            if access_flags.contains(MappingFlags::WRITE) && !flags.contains(MappingFlags::WRITE) {
+                copy_on_write(vaddr.align_down_4k(), frame, orig_flags, pt)
+            } else {
+                false
+            }
+        } else if populate {
             false // Populated mappings should not trigger page faults.
         } else if let Some(frame) = alloc_frame(true) {
             // Allocate a physical frame lazily and map it to the fault address.
             // `vaddr` does not need to be aligned. It will be automatically
             // aligned during `pt.remap` regardless of the page size.
-            pt.remap(vaddr, frame, orig_flags)
-                .map(|(_, tlb)| tlb.flush())
-                .is_ok()
+            if let Ok((_, tlb)) = pt.remap(vaddr, frame, orig_flags) {
+                tlb.flush();
+                return true;
+            }
+            // There is no empty entry, i.e., in the range of huge pages, where
+            // a huge page is mapped if possible.
+            if huge_range.is_some_and(|range| map_huge_page(vaddr, orig_flags, pt, range)) {
+                dealloc_frame(frame);
+                return true;
+            }
+            match pt.map(vaddr.align_down_4k(), frame, PageSize::Size4K, orig_flags) {
+                Ok(tlb) => {
+                    tlb.flush();
+                    true
+                }
+                Err(_) => {
+                    dealloc_frame(frame);
+                    false
+                }
+            }
         } else {
             false
         }
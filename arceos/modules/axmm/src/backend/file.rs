@@ -0,0 +1,325 @@
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::ops::Range;
+
+use axerrno::AxResult;
+use axfs::fops::File;
+use axhal::mem::phys_to_virt;
+use axhal::paging::{MappingFlags, PageTable};
+use kspin::SpinNoIrq;
+use memory_addr::{MemoryAddr, PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+use super::alloc::{alloc_frame, copy_on_write, dealloc_frame, is_frame_shared, unref_frame};
+use super::Backend;
+
+/// A file mapped into address spaces, with the pages read from it.
+///
+/// The frames of the pages are freed, and the pages written through shared
+/// mappings are written back, when the last mapping of it is unmapped.
+/// Different `FileMapping`s of the same file do not share pages, so the
+/// changes in one are only visible to the others after they are written back
+/// and before the others read the pages.
+pub struct FileMapping {
+    file: File,
+    /// The frames of the pages read, by the page index in the file.
+    pages: SpinNoIrq<BTreeMap<u64, PhysAddr>>,
+    /// The indices of the pages written through shared mappings.
+    dirty: SpinNoIrq<BTreeSet<u64>>,
+}
+
+impl FileMapping {
+    /// Creates a new mapping of `file`, which must be opened for reading, and
+    /// also for writing to be mapped writable and shared.
+    pub fn new(file: File) -> Self {
+        Self {
+            file,
+            pages: SpinNoIrq::new(BTreeMap::new()),
+            dirty: SpinNoIrq::new(BTreeSet::new()),
+        }
+    }
+
+    /// Returns the frame of the page at `index`, which is read from the file
+    /// if it has not been read yet. The part beyond the end of the file is
+    /// filled with zeros.
+    fn get_page(&self, index: u64) -> Option<PhysAddr> {
+        if let Some(&frame) = self.pages.lock().get(&index) {
+            return Some(frame);
+        }
+        let frame = alloc_frame(true)?;
+        let buf = unsafe { frame_bytes(frame) };
+        let offset = index * PAGE_SIZE_4K as u64;
+        let mut pos = 0;
+        while pos < PAGE_SIZE_4K {
+            match self.file.read_at(offset + pos as u64, &mut buf[pos..]) {
+                Ok(0) => break,
+                Ok(n) => pos += n,
+                Err(e) => {
+                    warn!("failed to read the mapped file at {:#x}: {:?}", offset, e);
+                    dealloc_frame(frame);
+                    return None;
+                }
+            }
+        }
+        // the file is read without the lock, another fault may have read the
+        // same page meanwhile
+        let mut pages = self.pages.lock();
+        if let Some(&other) = pages.get(&index) {
+            dealloc_frame(frame);
+            return Some(other);
+        }
+        pages.insert(index, frame);
+        Some(frame)
+    }
+
+    /// Returns the frame of the page at `index` if it has been read.
+    fn cached_page(&self, index: u64) -> Option<PhysAddr> {
+        self.pages.lock().get(&index).copied()
+    }
+
+    /// Writes the pages written through shared mappings whose indices are in
+    /// `range` back to the file, after which they are clean until written
+    /// again.
+    ///
+    /// The file does not grow, the part of the pages beyond the end of the
+    /// file is discarded.
+    pub fn write_back(&self, range: Range<u64>) -> AxResult {
+        let dirty: Vec<u64> = self.dirty.lock().range(range).copied().collect();
+        if dirty.is_empty() {
+            return Ok(());
+        }
+        let size = self.file.get_attr()?.size();
+        for index in dirty {
+            // cleaned before writing, so that a write meanwhile dirties it again
+            self.dirty.lock().remove(&index);
+            let offset = index * PAGE_SIZE_4K as u64;
+            let Some(frame) = self.cached_page(index).filter(|_| offset < size) else {
+                continue;
+            };
+            if let Err(e) = self.write_page(frame, offset, size) {
+                self.dirty.lock().insert(index);
+                return Err(e);
+            }
+        }
+        self.file.flush()
+    }
+
+    /// Writes the page in `frame` to `offset` of the file of `size` bytes.
+    fn write_page(&self, frame: PhysAddr, offset: u64, size: u64) -> AxResult {
+        let len = (size - offset).min(PAGE_SIZE_4K as u64) as usize;
+        let buf = unsafe { &frame_bytes(frame)[..len] };
+        let mut pos = 0;
+        while pos < len {
+            pos += self.file.write_at(offset + pos as u64, &buf[pos..])?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for FileMapping {
+    fn drop(&mut self) {
+        if let Err(e) = self.write_back(0..u64::MAX) {
+            warn!("failed to write back the mapped file: {:?}", e);
+        }
+        for &frame in self.pages.get_mut().values() {
+            dealloc_frame(frame);
+        }
+    }
+}
+
+/// Returns the bytes of the frame at `paddr`.
+///
+/// # Safety
+///
+/// The frame must be allocated and not be accessed by others at the same
+/// time.
+unsafe fn frame_bytes<'a>(paddr: PhysAddr) -> &'a mut [u8] {
+    core::slice::from_raw_parts_mut(phys_to_virt(paddr).as_mut_ptr(), PAGE_SIZE_4K)
+}
+
+impl Backend {
+    /// Creates a new file mapping backend, where the virtual address `start`
+    /// is mapped to `offset` of the file.
+    pub fn new_file(file: Arc<FileMapping>, start: VirtAddr, offset: u64, shared: bool) -> Self {
+        Self::File {
+            file,
+            start,
+            offset,
+            shared,
+        }
+    }
+
+    /// Returns the file mapping, whether it is shared, and the index in the
+    /// file of the page at `vaddr`.
+    fn file_page(&self, vaddr: VirtAddr) -> (&FileMapping, bool, u64) {
+        let Self::File {
+            file,
+            start,
+            offset,
+            shared,
+        } = self
+        else {
+            unreachable!()
+        };
+        let index = (offset + (vaddr.align_down_4k().as_usize() - start.as_usize()) as u64)
+            / PAGE_SIZE_4K as u64;
+        (file.as_ref(), *shared, index)
+    }
+
+    /// Whether `frame` mapped at `vaddr` is the page of the file, rather than
+    /// a private copy of it.
+    pub(super) fn is_file_page(&self, vaddr: VirtAddr, frame: PhysAddr) -> bool {
+        let (file, _, index) = self.file_page(vaddr);
+        file.cached_page(index) == Some(frame)
+    }
+
+    pub(crate) fn map_file(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        debug!("map_file: [{:#x}, {:#x}) {:?}", start, start + size, flags);
+        // Map to a empty entry for on-demand mapping.
+        pt.map_region(
+            start,
+            |_| 0.into(),
+            size,
+            MappingFlags::empty(),
+            false,
+            false,
+        )
+        .map(|tlb| tlb.ignore())
+        .is_ok()
+    }
+
+    /// Writes the pages in `[start, start + size)` of a shared mapping back
+    /// to the file.
+    ///
+    /// The pages written through any mapping are dirty until they are written
+    /// back through one of them, after which the other mappings may still map
+    /// them writable. So the pages mapped writable in `pt` are written back
+    /// too, and mapped read-only to track the next write to them.
+    pub(crate) fn write_back_file(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        pt: &mut PageTable,
+    ) -> AxResult {
+        let (file, shared, first) = self.file_page(start);
+        if !shared {
+            return Ok(());
+        }
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            let Ok((frame, flags, _)) = pt.query(addr) else {
+                continue; // not read yet
+            };
+            if flags.contains(MappingFlags::WRITE) && self.is_file_page(addr, frame) {
+                let (_, _, index) = self.file_page(addr);
+                file.dirty.lock().insert(index);
+                if let Ok((_, tlb)) = pt.protect(addr, flags - MappingFlags::WRITE) {
+                    tlb.flush();
+                }
+            }
+        }
+        file.write_back(first..first + (size / PAGE_SIZE_4K) as u64)
+    }
+
+    pub(crate) fn unmap_file(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
+        debug!("unmap_file: [{:#x}, {:#x})", start, start + size);
+        if let Err(e) = self.write_back_file(start, size, pt) {
+            warn!("failed to write back the mapped file: {:?}", e);
+        }
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            if let Ok((frame, page_size, tlb)) = pt.unmap(addr) {
+                if page_size.is_huge() {
+                    return false;
+                }
+                tlb.flush();
+                // the pages of the file are freed with the file mapping
+                if !self.is_file_page(addr, frame) {
+                    unref_frame(frame);
+                }
+            }
+        }
+        true
+    }
+
+    pub(crate) fn protect_file(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        new_flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            let Ok((frame, _, _)) = pt.query(addr) else {
+                continue; // not read yet
+            };
+            // keep the pages not written yet read-only, to track the pages to
+            // write back, or to copy them on write
+            let (file, shared, index) = self.file_page(addr);
+            let writable = if shared {
+                file.dirty.lock().contains(&index)
+            } else {
+                file.cached_page(index) != Some(frame) && !is_frame_shared(frame)
+            };
+            let mut flags = new_flags;
+            if !writable {
+                flags.remove(MappingFlags::WRITE);
+            }
+            match pt.protect(addr, flags) {
+                Ok((_, tlb)) => tlb.flush(),
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    pub(crate) fn handle_page_fault_file(
+        &self,
+        vaddr: VirtAddr,
+        access_flags: MappingFlags,
+        orig_flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        let (file, shared, index) = self.file_page(vaddr);
+        let vaddr = vaddr.align_down_4k();
+        let is_write = access_flags.contains(MappingFlags::WRITE);
+        let frame = match pt.query(vaddr) {
+            Ok((frame, flags, _)) => {
+                if !is_write || flags.contains(MappingFlags::WRITE) {
+                    return false; // not caused by the mapping
+                }
+                if !shared && !self.is_file_page(vaddr, frame) {
+                    // a private copy shared by `clone_cow`
+                    return copy_on_write(vaddr, frame, orig_flags, pt);
+                }
+                frame
+            }
+            Err(_) => match file.get_page(index) {
+                Some(frame) => frame,
+                None => return false,
+            },
+        };
+
+        let (frame, flags) = if !is_write {
+            // map it read-only until it is written
+            (frame, orig_flags - MappingFlags::WRITE)
+        } else if shared {
+            file.dirty.lock().insert(index);
+            (frame, orig_flags)
+        } else {
+            // copy on write
+            let Some(copy) = alloc_frame(false) else {
+                return false;
+            };
+            unsafe { frame_bytes(copy).copy_from_slice(frame_bytes(frame)) };
+            (copy, orig_flags)
+        };
+        pt.remap(vaddr, frame, flags)
+            .map(|(_, tlb)| tlb.flush())
+            .is_ok()
+    }
+}
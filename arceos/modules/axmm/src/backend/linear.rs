@@ -1,7 +1,7 @@
 use axhal::paging::{MappingFlags, PageTable};
 use memory_addr::{PhysAddr, VirtAddr};
 
-use super::Backend;
+use super::{split_huge_pages, Backend};
 
 impl Backend {
     /// Creates a new linear mapping backend.
@@ -26,7 +26,7 @@ impl Backend {
             va_to_pa(start + size),
             flags
         );
-        pt.map_region(start, va_to_pa, size, flags, false, false)
+        pt.map_region(start, va_to_pa, size, flags, true, false)
             .map(|tlb| tlb.ignore()) // TLB flush on map is unnecessary, as there are no outdated mappings.
             .is_ok()
     }
@@ -39,6 +39,9 @@ impl Backend {
         _pa_va_offset: usize,
     ) -> bool {
         debug!("unmap_linear: [{:#x}, {:#x})", start, start + size);
+        if !split_huge_pages(pt, start, start + size) {
+            return false;
+        }
         pt.unmap_region(start, size, true)
             .map(|tlb| tlb.ignore()) // flush each page on unmap, do not flush the entire TLB.
             .is_ok()
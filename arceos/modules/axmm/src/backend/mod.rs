@@ -1,21 +1,38 @@
 //! Memory mapping backends.
 #![allow(dead_code)]
 
-use axhal::paging::{MappingFlags, PageTable};
-use memory_addr::VirtAddr;
+use ::alloc::sync::Arc;
+
+use axhal::paging::{MappingFlags, PageSize, PageTable};
+use memory_addr::{MemoryAddr, PageIter4K, PhysAddr, VirtAddr, VirtAddrRange, PAGE_SIZE_4K};
 use memory_set::MappingBackend;
 
 mod alloc;
+#[cfg(feature = "fs")]
+mod file;
 mod linear;
+mod shared;
+
+#[cfg(feature = "swap")]
+pub(crate) use self::alloc::{swap_in_page, swap_out_page};
+#[cfg(feature = "fs")]
+pub use self::file::FileMapping;
+pub use self::shared::SharedPages;
 
 /// A unified enum type for different memory mapping backends.
 ///
-/// Currently, two backends are implemented:
+/// Currently, four backends are implemented:
 ///
 /// - **Linear**: used for linear mappings. The target physical frames are
 ///   contiguous and their addresses should be known when creating the mapping.
+///   Huge pages are used where the addresses are aligned to them.
 /// - **Allocation**: used in general, or for lazy mappings. The target physical
-///   frames are obtained from the global allocator.
+///   frames are obtained from the global allocator, which may be huge frames
+///   according to the [`HugePagePolicy`](crate::HugePagePolicy).
+/// - **Shared**: used for shared anonymous mappings. The target physical
+///   frames are allocated on demand, and shared by all mappings of them.
+/// - **File**: used for memory-mapped files (requires the `fs` feature). The
+///   target physical frames hold the pages read from the file on demand.
 #[derive(Clone)]
 pub enum Backend {
     /// Linear mapping backend.
@@ -36,6 +53,38 @@ pub enum Backend {
     Alloc {
         /// Whether to populate the physical frames when creating the mapping.
         populate: bool,
+        /// Whether the mapping opts in to huge pages.
+        huge: bool,
+    },
+    /// Shared anonymous mapping backend.
+    ///
+    /// The frames of the [`SharedPages`] are allocated on demand (by handling
+    /// page faults), and are mapped writable by all mappings of them, even in
+    /// the copies of the address space.
+    Shared {
+        /// The pages shared by the mappings.
+        pages: Arc<SharedPages>,
+        /// The virtual address mapped to the first page.
+        start: VirtAddr,
+    },
+    /// File mapping backend.
+    ///
+    /// The pages of the file are read on demand (by handling page faults) into
+    /// the frames of the [`FileMapping`], which are shared by all mappings of
+    /// it. If `shared` is `true`, the frames are mapped writable and the
+    /// pages written are written back to the file. Otherwise, the frames are
+    /// mapped read-only and copied on write.
+    #[cfg(feature = "fs")]
+    File {
+        /// The mapped file with the pages read from it.
+        file: Arc<FileMapping>,
+        /// The virtual address mapped to `offset` of the file.
+        start: VirtAddr,
+        /// The file offset mapped at `start`, aligned to the page size.
+        offset: u64,
+        /// Whether changes are shared with the file (`MAP_SHARED`), or private
+        /// to the mapping (`MAP_PRIVATE`).
+        shared: bool,
     },
 }
 
@@ -46,14 +95,22 @@ impl MappingBackend for Backend {
     fn map(&self, start: VirtAddr, size: usize, flags: MappingFlags, pt: &mut PageTable) -> bool {
         match *self {
             Self::Linear { pa_va_offset } => self.map_linear(start, size, flags, pt, pa_va_offset),
-            Self::Alloc { populate } => self.map_alloc(start, size, flags, pt, populate),
+            Self::Alloc { populate, huge } => {
+                self.map_alloc(start, size, flags, pt, populate, huge)
+            }
+            Self::Shared { .. } => self.map_shared(start, size, flags, pt),
+            #[cfg(feature = "fs")]
+            Self::File { .. } => self.map_file(start, size, flags, pt),
         }
     }
 
     fn unmap(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
         match *self {
             Self::Linear { pa_va_offset } => self.unmap_linear(start, size, pt, pa_va_offset),
-            Self::Alloc { populate } => self.unmap_alloc(start, size, pt, populate),
+            Self::Alloc { populate, .. } => self.unmap_alloc(start, size, pt, populate),
+            Self::Shared { .. } => self.unmap_shared(start, size, pt),
+            #[cfg(feature = "fs")]
+            Self::File { .. } => self.unmap_file(start, size, pt),
         }
     }
 
@@ -64,10 +121,19 @@ impl MappingBackend for Backend {
         new_flags: Self::Flags,
         page_table: &mut Self::PageTable,
     ) -> bool {
-        page_table
-            .protect_region(start, size, new_flags, true)
-            .map(|tlb| tlb.ignore())
-            .is_ok()
+        match *self {
+            Self::Linear { .. } => {
+                split_huge_pages(page_table, start, start + size)
+                    && page_table
+                        .protect_region(start, size, new_flags, true)
+                        .map(|tlb| tlb.ignore())
+                        .is_ok()
+            }
+            Self::Alloc { .. } => self.protect_alloc(start, size, new_flags, page_table),
+            Self::Shared { .. } => self.protect_shared(start, size, new_flags, page_table),
+            #[cfg(feature = "fs")]
+            Self::File { .. } => self.protect_file(start, size, new_flags, page_table),
+        }
     }
 }
 
@@ -75,13 +141,155 @@ impl Backend {
     pub(crate) fn handle_page_fault(
         &self,
         vaddr: VirtAddr,
+        access_flags: MappingFlags,
         orig_flags: MappingFlags,
+        area_range: VirtAddrRange,
         page_table: &mut PageTable,
     ) -> bool {
         match *self {
             Self::Linear { .. } => false, // Linear mappings should not trigger page faults.
-            Self::Alloc { populate } => {
-                self.handle_page_fault_alloc(vaddr, orig_flags, page_table, populate)
+            Self::Alloc { populate, huge } => {
+                // a huge page can be mapped only within the area
+                let huge_range = self::alloc::use_huge_pages(huge).then_some(area_range);
+                self.handle_page_fault_alloc(
+                    vaddr,
+                    access_flags,
+                    orig_flags,
+                    page_table,
+                    populate,
+                    huge_range,
+                )
+            }
+            Self::Shared { .. } => self.handle_page_fault_shared(vaddr, orig_flags, page_table),
+            #[cfg(feature = "fs")]
+            Self::File { .. } => {
+                self.handle_page_fault_file(vaddr, access_flags, orig_flags, page_table)
+            }
+        }
+    }
+
+    /// Returns the same backend that maps no pages on mapping, e.g., for the
+    /// copy of a mapping by [`share_pages`](Self::share_pages), or to map the
+    /// pages again on demand after they are dropped.
+    pub(crate) fn clone_lazy(&self) -> Self {
+        match self {
+            // the pages are shared or dropped, so nothing is allocated on mapping
+            Self::Alloc { huge, .. } => Self::Alloc {
+                populate: false,
+                huge: *huge,
+            },
+            _ => self.clone(),
+        }
+    }
+
+    /// Maps the pages in `[start, start + size)` of `pt` to the same frames in
+    /// `new_pt`, where the range is mapped with the backend returned by
+    /// [`clone_lazy`](Self::clone_lazy).
+    ///
+    /// The frames that are private to the mapping are shared read-only by
+    /// both, and copied on write, while the frames that are shared with other
+    /// mappings anyway (e.g., the pages of shared mappings) are mapped
+    /// as they are.
+    pub(crate) fn share_pages(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        pt: &mut PageTable,
+        new_pt: &mut PageTable,
+    ) -> bool {
+        if let Self::Linear { .. } = self {
+            return true; // already mapped on mapping
+        }
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            let Ok((_, _, page_size)) = pt.query(addr) else {
+                continue; // not allocated yet
+            };
+            // the huge pages are shared and copied per 4K page, so split the
+            // one at `addr` by splitting at `addr + 4K`
+            if page_size.is_huge() && !split_huge_pages(pt, addr, addr + PAGE_SIZE_4K) {
+                return false;
+            }
+            let (frame, flags, _) = pt.query(addr).unwrap();
+            let new_flags = if self.is_frame_private(addr, frame) {
+                self::alloc::ref_frame(frame);
+                flags - MappingFlags::WRITE
+            } else {
+                flags
+            };
+            if new_flags != flags {
+                match pt.protect(addr, new_flags) {
+                    Ok((_, tlb)) => tlb.flush(),
+                    Err(_) => return false,
+                }
+            }
+            match new_pt.remap(addr, frame, new_flags) {
+                Ok((_, tlb)) => tlb.ignore(),
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    /// Whether the pages of the mapping can be swapped out, i.e., the pages
+    /// allocated on demand by allocation mappings.
+    #[cfg(feature = "swap")]
+    pub(crate) fn is_swappable(&self) -> bool {
+        matches!(
+            self,
+            Self::Alloc {
+                populate: false,
+                ..
+            }
+        )
+    }
+
+    /// Whether `frame` mapped at `vaddr` is private to the mapping, rather
+    /// than shared with other mappings anyway.
+    #[cfg_attr(not(feature = "fs"), allow(unused_variables))]
+    fn is_frame_private(&self, vaddr: VirtAddr, frame: PhysAddr) -> bool {
+        match self {
+            Self::Linear { .. } | Self::Shared { .. } => false,
+            Self::Alloc { .. } => true,
+            #[cfg(feature = "fs")]
+            Self::File { .. } => !self.is_file_page(vaddr, frame),
+        }
+    }
+}
+
+/// Splits the huge pages containing `start` and `end` that are not aligned to
+/// them into smaller pages mapped to the same frames with the same flags, so
+/// that the pages in `[start, end)` can be unmapped or protected individually.
+///
+/// The huge page being split is unmapped for a moment, so it must not be
+/// accessed during the split (e.g., the active kernel mappings).
+pub(crate) fn split_huge_pages(pt: &mut PageTable, start: VirtAddr, end: VirtAddr) -> bool {
+    [start, end]
+        .into_iter()
+        .all(|vaddr| split_huge_page(pt, vaddr))
+}
+
+fn split_huge_page(pt: &mut PageTable, vaddr: VirtAddr) -> bool {
+    loop {
+        let Ok((paddr, flags, page_size)) = pt.query(vaddr) else {
+            return true; // not mapped
+        };
+        if !page_size.is_huge() || vaddr.is_aligned(page_size) {
+            return true;
+        }
+        // split 1G pages into 2M pages, and 2M pages into 4K pages
+        let small_size = match page_size {
+            PageSize::Size1G => PageSize::Size2M,
+            _ => PageSize::Size4K,
+        };
+        let (start, frame) = (vaddr.align_down(page_size), paddr.align_down(page_size));
+        match pt.unmap(start) {
+            Ok((_, _, tlb)) => tlb.flush(),
+            Err(_) => return false,
+        }
+        for offset in (0..usize::from(page_size)).step_by(small_size.into()) {
+            match pt.map(start + offset, frame + offset, small_size, flags) {
+                Ok(tlb) => tlb.ignore(), // the TLB entry of the huge page is flushed
+                Err(_) => return false,
             }
         }
     }
@@ -0,0 +1,147 @@
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+use axhal::paging::{MappingFlags, PageTable};
+use kspin::SpinNoIrq;
+use memory_addr::{MemoryAddr, PageIter4K, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+use super::alloc::{alloc_frame, dealloc_frame};
+use super::Backend;
+
+/// Anonymous pages shared by all mappings of them, including those in the
+/// copies of the address space by
+/// [`AddrSpace::clone_cow`](crate::AddrSpace::clone_cow).
+///
+/// The frames are allocated zero-filled on demand, and freed when the last
+/// mapping of them is unmapped.
+pub struct SharedPages {
+    /// The frames of the pages allocated, by the page index.
+    pages: SpinNoIrq<BTreeMap<usize, PhysAddr>>,
+}
+
+impl SharedPages {
+    /// Creates a new set of shared pages, with none allocated yet.
+    pub fn new() -> Self {
+        Self {
+            pages: SpinNoIrq::new(BTreeMap::new()),
+        }
+    }
+
+    /// Returns the frame of the page at `index`, which is allocated if it has
+    /// not been allocated yet.
+    fn get_page(&self, index: usize) -> Option<PhysAddr> {
+        let mut pages = self.pages.lock();
+        if let Some(&frame) = pages.get(&index) {
+            return Some(frame);
+        }
+        let frame = alloc_frame(true)?;
+        pages.insert(index, frame);
+        Some(frame)
+    }
+}
+
+impl Default for SharedPages {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Drop for SharedPages {
+    fn drop(&mut self) {
+        for &frame in self.pages.get_mut().values() {
+            dealloc_frame(frame);
+        }
+    }
+}
+
+impl Backend {
+    /// Creates a new shared anonymous mapping backend, where the virtual
+    /// address `start` is mapped to the first page of `pages`.
+    pub fn new_shared(pages: Arc<SharedPages>, start: VirtAddr) -> Self {
+        Self::Shared { pages, start }
+    }
+
+    /// Returns the shared pages and the index of the page at `vaddr`.
+    fn shared_page(&self, vaddr: VirtAddr) -> (&SharedPages, usize) {
+        let Self::Shared { pages, start } = self else {
+            unreachable!()
+        };
+        let index = (vaddr.align_down_4k().as_usize() - start.as_usize()) / PAGE_SIZE_4K;
+        (pages.as_ref(), index)
+    }
+
+    pub(crate) fn map_shared(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        debug!(
+            "map_shared: [{:#x}, {:#x}) {:?}",
+            start,
+            start + size,
+            flags
+        );
+        // Map to a empty entry for on-demand mapping.
+        pt.map_region(
+            start,
+            |_| 0.into(),
+            size,
+            MappingFlags::empty(),
+            false,
+            false,
+        )
+        .map(|tlb| tlb.ignore())
+        .is_ok()
+    }
+
+    pub(crate) fn unmap_shared(&self, start: VirtAddr, size: usize, pt: &mut PageTable) -> bool {
+        debug!("unmap_shared: [{:#x}, {:#x})", start, start + size);
+        // the frames are freed with the shared pages
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            if let Ok((_, _, tlb)) = pt.unmap(addr) {
+                tlb.flush();
+            }
+        }
+        true
+    }
+
+    pub(crate) fn protect_shared(
+        &self,
+        start: VirtAddr,
+        size: usize,
+        new_flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        for addr in PageIter4K::new(start, start + size).unwrap() {
+            if pt.query(addr).is_err() {
+                continue; // not allocated yet
+            }
+            match pt.protect(addr, new_flags) {
+                Ok((_, tlb)) => tlb.flush(),
+                Err(_) => return false,
+            }
+        }
+        true
+    }
+
+    pub(crate) fn handle_page_fault_shared(
+        &self,
+        vaddr: VirtAddr,
+        orig_flags: MappingFlags,
+        pt: &mut PageTable,
+    ) -> bool {
+        let (pages, index) = self.shared_page(vaddr);
+        let vaddr = vaddr.align_down_4k();
+        if pt.query(vaddr).is_ok() {
+            return false; // not caused by the mapping
+        }
+        let Some(frame) = pages.get_page(index) else {
+            return false;
+        };
+        pt.remap(vaddr, frame, orig_flags)
+            .map(|(_, tlb)| tlb.flush())
+            .is_ok()
+    }
+}
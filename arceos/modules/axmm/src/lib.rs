@@ -1,6 +1,23 @@
 //! [ArceOS](https://github.com/arceos-org/arceos) memory management module.
-
-#![no_std]
+//!
+//! # Cargo Features
+//!
+//! - `fs`: Support mapping files of [`axfs`] into address spaces, see
+//!   [`AddrSpace::map_file`].
+//! - `swap`: Swap the cold anonymous pages of user address spaces out to a
+//!   swap file or block device enabled by [`swapon`], and swap them in on
+//!   page faults (requires `fs`).
+//!
+//! # Huge Pages
+//!
+//! Linear mappings use 2M or 1G pages wherever the addresses are aligned to
+//! them. Allocation mappings use 2M pages according to the
+//! [`HugePagePolicy`], like the transparent huge pages of Linux: either for
+//! all of them, only for those mapped by [`AddrSpace::map_alloc_huge`], or
+//! never. The huge pages are split into smaller pages when partially
+//! unmapped, protected or shared.
+
+#![cfg_attr(not(test), no_std)]
 
 #[macro_use]
 extern crate log;
@@ -8,11 +25,22 @@ extern crate alloc;
 
 mod aspace;
 mod backend;
+#[cfg(feature = "swap")]
+mod swap;
+
+#[cfg(test)]
+mod tests;
 
 pub use self::aspace::AddrSpace;
+#[cfg(feature = "fs")]
+pub use self::backend::FileMapping;
+#[cfg(feature = "swap")]
+pub use self::swap::{swap_usage, swapon};
+
+use core::sync::atomic::{AtomicU8, Ordering};
 
 use axerrno::{AxError, AxResult};
-use axhal::mem::phys_to_virt;
+use axhal::mem::{phys_to_virt, MemRegionFlags};
 use axhal::paging::PagingError;
 use kspin::SpinNoIrq;
 use lazyinit::LazyInit;
@@ -24,6 +52,21 @@ const USER_ASPACE_SIZE: usize = 0x40_0000_0000;
 
 static KERNEL_ASPACE: LazyInit<SpinNoIrq<AddrSpace>> = LazyInit::new();
 
+static HUGE_PAGE_POLICY: AtomicU8 = AtomicU8::new(HugePagePolicy::Madvise as u8);
+
+/// The policy of using huge pages for allocation mappings.
+#[repr(u8)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HugePagePolicy {
+    /// Use huge pages for all allocation mappings.
+    Always,
+    /// Use huge pages only for the allocation mappings that opt in, i.e., by
+    /// [`AddrSpace::map_alloc_huge`].
+    Madvise,
+    /// Never use huge pages for allocation mappings.
+    Never,
+}
+
 fn mapping_err_to_ax_err(err: MappingError) -> AxError {
     warn!("Mapping error: {:?}", err);
     match err {
@@ -58,7 +101,17 @@ pub fn new_kernel_aspace() -> AxResult<AddrSpace> {
         axconfig::KERNEL_ASPACE_SIZE,
     )?;
     for r in axhal::mem::memory_regions() {
-        aspace.map_linear(phys_to_virt(r.paddr), r.paddr, r.size, r.flags.into())?;
+        // The flags of the free memory may be changed page by page at runtime
+        // (e.g., by `axdma`), which would split the huge pages of the active
+        // page table, so it is mapped with 4K pages.
+        let allow_huge = !r.flags.contains(MemRegionFlags::FREE);
+        aspace.map_linear_with(
+            phys_to_virt(r.paddr),
+            r.paddr,
+            r.size,
+            r.flags.into(),
+            allow_huge,
+        )?;
     }
     Ok(aspace)
 }
@@ -68,6 +121,23 @@ pub fn kernel_aspace() -> &'static SpinNoIrq<AddrSpace> {
     &KERNEL_ASPACE
 }
 
+/// Returns the current policy of using huge pages for allocation mappings.
+pub fn huge_page_policy() -> HugePagePolicy {
+    match HUGE_PAGE_POLICY.load(Ordering::Relaxed) {
+        0 => HugePagePolicy::Always,
+        1 => HugePagePolicy::Madvise,
+        _ => HugePagePolicy::Never,
+    }
+}
+
+/// Sets the policy of using huge pages for allocation mappings.
+///
+/// It takes effect on the following mappings and page faults, the huge pages
+/// already mapped are kept.
+pub fn set_huge_page_policy(policy: HugePagePolicy) {
+    HUGE_PAGE_POLICY.store(policy as u8, Ordering::Relaxed);
+}
+
 /// Returns the root physical address of the kernel page table.
 pub fn kernel_page_table_root() -> PhysAddr {
     KERNEL_ASPACE.lock().page_table_root()
@@ -0,0 +1,283 @@
+//! Swapping the cold anonymous pages of user address spaces out to a swap
+//! area, i.e., a swap file or a dedicated block device.
+//!
+//! Only the pages allocated on demand (by allocation mappings that are not
+//! populated) with the `USER` flag are swapped out. Each address space tracks
+//! the ages of such pages, and when the free memory is low on a page fault,
+//! it swaps out its oldest pages that are not shared with other address
+//! spaces. The swapped out pages are read back on the next page fault, or
+//! when the kernel accesses them (e.g., by [`AddrSpace::read`]).
+//!
+//! The pages are written to the swap area through the block cache, which is
+//! written back and dropped after each batch of pages swapped out, so that
+//! the swapped out pages do not stay in memory.
+//!
+//! The page tables do not expose the accessed bits, so the age of a page is
+//! the number of page faults in the address space since the page was last
+//! faulted in (including the copy-on-write faults).
+//!
+//! [`AddrSpace::read`]: crate::AddrSpace::read
+
+use alloc::{collections::BTreeMap, vec, vec::Vec};
+
+use axalloc::global_allocator;
+use axerrno::{ax_err, AxError, AxResult};
+use axfs::fops::{File, OpenOptions};
+use axsync::Mutex;
+use memory_addr::{VirtAddr, PAGE_SIZE_4K};
+
+/// Pages are swapped out on page faults when the number of free pages is
+/// below it.
+const LOW_FREE_PAGES: usize = 256;
+
+/// The number of pages swapped out at a time.
+pub(crate) const SWAP_CLUSTER: usize = 32;
+
+static SWAP_AREA: Mutex<Option<SwapArea>> = Mutex::new(None);
+
+/// The swap area, whose slots hold the swapped out pages.
+struct SwapArea {
+    file: File,
+    /// One bit for each slot, set if the slot is used.
+    used: Vec<u64>,
+    num_slots: usize,
+    num_used: usize,
+    /// The slot to search for free slots from.
+    next: usize,
+    /// The reference counts of the slots shared by multiple address spaces,
+    /// e.g., by [`AddrSpace::clone_cow`](crate::AddrSpace::clone_cow). The
+    /// used slots not in it are referenced once.
+    refs: BTreeMap<usize, usize>,
+}
+
+impl SwapArea {
+    fn alloc_slot(&mut self) -> Option<usize> {
+        if self.num_used == self.num_slots {
+            return None;
+        }
+        let slot = (self.next..self.num_slots)
+            .chain(0..self.next)
+            .find(|&slot| self.used[slot / 64] & (1 << (slot % 64)) == 0)?;
+        self.used[slot / 64] |= 1 << (slot % 64);
+        self.num_used += 1;
+        self.next = (slot + 1) % self.num_slots;
+        Some(slot)
+    }
+
+    fn free_slot(&mut self, slot: usize) {
+        match self.refs.get_mut(&slot) {
+            Some(count) if *count > 2 => *count -= 1,
+            Some(_) => {
+                self.refs.remove(&slot);
+            }
+            None => {
+                self.used[slot / 64] &= !(1 << (slot % 64));
+                self.num_used -= 1;
+            }
+        }
+    }
+}
+
+/// Enables swapping to the swap file or block device (e.g., `/dev/vda1`,
+/// requires the `devfs` feature of [`axfs`]) at `path`.
+///
+/// The whole file or device is used as the swap area, and its previous
+/// contents are overwritten. A swap file must not have holes, as the pages
+/// are written in place.
+///
+/// Returns an error if swapping is already enabled, or the file cannot be
+/// opened for reading and writing, or it is smaller than a page.
+pub fn swapon(path: &str) -> AxResult {
+    let mut swap = SWAP_AREA.lock();
+    if swap.is_some() {
+        return ax_err!(AlreadyExists, "swapping already enabled");
+    }
+    let mut opts = OpenOptions::new();
+    opts.read(true);
+    opts.write(true);
+    let file = File::open(path, &opts)?;
+    let num_slots = (file.get_attr()?.size() / PAGE_SIZE_4K as u64) as usize;
+    if num_slots == 0 {
+        return ax_err!(InvalidInput, "swap area too small");
+    }
+    info!("swapon {:?}: {} pages", path, num_slots);
+    *swap = Some(SwapArea {
+        file,
+        used: vec![0; num_slots.div_ceil(64)],
+        num_slots,
+        num_used: 0,
+        next: 0,
+        refs: BTreeMap::new(),
+    });
+    Ok(())
+}
+
+/// Returns the total and the used number of pages of the swap area, or
+/// `None` if swapping is not enabled.
+pub fn swap_usage() -> Option<(usize, usize)> {
+    let swap = SWAP_AREA.lock();
+    swap.as_ref().map(|swap| (swap.num_slots, swap.num_used))
+}
+
+/// Whether swapping is enabled and there are free slots in the swap area.
+pub(crate) fn has_free_slots() -> bool {
+    swap_usage().is_some_and(|(total, used)| used < total)
+}
+
+/// Whether there is so little free memory that pages should be swapped out.
+pub(crate) fn should_reclaim() -> bool {
+    global_allocator().available_pages() < LOW_FREE_PAGES && has_free_slots()
+}
+
+/// Writes the page `data` to a free slot of the swap area, and returns the
+/// slot.
+pub(crate) fn write_slot(data: &[u8]) -> AxResult<usize> {
+    let mut swap = SWAP_AREA.lock();
+    let swap = swap.as_mut().ok_or(AxError::Unsupported)?;
+    let slot = swap.alloc_slot().ok_or(AxError::StorageFull)?;
+    let offset = (slot * PAGE_SIZE_4K) as u64;
+    match swap.file.write_at(offset, data) {
+        Ok(n) if n == data.len() => Ok(slot),
+        res => {
+            warn!("failed to write swap slot {}: {:?}", slot, res);
+            swap.free_slot(slot);
+            Err(AxError::Io)
+        }
+    }
+}
+
+/// Writes the pages written to the swap area back to the block devices, and
+/// drops them from the block cache.
+pub(crate) fn drop_cached() {
+    if let Err(e) = axfs::cache::drop_all() {
+        warn!("failed to write back the swapped out pages: {:?}", e);
+    }
+}
+
+/// Reads the page in `slot` of the swap area into `buf`.
+pub(crate) fn read_slot(slot: usize, buf: &mut [u8]) -> AxResult {
+    let swap = SWAP_AREA.lock();
+    let swap = swap.as_ref().ok_or(AxError::Unsupported)?;
+    let offset = (slot * PAGE_SIZE_4K) as u64;
+    match swap.file.read_at(offset, buf) {
+        Ok(n) if n == buf.len() => Ok(()),
+        res => {
+            warn!("failed to read swap slot {}: {:?}", slot, res);
+            Err(AxError::Io)
+        }
+    }
+}
+
+/// Adds a reference to the used slot, which is then shared.
+fn ref_slot(slot: usize) {
+    if let Some(swap) = SWAP_AREA.lock().as_mut() {
+        *swap.refs.entry(slot).or_insert(1) += 1;
+    }
+}
+
+/// Removes a reference to the used slot, and frees it if it is the last one.
+pub(crate) fn free_slot(slot: usize) {
+    if let Some(swap) = SWAP_AREA.lock().as_mut() {
+        swap.free_slot(slot);
+    }
+}
+
+/// The swap state of an address space, i.e., the ages of the pages that can
+/// be swapped out, and the slots of the pages swapped out.
+#[derive(Default)]
+pub(crate) struct SwapState {
+    /// The number of page faults so far, which ages the pages.
+    clock: u64,
+    /// The resident pages that can be swapped out, with the clock when they
+    /// are last faulted in.
+    resident: BTreeMap<VirtAddr, u64>,
+    /// The resident pages by the clock when they are last faulted in, i.e.,
+    /// from the oldest to the youngest.
+    by_age: BTreeMap<u64, VirtAddr>,
+    /// The swapped out pages with their slots in the swap area.
+    swapped: BTreeMap<VirtAddr, usize>,
+}
+
+impl SwapState {
+    /// Marks the resident page at `vaddr` as faulted in just now.
+    pub(crate) fn touch(&mut self, vaddr: VirtAddr) {
+        self.clock += 1;
+        if let Some(clock) = self.resident.insert(vaddr, self.clock) {
+            self.by_age.remove(&clock);
+        }
+        self.by_age.insert(self.clock, vaddr);
+    }
+
+    /// Removes the oldest resident page from tracking and returns it.
+    pub(crate) fn pop_oldest(&mut self) -> Option<VirtAddr> {
+        let (_, vaddr) = self.by_age.pop_first()?;
+        self.resident.remove(&vaddr);
+        Some(vaddr)
+    }
+
+    /// Returns the slot of the page at `vaddr` if it is swapped out.
+    pub(crate) fn swapped(&self, vaddr: VirtAddr) -> Option<usize> {
+        self.swapped.get(&vaddr).copied()
+    }
+
+    /// Returns the pages in `[start, end)` that are swapped out, with their
+    /// slots.
+    pub(crate) fn swapped_range(&self, start: VirtAddr, end: VirtAddr) -> Vec<(VirtAddr, usize)> {
+        self.swapped
+            .range(start..end)
+            .map(|(&v, &slot)| (v, slot))
+            .collect()
+    }
+
+    /// Records that the page at `vaddr` is swapped out to `slot`.
+    pub(crate) fn set_swapped(&mut self, vaddr: VirtAddr, slot: usize) {
+        self.swapped.insert(vaddr, slot);
+    }
+
+    /// Records that the page at `vaddr` is swapped in, and frees its slot.
+    pub(crate) fn clear_swapped(&mut self, vaddr: VirtAddr) {
+        if let Some(slot) = self.swapped.remove(&vaddr) {
+            free_slot(slot);
+        }
+    }
+
+    /// Stops tracking the pages in `[start, end)`, e.g., when they are
+    /// unmapped, and frees the slots of those swapped out.
+    pub(crate) fn forget(&mut self, start: VirtAddr, end: VirtAddr) {
+        let pages: Vec<_> = self
+            .resident
+            .range(start..end)
+            .map(|(&v, &c)| (v, c))
+            .collect();
+        for (vaddr, clock) in pages {
+            self.resident.remove(&vaddr);
+            self.by_age.remove(&clock);
+        }
+        let pages: Vec<_> = self.swapped.range(start..end).map(|(&v, _)| v).collect();
+        for vaddr in pages {
+            self.clear_swapped(vaddr);
+        }
+    }
+
+    /// Returns a copy for the copy of the address space, where the slots of
+    /// the pages swapped out are shared.
+    pub(crate) fn clone_cow(&self) -> Self {
+        for &slot in self.swapped.values() {
+            ref_slot(slot);
+        }
+        Self {
+            clock: self.clock,
+            resident: self.resident.clone(),
+            by_age: self.by_age.clone(),
+            swapped: self.swapped.clone(),
+        }
+    }
+}
+
+impl Drop for SwapState {
+    fn drop(&mut self) {
+        for &slot in self.swapped.values() {
+            free_slot(slot);
+        }
+    }
+}
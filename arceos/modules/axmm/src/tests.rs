@@ -0,0 +1,350 @@
+use std::alloc::Layout;
+use std::sync::{Mutex, MutexGuard, Once, PoisonError};
+
+use axalloc::global_allocator;
+use axhal::paging::{MappingFlags, PageSize};
+use memory_addr::{va, PhysAddr, VirtAddr, PAGE_SIZE_4K};
+
+use crate::{set_huge_page_policy, AddrSpace, HugePagePolicy};
+
+/// The memory managed by the global allocator, which holds the frames.
+const MEMORY_SIZE: usize = 32 * 1024 * 1024;
+const BASE: VirtAddr = va!(0x1000_0000);
+const SIZE: usize = 0x1000_0000;
+const HUGE_SIZE: usize = 0x20_0000;
+#[cfg(feature = "swap")]
+const SWAP_PAGES: usize = 16;
+
+static INIT: Once = Once::new();
+/// The tests share the frames, the swap area and the huge page policy, so
+/// they are run one at a time.
+static SERIAL: Mutex<()> = Mutex::new(());
+
+fn init() -> MutexGuard<'static, ()> {
+    let guard = SERIAL.lock().unwrap_or_else(PoisonError::into_inner);
+    INIT.call_once(|| {
+        // aligned to the huge pages, as the physical addresses are the virtual
+        // addresses on hosted targets
+        let layout = Layout::from_size_align(MEMORY_SIZE, HUGE_SIZE).unwrap();
+        let start = unsafe { std::alloc::alloc(layout) } as usize;
+        axalloc::global_init(start, MEMORY_SIZE);
+        #[cfg(feature = "fs")]
+        init_fs();
+    });
+    guard
+}
+
+#[cfg(feature = "fs")]
+fn init_fs() {
+    use std::sync::Arc;
+
+    use axdriver::AxDeviceContainer;
+    use axdriver_block::ramdisk::RamDisk;
+    use axerrno::AxResult;
+    use axfs::fops::{register_filesystem, BlockDevice, FileSystemType};
+    use axfs_ramfs::RamFileSystem;
+    use axfs_vfs::VfsOps;
+
+    struct RamFsType;
+
+    impl FileSystemType for RamFsType {
+        fn name(&self) -> &str {
+            "ramfs"
+        }
+
+        fn mount(&self, _dev: Box<dyn BlockDevice>) -> AxResult<Arc<dyn VfsOps>> {
+            Ok(Arc::new(RamFileSystem::new()))
+        }
+    }
+
+    register_filesystem(Arc::new(RamFsType)).unwrap();
+    axfs::init_filesystems_with(
+        AxDeviceContainer::from_one(RamDisk::new(256 * 512)), // dummy disk
+        "vda0:/:ramfs",
+    );
+    #[cfg(feature = "swap")]
+    {
+        axfs::api::write("/swap", vec![0; SWAP_PAGES * PAGE_SIZE_4K]).unwrap();
+        crate::swapon("/swap").unwrap();
+    }
+}
+
+fn user_flags() -> MappingFlags {
+    MappingFlags::READ | MappingFlags::WRITE | MappingFlags::USER
+}
+
+fn query(aspace: &AddrSpace, vaddr: VirtAddr) -> (PhysAddr, MappingFlags, PageSize) {
+    aspace.page_table().query(vaddr).unwrap()
+}
+
+fn read_bytes<const N: usize>(aspace: &mut AddrSpace, vaddr: VirtAddr) -> [u8; N] {
+    let mut buf = [0; N];
+    aspace.read(vaddr, &mut buf).unwrap();
+    buf
+}
+
+#[test]
+fn test_clone_cow() {
+    let _guard = init();
+    let used = global_allocator().used_pages();
+    let mut parent = AddrSpace::new_empty(BASE, SIZE).unwrap();
+    parent
+        .map_alloc(BASE, 2 * PAGE_SIZE_4K, user_flags(), false)
+        .unwrap();
+    assert!(parent.handle_page_fault(BASE, MappingFlags::WRITE));
+    parent.write(BASE, b"parent").unwrap();
+    let frame = query(&parent, BASE).0;
+
+    // the frame is shared read-only, the pages not allocated yet are not
+    let used_before_clone = global_allocator().used_pages();
+    let mut child = parent.clone_cow().unwrap();
+    for aspace in [&parent, &child] {
+        let (shared_frame, flags, _) = query(aspace, BASE);
+        assert_eq!(shared_frame, frame);
+        assert!(!flags.contains(MappingFlags::WRITE));
+        assert!(aspace.page_table().query(BASE + PAGE_SIZE_4K).is_err());
+    }
+
+    // the first writer gets a copy
+    let used_after_clone = global_allocator().used_pages();
+    assert!(child.handle_page_fault(BASE, MappingFlags::WRITE));
+    assert_ne!(query(&child, BASE).0, frame);
+    assert_eq!(global_allocator().used_pages(), used_after_clone + 1);
+    child.write(BASE, b"child!").unwrap();
+    assert_eq!(&read_bytes::<6>(&mut parent, BASE), b"parent");
+    assert_eq!(&read_bytes::<6>(&mut child, BASE), b"child!");
+
+    // the last owner writes to the frame without copying
+    assert!(parent.handle_page_fault(BASE, MappingFlags::WRITE));
+    assert_eq!(query(&parent, BASE).0, frame);
+    assert!(query(&parent, BASE).1.contains(MappingFlags::WRITE));
+    assert_eq!(global_allocator().used_pages(), used_after_clone + 1);
+
+    drop(child);
+    assert_eq!(global_allocator().used_pages(), used_before_clone);
+    drop(parent);
+    assert_eq!(global_allocator().used_pages(), used);
+}
+
+#[test]
+fn test_shared_anonymous() {
+    let _guard = init();
+    let used = global_allocator().used_pages();
+    let mut parent = AddrSpace::new_empty(BASE, SIZE).unwrap();
+    parent
+        .map_shared(BASE, 2 * PAGE_SIZE_4K, user_flags())
+        .unwrap();
+    assert!(parent.handle_page_fault(BASE, MappingFlags::READ));
+    let frame = query(&parent, BASE).0;
+    assert!(query(&parent, BASE).1.contains(MappingFlags::WRITE));
+
+    // the frames are shared writable, instead of copied on write
+    let mut child = parent.clone_cow().unwrap();
+    assert_eq!(query(&child, BASE), query(&parent, BASE));
+    child.write(BASE, b"child!").unwrap();
+    assert_eq!(&read_bytes::<6>(&mut parent, BASE), b"child!");
+
+    // the pages allocated later are shared too
+    assert!(child.handle_page_fault(BASE + PAGE_SIZE_4K, MappingFlags::WRITE));
+    assert!(parent.handle_page_fault(BASE + PAGE_SIZE_4K, MappingFlags::READ));
+    assert_eq!(
+        query(&parent, BASE + PAGE_SIZE_4K).0,
+        query(&child, BASE + PAGE_SIZE_4K).0
+    );
+
+    // the frames are freed with the last mapping
+    drop(parent);
+    assert_eq!(query(&child, BASE).0, frame);
+    drop(child);
+    assert_eq!(global_allocator().used_pages(), used);
+}
+
+#[test]
+fn test_huge_pages() {
+    let _guard = init();
+    let used = global_allocator().used_pages();
+    let mut aspace = AddrSpace::new_empty(BASE, SIZE).unwrap();
+    let page_size = |aspace: &AddrSpace, vaddr| query(aspace, vaddr).2;
+
+    // leave a 4K page table in the second 2M page
+    aspace
+        .map_alloc(BASE + HUGE_SIZE, PAGE_SIZE_4K, user_flags(), true)
+        .unwrap();
+    aspace.unmap(BASE + HUGE_SIZE, PAGE_SIZE_4K).unwrap();
+
+    // huge pages where there is no 4K page table, and 4K pages otherwise
+    aspace
+        .map_alloc_huge(BASE, 2 * HUGE_SIZE, user_flags(), true)
+        .unwrap();
+    assert_eq!(page_size(&aspace, BASE), PageSize::Size2M);
+    assert_eq!(page_size(&aspace, BASE + HUGE_SIZE), PageSize::Size4K);
+    aspace.unmap(BASE, 2 * HUGE_SIZE).unwrap();
+
+    // likewise on demand
+    aspace
+        .map_alloc_huge(BASE, 2 * HUGE_SIZE, user_flags(), false)
+        .unwrap();
+    assert!(aspace.handle_page_fault(BASE + 8, MappingFlags::WRITE));
+    assert!(aspace.handle_page_fault(BASE + HUGE_SIZE + 8, MappingFlags::WRITE));
+    assert_eq!(page_size(&aspace, BASE), PageSize::Size2M);
+    assert_eq!(page_size(&aspace, BASE + HUGE_SIZE), PageSize::Size4K);
+    aspace.write(BASE + PAGE_SIZE_4K, b"huge").unwrap();
+
+    // split when partially protected
+    let flags = MappingFlags::READ | MappingFlags::USER;
+    aspace.protect(BASE, PAGE_SIZE_4K, flags).unwrap();
+    assert!(!query(&aspace, BASE).1.contains(MappingFlags::WRITE));
+    assert_eq!(page_size(&aspace, BASE + PAGE_SIZE_4K), PageSize::Size4K);
+    assert!(query(&aspace, BASE + PAGE_SIZE_4K)
+        .1
+        .contains(MappingFlags::WRITE));
+    assert_eq!(&read_bytes::<4>(&mut aspace, BASE + PAGE_SIZE_4K), b"huge");
+
+    // by the policy
+    let start = BASE + 2 * HUGE_SIZE;
+    set_huge_page_policy(HugePagePolicy::Never);
+    aspace
+        .map_alloc_huge(start, HUGE_SIZE, user_flags(), true)
+        .unwrap();
+    set_huge_page_policy(HugePagePolicy::Always);
+    aspace
+        .map_alloc(start + HUGE_SIZE, HUGE_SIZE, user_flags(), true)
+        .unwrap();
+    set_huge_page_policy(HugePagePolicy::Madvise);
+    assert_eq!(page_size(&aspace, start), PageSize::Size4K);
+    assert_eq!(page_size(&aspace, start + HUGE_SIZE), PageSize::Size2M);
+
+    drop(aspace);
+    assert_eq!(global_allocator().used_pages(), used);
+}
+
+#[cfg(feature = "fs")]
+#[test]
+fn test_file_mapping() {
+    use std::sync::Arc;
+
+    use axfs::api as fs;
+    use axfs::fops::{File, OpenOptions};
+
+    use crate::FileMapping;
+
+    let _guard = init();
+    let path = "/mapped.bin";
+    let mut data = vec![b'a'; PAGE_SIZE_4K];
+    data.extend_from_slice(&[b'b'; 100]);
+    fs::write(path, &data).unwrap();
+    let mut opts = OpenOptions::new();
+    opts.read(true);
+    opts.write(true);
+    let file = Arc::new(FileMapping::new(File::open(path, &opts).unwrap()));
+    let used = global_allocator().used_pages();
+    let mut aspace = AddrSpace::new_empty(BASE, SIZE).unwrap();
+    let (shared, private) = (BASE, BASE + 4 * PAGE_SIZE_4K);
+    let size = 2 * PAGE_SIZE_4K;
+    aspace
+        .map_file(shared, size, user_flags(), file.clone(), 0, true)
+        .unwrap();
+    aspace
+        .map_file(private, size, user_flags(), file.clone(), 0, false)
+        .unwrap();
+
+    // read on demand, with zeros beyond the end of the file
+    assert!(aspace.handle_page_fault(shared + PAGE_SIZE_4K, MappingFlags::READ));
+    assert_eq!(
+        read_bytes::<2>(&mut aspace, shared + PAGE_SIZE_4K + 99),
+        [b'b', 0]
+    );
+
+    // the private mapping reads the same page until writing to it
+    assert!(aspace.handle_page_fault(shared, MappingFlags::READ));
+    assert!(aspace.handle_page_fault(private, MappingFlags::READ));
+    assert_eq!(query(&aspace, private).0, query(&aspace, shared).0);
+    assert!(!query(&aspace, shared).1.contains(MappingFlags::WRITE));
+    assert!(aspace.handle_page_fault(private, MappingFlags::WRITE));
+    assert_ne!(query(&aspace, private).0, query(&aspace, shared).0);
+    aspace.write(private, b"private").unwrap();
+
+    // the shared pages are clean after written back, until written again
+    assert!(aspace.handle_page_fault(shared, MappingFlags::WRITE));
+    aspace.write(shared, b"shared").unwrap();
+    aspace.msync(shared, size).unwrap();
+    assert!(fs::read(path).unwrap().starts_with(b"shareda"));
+    assert!(!query(&aspace, shared).1.contains(MappingFlags::WRITE));
+    fs::write(path, &data).unwrap();
+    aspace.msync(shared, size).unwrap();
+    assert_eq!(fs::read(path).unwrap(), data);
+    assert!(aspace.handle_page_fault(shared, MappingFlags::WRITE));
+    aspace.write(shared, b"again").unwrap();
+
+    // the copy of the address space maps the page writable, and writes it back
+    // when unmapped, although the page is written back by the parent
+    let mut child = aspace.clone_cow().unwrap();
+    aspace.msync(shared, size).unwrap();
+    assert!(fs::read(path).unwrap().starts_with(b"againda"));
+    child.write(shared, b"child").unwrap();
+    drop(child);
+    assert!(fs::read(path).unwrap().starts_with(b"childda"));
+    assert_eq!(&read_bytes::<5>(&mut aspace, shared), b"child");
+
+    aspace.unmap(shared, size).unwrap();
+    assert_eq!(&read_bytes::<7>(&mut aspace, private), b"private");
+    drop(aspace);
+    let contents = fs::read(path).unwrap();
+    assert_eq!(contents.len(), data.len());
+    assert!(contents.starts_with(b"childda"));
+    drop(file);
+    assert_eq!(global_allocator().used_pages(), used);
+    fs::remove_file(path).unwrap();
+}
+
+#[cfg(feature = "swap")]
+#[test]
+fn test_swap() {
+    use crate::swap_usage;
+
+    let _guard = init();
+    let used_slots = || swap_usage().unwrap().1;
+    let used = used_slots();
+    let mut aspace = AddrSpace::new_empty(BASE, SIZE).unwrap();
+    aspace
+        .map_alloc(BASE, 4 * PAGE_SIZE_4K, user_flags(), false)
+        .unwrap();
+    let page = |i: usize| BASE + i * PAGE_SIZE_4K;
+    for i in 0..4 {
+        assert!(aspace.handle_page_fault(page(i), MappingFlags::WRITE));
+        aspace.write(page(i), &[i as u8 + 1; 8]).unwrap();
+    }
+
+    // the oldest pages are swapped out
+    assert_eq!(aspace.reclaim(2), 2);
+    assert_eq!(used_slots(), used + 2);
+    assert!(aspace.page_table().query(page(0)).is_err());
+    assert!(aspace.page_table().query(page(1)).is_err());
+    assert!(aspace.page_table().query(page(2)).is_ok());
+
+    // and read back on the next access, while the copy of the address space
+    // shares the slots
+    let mut child = aspace.clone_cow().unwrap();
+    assert!(aspace.handle_page_fault(page(0), MappingFlags::READ));
+    assert_eq!(read_bytes::<8>(&mut aspace, page(0)), [1; 8]);
+    assert!(query(&aspace, page(0)).1.contains(MappingFlags::WRITE));
+    assert_eq!(used_slots(), used + 2);
+    assert!(child.handle_page_fault(page(1), MappingFlags::WRITE));
+    assert_eq!(read_bytes::<8>(&mut child, page(1)), [2; 8]);
+    assert_eq!(read_bytes::<8>(&mut child, page(3)), [4; 8]);
+
+    // the frames shared with the copy are not swapped out
+    assert_eq!(child.reclaim(4), 1);
+    assert!(child.page_table().query(page(1)).is_err());
+    assert_eq!(used_slots(), used + 3);
+
+    // the pages accessed by the kernel are swapped in
+    assert_eq!(read_bytes::<8>(&mut child, page(1)), [2; 8]);
+    child.write(page(0), &[5; 8]).unwrap();
+    assert_eq!(read_bytes::<8>(&mut aspace, page(0)), [1; 8]);
+    assert_eq!(used_slots(), used + 1);
+    drop(child);
+    assert_eq!(used_slots(), used + 1);
+    drop(aspace);
+    assert_eq!(used_slots(), used);
+}
@@ -35,6 +35,8 @@ pub use self::net_impl::TcpSocket;
 pub use self::net_impl::UdpSocket;
 pub use self::net_impl::{bench_receive, bench_transmit};
 pub use self::net_impl::{dns_query, poll_interfaces};
+pub use self::net_impl::{set_tcp_rx_buffer_size, set_tcp_tx_buffer_size};
+pub use self::net_impl::{tcp_rx_buffer_size, tcp_tx_buffer_size};
 
 use axdriver::{prelude::*, AxDeviceContainer};
 
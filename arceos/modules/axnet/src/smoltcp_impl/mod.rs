@@ -8,6 +8,7 @@ mod udp;
 use alloc::vec;
 use core::cell::RefCell;
 use core::ops::DerefMut;
+use core::sync::atomic::{AtomicUsize, Ordering};
 
 use axdriver::prelude::*;
 use axdriver_net::{DevError, NetBufPtr};
@@ -44,8 +45,8 @@ const STANDARD_MTU: usize = 1500;
 
 const RANDOM_SEED: u64 = 0xA2CE_05A2_CE05_A2CE;
 
-const TCP_RX_BUF_LEN: usize = 64 * 1024;
-const TCP_TX_BUF_LEN: usize = 64 * 1024;
+static TCP_RX_BUF_LEN: AtomicUsize = AtomicUsize::new(64 * 1024);
+static TCP_TX_BUF_LEN: AtomicUsize = AtomicUsize::new(64 * 1024);
 const UDP_RX_BUF_LEN: usize = 64 * 1024;
 const UDP_TX_BUF_LEN: usize = 64 * 1024;
 const LISTEN_QUEUE_SIZE: usize = 512;
@@ -73,8 +74,10 @@ impl<'a> SocketSetWrapper<'a> {
     }
 
     pub fn new_tcp_socket() -> socket::tcp::Socket<'a> {
-        let tcp_rx_buffer = socket::tcp::SocketBuffer::new(vec![0; TCP_RX_BUF_LEN]);
-        let tcp_tx_buffer = socket::tcp::SocketBuffer::new(vec![0; TCP_TX_BUF_LEN]);
+        let rx_len = TCP_RX_BUF_LEN.load(Ordering::Relaxed);
+        let tx_len = TCP_TX_BUF_LEN.load(Ordering::Relaxed);
+        let tcp_rx_buffer = socket::tcp::SocketBuffer::new(vec![0; rx_len]);
+        let tcp_tx_buffer = socket::tcp::SocketBuffer::new(vec![0; tx_len]);
         socket::tcp::Socket::new(tcp_rx_buffer, tcp_tx_buffer)
     }
 
@@ -292,6 +295,36 @@ fn snoop_tcp_packet(buf: &[u8], sockets: &mut SocketSet<'_>) -> Result<(), smolt
     Ok(())
 }
 
+/// Returns the receive buffer size in bytes of new TCP sockets.
+pub fn tcp_rx_buffer_size() -> usize {
+    TCP_RX_BUF_LEN.load(Ordering::Relaxed)
+}
+
+/// Returns the send buffer size in bytes of new TCP sockets.
+pub fn tcp_tx_buffer_size() -> usize {
+    TCP_TX_BUF_LEN.load(Ordering::Relaxed)
+}
+
+/// Sets the receive buffer size in bytes of new TCP sockets, existing sockets
+/// are not affected. Returns `false` if `size` is zero.
+pub fn set_tcp_rx_buffer_size(size: usize) -> bool {
+    if size == 0 {
+        return false;
+    }
+    TCP_RX_BUF_LEN.store(size, Ordering::Relaxed);
+    true
+}
+
+/// Sets the send buffer size in bytes of new TCP sockets, existing sockets
+/// are not affected. Returns `false` if `size` is zero.
+pub fn set_tcp_tx_buffer_size(size: usize) -> bool {
+    if size == 0 {
+        return false;
+    }
+    TCP_TX_BUF_LEN.store(size, Ordering::Relaxed);
+    true
+}
+
 /// Poll the network stack.
 ///
 /// It may receive packets from the NIC and process them, and transmit queued
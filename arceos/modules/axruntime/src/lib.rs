@@ -10,7 +10,12 @@
 //! - `irq`: Enable interrupt handling support.
 //! - `multitask`: Enable multi-threading support.
 //! - `smp`: Enable SMP (symmetric multiprocessing) support.
-//! - `fs`: Enable filesystem support.
+//! - `fs`: Enable filesystem support. The filesystems are written back and
+//!   unmounted when the system terminates.
+//! - `ninepfs`: Mount the directories shared by 9P devices.
+//! - `swap`: Swap out the anonymous pages of user address spaces to the swap
+//!   file or block device configured by `swap-file`, once the filesystems
+//!   are initialized.
 //! - `net`: Enable networking support.
 //! - `display`: Enable graphics support.
 //!
@@ -22,12 +27,18 @@
 #[macro_use]
 extern crate axlog;
 
+#[cfg(feature = "fs")]
+extern crate alloc;
+
 #[cfg(all(target_os = "none", not(test)))]
 mod lang_items;
 
 #[cfg(feature = "smp")]
 mod mp;
 
+#[cfg(feature = "fs")]
+mod sysfs;
+
 #[cfg(feature = "smp")]
 pub use self::mp::rust_main_secondary;
 
@@ -158,6 +169,16 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
         #[allow(unused_variables)]
         let all_devices = axdriver::init_drivers();
 
+        #[cfg(feature = "ninepfs")]
+        {
+            let mut ninep_devs = all_devices.ninep;
+            while let Some(dev) = ninep_devs.take_one() {
+                if let Err(e) = axfs::fops::register_ninep_device(dev) {
+                    warn!("failed to register 9p device: {:?}", e);
+                }
+            }
+        }
+
         #[cfg(feature = "fs")]
         axfs::init_filesystems(all_devices.block);
 
@@ -166,6 +187,16 @@ pub extern "C" fn rust_main(cpu_id: usize, dtb: usize) -> ! {
 
         #[cfg(feature = "display")]
         axdisplay::init_display(all_devices.display);
+
+        #[cfg(feature = "fs")]
+        self::sysfs::init();
+
+        #[cfg(feature = "swap")]
+        if !axconfig::SWAP_FILE.is_empty() {
+            if let Err(e) = axmm::swapon(axconfig::SWAP_FILE) {
+                warn!("failed to enable swapping to {}: {:?}", axconfig::SWAP_FILE, e);
+            }
+        }
     }
 
     #[cfg(feature = "smp")]
@@ -291,6 +322,12 @@ fn init_interrupt() {
     axhal::arch::enable_irqs();
 }
 
+#[cfg(feature = "fs")]
+#[axhal::misc::register_terminate_hook(axhal::misc::TERMINATE_HOOKS)]
+fn shutdown_filesystems() {
+    axfs::shutdown_filesystems();
+}
+
 #[cfg(all(feature = "tls", not(feature = "multitask")))]
 fn init_tls() {
     let main_tls = axhal::tls::TlsArea::alloc();
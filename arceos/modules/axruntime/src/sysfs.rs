@@ -0,0 +1,99 @@
+//! Registers the kernel tunables of other modules in sysfs.
+
+use alloc::format;
+use axerrno::{ax_err, AxResult};
+use axfs::sysfs::register_attr_rw;
+
+#[allow(dead_code)]
+fn parse_usize(s: &str) -> AxResult<usize> {
+    s.parse().or_else(|_| ax_err!(InvalidInput))
+}
+
+/// Converts the result of a setter returning `false` on invalid values.
+fn check(ok: bool) -> AxResult {
+    if ok {
+        Ok(())
+    } else {
+        ax_err!(InvalidInput)
+    }
+}
+
+/// The values of `/sys/kernel/mm/transparent_hugepage/enabled`.
+#[cfg(feature = "paging")]
+const HUGE_PAGE_POLICIES: [(&str, axmm::HugePagePolicy); 3] = [
+    ("always", axmm::HugePagePolicy::Always),
+    ("madvise", axmm::HugePagePolicy::Madvise),
+    ("never", axmm::HugePagePolicy::Never),
+];
+
+/// Lists the values of the huge page policy with the current one in brackets,
+/// e.g., `always [madvise] never`.
+#[cfg(feature = "paging")]
+fn huge_page_policies() -> alloc::string::String {
+    let current = axmm::huge_page_policy();
+    let names: alloc::vec::Vec<_> = HUGE_PAGE_POLICIES
+        .iter()
+        .map(|&(name, policy)| {
+            if policy == current {
+                format!("[{}]", name)
+            } else {
+                name.into()
+            }
+        })
+        .collect();
+    format!("{}\n", names.join(" "))
+}
+
+pub(crate) fn init() {
+    register_attr_rw(
+        "kernel/log_level",
+        || format!("{}\n", axlog::max_level()),
+        |s| check(axlog::try_set_max_level(s)),
+    )
+    .expect("failed to register /sys/kernel/log_level");
+
+    #[cfg(feature = "paging")]
+    register_attr_rw(
+        "kernel/mm/transparent_hugepage/enabled",
+        huge_page_policies,
+        |s| match HUGE_PAGE_POLICIES.iter().find(|&&(name, _)| name == s) {
+            Some(&(_, policy)) => {
+                axmm::set_huge_page_policy(policy);
+                Ok(())
+            }
+            None => ax_err!(InvalidInput),
+        },
+    )
+    .expect("failed to register /sys/kernel/mm/transparent_hugepage/enabled");
+    #[cfg(not(feature = "paging"))]
+    axfs::sysfs::register_attr("kernel/mm/transparent_hugepage/enabled", || {
+        "always madvise [never]\n".into()
+    })
+    .expect("failed to register /sys/kernel/mm/transparent_hugepage/enabled");
+
+    #[cfg(feature = "multitask")]
+    if axtask::time_slice().is_some() {
+        register_attr_rw(
+            "kernel/sched/time_slice",
+            || format!("{}\n", axtask::time_slice().unwrap_or(0)),
+            |s| check(axtask::set_time_slice(parse_usize(s)?)),
+        )
+        .expect("failed to register /sys/kernel/sched/time_slice");
+    }
+
+    #[cfg(feature = "net")]
+    {
+        register_attr_rw(
+            "net/tcp/rx_buffer_size",
+            || format!("{}\n", axnet::tcp_rx_buffer_size()),
+            |s| check(axnet::set_tcp_rx_buffer_size(parse_usize(s)?)),
+        )
+        .expect("failed to register /sys/net/tcp/rx_buffer_size");
+        register_attr_rw(
+            "net/tcp/tx_buffer_size",
+            || format!("{}\n", axnet::tcp_tx_buffer_size()),
+            |s| check(axnet::set_tcp_tx_buffer_size(parse_usize(s)?)),
+        )
+        .expect("failed to register /sys/net/tcp/tx_buffer_size");
+    }
+}
@@ -1,11 +1,11 @@
 //! Task APIs for multi-task configuration.
 
-use alloc::{string::String, sync::Arc};
+use alloc::{string::String, sync::Arc, vec::Vec};
 
 pub(crate) use crate::run_queue::{AxRunQueue, RUN_QUEUE};
 
 #[doc(cfg(feature = "multitask"))]
-pub use crate::task::{CurrentTask, TaskId, TaskInner};
+pub use crate::task::{CurrentTask, TaskId, TaskInner, TaskState};
 #[doc(cfg(feature = "multitask"))]
 pub use crate::task_ext::{TaskExtMut, TaskExtRef};
 #[doc(cfg(feature = "multitask"))]
@@ -16,9 +16,10 @@ pub type AxTaskRef = Arc<AxTask>;
 
 cfg_if::cfg_if! {
     if #[cfg(feature = "sched_rr")] {
-        const MAX_TIME_SLICE: usize = 5;
-        pub(crate) type AxTask = scheduler::RRTask<TaskInner, MAX_TIME_SLICE>;
-        pub(crate) type Scheduler = scheduler::RRScheduler<TaskInner, MAX_TIME_SLICE>;
+        // The time slice is counted by `TaskInner` so that it can be changed at
+        // runtime, the scheduler only sees the end of each time slice.
+        pub(crate) type AxTask = scheduler::RRTask<TaskInner, 1>;
+        pub(crate) type Scheduler = scheduler::RRScheduler<TaskInner, 1>;
     } else if #[cfg(feature = "sched_cfs")] {
         pub(crate) type AxTask = scheduler::CFSTask<TaskInner>;
         pub(crate) type Scheduler = scheduler::CFScheduler<TaskInner>;
@@ -63,6 +64,41 @@ pub fn current() -> CurrentTask {
     CurrentTask::get()
 }
 
+/// Returns all the tasks that have not been dropped, sorted by their IDs.
+///
+/// Exited tasks are included until they are recycled.
+pub fn all_tasks() -> Vec<AxTaskRef> {
+    crate::task::TASK_LIST
+        .lock()
+        .values()
+        .filter_map(|task| task.upgrade())
+        .collect()
+}
+
+/// Returns the time slice of the round-robin scheduler in timer ticks, or
+/// [`None`] if other schedulers are used.
+pub fn time_slice() -> Option<usize> {
+    #[cfg(feature = "sched_rr")]
+    return Some(crate::run_queue::TIME_SLICE.load(core::sync::atomic::Ordering::Relaxed));
+    #[cfg(not(feature = "sched_rr"))]
+    None
+}
+
+/// Sets the time slice of the round-robin scheduler in timer ticks.
+///
+/// It takes effect on the current time slice of each task. Returns `false` if
+/// `ticks` is zero or other schedulers are used.
+pub fn set_time_slice(ticks: usize) -> bool {
+    #[cfg(feature = "sched_rr")]
+    if ticks > 0 {
+        crate::run_queue::TIME_SLICE.store(ticks, core::sync::atomic::Ordering::Relaxed);
+        return true;
+    }
+    #[cfg(not(feature = "sched_rr"))]
+    let _ = ticks;
+    false
+}
+
 /// Initializes the task scheduler (for the primary CPU).
 pub fn init_scheduler() {
     info!("Initialize scheduling...");
@@ -156,7 +192,14 @@ pub fn sleep_until(deadline: axhal::time::TimeValue) {
 }
 
 /// Exits the current task.
+///
+/// If it is the init task, the system terminates.
 pub fn exit(exit_code: i32) -> ! {
+    if current().is_init() {
+        // the terminate hooks may block, so call them before the run queue
+        // is locked
+        axhal::misc::run_terminate_hooks();
+    }
     RUN_QUEUE.lock().exit_current(exit_code)
 }
 
@@ -15,6 +15,11 @@ static EXITED_TASKS: SpinNoIrq<VecDeque<AxTaskRef>> = SpinNoIrq::new(VecDeque::n
 
 static WAIT_FOR_EXIT: WaitQueue = WaitQueue::new();
 
+/// The time slice of the round-robin scheduler in timer ticks.
+#[cfg(feature = "sched_rr")]
+pub(crate) static TIME_SLICE: core::sync::atomic::AtomicUsize =
+    core::sync::atomic::AtomicUsize::new(5);
+
 #[percpu::def_percpu]
 static IDLE_TASK: LazyInit<AxTaskRef> = LazyInit::new();
 
@@ -39,7 +44,8 @@ impl AxRunQueue {
     #[cfg(feature = "irq")]
     pub fn scheduler_timer_tick(&mut self) {
         let curr = crate::current();
-        if !curr.is_idle() && self.scheduler.task_tick(curr.as_task_ref()) {
+        if !curr.is_idle() && curr.time_slice_tick() && self.scheduler.task_tick(curr.as_task_ref())
+        {
             #[cfg(feature = "preempt")]
             curr.set_preempt_pending(true);
         }
@@ -88,6 +94,8 @@ impl AxRunQueue {
         assert!(!curr.is_idle());
         if curr.is_init() {
             EXITED_TASKS.lock().clear();
+            // the terminate hooks have been called by `exit`, with the run
+            // queue unlocked
             axhal::misc::terminate();
         } else {
             curr.set_state(TaskState::Exited);
@@ -153,6 +161,8 @@ impl AxRunQueue {
         if prev.is_running() {
             prev.set_state(TaskState::Ready);
             if !prev.is_idle() {
+                #[cfg(feature = "sched_rr")]
+                prev.put_time_slice(preempt);
                 self.scheduler.put_prev_task(prev.clone(), preempt);
             }
         }
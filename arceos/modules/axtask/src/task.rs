@@ -1,15 +1,17 @@
-use alloc::{boxed::Box, string::String, sync::Arc};
+use alloc::collections::BTreeMap;
+use alloc::{boxed::Box, string::String, sync::Arc, sync::Weak};
 use core::ops::Deref;
 use core::sync::atomic::{AtomicBool, AtomicI32, AtomicU64, AtomicU8, Ordering};
 use core::{alloc::Layout, cell::UnsafeCell, fmt, ptr::NonNull};
 
-#[cfg(feature = "preempt")]
+#[cfg(any(feature = "preempt", feature = "sched_rr"))]
 use core::sync::atomic::AtomicUsize;
 
 #[cfg(feature = "tls")]
 use axhal::tls::TlsArea;
 
 use axhal::arch::TaskContext;
+use kspin::SpinNoIrq;
 use memory_addr::{align_up_4k, VirtAddr};
 
 use crate::task_ext::AxTaskExt;
@@ -22,13 +24,21 @@ pub struct TaskId(u64);
 /// The possible states of a task.
 #[repr(u8)]
 #[derive(Debug, Clone, Copy, Eq, PartialEq)]
-pub(crate) enum TaskState {
+pub enum TaskState {
+    /// The task is running on a CPU.
     Running = 1,
+    /// The task is in the run queue, waiting to be scheduled.
     Ready = 2,
+    /// The task is waiting for an event (e.g., in a wait queue or sleeping).
     Blocked = 3,
+    /// The task has exited but has not been dropped yet.
     Exited = 4,
 }
 
+/// All tasks that have been created and not dropped yet, indexed by their IDs.
+pub(crate) static TASK_LIST: SpinNoIrq<BTreeMap<u64, Weak<AxTask>>> =
+    SpinNoIrq::new(BTreeMap::new());
+
 /// The inner task structure.
 pub struct TaskInner {
     id: TaskId,
@@ -48,6 +58,10 @@ pub struct TaskInner {
     #[cfg(feature = "preempt")]
     preempt_disable_count: AtomicUsize,
 
+    /// Timer ticks used in the current time slice.
+    #[cfg(feature = "sched_rr")]
+    slice_ticks: AtomicUsize,
+
     exit_code: AtomicI32,
     wait_for_exit: WaitQueue,
 
@@ -121,6 +135,12 @@ impl TaskInner {
         self.name.as_str()
     }
 
+    /// Gets the current state of the task.
+    #[inline]
+    pub fn state(&self) -> TaskState {
+        self.state.load(Ordering::Acquire).into()
+    }
+
     /// Get a combined string of the task ID and name.
     pub fn id_name(&self) -> alloc::string::String {
         alloc::format!("Task({}, {:?})", self.id.as_u64(), self.name)
@@ -178,6 +198,8 @@ impl TaskInner {
             need_resched: AtomicBool::new(false),
             #[cfg(feature = "preempt")]
             preempt_disable_count: AtomicUsize::new(0),
+            #[cfg(feature = "sched_rr")]
+            slice_ticks: AtomicUsize::new(0),
             exit_code: AtomicI32::new(0),
             wait_for_exit: WaitQueue::new(),
             kstack: None,
@@ -206,12 +228,10 @@ impl TaskInner {
     }
 
     pub(crate) fn into_arc(self) -> AxTaskRef {
-        Arc::new(AxTask::new(self))
-    }
-
-    #[inline]
-    pub(crate) fn state(&self) -> TaskState {
-        self.state.load(Ordering::Acquire).into()
+        let id = self.id.as_u64();
+        let task = Arc::new(AxTask::new(self));
+        TASK_LIST.lock().insert(id, Arc::downgrade(&task));
+        task
     }
 
     #[inline]
@@ -266,6 +286,29 @@ impl TaskInner {
         self.in_timer_list.store(in_timer_list, Ordering::Release);
     }
 
+    /// Counts a timer tick in the current time slice, returns whether the time
+    /// slice is used up.
+    #[inline]
+    #[cfg(feature = "irq")]
+    pub(crate) fn time_slice_tick(&self) -> bool {
+        #[cfg(feature = "sched_rr")]
+        return self.slice_ticks.fetch_add(1, Ordering::Relaxed) + 1
+            >= crate::run_queue::TIME_SLICE.load(Ordering::Relaxed);
+        #[cfg(not(feature = "sched_rr"))]
+        true
+    }
+
+    /// Called when the task is put back to the scheduler. If `preempt` and
+    /// the time slice is not used up, keep it, otherwise start a new one.
+    #[inline]
+    #[cfg(feature = "sched_rr")]
+    pub(crate) fn put_time_slice(&self, preempt: bool) {
+        let used = self.slice_ticks.load(Ordering::Relaxed);
+        if !preempt || used >= crate::run_queue::TIME_SLICE.load(Ordering::Relaxed) {
+            self.slice_ticks.store(0, Ordering::Relaxed);
+        }
+    }
+
     #[inline]
     #[cfg(feature = "preempt")]
     pub(crate) fn set_preempt_pending(&self, pending: bool) {
@@ -343,6 +386,7 @@ impl fmt::Debug for TaskInner {
 impl Drop for TaskInner {
     fn drop(&mut self) {
         debug!("task drop: {}", self.id_name());
+        TASK_LIST.lock().remove(&self.id.as_u64());
     }
 }
 
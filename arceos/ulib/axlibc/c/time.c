@@ -160,13 +160,6 @@ int gettimeofday(struct timeval *tv, struct timezone *tz)
     return 0;
 }
 
-// TODO:
-int utimes(const char *filename, const struct timeval times[2])
-{
-    unimplemented();
-    return 0;
-}
-
 // TODO
 void tzset()
 {
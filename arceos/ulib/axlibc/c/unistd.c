@@ -61,13 +61,6 @@ int access(const char *pathname, int mode)
     return 0;
 }
 
-// TODO:
-ssize_t readlink(const char *path, char *buf, size_t bufsiz)
-{
-    unimplemented();
-    return 0;
-}
-
 // TODO:
 int unlink(const char *pathname)
 {
@@ -96,13 +89,6 @@ int fdatasync(int __fildes)
     return 0;
 }
 
-// TODO:
-int fchown(int fd, uid_t owner, gid_t group)
-{
-    unimplemented("owner: %x group: %x", owner, group);
-    return 0;
-}
-
 // TODO:
 int ftruncate(int fd, off_t length)
 {
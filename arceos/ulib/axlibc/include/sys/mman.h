@@ -31,12 +31,22 @@
 #define MAP_ANONYMOUS 0x20 /* Don't use a file.  */
 #endif
 #define MAP_ANON MAP_ANONYMOUS
+#define MAP_NORESERVE       0x4000   /* Don't check for reservations.  */
+#define MAP_POPULATE        0x8000   /* Populate (prefault) pagetables.  */
+#define MAP_FIXED_NOREPLACE 0x100000 /* MAP_FIXED but do not unmap underlying mapping.  */
 /* When MAP_HUGETLB is set bits [26:31] encode the log2 of the huge page size.  */
 #define MAP_HUGE_SHIFT 26
 #define MAP_HUGE_MASK  0x3f
 
 #define MAP_FAILED ((void *)-1)
 
+/* Advice to madvise.  */
+#define MADV_NORMAL     0 /* No further special treatment.  */
+#define MADV_RANDOM     1 /* Expect random page references.  */
+#define MADV_SEQUENTIAL 2 /* Expect sequential page references.  */
+#define MADV_WILLNEED   3 /* Will need these pages.  */
+#define MADV_DONTNEED   4 /* Don't need these pages.  */
+
 /* Flags for mremap.  */
 #define MREMAP_MAYMOVE   1
 #define MREMAP_FIXED     2
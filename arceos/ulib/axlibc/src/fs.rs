@@ -1,7 +1,9 @@
 use core::ffi::{c_char, c_int};
 
 use arceos_posix_api::{
-    sys_fstat, sys_getcwd, sys_lseek, sys_lstat, sys_open, sys_rename, sys_stat,
+    sys_chmod, sys_chown, sys_fchmod, sys_fchown, sys_flock, sys_fstat, sys_fstatfs, sys_getcwd,
+    sys_link, sys_lseek, sys_lstat, sys_open, sys_readlink, sys_rename, sys_stat, sys_statfs,
+    sys_symlink, sys_sync, sys_utimes,
 };
 
 use crate::{ctypes, utils::e};
@@ -51,6 +53,32 @@ pub unsafe extern "C" fn lstat(path: *const c_char, buf: *mut ctypes::stat) -> c
     e(sys_lstat(path, buf) as _)
 }
 
+/// Get the attributes of the filesystem which `path` is located in, and
+/// write into `buf`.
+///
+/// Return 0 if success.
+#[no_mangle]
+pub unsafe extern "C" fn statfs(path: *const c_char, buf: *mut ctypes::statfs) -> c_int {
+    e(sys_statfs(path, buf))
+}
+
+/// Get the attributes of the filesystem which the file indicated by `fd` is
+/// located in, and write into `buf`.
+///
+/// Return 0 if success.
+#[no_mangle]
+pub unsafe extern "C" fn fstatfs(fd: c_int, buf: *mut ctypes::statfs) -> c_int {
+    e(sys_fstatfs(fd, buf))
+}
+
+/// Apply or remove an advisory lock on the whole file `fd`.
+///
+/// Return 0 if success.
+#[no_mangle]
+pub unsafe extern "C" fn flock(fd: c_int, operation: c_int) -> c_int {
+    e(sys_flock(fd, operation))
+}
+
 /// Get the path of the current directory.
 #[no_mangle]
 pub unsafe extern "C" fn getcwd(buf: *mut c_char, size: usize) -> *mut c_char {
@@ -65,3 +93,81 @@ pub unsafe extern "C" fn getcwd(buf: *mut c_char, size: usize) -> *mut c_char {
 pub unsafe extern "C" fn rename(old: *const c_char, new: *const c_char) -> c_int {
     e(sys_rename(old, new))
 }
+
+/// Read the target of the symbolic link `path` into `buf`.
+///
+/// Return the number of bytes placed in `buf`.
+#[no_mangle]
+pub unsafe extern "C" fn readlink(
+    path: *const c_char,
+    buf: *mut c_char,
+    bufsiz: usize,
+) -> ctypes::ssize_t {
+    e(sys_readlink(path, buf, bufsiz) as _) as _
+}
+
+/// Create a symbolic link `linkpath` which points to `target`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+#[no_mangle]
+pub unsafe extern "C" fn symlink(target: *const c_char, linkpath: *const c_char) -> c_int {
+    e(sys_symlink(target, linkpath))
+}
+
+/// Create a hard link `new` which refers to the same file as `old`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+#[no_mangle]
+pub unsafe extern "C" fn link(old: *const c_char, new: *const c_char) -> c_int {
+    e(sys_link(old, new))
+}
+
+/// Write all cached data and metadata of the filesystems back to the devices.
+#[no_mangle]
+pub unsafe extern "C" fn sync() {
+    sys_sync()
+}
+
+/// Change the permission of the file `path` to `mode`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+#[no_mangle]
+pub unsafe extern "C" fn chmod(path: *const c_char, mode: ctypes::mode_t) -> c_int {
+    e(sys_chmod(path, mode))
+}
+
+/// Change the permission of the file indicated by `fd` to `mode`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+#[no_mangle]
+pub unsafe extern "C" fn fchmod(fd: c_int, mode: ctypes::mode_t) -> c_int {
+    e(sys_fchmod(fd, mode))
+}
+
+/// Change the owner and group of the file `path`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+#[no_mangle]
+pub unsafe extern "C" fn chown(
+    path: *const c_char,
+    owner: ctypes::uid_t,
+    group: ctypes::gid_t,
+) -> c_int {
+    e(sys_chown(path, owner, group))
+}
+
+/// Change the owner and group of the file indicated by `fd`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+#[no_mangle]
+pub unsafe extern "C" fn fchown(fd: c_int, owner: ctypes::uid_t, group: ctypes::gid_t) -> c_int {
+    e(sys_fchown(fd, owner, group))
+}
+
+/// Change the last access and modification times of the file `path`.
+///
+/// Return 0 if the operation succeeds, otherwise return -1.
+#[no_mangle]
+pub unsafe extern "C" fn utimes(path: *const c_char, times: *const ctypes::timeval) -> c_int {
+    e(sys_utimes(path, times))
+}
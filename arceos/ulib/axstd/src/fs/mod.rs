@@ -42,6 +42,11 @@ pub fn metadata(path: &str) -> io::Result<Metadata> {
     File::open(path)?.metadata()
 }
 
+/// Query the metadata about a file without following symbolic links.
+pub fn symlink_metadata(path: &str) -> io::Result<Metadata> {
+    arceos_api::fs::ax_symlink_attr(path).map(Metadata)
+}
+
 /// Returns an iterator over the entries within a directory.
 pub fn read_dir(path: &str) -> io::Result<ReadDir> {
     ReadDir::new(path)
@@ -69,9 +74,32 @@ pub fn remove_file(path: &str) -> io::Result<()> {
 }
 
 /// Rename a file or directory to a new name.
-/// Delete the original file if `old` already exists.
+/// Replace the original file if `new` already exists.
 ///
-/// This only works then the new path is in the same mounted fs.
+/// This only works then the new path is in the same mounted fs, otherwise
+/// [`CrossesDevices`](io::Error::CrossesDevices) is returned.
 pub fn rename(old: &str, new: &str) -> io::Result<()> {
     arceos_api::fs::ax_rename(old, new)
 }
+
+/// Creates a new symbolic link at `link` which points to `original`.
+///
+/// The `original` path is stored as is, it is not required to exist.
+pub fn symlink(original: &str, link: &str) -> io::Result<()> {
+    arceos_api::fs::ax_symlink(original, link)
+}
+
+/// Creates a new hard link at `link` which refers to the same file as
+/// `original`.
+///
+/// Both paths must be located in the same mounted fs, and `original` must
+/// not be a directory.
+pub fn hard_link(original: &str, link: &str) -> io::Result<()> {
+    arceos_api::fs::ax_hard_link(original, link)
+}
+
+/// Reads a symbolic link, returning the path that the link points to.
+#[cfg(feature = "alloc")]
+pub fn read_link(path: &str) -> io::Result<String> {
+    arceos_api::fs::ax_read_link(path)
+}
@@ -29,7 +29,9 @@
 //!     - `sched_cfs`: Use the Completely Fair Scheduler (CFS) preemptive scheduler.
 //! - Upperlayer stacks
 //!     - `fs`: Enable file system support.
-//!     - `myfs`: Allow users to define their custom filesystems to override the default.
+//!     - `myfs`: Allow users to register their custom filesystem types alongside the built-in ones.
+//!     - `ninepfs`: Mount the host directories shared by virtio-9p devices on `/<mount tag>`.
+//!     - `swap`: Swap out the anonymous pages of user address spaces to the configured swap file or device.
 //!     - `net`: Enable networking support.
 //!     - `dns`: Enable DNS lookup support.
 //!     - `display`: Enable graphics support.
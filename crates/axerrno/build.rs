@@ -0,0 +1,88 @@
+use std::io::{Result, Write};
+
+fn main() {
+    gen_linux_errno().unwrap();
+}
+
+fn gen_linux_errno() -> Result<()> {
+    let mut output = Vec::new();
+    writeln!(output, "// Generated by build.rs, DO NOT edit\n")?;
+    writeln!(
+        output,
+        "\
+/// Linux specific error codes defined in `errno.h`.
+#[repr(i32)]
+#[non_exhaustive]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinuxError {{"
+    )?;
+
+    let mut errors = Vec::new();
+    for line in include_str!("src/errno.h").lines() {
+        // #define EPERM           1      /* Operation not permitted */
+        let Some(line) = line.strip_prefix("#define ") else {
+            continue;
+        };
+        let mut iter = line.split_whitespace();
+        let (Some(name), Some(num)) = (iter.next(), iter.next()) else {
+            continue;
+        };
+        let Ok(num) = num.parse::<i32>() else {
+            continue;
+        };
+        let desc = line
+            .split_once("/*")
+            .and_then(|(_, rest)| rest.split_once("*/"))
+            .map(|(desc, _)| desc.trim())
+            .unwrap_or_default();
+        writeln!(output, "    /// {desc}\n    {name} = {num},")?;
+        errors.push((name.to_string(), num, desc.to_string()));
+    }
+    writeln!(output, "}}\n")?;
+
+    writeln!(
+        output,
+        "\
+impl LinuxError {{
+    /// Returns the error description.
+    pub const fn as_str(&self) -> &'static str {{
+        use self::LinuxError::*;
+        match self {{"
+    )?;
+    for (name, _, desc) in &errors {
+        writeln!(output, "            {name} => \"{desc}\",")?;
+    }
+    writeln!(
+        output,
+        "        }}
+    }}
+
+    /// Returns the error code value in `i32`.
+    pub const fn code(self) -> i32 {{
+        self as i32
+    }}
+}}
+
+impl TryFrom<i32> for LinuxError {{
+    type Error = i32;
+
+    fn try_from(value: i32) -> Result<Self, Self::Error> {{
+        use self::LinuxError::*;
+        Ok(match value {{"
+    )?;
+    for (name, num, _) in &errors {
+        writeln!(output, "            {num} => {name},")?;
+    }
+    writeln!(
+        output,
+        "            _ => return Err(value),
+        }})
+    }}
+}}"
+    )?;
+
+    let out_path = format!("{}/linux_errno.rs", std::env::var("OUT_DIR").unwrap());
+    std::fs::write(out_path, output)?;
+    println!("cargo:rerun-if-changed=src/errno.h");
+    Ok(())
+}